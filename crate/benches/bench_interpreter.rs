@@ -5,7 +5,10 @@ extern crate wasm_cel;
 use criterion::black_box;
 use criterion::Criterion;
 use wasm_cel::interpreter;
+use wasm_cel::model::{Identifier, Value};
 use wasm_cel::parser;
+use wasm_cel::register;
+use wasm_cel::stack;
 
 fn benchmark_addition(c: &mut Criterion) {
     let lots_of_ones = r#"
@@ -88,5 +91,29 @@ fn benchmark_bindings(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, benchmark_addition, benchmark_bindings);
+/// The same flat arithmetic on each backend, to choose between them.
+fn benchmark_backends(c: &mut Criterion) {
+    let expr = parser::parse("x * 2 + 1 - x / 3 % 4 + x * x - 7 * (x + 1)").unwrap();
+    let with_x = |f: &dyn Fn(&interpreter::EvalContext)| {
+        let ctx = interpreter::EvalContext::default();
+        f(&ctx.with_binding(Identifier::new("x"), Ok(Value::I64(20))))
+    };
+
+    let tree = expr.clone();
+    c.bench_function("flat arithmetic: interpreter", move |b| {
+        b.iter(|| with_x(&|ctx| drop(black_box(ctx.evaluate(tree.clone())))))
+    });
+    let ops = stack::walker::linearize(expr.clone());
+    c.bench_function("flat arithmetic: stack machine", move |b| {
+        b.iter(|| {
+            with_x(&|ctx| drop(black_box(stack::runtime::evaluate_in(ops.clone(), ctx))))
+        })
+    });
+    let program = register::compiler::compile(expr);
+    c.bench_function("flat arithmetic: register machine", move |b| {
+        b.iter(|| with_x(&|ctx| drop(black_box(register::runtime::evaluate_in(&program, ctx)))))
+    });
+}
+
+criterion_group!(benches, benchmark_addition, benchmark_bindings, benchmark_backends);
 criterion_main!(benches);
@@ -0,0 +1,191 @@
+//! Interchangeable ways of evaluating an expression, so that callers can switch between them, or
+//! compare them, without changing how they call them.
+//!
+//! Every backend agrees with the tree-walking interpreter on every result, errors included, with
+//! two exceptions: the machines stop as soon as a limit is exceeded, the depth limit included, rather
+//! than carrying on with the error as the interpreter does, and they don't attribute errors to spans.
+
+use crate::interpreter::EvalContext;
+use crate::model::{EvalResult, Expression};
+use crate::register;
+use crate::stack::{runtime, walker};
+use std::fmt;
+use std::str::FromStr;
+
+/// Something that can evaluate an expression with the bindings, error policy and limits of a
+/// context.
+pub trait Evaluator {
+    fn evaluate(&self, expr: Expression, ctx: &EvalContext) -> EvalResult;
+}
+
+/// The tree-walking interpreter, `EvalContext::evaluate`.
+pub struct Interpreter;
+
+impl Evaluator for Interpreter {
+    fn evaluate(&self, expr: Expression, ctx: &EvalContext) -> EvalResult {
+        ctx.evaluate(expr)
+    }
+}
+
+/// The stack machine (see `stack`), compiling the expression first.
+pub struct StackMachine;
+
+impl Evaluator for StackMachine {
+    fn evaluate(&self, expr: Expression, ctx: &EvalContext) -> EvalResult {
+        runtime::evaluate_in(walker::linearize(expr), ctx)
+    }
+}
+
+/// The register machine (see `register`), compiling the expression first.
+pub struct RegisterMachine;
+
+impl Evaluator for RegisterMachine {
+    fn evaluate(&self, expr: Expression, ctx: &EvalContext) -> EvalResult {
+        register::runtime::evaluate_in(&register::compiler::compile(expr), ctx)
+    }
+}
+
+/// A backend chosen by name, e.g. from a setting.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Backend {
+    #[default]
+    Interpreter,
+    Stack,
+    Register,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Interpreter, Backend::Stack, Backend::Register];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Interpreter => "interpreter",
+            Backend::Stack => "stack",
+            Backend::Register => "register",
+        }
+    }
+
+    pub fn evaluator(self) -> &'static dyn Evaluator {
+        match self {
+            Backend::Interpreter => &Interpreter,
+            Backend::Stack => &StackMachine,
+            Backend::Register => &RegisterMachine,
+        }
+    }
+}
+
+impl Evaluator for Backend {
+    fn evaluate(&self, expr: Expression, ctx: &EvalContext) -> EvalResult {
+        self.evaluator().evaluate(expr, ctx)
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A name that isn't one of `Backend::ALL`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownBackend(pub String);
+
+impl FromStr for Backend {
+    type Err = UnknownBackend;
+    fn from_str(input: &str) -> Result<Backend, UnknownBackend> {
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name() == input)
+            .ok_or_else(|| UnknownBackend(input.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::EvalLimits;
+    use crate::model::{Error, ErrorPolicy, Identifier, Value};
+    use crate::parser::parse;
+
+    #[test]
+    fn names() {
+        for backend in &Backend::ALL {
+            assert_eq!(backend.name().parse(), Ok(*backend));
+        }
+        assert_eq!(
+            "jit".parse::<Backend>(),
+            Err(UnknownBackend("jit".to_owned()))
+        );
+    }
+
+    #[test]
+    fn backends_agree() {
+        let inputs = [
+            r#" let y = x * x; [y, y > 5 ? "big" : "small", {"k": y}.k] "#,
+            r#" (1 / 0) + ("a" + 1) "#,
+            r#" false || (1 / 0) || x == 3 "#,
+            r#" int("42").pow(2) + missing "#,
+            r#" [{"k": x}?.k, null?.k.j, x?.k] "#,
+            r#" [{"k": x}[?"k"].orValue(0), optional.ofNonZeroValue(x - 3)] "#,
+        ];
+        for &policy in &[ErrorPolicy::Leftmost, ErrorPolicy::Merged] {
+            let mut ctx = EvalContext::default();
+            ctx.set_error_policy(policy);
+            let ctx = ctx.with_binding(Identifier::new("x"), Ok(Value::I64(3)));
+            for input in &inputs {
+                let expr = parse(input).unwrap();
+                let expected = ctx.evaluate(expr.clone());
+                for backend in &Backend::ALL {
+                    assert_eq!(
+                        backend.evaluate(expr.clone(), &ctx),
+                        expected,
+                        "{} on {} under {:?}",
+                        input,
+                        backend,
+                        policy
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_depth() {
+        let deep = format!("{}1", "-".repeat(5000));
+        let expr = parse(&deep).unwrap();
+        for backend in &Backend::ALL {
+            assert_eq!(
+                backend.evaluate(expr.clone(), &EvalContext::default()),
+                Err(Error::RecursionLimitExceeded),
+                "{}",
+                backend
+            );
+        }
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_depth: 3,
+            ..EvalLimits::default()
+        });
+        let inputs = [
+            r#" -(-1) "#,
+            r#" -(-(-1)) "#,
+            r#" [[1], [[1]]] "#,
+            r#" 1 + 2 * 3 - 4 "#,
+            r#" true || [[[1]]] == [] "#,
+            r#" false ? 1 : [[[1]]] "#,
+        ];
+        for input in &inputs {
+            let expr = parse(input).unwrap();
+            let expected = ctx.evaluate(expr.clone());
+            for backend in &Backend::ALL {
+                assert_eq!(
+                    backend.evaluate(expr.clone(), &ctx),
+                    expected,
+                    "{} on {}",
+                    input,
+                    backend
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,10 @@
+//! Serve the `wasm_cel::wasi` protocol on stdin and stdout. Build with
+//! `cargo build --target wasm32-wasi --bin cel-wasi` to run under a WASI runtime.
+
+use std::io;
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    wasm_cel::wasi::serve(stdin.lock(), io::BufWriter::new(stdout.lock()))
+}
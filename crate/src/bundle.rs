@@ -0,0 +1,390 @@
+//! Policy bundles: a set of named expressions shipped as a single artifact, along with a prelude of
+//! definitions they share and declarations of the bindings they'll be evaluated with.
+//!
+//! A bundle is stored as JSON, with each expression as source text:
+//!
+//! ```json
+//! {
+//!   "format": 1,
+//!   "version": "0.1.0",
+//!   "features": 0,
+//!   "prelude": [{"name": "admins", "source": "['alice', 'bob']"}],
+//!   "declarations": {"request": {"Map": {"user": {"Kind": "String"}}}},
+//!   "policies": [{"name": "is_admin", "source": "admins.contains(request.user)"}]
+//! }
+//! ```
+//!
+//! Each policy is evaluated as if the prelude's definitions were `let`s around it, in order, so
+//! later definitions may refer to earlier ones. Numbers in the bindings can be converted to the
+//! kinds that the declarations give them with `Bundle::coerce`.
+//!
+//! `features` are the language features its expressions use. A bundle using any that this runtime
+//! doesn't support is rejected on load, naming the version of the crate that wrote it.
+
+use crate::checker::{CheckError, Checker, Schema};
+use crate::features::Features;
+use crate::interpreter::EvalContext;
+use crate::model::{EvalResult, Expression, Identifier, Value};
+use crate::optimize;
+use crate::parser::{self, ParseError};
+use crate::validation::{self, BindingError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The version of the bundle format that this crate reads and writes.
+pub const FORMAT: u32 = 1;
+
+/// A bundle as stored, with its expressions not yet parsed.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    /// The version of the bundle format.
+    pub format: u32,
+    /// The version of this crate that wrote the bundle.
+    pub version: String,
+    /// The language features that the bundle's expressions use.
+    #[serde(default)]
+    pub features: Features,
+    pub prelude: Vec<Definition>,
+    pub declarations: BTreeMap<String, Schema>,
+    pub policies: Vec<Definition>,
+}
+
+/// A named expression.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Definition {
+    pub name: String,
+    pub source: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BundleError {
+    /// The bundle isn't valid JSON, or isn't shaped like a bundle.
+    Malformed(String),
+    /// The bundle is in a format this crate can't read.
+    UnsupportedFormat(u32),
+    /// The bundle, written by the given version of this crate, uses language features that this
+    /// runtime doesn't support.
+    UnsupportedFeatures(String, Features),
+    /// Two prelude definitions, or two policies, have the same name.
+    DuplicateName(String),
+    /// The named definition or policy doesn't parse.
+    Parse(String, ParseError),
+    /// The named policy doesn't check against the declarations.
+    Check(String, CheckError),
+}
+
+impl Default for Bundle {
+    fn default() -> Bundle {
+        Bundle {
+            format: FORMAT,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            features: Features::NONE,
+            prelude: Vec::new(),
+            declarations: BTreeMap::new(),
+            policies: Vec::new(),
+        }
+    }
+}
+
+impl Bundle {
+    pub fn new() -> Bundle {
+        Bundle::default()
+    }
+
+    /// Read a bundle from JSON, without verifying its expressions.
+    pub fn from_json(json: &str) -> Result<Bundle, BundleError> {
+        // Check the format before the rest, which may be shaped differently in other formats.
+        #[derive(Deserialize)]
+        struct Header {
+            format: u32,
+            #[serde(default)]
+            version: String,
+            #[serde(default)]
+            features: Features,
+        }
+        let header: Header =
+            serde_json::from_str(json).map_err(|err| BundleError::Malformed(err.to_string()))?;
+        if header.format != FORMAT {
+            return Err(BundleError::UnsupportedFormat(header.format));
+        }
+        let unsupported = header.features.unsupported();
+        if !unsupported.is_empty() {
+            return Err(BundleError::UnsupportedFeatures(
+                header.version,
+                unsupported,
+            ));
+        }
+        serde_json::from_str(json).map_err(|err| BundleError::Malformed(err.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("serialize")
+    }
+
+    /// Add `name` to the prelude, defined as the value of `source`.
+    pub fn define(&mut self, name: &str, source: &str) {
+        self.note_features(source);
+        self.prelude.push(Definition {
+            name: name.to_owned(),
+            source: source.to_owned(),
+        });
+    }
+
+    /// Declare that `name` will be bound to values shaped like `schema`.
+    pub fn declare(&mut self, name: &str, schema: Schema) {
+        self.declarations.insert(name.to_owned(), schema);
+    }
+
+    /// Add a policy named `name`.
+    pub fn add_policy(&mut self, name: &str, source: &str) {
+        self.note_features(source);
+        self.policies.push(Definition {
+            name: name.to_owned(),
+            source: source.to_owned(),
+        });
+    }
+
+    /// Add the features that `source` uses to the bundle's. Sources that don't parse are reported
+    /// by `verify` instead.
+    fn note_features(&mut self, source: &str) {
+        if let Ok(expr) = parser::parse(source) {
+            self.features = self.features | Features::of(&expr);
+        }
+    }
+
+    /// Convert the numbers in `bindings` to the kinds that the bundle declares them to be.
+    pub fn coerce(
+        &self,
+        bindings: Vec<(Identifier, Value)>,
+    ) -> Result<Vec<(Identifier, Value)>, BindingError> {
+        bindings
+            .into_iter()
+            .map(|(id, value)| match self.declarations.get(&id.0) {
+                Some(schema) => Ok((id.clone(), validation::coerce(&id.0, value, schema)?)),
+                None => Ok((id, value)),
+            })
+            .collect()
+    }
+
+    /// Every problem with the bundle's expressions: duplicate names, parse errors, and accesses
+    /// that don't check against the declarations.
+    pub fn verify(&self) -> Vec<BundleError> {
+        match self.compile() {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors,
+        }
+    }
+
+    /// Parse and check every expression, for evaluation.
+    pub fn compile(&self) -> Result<Policies, Vec<BundleError>> {
+        let mut errors = Vec::new();
+        duplicates(&self.prelude, &mut errors);
+        duplicates(&self.policies, &mut errors);
+        let mut prelude = Vec::new();
+        for definition in &self.prelude {
+            match parser::parse(&definition.source) {
+                Ok(expr) => prelude.push((Identifier(definition.name.clone()), expr)),
+                Err(err) => errors.push(BundleError::Parse(definition.name.clone(), err)),
+            }
+        }
+        let mut checker = Checker::new();
+        for (name, schema) in &self.declarations {
+            checker.declare(name, schema.clone());
+        }
+        let mut policies = Vec::new();
+        for policy in &self.policies {
+            let expr = match parser::parse(&policy.source) {
+                Ok(expr) => expr,
+                Err(err) => {
+                    errors.push(BundleError::Parse(policy.name.clone(), err));
+                    continue;
+                }
+            };
+            let expr =
+                prelude
+                    .iter()
+                    .rev()
+                    .fold(expr, |body, (id, value)| Expression::LetBinding {
+                        id: id.clone(),
+                        value: Box::new(value.clone()),
+                        body: Box::new(body),
+                    });
+            let name = &policy.name;
+            errors.extend(
+                checker
+                    .check(&expr)
+                    .into_iter()
+                    .map(|err| BundleError::Check(name.clone(), err)),
+            );
+            policies.push((name.clone(), expr));
+        }
+        if errors.is_empty() {
+            Ok(Policies { policies })
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Read a bundle from JSON and compile it.
+pub fn load(json: &str) -> Result<Policies, Vec<BundleError>> {
+    Bundle::from_json(json).map_err(|err| vec![err])?.compile()
+}
+
+fn duplicates(definitions: &[Definition], errors: &mut Vec<BundleError>) {
+    for (i, definition) in definitions.iter().enumerate() {
+        if definitions[..i].iter().any(|d| d.name == definition.name) {
+            errors.push(BundleError::DuplicateName(definition.name.clone()));
+        }
+    }
+}
+
+/// The policies of a verified bundle, ready to evaluate.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Policies {
+    policies: Vec<(String, Expression)>,
+}
+
+impl Policies {
+    /// The names of the policies, in the order the bundle lists them.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.policies.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Evaluate the policy named `name` in `ctx`, or `None` if there is no such policy.
+    pub fn evaluate(&self, ctx: &EvalContext, name: &str) -> Option<EvalResult> {
+        let (_, expr) = self.policies.iter().find(|(n, _)| n == name)?;
+        Some(ctx.evaluate(expr.clone()))
+    }
+
+    /// Fold the constant subexpressions of every policy (see `optimize::fold_constants`), for
+    /// evaluation in contexts with the same functions as `ctx`.
+    pub fn fold_constants(self, ctx: &EvalContext) -> Policies {
+        let policies = self
+            .policies
+            .into_iter()
+            .map(|(name, expr)| (name, optimize::fold_constants(ctx, expr)))
+            .collect();
+        Policies { policies }
+    }
+
+    /// Evaluate every policy in `ctx`.
+    pub fn evaluate_all(&self, ctx: &EvalContext) -> Vec<(String, EvalResult)> {
+        self.policies
+            .iter()
+            .map(|(name, expr)| (name.clone(), ctx.evaluate(expr.clone())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::{Error, Value};
+
+    fn bundle() -> Bundle {
+        let mut bundle = Bundle::new();
+        bundle.define("admins", "['alice', 'bob']");
+        bundle.define("isAdmin", "admins.contains(request.user)");
+        bundle.declare(
+            "request",
+            Schema::from_json_schema(&serde_json::json!({
+                "properties": {"user": {"type": "string"}, "size": {"type": "integer"}},
+            })),
+        );
+        bundle.add_policy("admin", "isAdmin");
+        bundle.add_policy("small", "isAdmin || request.size < 10");
+        bundle
+    }
+
+    #[test]
+    fn features() {
+        let mut bundle = bundle();
+        assert_eq!(bundle.features, Features::NONE);
+        bundle.add_policy(
+            "recent",
+            "request.size > 0 ? true : timestamp('2024-01-01T00:00:00Z') < now",
+        );
+        assert_eq!(bundle.features, Features::TERNARIES | Features::TIME);
+    }
+
+    #[test]
+    fn round_trip() {
+        let bundle = bundle();
+        assert_eq!(Bundle::from_json(&bundle.to_json()), Ok(bundle.clone()));
+        assert!(load(&bundle.to_json()).is_ok());
+
+        let mut json: serde_json::Value = serde_json::from_str(&bundle.to_json()).unwrap();
+        json["format"] = serde_json::json!(FORMAT + 1);
+        json["policies"] = serde_json::json!("changed shape");
+        assert_eq!(
+            Bundle::from_json(&json.to_string()),
+            Err(BundleError::UnsupportedFormat(FORMAT + 1))
+        );
+        json["format"] = serde_json::json!(FORMAT);
+        json["features"] = serde_json::json!(Features::SUPPORTED.0 | 1 << 31);
+        assert_eq!(
+            Bundle::from_json(&json.to_string()),
+            Err(BundleError::UnsupportedFeatures(
+                bundle.version.clone(),
+                Features(1 << 31)
+            ))
+        );
+        assert!(matches!(
+            Bundle::from_json("{}"),
+            Err(BundleError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn evaluate() {
+        let policies = bundle().compile().unwrap();
+        assert_eq!(policies.names().collect::<Vec<_>>(), vec!["admin", "small"]);
+        let mut request = std::collections::HashMap::new();
+        request.insert("user".to_owned(), Value::String("carol".to_owned().into()));
+        request.insert("size".to_owned(), Value::I64(3));
+        request.insert("size".to_owned(), Value::F64(3.0));
+        let bindings = vec![(Identifier::new("request"), Value::Map(request))];
+        let bindings = bundle().coerce(bindings).unwrap();
+        let ctx = EvalContext::default();
+        let (id, request) = bindings[0].clone();
+        let ctx = ctx.with_binding(id, Ok(request));
+        assert_eq!(
+            policies.evaluate(&ctx, "admin"),
+            Some(Ok(Value::Bool(false)))
+        );
+        // `size` is declared an integer, so compares with one even if bound as a double.
+        assert_eq!(
+            policies.evaluate(&ctx, "small"),
+            Some(Ok(Value::Bool(true)))
+        );
+        assert_eq!(policies.evaluate(&ctx, "other"), None);
+        let no_request = Error::NoSuchBinding(Identifier::new("request"), vec![]);
+        assert_eq!(
+            policies.evaluate_all(&EvalContext::default()),
+            vec![
+                ("admin".to_owned(), Err(no_request.clone())),
+                ("small".to_owned(), Err(no_request)),
+            ]
+        );
+
+        // Folding inlines the constant `admins`, but the policies evaluate the same.
+        let folded = policies.clone().fold_constants(&EvalContext::default());
+        assert_ne!(folded, policies);
+        assert_eq!(folded.evaluate_all(&ctx), policies.evaluate_all(&ctx));
+    }
+
+    #[test]
+    fn verify() {
+        let mut bundle = bundle();
+        bundle.define("admins", "[]");
+        bundle.add_policy("broken", "1 +");
+        bundle.add_policy("typo", "request.usr == 'x'");
+        let errors = bundle.verify();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], BundleError::DuplicateName("admins".to_owned()));
+        assert!(matches!(&errors[1], BundleError::Parse(name, _) if name == "broken"));
+        assert!(matches!(&errors[2], BundleError::Check(name, _) if name == "typo"));
+        assert!(load(&bundle.to_json()).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! A canonical form for expressions, and hashes of it, so that rules that mean the same thing can
+//! be found among many however they were written.
+//!
+//! The canonical form of an expression has no spans, and orders the operands of commutative
+//! operators: those of `||`, `&&`, `==`, `!=` and `*` are sorted by their formatted text, nested
+//! `||`s and `&&`s are flattened into one, `a > b` becomes `b < a` and `a >= b` becomes `b <= a`,
+//! and the entries of map literals are sorted by key. Literals are compared as values, so `1.0` and
+//! `1.00` are the same, as are `'a'` and `"a"`.
+//!
+//! Two expressions with the same canonical form evaluate to the same value in the same context.
+//! When they fail, which error they report may differ, since that depends on the order of operands.
+//! Names bound by `let` are kept as they are, so `let a = 1; a` and `let b = 1; b` differ.
+
+use crate::format;
+use crate::model::{Expression, Literal};
+use crate::residual::split;
+use std::mem;
+
+/// `expr` in canonical form.
+pub fn canonicalize(mut expr: Expression) -> Expression {
+    match &mut expr {
+        Expression::Spanned(_, e) => canonicalize(e.take()),
+        Expression::LetBinding { id, value, body } => Expression::LetBinding {
+            id: mem::take(id),
+            value: Box::new(canonicalize(value.take())),
+            body: Box::new(canonicalize(body.take())),
+        },
+        Expression::Or(operands) => Expression::Or(flatten(mem::take(operands), true)),
+        Expression::And(operands) => Expression::And(flatten(mem::take(operands), false)),
+        Expression::Eq(a, b) => commute(Expression::Eq, a.take(), b.take()),
+        Expression::Neq(a, b) => commute(Expression::Neq, a.take(), b.take()),
+        Expression::Mul(a, b) => commute(Expression::Mul, a.take(), b.take()),
+        Expression::Gt(a, b) => Expression::Lt(
+            Box::new(canonicalize(b.take())),
+            Box::new(canonicalize(a.take())),
+        ),
+        Expression::Gte(a, b) => Expression::Lte(
+            Box::new(canonicalize(b.take())),
+            Box::new(canonicalize(a.take())),
+        ),
+        Expression::Lit(Literal::Map(kvs)) => {
+            let mut kvs: Vec<(String, Expression, Expression)> = mem::take(kvs)
+                .into_iter()
+                .map(|(k, v)| {
+                    let k = canonicalize(k);
+                    (format::one_line(&k), k, canonicalize(v))
+                })
+                .collect();
+            kvs.sort_by(|a, b| a.0.cmp(&b.0));
+            Expression::Lit(Literal::Map(
+                kvs.into_iter().map(|(_, k, v)| (k, v)).collect(),
+            ))
+        }
+        _ => {
+            let (operands, rebuild) = split(expr);
+            rebuild(operands.into_iter().map(canonicalize).collect())
+        }
+    }
+}
+
+/// A hash of the canonical form of `expr`. It is the same from one run, platform and version to
+/// the next, so it can be stored alongside rules to find duplicates among them later.
+pub fn semantic_hash(expr: &Expression) -> u64 {
+    hash(&format::one_line(&canonicalize(expr.clone())))
+}
+
+/// A 128-bit hash of the canonical form of `expr`, fixed like `semantic_hash`'s but wide enough
+/// that distinct rules won't collide in practice, so it can stand in for the rule as a cache key.
+pub fn fingerprint(expr: &Expression) -> u128 {
+    let text = format::one_line(&canonicalize(expr.clone()));
+    text.bytes()
+        .fold(0x6c62_272e_07bb_0142_62b8_2175_6295_c58d, |h, b| {
+            (h ^ u128::from(b)).wrapping_mul(0x0000_0000_0100_0000_0000_0000_0000_013b)
+        })
+}
+
+/// The operands of an `||` (if `is_or`) or `&&`, canonicalized, with those that are themselves
+/// `||`s (or `&&`s) replaced by their operands, in order of their formatted text.
+fn flatten(operands: Vec<Expression>, is_or: bool) -> Vec<Expression> {
+    let mut flat = Vec::new();
+    for operand in operands {
+        match canonicalize(operand) {
+            Expression::Or(ref mut nested) if is_or => flat.append(nested),
+            Expression::And(ref mut nested) if !is_or => flat.append(nested),
+            operand => flat.push(operand),
+        }
+    }
+    sorted(flat)
+}
+
+/// `f(a, b)` or `f(b, a)`, whichever has its canonical operands in order of formatted text.
+fn commute(
+    f: fn(Box<Expression>, Box<Expression>) -> Expression,
+    a: Expression,
+    b: Expression,
+) -> Expression {
+    let mut operands = sorted(vec![canonicalize(a), canonicalize(b)]);
+    let b = operands.pop().expect("operand");
+    let a = operands.pop().expect("operand");
+    f(Box::new(a), Box::new(b))
+}
+
+fn sorted(operands: Vec<Expression>) -> Vec<Expression> {
+    let mut keyed: Vec<(String, Expression)> = operands
+        .into_iter()
+        .map(|e| (format::one_line(&e), e))
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|(_, e)| e).collect()
+}
+
+/// The 64-bit FNV-1a hash of `text`, which, unlike `std`'s hashers, is fixed.
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse, parse_with_spans};
+
+    fn canonical(input: &str) -> String {
+        format::format(&canonicalize(parse_with_spans(input).unwrap()))
+    }
+
+    #[test]
+    fn commutative_operands() {
+        assert_eq!(canonical("y == x"), "x == y");
+        assert_eq!(canonical("b || (c || a)"), "a || b || c");
+        assert_eq!(canonical("b && c || a"), "a || b && c");
+        assert_eq!(canonical("x > 2 && y >= 1"), "1 <= y && 2 < x");
+        assert_eq!(canonical("2 * x - 1"), "2 * x - 1");
+        assert_eq!(canonical("x * 2 - 1"), "2 * x - 1");
+        // Neither `-` nor `+`, which concatenates, commutes.
+        assert_eq!(canonical("b + a"), "b + a");
+        assert_eq!(
+            canonical(r#"{'b': 1.00, "a": [y != x]}"#),
+            r#"{"a": [x != y], "b": 1.0}"#
+        );
+        assert_eq!(canonical("let b = a; b"), "let b = a;\nb");
+    }
+
+    #[test]
+    fn hashes() {
+        let hash = |input: &str| semantic_hash(&parse(input).unwrap());
+        assert_eq!(
+            hash("user == 'alice' || resource.public"),
+            hash("resource.public||'alice'==user")
+        );
+        assert_ne!(hash("a - b"), hash("b - a"));
+        // The hash is fixed, so it can be stored.
+        assert_eq!(super::hash("a"), 0xaf63_dc4c_8601_ec8c);
+
+        let fingerprint = |input: &str| parse(input).unwrap().fingerprint();
+        assert_eq!(fingerprint("y==x"), fingerprint("x == y"));
+        assert_ne!(fingerprint("x == y"), fingerprint("x != y"));
+        assert_eq!(
+            fingerprint("y == x"),
+            0xb665_dbef_a03c_64bf_6f2f_3e95_7e8b_24d4
+        );
+    }
+}
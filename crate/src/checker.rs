@@ -0,0 +1,327 @@
+//! Static checks of an expression against declarations of the bindings it will be evaluated with,
+//! to catch mistakes like a misspelled field (`request.autth`) before anything is evaluated.
+
+use crate::model::{Expression, Identifier, Kind, Literal};
+use crate::suggest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The declared shape of a binding's value.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Schema {
+    /// Any value at all. Nothing about it is checked.
+    Any,
+    /// A value of this kind.
+    Kind(Kind),
+    /// A map with exactly these fields.
+    Map(HashMap<String, Schema>),
+    /// A string that is one of these.
+    Enum(Vec<String>),
+}
+
+impl Schema {
+    /// Translate a JSON Schema. Objects with `properties` become maps with just those fields,
+    /// `enum`s of strings become enums, primitive `type`s become kinds, and anything else is `Any`.
+    pub fn from_json_schema(schema: &serde_json::Value) -> Schema {
+        if let Some(values) = schema["enum"].as_array() {
+            let strings: Option<Vec<String>> = values
+                .iter()
+                .map(|v| v.as_str().map(str::to_owned))
+                .collect();
+            if let Some(strings) = strings {
+                return Schema::Enum(strings);
+            }
+        }
+        if let Some(properties) = schema["properties"].as_object() {
+            let fields = properties
+                .iter()
+                .map(|(name, field)| (name.clone(), Schema::from_json_schema(field)))
+                .collect();
+            return Schema::Map(fields);
+        }
+        match schema["type"].as_str() {
+            Some("object") => Schema::Kind(Kind::Map),
+            Some("array") => Schema::Kind(Kind::List),
+            Some("string") => Schema::Kind(Kind::String),
+            Some("integer") => Schema::Kind(Kind::I64),
+            Some("number") => Schema::Kind(Kind::F64),
+            Some("boolean") => Schema::Kind(Kind::Bool),
+            Some("null") => Schema::Kind(Kind::Null),
+            _ => Schema::Any,
+        }
+    }
+}
+
+/// A member access that can't succeed on a value of its binding's declared shape, or a comparison
+/// that can't.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum CheckError {
+    /// The map at the given path, e.g. `request`, has no such field. Includes similarly named
+    /// fields that it does have.
+    NoSuchField(String, Identifier, Vec<Identifier>),
+    /// The value at the given path is declared to be of a kind other than a map.
+    NotAMap(String, Kind),
+    /// The value at the given path is declared to be an enum, and is compared to a string that
+    /// isn't one of its values. Includes similar values that are.
+    NotInEnum(String, String, Vec<Identifier>),
+}
+
+/// Checks expressions against the declared shapes of their bindings. Undeclared bindings are not
+/// checked.
+#[derive(Default)]
+pub struct Checker {
+    declarations: HashMap<String, Schema>,
+}
+
+enum Task<'a> {
+    Visit(&'a Expression),
+    Shadow(&'a Identifier),
+    Unshadow,
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        Checker::default()
+    }
+
+    /// Declare that `name` will be bound to values shaped like `schema`.
+    pub fn declare(&mut self, name: &str, schema: Schema) {
+        self.declarations.insert(name.to_owned(), schema);
+    }
+
+    /// Every member access and comparison in `expr` that can't succeed given the declarations, in
+    /// source order.
+    pub fn check(&self, expr: &Expression) -> Vec<CheckError> {
+        let mut errors = Vec::new();
+        // Names bound by enclosing `let`s, which hide declarations of the same name.
+        let mut shadowed: Vec<&Identifier> = Vec::new();
+        let mut tasks = vec![Task::Visit(expr)];
+        while let Some(task) = tasks.pop() {
+            let expr = match task {
+                Task::Visit(expr) => expr,
+                Task::Shadow(id) => {
+                    shadowed.push(id);
+                    continue;
+                }
+                Task::Unshadow => {
+                    shadowed.pop();
+                    continue;
+                }
+            };
+            match strip_span(expr) {
+                Expression::LetBinding { id, value, body } => {
+                    tasks.push(Task::Unshadow);
+                    tasks.push(Task::Visit(body));
+                    tasks.push(Task::Shadow(id));
+                    tasks.push(Task::Visit(value));
+                }
+                Expression::Member(..) => {
+                    let (root, path) = member_path(expr);
+                    match strip_span(root) {
+                        Expression::Binding(id) if !shadowed.contains(&id) => {
+                            if let Err(err) = self.resolve(id, &path) {
+                                errors.push(err);
+                            }
+                        }
+                        root => tasks.push(Task::Visit(root)),
+                    }
+                }
+                Expression::Eq(a, b) | Expression::Neq(a, b) => {
+                    let err = self.check_enum(a, b, &shadowed);
+                    errors.extend(err.or_else(|| self.check_enum(b, a, &shadowed)));
+                    tasks.push(Task::Visit(b));
+                    tasks.push(Task::Visit(a));
+                }
+                expr => {
+                    let children = expr.children();
+                    tasks.extend(children.into_iter().rev().map(|(_, c)| Task::Visit(c)));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Follow `fields` from the declaration of `root` to the schema of the value they lead to, if
+    /// it is known.
+    fn resolve(
+        &self,
+        root: &Identifier,
+        fields: &[&Identifier],
+    ) -> Result<Option<&Schema>, CheckError> {
+        let mut schema = match self.declarations.get(&root.0) {
+            Some(schema) => schema,
+            None => return Ok(None),
+        };
+        let mut path = root.0.clone();
+        for field in fields {
+            match schema {
+                Schema::Any | Schema::Kind(Kind::Map) => return Ok(None),
+                Schema::Kind(kind) => return Err(CheckError::NotAMap(path, *kind)),
+                Schema::Enum(_) => return Err(CheckError::NotAMap(path, Kind::String)),
+                Schema::Map(map) => match map.get(&field.0) {
+                    Some(s) => schema = s,
+                    None => {
+                        let names = map.keys().map(String::as_str);
+                        let suggestions = suggest::similar(&field.0, names);
+                        return Err(CheckError::NoSuchField(path, (*field).clone(), suggestions));
+                    }
+                },
+            }
+            path.push('.');
+            path.push_str(&field.0);
+        }
+        Ok(Some(schema))
+    }
+
+    /// If `side` is declared to be an enum and `other` is a string literal, check that the string
+    /// is one of the enum's values.
+    fn check_enum(
+        &self,
+        side: &Expression,
+        other: &Expression,
+        shadowed: &[&Identifier],
+    ) -> Option<CheckError> {
+        let (root, fields) = member_path(side);
+        let root = match strip_span(root) {
+            Expression::Binding(id) if !shadowed.contains(&id) => id,
+            _ => return None,
+        };
+        let values = match self.resolve(root, &fields) {
+            Ok(Some(Schema::Enum(values))) => values,
+            _ => return None,
+        };
+        match strip_span(other) {
+            Expression::Lit(Literal::String(s)) if !values.contains(s) => {
+                let path = std::iter::once(root)
+                    .chain(fields)
+                    .map(|id| id.0.as_str())
+                    .collect::<Vec<_>>()
+                    .join(".");
+                let suggestions = suggest::similar(s, values.iter().map(String::as_str));
+                Some(CheckError::NotInEnum(path, s.clone(), suggestions))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Split a chain of member accesses like `a.b.c` into its root `a` and fields `[b, c]`.
+fn member_path(mut expr: &Expression) -> (&Expression, Vec<&Identifier>) {
+    let mut fields = Vec::new();
+    while let Expression::Member(inner, field) = strip_span(expr) {
+        fields.push(field);
+        expr = inner;
+    }
+    fields.reverse();
+    (expr, fields)
+}
+
+pub(crate) fn strip_span(expr: &Expression) -> &Expression {
+    match expr {
+        Expression::Spanned(_, e) => e,
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse, parse_with_spans};
+
+    fn request() -> Schema {
+        Schema::from_json_schema(&serde_json::json!({
+            "type": "object",
+            "properties": {
+                "auth": {
+                    "type": "object",
+                    "properties": {"uid": {"type": "string"}, "token": {"type": "object"}},
+                },
+                "method": {"type": "string"},
+                "status": {"enum": ["PENDING", "DONE"]},
+            },
+        }))
+    }
+
+    fn check(input: &str) -> Vec<CheckError> {
+        let mut checker = Checker::new();
+        checker.declare("request", request());
+        let errors = checker.check(&parse(input).unwrap());
+        assert_eq!(errors, checker.check(&parse_with_spans(input).unwrap()));
+        errors
+    }
+
+    #[test]
+    fn from_json_schema() {
+        let mut auth = HashMap::new();
+        auth.insert("uid".to_owned(), Schema::Kind(Kind::String));
+        auth.insert("token".to_owned(), Schema::Kind(Kind::Map));
+        let mut fields = HashMap::new();
+        fields.insert("auth".to_owned(), Schema::Map(auth));
+        fields.insert("method".to_owned(), Schema::Kind(Kind::String));
+        let status = vec!["PENDING".to_owned(), "DONE".to_owned()];
+        fields.insert("status".to_owned(), Schema::Enum(status));
+        assert_eq!(request(), Schema::Map(fields));
+        assert_eq!(
+            Schema::from_json_schema(&serde_json::json!({"oneOf": []})),
+            Schema::Any
+        );
+    }
+
+    #[test]
+    fn misspelled_fields() {
+        assert_eq!(
+            check("request.autth.uid == 'x' && request.auth.uidd == 'y'"),
+            vec![
+                CheckError::NoSuchField(
+                    "request".to_owned(),
+                    Identifier::new("autth"),
+                    vec![Identifier::new("auth")]
+                ),
+                CheckError::NoSuchField(
+                    "request.auth".to_owned(),
+                    Identifier::new("uidd"),
+                    vec![Identifier::new("uid")]
+                ),
+            ]
+        );
+        assert_eq!(
+            check("f(request.method.length)"),
+            vec![CheckError::NotAMap(
+                "request.method".to_owned(),
+                Kind::String
+            )]
+        );
+    }
+
+    #[test]
+    fn enums() {
+        assert_eq!(
+            check(r#" request.status == "PENDNG" || "DONE" != request.status "#),
+            vec![CheckError::NotInEnum(
+                "request.status".to_owned(),
+                "PENDNG".to_owned(),
+                vec![Identifier::new("PENDING")]
+            )]
+        );
+        assert_eq!(
+            check("request.status.x"),
+            vec![CheckError::NotAMap(
+                "request.status".to_owned(),
+                Kind::String
+            )]
+        );
+        assert_eq!(
+            check(r#" let request = {"status": "x"}; request.status == "x" "#),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn valid_accesses() {
+        assert_eq!(check("request.auth.token.anything"), vec![]);
+        assert_eq!(check("other.autth"), vec![]);
+        // A `let` hides the declaration.
+        assert_eq!(check("let request = {}; request.autth"), vec![]);
+        assert_eq!(check("let x = request.auth; x.uidd"), vec![]);
+    }
+}
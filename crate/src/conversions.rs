@@ -0,0 +1,223 @@
+use crate::functions::{
+    FUNCTION_BOOL, FUNCTION_BYTES, FUNCTION_DOUBLE, FUNCTION_INT, FUNCTION_STRING, FUNCTION_UINT,
+};
+use crate::model::{Error, EvalResult, Identifier, Kind, Value};
+use crate::time;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+fn unsupported(function: &str, value: Value) -> EvalResult {
+    Err(Error::NoFunctionWithSignature(
+        Identifier::new(function),
+        vec![value.kind()],
+    ))
+}
+
+pub fn to_int(value: Value) -> EvalResult {
+    match value {
+        Value::I64(v) => Ok(Value::I64(v)),
+        Value::U64(v) => i64::try_from(v)
+            .map(Value::I64)
+            .map_err(|_| Error::ConversionOutOfRange(Kind::U64, Kind::I64)),
+        // `i64::MAX as f64` rounds up to 2^63, which is itself out of range.
+        Value::F64(v) if v >= i64::MIN as f64 && v < i64::MAX as f64 => Ok(Value::I64(v as i64)),
+        Value::F64(_) => Err(Error::ConversionOutOfRange(Kind::F64, Kind::I64)),
+        Value::String(s) => match s.parse() {
+            Ok(v) => Ok(Value::I64(v)),
+            Err(_) => Err(Error::InvalidConversion(Kind::I64, s.to_string())),
+        },
+        Value::Timestamp(t) => Ok(Value::I64(t.seconds)),
+        other => unsupported(FUNCTION_INT, other),
+    }
+}
+
+pub fn to_uint(value: Value) -> EvalResult {
+    match value {
+        Value::U64(v) => Ok(Value::U64(v)),
+        Value::I64(v) => u64::try_from(v)
+            .map(Value::U64)
+            .map_err(|_| Error::ConversionOutOfRange(Kind::I64, Kind::U64)),
+        // `u64::MAX as f64` rounds up to 2^64, which is itself out of range.
+        Value::F64(v) if v > -1.0 && v < u64::MAX as f64 => Ok(Value::U64(v as u64)),
+        Value::F64(_) => Err(Error::ConversionOutOfRange(Kind::F64, Kind::U64)),
+        Value::String(s) => match s.parse() {
+            Ok(v) => Ok(Value::U64(v)),
+            Err(_) => Err(Error::InvalidConversion(Kind::U64, s.to_string())),
+        },
+        other => unsupported(FUNCTION_UINT, other),
+    }
+}
+
+pub fn to_double(value: Value) -> EvalResult {
+    match value {
+        Value::F64(v) => Ok(Value::F64(v)),
+        Value::I64(v) => Ok(Value::F64(v as f64)),
+        Value::U64(v) => Ok(Value::F64(v as f64)),
+        Value::String(s) => match s.parse() {
+            Ok(v) => Ok(Value::F64(v)),
+            Err(_) => Err(Error::InvalidConversion(Kind::F64, s.to_string())),
+        },
+        other => unsupported(FUNCTION_DOUBLE, other),
+    }
+}
+
+pub fn to_string(value: Value) -> EvalResult {
+    match value {
+        Value::String(s) => Ok(Value::String(s)),
+        Value::I64(v) => Ok(Value::String(v.to_string().into())),
+        Value::U64(v) => Ok(Value::String(v.to_string().into())),
+        Value::F64(v) => Ok(Value::String(format_double(v).into())),
+        Value::Bool(v) => Ok(Value::String(v.to_string().into())),
+        Value::Bytes(b) => match String::from_utf8(Rc::unwrap_or_clone(b)) {
+            Ok(s) => Ok(Value::String(s.into())),
+            Err(err) => Err(Error::InvalidConversion(
+                Kind::String,
+                String::from_utf8_lossy(err.as_bytes()).into_owned(),
+            )),
+        },
+        Value::Timestamp(t) => Ok(Value::String(time::format_timestamp(t).into())),
+        Value::Duration(d) => Ok(Value::String(time::format_duration(d).into())),
+        other => unsupported(FUNCTION_STRING, other),
+    }
+}
+
+/// Format `v` with the fewest digits that read back as it, like other CEL implementations: with an
+/// exponent, as in `1e+300` or `2.5e-07`, if it's at least a million or less than 0.0001.
+fn format_double(v: f64) -> String {
+    if !v.is_finite() {
+        return v.to_string();
+    }
+    let scientific = format!("{:e}", v);
+    let (mantissa, exponent) = scientific.split_once('e').expect("exponent");
+    let exponent: i32 = exponent.parse().expect("exponent");
+    if (-4..6).contains(&exponent) {
+        return v.to_string();
+    }
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+pub fn to_bytes(value: Value) -> EvalResult {
+    match value {
+        Value::Bytes(b) => Ok(Value::Bytes(b)),
+        Value::String(s) => Ok(Value::Bytes(Rc::unwrap_or_clone(s).into_bytes().into())),
+        other => unsupported(FUNCTION_BYTES, other),
+    }
+}
+
+pub fn to_bool(value: Value) -> EvalResult {
+    match value {
+        Value::Bool(b) => Ok(Value::Bool(b)),
+        Value::String(s) => match s.as_str() {
+            "1" | "t" | "true" | "TRUE" | "True" => Ok(Value::Bool(true)),
+            "0" | "f" | "false" | "FALSE" | "False" => Ok(Value::Bool(false)),
+            _ => Err(Error::InvalidConversion(Kind::Bool, s.to_string())),
+        },
+        other => unsupported(FUNCTION_BOOL, other),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned().into())
+    }
+
+    #[test]
+    fn int_from_double_truncates() {
+        assert_eq!(to_int(Value::F64(2.9)), Ok(Value::I64(2)));
+        assert_eq!(to_int(Value::F64(-2.9)), Ok(Value::I64(-2)));
+    }
+
+    #[test]
+    fn int_from_double_out_of_range() {
+        for v in &[9.3e18, -9.3e18, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                to_int(Value::F64(*v)),
+                Err(Error::ConversionOutOfRange(Kind::F64, Kind::I64))
+            );
+        }
+    }
+
+    #[test]
+    fn int_from_string() {
+        assert_eq!(to_int(s("-42")), Ok(Value::I64(-42)));
+        assert_eq!(
+            to_int(s("4 2")),
+            Err(Error::InvalidConversion(Kind::I64, "4 2".to_owned()))
+        );
+    }
+
+    #[test]
+    fn uint_from_negative() {
+        assert_eq!(
+            to_uint(Value::I64(-1)),
+            Err(Error::ConversionOutOfRange(Kind::I64, Kind::U64))
+        );
+        assert_eq!(
+            to_uint(Value::F64(-1.0)),
+            Err(Error::ConversionOutOfRange(Kind::F64, Kind::U64))
+        );
+    }
+
+    #[test]
+    fn int_from_large_uint() {
+        assert_eq!(
+            to_int(Value::U64(u64::MAX)),
+            Err(Error::ConversionOutOfRange(Kind::U64, Kind::I64))
+        );
+    }
+
+    #[test]
+    fn string_from_invalid_utf8() {
+        assert_eq!(
+            to_string(Value::Bytes(vec![b'a', 0xFF].into())),
+            Err(Error::InvalidConversion(
+                Kind::String,
+                "a\u{FFFD}".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn string_from_double() {
+        let cases = [
+            (1.5, "1.5"),
+            (-2.0, "-2"),
+            (0.1, "0.1"),
+            (123456.0, "123456"),
+            (0.0001, "0.0001"),
+            (1e6, "1e+06"),
+            (1e300, "1e+300"),
+            (-1.25e-7, "-1.25e-07"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (f64::INFINITY, "inf"),
+        ];
+        for (v, expected) in cases {
+            assert_eq!(to_string(Value::F64(v)), Ok(s(expected)), "{}", v);
+        }
+    }
+
+    #[test]
+    fn bool_from_string() {
+        assert_eq!(to_bool(s("TRUE")), Ok(Value::Bool(true)));
+        assert_eq!(to_bool(s("f")), Ok(Value::Bool(false)));
+        assert_eq!(
+            to_bool(s("yes")),
+            Err(Error::InvalidConversion(Kind::Bool, "yes".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unsupported_kind() {
+        assert_eq!(
+            to_bytes(Value::I64(1)),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new(FUNCTION_BYTES),
+                vec![Kind::I64]
+            ))
+        );
+    }
+}
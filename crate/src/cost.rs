@@ -0,0 +1,316 @@
+//! An upper bound on how much work evaluating an expression takes, found from the expression alone,
+//! so that hosts can refuse expensive expressions when they are saved rather than only when they
+//! run into `EvalLimits`.
+//!
+//! Costs are in units of about one operation. Each node costs one, and copying, comparing or
+//! scanning a value costs one per element, entry or byte of it. Bound values may be of any size up
+//! to `CostOptions::max_binding_size`, so the bound is only as tight as that is.
+
+use crate::functions::{
+    FUNCTION_BYTES, FUNCTION_CRC32, FUNCTION_SHA1, FUNCTION_SHA256, FUNCTION_STRING,
+};
+use crate::methods::{
+    METHOD_ABS, METHOD_AT, METHOD_CEIL, METHOD_CONTAINS, METHOD_CONTAINS_KEY, METHOD_DISTINCT,
+    METHOD_FLATTEN, METHOD_FLOOR, METHOD_FORMAT, METHOD_GET, METHOD_INDEX_OF, METHOD_JOIN,
+    METHOD_KEYS, METHOD_LAST_INDEX_OF, METHOD_LOWER_ASCII, METHOD_MATCHES, METHOD_MAX, METHOD_MIN,
+    METHOD_PATCH, METHOD_POW, METHOD_REVERSE, METHOD_ROUND, METHOD_SLICE, METHOD_SORT,
+    METHOD_SPLIT, METHOD_SQRT, METHOD_SUBSTRING, METHOD_SUM, METHOD_TO_UTF8, METHOD_TO_UTF8_LOSSY,
+    METHOD_TRIM, METHOD_TRIM_END, METHOD_TRIM_START, METHOD_UPPER_ASCII,
+};
+use crate::model::{Expression, Identifier, Literal};
+
+/// What a cost estimate assumes of the values an expression will be evaluated with.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CostOptions {
+    /// The most elements, entries or bytes that a bound value, or any value in one, may hold.
+    pub max_binding_size: u64,
+}
+
+impl Default for CostOptions {
+    /// As many values as `BindingOptions::default()` allows a binding.
+    fn default() -> CostOptions {
+        CostOptions {
+            max_binding_size: 1 << 16,
+        }
+    }
+}
+
+/// The most that evaluating `expr` can cost, assuming `options` of its bindings. Never less than
+/// the number of its nodes.
+pub fn estimate(expr: &Expression, options: &CostOptions) -> u64 {
+    Estimator {
+        options,
+        scopes: Vec::new(),
+    }
+    .estimate(expr)
+    .cost
+}
+
+/// The cost of evaluating an expression, and the most elements, entries or bytes its value holds.
+#[derive(Debug, Copy, Clone)]
+struct Estimate {
+    cost: u64,
+    size: u64,
+}
+
+struct Estimator<'a> {
+    options: &'a CostOptions,
+    /// The sizes of the values of the `let`s in scope, innermost last.
+    scopes: Vec<(&'a Identifier, u64)>,
+}
+
+impl<'a> Estimator<'a> {
+    fn estimate(&mut self, expr: &'a Expression) -> Estimate {
+        let scalar = |cost: u64| Estimate { cost, size: 1 };
+        match expr {
+            Expression::Spanned(_, e) => self.estimate(e),
+            Expression::LetBinding { id, value, body } => {
+                let value = self.estimate(value);
+                self.scopes.push((id, value.size));
+                let body = self.estimate(body);
+                self.scopes.pop();
+                Estimate {
+                    cost: sum(&[1, value.cost, body.cost]),
+                    size: body.size,
+                }
+            }
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => {
+                let condition = self.estimate(condition);
+                let a = self.estimate(true_branch);
+                let b = self.estimate(else_branch);
+                Estimate {
+                    cost: sum(&[1, condition.cost, a.cost.max(b.cost)]),
+                    size: a.size.max(b.size),
+                }
+            }
+            Expression::Or(operands) | Expression::And(operands) => {
+                scalar(sum(&[1, self.all(operands).cost]))
+            }
+            // Comparing scans the smaller of the two.
+            Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::Gt(a, b) => {
+                let (a, b) = (self.estimate(a), self.estimate(b));
+                scalar(sum(&[1, a.cost, b.cost, a.size.min(b.size)]))
+            }
+            // Concatenating copies both.
+            Expression::Add(a, b) => {
+                let (a, b) = (self.estimate(a), self.estimate(b));
+                let size = sum(&[a.size, b.size]);
+                Estimate {
+                    cost: sum(&[1, a.cost, b.cost, size]),
+                    size,
+                }
+            }
+            Expression::Sub(a, b)
+            | Expression::Mul(a, b)
+            | Expression::Div(a, b)
+            | Expression::Mod(a, b) => {
+                let (a, b) = (self.estimate(a), self.estimate(b));
+                scalar(sum(&[1, a.cost, b.cost]))
+            }
+            // An element of a value holds no more than the value does.
+            Expression::OptionalIndex(a, b) => {
+                let (a, b) = (self.estimate(a), self.estimate(b));
+                Estimate {
+                    cost: sum(&[1, a.cost, b.cost]),
+                    size: a.size,
+                }
+            }
+            Expression::Neg(a) | Expression::Not(a) => scalar(sum(&[1, self.estimate(a).cost])),
+            // A member of a value holds no more than the value does.
+            Expression::Member(a, _) | Expression::SafeMember(a, _) => {
+                let a = self.estimate(a);
+                Estimate {
+                    cost: sum(&[1, a.cost]),
+                    size: a.size,
+                }
+            }
+            Expression::Method(receiver, id, args) => {
+                let receiver = self.estimate(receiver);
+                let args = self.all(args);
+                let (work, size) = match id.0.as_str() {
+                    METHOD_POW | METHOD_ABS | METHOD_CEIL | METHOD_FLOOR | METHOD_ROUND
+                    | METHOD_SQRT => (0, 1),
+                    // The value at the key, or the default, each no larger than what it's from.
+                    METHOD_GET => (1, receiver.size.max(args.size)),
+                    // A lookup of the key.
+                    METHOD_CONTAINS_KEY | METHOD_AT => (1, 1),
+                    METHOD_PATCH => {
+                        let size = sum(&[receiver.size, args.size]);
+                        (size, size)
+                    }
+                    METHOD_KEYS | METHOD_REVERSE | METHOD_SLICE => (receiver.size, receiver.size),
+                    // Each element compared with each kept before it.
+                    METHOD_DISTINCT => (receiver.size.saturating_mul(receiver.size), receiver.size),
+                    // The elements of elements, and so on, each no larger than the receiver.
+                    METHOD_FLATTEN => {
+                        let size = receiver.size.saturating_mul(receiver.size);
+                        (size, size)
+                    }
+                    // A comparison for each element at each of the log2(n) levels of a merge sort.
+                    METHOD_SORT => {
+                        let levels = u64::from(64 - receiver.size.leading_zeros());
+                        (receiver.size.saturating_mul(levels), receiver.size)
+                    }
+                    // Strings no longer than the receiver.
+                    METHOD_LOWER_ASCII | METHOD_UPPER_ASCII | METHOD_TRIM | METHOD_TRIM_END
+                    | METHOD_TRIM_START | METHOD_SUBSTRING => (receiver.size, receiver.size),
+                    // `toUtf8Lossy` makes each invalid byte a three-byte replacement character.
+                    METHOD_TO_UTF8 | METHOD_TO_UTF8_LOSSY => {
+                        (receiver.size, receiver.size.saturating_mul(3))
+                    }
+                    // No more parts than the receiver has characters, each no longer than it.
+                    METHOD_SPLIT => (receiver.size, receiver.size),
+                    // Each element, and a separator after each.
+                    METHOD_JOIN => {
+                        let size = receiver
+                            .size
+                            .saturating_mul(sum(&[receiver.size, args.size]));
+                        (size, size)
+                    }
+                    // Each verb, no more than one per byte of the format, writes an argument or a
+                    // number of at most a few hundred digits.
+                    METHOD_FORMAT => {
+                        let size = receiver.size.saturating_mul(sum(&[args.size, 512]));
+                        (size, size)
+                    }
+                    // Each element read once, and one number made.
+                    METHOD_MIN | METHOD_MAX | METHOD_SUM => (receiver.size, 1),
+                    METHOD_CONTAINS | METHOD_MATCHES | METHOD_INDEX_OF | METHOD_LAST_INDEX_OF => {
+                        (receiver.size.saturating_mul(args.size.max(1)), 1)
+                    }
+                    // `len`, which counts the characters of a string, `startsWith` and `endsWith`,
+                    // which read no more than it, and methods this estimate doesn't know of, which
+                    // take at least as long.
+                    _ => (receiver.size, 1),
+                };
+                Estimate {
+                    cost: sum(&[1, receiver.cost, args.cost, work]),
+                    size,
+                }
+            }
+            // Conversions and hashes read their argument once. Converting to a string or bytes
+            // gives one at most as long as the argument, or as a number's longest text, and a hash
+            // is no longer than a SHA-256 digest.
+            Expression::Function(id, args) => {
+                let args = self.all(args);
+                let size = match id.0.as_str() {
+                    FUNCTION_STRING | FUNCTION_BYTES => args.size.max(32),
+                    FUNCTION_SHA256 | FUNCTION_SHA1 | FUNCTION_CRC32 => 32,
+                    _ => 1,
+                };
+                Estimate {
+                    cost: sum(&[1, args.cost, args.size]),
+                    size,
+                }
+            }
+            Expression::Lit(Literal::List(elems)) => {
+                let elems = self.all(elems);
+                Estimate {
+                    cost: sum(&[1, elems.cost]),
+                    size: elems.size.max(1),
+                }
+            }
+            Expression::Lit(Literal::Map(kvs)) => {
+                let mut cost = 1;
+                let mut size = 0;
+                for (k, v) in kvs {
+                    let (k, v) = (self.estimate(k), self.estimate(v));
+                    cost = sum(&[cost, k.cost, v.cost]);
+                    size = sum(&[size, k.size, v.size]);
+                }
+                Estimate {
+                    cost,
+                    size: size.max(1),
+                }
+            }
+            Expression::Lit(Literal::String(s)) => Estimate {
+                cost: 1,
+                size: (s.len() as u64).max(1),
+            },
+            Expression::Lit(Literal::Bytes(bs)) => Estimate {
+                cost: 1,
+                size: (bs.len() as u64).max(1),
+            },
+            Expression::Lit(_) => scalar(1),
+            Expression::Binding(id) => {
+                let bound = self.scopes.iter().rev().find(|(name, _)| *name == id);
+                Estimate {
+                    cost: 1,
+                    size: bound.map_or(self.options.max_binding_size, |&(_, size)| size),
+                }
+            }
+        }
+    }
+
+    /// The total cost and size of `exprs`.
+    fn all(&mut self, exprs: &'a [Expression]) -> Estimate {
+        let mut total = Estimate { cost: 0, size: 0 };
+        for expr in exprs {
+            let e = self.estimate(expr);
+            total.cost = sum(&[total.cost, e.cost]);
+            total.size = sum(&[total.size, e.size]);
+        }
+        total
+    }
+}
+
+/// The sum of `parts`, or `u64::MAX` if that's more.
+fn sum(parts: &[u64]) -> u64 {
+    parts
+        .iter()
+        .fold(0, |total, &part| total.saturating_add(part))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    fn cost(input: &str) -> u64 {
+        let options = CostOptions {
+            max_binding_size: 100,
+        };
+        estimate(&parse(input).unwrap(), &options)
+    }
+
+    #[test]
+    fn estimates() {
+        // Adding may concatenate, which copies both sides.
+        assert_eq!(cost("1 + 2 * 3"), 1 + (1 + 3) + 2);
+        assert_eq!(cost("true || false"), 3);
+        // Comparing scans the smaller side; the binding may be as large as 100.
+        assert_eq!(cost("x == 'abc'"), 3 + 3);
+        assert_eq!(cost("x == y"), 3 + 100);
+        // A ternary costs its condition and its more expensive branch.
+        assert_eq!(cost("c ? 1 : x == y"), 1 + 1 + 103);
+        // `contains` scans the list, comparing each element with the argument.
+        assert_eq!(cost("[1, 2].contains(3)"), 1 + 3 + 1 + 2);
+        assert_eq!(cost("x.contains('ab')"), 3 + 100 * 2);
+        // A `let` binds the size of its value, not that of an unknown binding.
+        assert_eq!(cost("let s = 'ab'; s + s"), 1 + 1 + (1 + 2 + 4));
+    }
+
+    #[test]
+    fn bounds_growth() {
+        // Each doubling of the list is paid for, so the cost grows with the size of the result.
+        let doubling = |n: usize| {
+            let mut input = "let x = [0];".to_owned();
+            for _ in 0..n {
+                input.push_str(" let x = x + x;");
+            }
+            cost(&(input + " x"))
+        };
+        assert!(doubling(10) > 1 << 10);
+        assert!(doubling(20) > 1 << 20);
+        assert_eq!(doubling(100), u64::MAX);
+    }
+}
@@ -0,0 +1,264 @@
+//! Which parts of the language an expression uses, recorded in serialized artifacts (see
+//! `bundle`) so that a runtime loading one can refuse it up front if it uses parts this runtime
+//! doesn't have, rather than misbehaving.
+//!
+//! `Usage` reports in finer detail which operators, methods, functions and kinds of literal an
+//! expression uses, so that hosts that allow only some of them can refuse expressions that use
+//! others without evaluating them.
+
+use crate::checker::strip_span;
+use crate::functions::{
+    FUNCTION_DURATION, FUNCTION_OPTIONAL_NONE, FUNCTION_OPTIONAL_OF,
+    FUNCTION_OPTIONAL_OF_NON_ZERO_VALUE, FUNCTION_TIMESTAMP,
+};
+use crate::model::{Expression, Literal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ops::BitOr;
+
+/// A set of language features, as a bitmap. Bits this runtime doesn't know of are kept, so that
+/// they can be reported.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Features(pub u32);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    pub const LET_BINDINGS: Features = Features(1 << 0);
+    pub const TERNARIES: Features = Features(1 << 1);
+    pub const MAP_LITERALS: Features = Features(1 << 2);
+    pub const BYTES_LITERALS: Features = Features(1 << 3);
+    /// The `timestamp` and `duration` functions.
+    pub const TIME: Features = Features(1 << 4);
+    /// `?.`, null-safe member access.
+    pub const SAFE_MEMBERS: Features = Features(1 << 5);
+    /// Optional values: `m[?k]` and the `optional.` functions.
+    pub const OPTIONALS: Features = Features(1 << 6);
+
+    /// Every feature this runtime supports.
+    pub const SUPPORTED: Features = Features(
+        Features::LET_BINDINGS.0
+            | Features::TERNARIES.0
+            | Features::MAP_LITERALS.0
+            | Features::BYTES_LITERALS.0
+            | Features::TIME.0
+            | Features::SAFE_MEMBERS.0
+            | Features::OPTIONALS.0,
+    );
+
+    /// The features that `expr` uses.
+    pub fn of(expr: &Expression) -> Features {
+        let mut features = Features::NONE;
+        let mut stack = vec![expr];
+        while let Some(expr) = stack.pop() {
+            features = features
+                | match strip_span(expr) {
+                    Expression::LetBinding { .. } => Features::LET_BINDINGS,
+                    Expression::Ternary { .. } => Features::TERNARIES,
+                    Expression::Lit(Literal::Map(_)) => Features::MAP_LITERALS,
+                    Expression::Lit(Literal::Bytes(_)) => Features::BYTES_LITERALS,
+                    Expression::SafeMember(..) => Features::SAFE_MEMBERS,
+                    Expression::OptionalIndex(..) => Features::OPTIONALS,
+                    Expression::Function(id, _)
+                        if id.0 == FUNCTION_OPTIONAL_NONE
+                            || id.0 == FUNCTION_OPTIONAL_OF
+                            || id.0 == FUNCTION_OPTIONAL_OF_NON_ZERO_VALUE =>
+                    {
+                        Features::OPTIONALS
+                    }
+                    Expression::Function(id, _)
+                        if id.0 == FUNCTION_TIMESTAMP || id.0 == FUNCTION_DURATION =>
+                    {
+                        Features::TIME
+                    }
+                    _ => Features::NONE,
+                };
+            stack.extend(expr.children().into_iter().map(|(_, child)| child));
+        }
+        features
+    }
+
+    /// Those of these features that this runtime doesn't support.
+    pub fn unsupported(self) -> Features {
+        Features(self.0 & !Features::SUPPORTED.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
+/// The operators, methods, functions and kinds of literal an expression uses, each by name.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    /// E.g. `"div"` for `/` and `"neg"` for a unary `-`, as well as `"member"` for `.`, `"ternary"`
+    /// and `"let"`.
+    pub operators: BTreeSet<String>,
+    pub methods: BTreeSet<String>,
+    pub functions: BTreeSet<String>,
+    /// By their CEL names, e.g. `"int"` and `"map"`.
+    pub literals: BTreeSet<String>,
+}
+
+impl Usage {
+    /// What `expr` uses.
+    pub fn of(expr: &Expression) -> Usage {
+        let mut usage = Usage::default();
+        let mut stack = vec![expr];
+        while let Some(expr) = stack.pop() {
+            let used = match strip_span(expr) {
+                Expression::Method(_, id, _) => Some((&mut usage.methods, id.0.as_str())),
+                Expression::Function(id, _) => Some((&mut usage.functions, id.0.as_str())),
+                Expression::Lit(lit) => Some((&mut usage.literals, literal_name(lit))),
+                Expression::Binding(_) => None,
+                expr => Some((&mut usage.operators, operator_name(expr))),
+            };
+            if let Some((set, name)) = used {
+                if !set.contains(name) {
+                    set.insert(name.to_owned());
+                }
+            }
+            stack.extend(expr.children().into_iter().map(|(_, child)| child));
+        }
+        usage
+    }
+
+    /// What this uses that `allowed` doesn't list.
+    pub fn excluding(&self, allowed: &Usage) -> Usage {
+        let difference =
+            |a: &BTreeSet<String>, b: &BTreeSet<String>| a.difference(b).cloned().collect();
+        Usage {
+            operators: difference(&self.operators, &allowed.operators),
+            methods: difference(&self.methods, &allowed.methods),
+            functions: difference(&self.functions, &allowed.functions),
+            literals: difference(&self.literals, &allowed.literals),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operators.is_empty()
+            && self.methods.is_empty()
+            && self.functions.is_empty()
+            && self.literals.is_empty()
+    }
+}
+
+/// The name `Usage` gives the operator of `expr`, which is neither a literal, a binding, nor a
+/// call.
+fn operator_name(expr: &Expression) -> &'static str {
+    match expr {
+        Expression::LetBinding { .. } => "let",
+        Expression::Ternary { .. } => "ternary",
+        Expression::Or(_) => "or",
+        Expression::And(_) => "and",
+        Expression::Eq(..) => "eq",
+        Expression::Neq(..) => "neq",
+        Expression::Lt(..) => "lt",
+        Expression::Lte(..) => "lte",
+        Expression::Gte(..) => "gte",
+        Expression::Gt(..) => "gt",
+        Expression::Add(..) => "add",
+        Expression::Sub(..) => "sub",
+        Expression::Mul(..) => "mul",
+        Expression::Div(..) => "div",
+        Expression::Mod(..) => "mod",
+        Expression::Neg(_) => "neg",
+        Expression::Not(_) => "not",
+        Expression::Member(..) => "member",
+        Expression::SafeMember(..) => "safe_member",
+        Expression::OptionalIndex(..) => "optional_index",
+        Expression::Method(..)
+        | Expression::Function(..)
+        | Expression::Lit(_)
+        | Expression::Binding(_)
+        | Expression::Spanned(..) => unreachable!("not an operator"),
+    }
+}
+
+fn literal_name(lit: &Literal) -> &'static str {
+    match lit {
+        Literal::I64(_) => "int",
+        Literal::F64(_) => "double",
+        Literal::Bool(_) => "bool",
+        Literal::String(_) => "string",
+        Literal::Bytes(_) => "bytes",
+        Literal::List(_) => "list",
+        Literal::Map(_) => "map",
+        Literal::Null => "null_type",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse, parse_with_spans};
+
+    #[test]
+    fn features_of() {
+        let of = |input: &str| {
+            let features = Features::of(&parse(input).unwrap());
+            assert_eq!(features, Features::of(&parse_with_spans(input).unwrap()));
+            features
+        };
+        assert_eq!(of("1 + x.y(2)"), Features::NONE);
+        assert_eq!(
+            of("let m = {'a': b'x'}; [m.a ? 1 : 2]"),
+            Features::LET_BINDINGS
+                | Features::MAP_LITERALS
+                | Features::BYTES_LITERALS
+                | Features::TERNARIES
+        );
+        assert_eq!(of("f(timestamp('2024-01-01T00:00:00Z'))"), Features::TIME);
+        assert!(of("let x = duration('1s'); x").unsupported().is_empty());
+        assert_eq!(of("x?.y.z"), Features::SAFE_MEMBERS);
+        assert_eq!(of("x[?0]"), Features::OPTIONALS);
+        assert_eq!(of("optional.none().orValue(1)"), Features::OPTIONALS);
+        assert_eq!(Features(1 << 31).unsupported(), Features(1 << 31));
+    }
+
+    #[test]
+    fn usage() {
+        let names = |names: &[&str]| names.iter().map(|&n| n.to_owned()).collect();
+        let usage =
+            Usage::of(&parse_with_spans("let m = {'a': -1}; m.a / 2.0.pow(x) > size([])").unwrap());
+        assert_eq!(
+            usage,
+            Usage {
+                // `-1` is a negation of the literal `1`.
+                operators: names(&["div", "gt", "let", "member", "neg"]),
+                methods: names(&["pow"]),
+                functions: names(&["size"]),
+                literals: names(&["double", "int", "list", "map", "string"]),
+            }
+        );
+        assert_eq!(
+            usage,
+            Usage::of(&parse("let m = {'a': -1}; m.a / 2.0.pow(x) > size([])").unwrap())
+        );
+
+        let allowed = Usage {
+            operators: names(&["div", "gt", "member", "let", "neg"]),
+            literals: names(&["int", "double", "string", "map", "list"]),
+            ..Usage::default()
+        };
+        assert_eq!(
+            usage.excluding(&allowed),
+            Usage {
+                methods: names(&["pow"]),
+                functions: names(&["size"]),
+                ..Usage::default()
+            }
+        );
+        assert!(Usage::of(&parse("x.y > 1").unwrap())
+            .excluding(&allowed)
+            .is_empty());
+    }
+}
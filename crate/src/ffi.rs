@@ -0,0 +1,158 @@
+//! A C API for embedding the evaluator natively (from Python, Go, C++, ...) by linking the cdylib.
+//! Enabled by the `ffi` feature.
+//!
+//! Strings cross the boundary as NUL-terminated UTF-8. Every function returns a JSON string with
+//! the same schema as the wasm layer, `{"Ok": ...}` or `{"Err": ...}`, which the caller must
+//! release with `cel_free`. A panic is answered as `{"Err": {"Internal": message}}` rather than
+//! unwinding into the caller (see `panics`):
+//!
+//! ```c
+//! char *cel_parse(const char *input);
+//! char *cel_eval_json(const char *input, const char *bindings);
+//! void cel_free(char *result);
+//! ```
+
+use crate::interpreter::EvalContext;
+use crate::panics;
+use crate::parser;
+use crate::validation::{self, BindingOptions};
+use crate::wasi;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+/// Parse `input` into an AST.
+///
+/// # Safety
+///
+/// `input` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cel_parse(input: *const c_char) -> *mut c_char {
+    write(panics::guard_json("cel_parse", || match read(input) {
+        Ok(input) => match parser::parse(input) {
+            Ok(expr) => serde_json::json!({ "Ok": expr }),
+            Err(err) => serde_json::json!({ "Err": format!("{:?}", err) }),
+        },
+        Err(err) => err,
+    }))
+}
+
+/// Evaluate `input` with `bindings`, a JSON object mapping names to values, in scope. `bindings`
+/// may be null if there are none.
+///
+/// # Safety
+///
+/// `input`, and `bindings` unless it is null, must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn cel_eval_json(
+    input: *const c_char,
+    bindings: *const c_char,
+) -> *mut c_char {
+    write(panics::guard_json("cel_eval_json", || {
+        eval(input, bindings).unwrap_or_else(|err| err)
+    }))
+}
+
+unsafe fn eval(
+    input: *const c_char,
+    bindings: *const c_char,
+) -> Result<serde_json::Value, serde_json::Value> {
+    let input = read(input)?;
+    let bindings: HashMap<String, serde_json::Value> = if bindings.is_null() {
+        HashMap::new()
+    } else {
+        serde_json::from_str(read(bindings)?)
+            .map_err(|err| serde_json::json!({ "Err": format!("invalid bindings: {}", err) }))?
+    };
+    let expr =
+        parser::parse(input).map_err(|err| serde_json::json!({ "Err": format!("{:?}", err) }))?;
+    let bindings = validation::from_json_bindings(bindings, &BindingOptions::default())
+        .map_err(|err| serde_json::json!({ "Err": format!("invalid bindings: {:?}", err) }))?;
+    let result = wasi::evaluate(&EvalContext::default(), bindings, expr);
+    Ok(serde_json::json!(result))
+}
+
+/// Release a string returned by `cel_parse` or `cel_eval_json`.
+///
+/// # Safety
+///
+/// `result` must have been returned by one of those functions and not released already.
+#[no_mangle]
+pub unsafe extern "C" fn cel_free(result: *mut c_char) {
+    if !result.is_null() {
+        drop(CString::from_raw(result));
+    }
+}
+
+unsafe fn read<'a>(s: *const c_char) -> Result<&'a str, serde_json::Value> {
+    if s.is_null() {
+        return Err(serde_json::json!({ "Err": "null string" }));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|err| serde_json::json!({ "Err": format!("invalid UTF-8: {}", err) }))
+}
+
+fn write(response: serde_json::Value) -> *mut c_char {
+    // JSON escapes control characters, so the serialized form never contains a NUL.
+    CString::new(response.to_string())
+        .expect("no NUL in JSON")
+        .into_raw()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(f: impl FnOnce() -> *mut c_char) -> serde_json::Value {
+        let result = f();
+        let json = unsafe { CStr::from_ptr(result) }
+            .to_str()
+            .unwrap()
+            .to_owned();
+        unsafe { cel_free(result) };
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn cstr(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let parse = |input: &str| call(|| unsafe { cel_parse(cstr(input).as_ptr()) });
+        assert_eq!(parse("1"), serde_json::json!({"Ok": {"Lit": {"I64": 1}}}));
+        assert!(parse("1 +")["Err"].is_string());
+        assert!(call(|| unsafe { cel_parse(std::ptr::null()) })["Err"].is_string());
+    }
+
+    #[test]
+    fn eval_json() {
+        let eval = |input: &str, bindings: Option<&str>| {
+            let input = cstr(input);
+            let bindings = bindings.map(cstr);
+            let bindings_ptr = bindings.as_ref().map_or(std::ptr::null(), |b| b.as_ptr());
+            call(|| unsafe { cel_eval_json(input.as_ptr(), bindings_ptr) })
+        };
+        assert_eq!(
+            eval("x + y.z", Some(r#"{"x": 1, "y": {"z": 2}}"#)),
+            serde_json::json!({"Ok": {"t": "I64", "c": 3}})
+        );
+        assert_eq!(
+            eval("1 / 0", None),
+            serde_json::json!({"Err": "DivisionByZero"})
+        );
+        assert!(eval("1", Some("[]"))["Err"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid bindings"));
+        let many: serde_json::Map<String, serde_json::Value> = (0..100_000)
+            .map(|i| (format!("x{}", i), serde_json::json!(i)))
+            .collect();
+        let many = serde_json::Value::Object(many).to_string();
+        assert_eq!(
+            eval("x0 + x99999", Some(&many)),
+            serde_json::json!({"Ok": {"t": "I64", "c": 99999}})
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Converts an `Expression` back into source text, in a canonical form, so that expressions that
+//! differ only in layout can be stored and compared as the same text.
+//!
+//! Operators are spaced, strings are double-quoted, parentheses appear only where precedence needs
+//! them, and each `let` gets its own line. An expression too long for the line is wrapped: lists,
+//! maps and arguments one per line, and `||`, `&&` and ternaries before each operator.
+//!
+//! Parsing the formatted text of what `parse` returns gives back the same AST. Constructed ASTs may
+//! hold what the grammar can't express: doubles that aren't finite are written as calls to
+//! `double`, and a `let` anywhere but at the top is parenthesized, though that won't parse.
+
+use crate::checker::strip_span;
+use crate::model::{Expression, Literal};
+
+/// The width, in characters, that `format` wraps lines to.
+pub const WIDTH: usize = 80;
+
+const INDENT: &str = "    ";
+
+/// `expr` as canonical source text, wrapped to `WIDTH`.
+pub fn format(expr: &Expression) -> String {
+    format_to_width(expr, WIDTH)
+}
+
+/// `expr` as canonical source text, wrapped to `width`. Parts too long to fit even on a line of
+/// their own, like long strings, overflow it.
+pub fn format_to_width(expr: &Expression, width: usize) -> String {
+    let mut out = String::new();
+    Printer { width: Some(width) }.write(expr, 0, &mut out);
+    out
+}
+
+struct Printer {
+    /// `None` to write everything on one line.
+    width: Option<usize>,
+}
+
+impl Printer {
+    /// Write `expr` at the end of `out`, wrapping it if it doesn't fit on the line. Lines it wraps
+    /// onto are indented `indent` levels.
+    fn write(&self, expr: &Expression, indent: usize, out: &mut String) {
+        let expr = strip_span(expr);
+        let wrap = match self.width {
+            Some(width) => column(out) + one_line(expr).chars().count() > width,
+            None => false,
+        };
+        match expr {
+            Expression::LetBinding { id, value, body } => {
+                out.push_str("let ");
+                out.push_str(&id.0);
+                out.push_str(" = ");
+                self.operand(value, 1, indent, out);
+                out.push(';');
+                self.newline(indent, out);
+                self.operand(body, 0, indent, out);
+            }
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => {
+                self.operand(condition, 2, indent, out);
+                self.separator("? ", wrap, indent + 1, out);
+                self.operand(true_branch, 1, indent + 1, out);
+                self.separator(": ", wrap, indent + 1, out);
+                self.operand(else_branch, 1, indent + 1, out);
+            }
+            Expression::Or(operands) | Expression::And(operands) => {
+                let (op, min) = match expr {
+                    Expression::Or(_) => ("|| ", 3),
+                    _ => ("&& ", 4),
+                };
+                for (i, operand) in operands.iter().enumerate() {
+                    if i == 0 {
+                        self.operand(operand, min, indent, out);
+                    } else {
+                        self.separator(op, wrap, indent + 1, out);
+                        self.operand(operand, min, indent + 1, out);
+                    }
+                }
+            }
+            Expression::Eq(a, b) => self.binary(a, " == ", b, (5, 5), indent, out),
+            Expression::Neq(a, b) => self.binary(a, " != ", b, (5, 5), indent, out),
+            Expression::Lt(a, b) => self.binary(a, " < ", b, (5, 5), indent, out),
+            Expression::Lte(a, b) => self.binary(a, " <= ", b, (5, 5), indent, out),
+            Expression::Gte(a, b) => self.binary(a, " >= ", b, (5, 5), indent, out),
+            Expression::Gt(a, b) => self.binary(a, " > ", b, (5, 5), indent, out),
+            Expression::Add(a, b) => self.binary(a, " + ", b, (5, 6), indent, out),
+            Expression::Sub(a, b) => self.binary(a, " - ", b, (5, 6), indent, out),
+            Expression::Mul(a, b) => self.binary(a, " * ", b, (6, 7), indent, out),
+            Expression::Div(a, b) => self.binary(a, " / ", b, (6, 7), indent, out),
+            Expression::Mod(a, b) => self.binary(a, " % ", b, (6, 7), indent, out),
+            Expression::Neg(a) => {
+                out.push('-');
+                self.operand(a, 7, indent, out);
+            }
+            Expression::Not(a) => {
+                out.push('!');
+                self.operand(a, 7, indent, out);
+            }
+            Expression::Member(a, id) => {
+                self.operand(a, 8, indent, out);
+                out.push('.');
+                out.push_str(&id.0);
+            }
+            Expression::SafeMember(a, id) => {
+                self.operand(a, 8, indent, out);
+                out.push_str("?.");
+                out.push_str(&id.0);
+            }
+            Expression::OptionalIndex(a, k) => {
+                self.operand(a, 8, indent, out);
+                out.push_str("[?");
+                self.write(k, indent, out);
+                out.push(']');
+            }
+            Expression::Method(a, id, args) => {
+                self.operand(a, 8, indent, out);
+                out.push('.');
+                out.push_str(&id.0);
+                self.list("(", args, ")", wrap, indent, out);
+            }
+            Expression::Function(id, args) => {
+                out.push_str(&id.0);
+                self.list("(", args, ")", wrap, indent, out);
+            }
+            Expression::Lit(Literal::List(elems)) => self.list("[", elems, "]", wrap, indent, out),
+            Expression::Lit(Literal::Map(kvs)) => {
+                out.push('{');
+                for (i, (k, v)) in kvs.iter().enumerate() {
+                    if wrap {
+                        self.newline(indent + 1, out);
+                    } else if i > 0 {
+                        out.push_str(", ");
+                    }
+                    // A ternary key would read as though its `:` ended the key.
+                    self.operand(k, 2, indent + 1, out);
+                    out.push_str(": ");
+                    self.operand(v, 1, indent + 1, out);
+                    if wrap {
+                        out.push(',');
+                    }
+                }
+                if wrap && !kvs.is_empty() {
+                    self.newline(indent, out);
+                }
+                out.push('}');
+            }
+            Expression::Lit(lit) => literal(lit, out),
+            Expression::Binding(id) => out.push_str(&id.0),
+            Expression::Spanned(..) => unreachable!(),
+        }
+    }
+
+    /// Write `expr`, parenthesized if it binds less tightly than `min`, the precedence its position
+    /// needs.
+    fn operand(&self, expr: &Expression, min: u8, indent: usize, out: &mut String) {
+        if precedence(expr) < min {
+            out.push('(');
+            self.write(expr, indent, out);
+            out.push(')');
+        } else {
+            self.write(expr, indent, out);
+        }
+    }
+
+    /// Write `a op b`, where `mins` are the precedences the left and right operands need.
+    fn binary(
+        &self,
+        a: &Expression,
+        op: &str,
+        b: &Expression,
+        mins: (u8, u8),
+        indent: usize,
+        out: &mut String,
+    ) {
+        self.operand(a, mins.0, indent, out);
+        out.push_str(op);
+        self.operand(b, mins.1, indent, out);
+    }
+
+    /// Write `exprs` between `open` and `close`, separated by commas, or one per line if `wrap`.
+    fn list(
+        &self,
+        open: &str,
+        exprs: &[Expression],
+        close: &str,
+        wrap: bool,
+        indent: usize,
+        out: &mut String,
+    ) {
+        out.push_str(open);
+        for (i, expr) in exprs.iter().enumerate() {
+            if wrap {
+                self.newline(indent + 1, out);
+            } else if i > 0 {
+                out.push_str(", ");
+            }
+            self.operand(expr, 1, indent + 1, out);
+            if wrap {
+                out.push(',');
+            }
+        }
+        if wrap && !exprs.is_empty() {
+            self.newline(indent, out);
+        }
+        out.push_str(close);
+    }
+
+    /// Write `token`, starting a new line for it if `wrap`.
+    fn separator(&self, token: &str, wrap: bool, indent: usize, out: &mut String) {
+        if wrap {
+            self.newline(indent, out);
+        } else {
+            out.push(' ');
+        }
+        out.push_str(token);
+    }
+
+    /// Start a new line indented `indent` levels, or just a space if writing on one line.
+    fn newline(&self, indent: usize, out: &mut String) {
+        match self.width {
+            Some(_) => {
+                out.push('\n');
+                for _ in 0..indent {
+                    out.push_str(INDENT);
+                }
+            }
+            None => out.push(' '),
+        }
+    }
+}
+
+/// `expr` written on one line.
+pub(crate) fn one_line(expr: &Expression) -> String {
+    let mut out = String::new();
+    Printer { width: None }.write(expr, 0, &mut out);
+    out
+}
+
+/// How many characters into its last line `out` is.
+fn column(out: &str) -> usize {
+    out[out.rfind('\n').map_or(0, |i| i + 1)..].chars().count()
+}
+
+/// The precedence of `expr` as written. That's its operator's, except for negative numbers, which
+/// are written with a `-` like negations.
+fn precedence(expr: &Expression) -> u8 {
+    match strip_span(expr) {
+        Expression::Lit(Literal::I64(i64::MIN)) => 5,
+        Expression::Lit(Literal::I64(n)) if *n < 0 => 7,
+        Expression::Lit(Literal::F64(x)) if x.is_finite() && x.is_sign_negative() => 7,
+        expr => expr.precedence(),
+    }
+}
+
+fn literal(lit: &Literal, out: &mut String) {
+    match lit {
+        // Its magnitude is one too many for an int literal.
+        Literal::I64(i64::MIN) => out.push_str("-9223372036854775807 - 1"),
+        Literal::I64(n) => out.push_str(&n.to_string()),
+        Literal::F64(x) if x.is_nan() => out.push_str("double(\"NaN\")"),
+        Literal::F64(x) if x.is_infinite() => out.push_str(if *x > 0.0 {
+            "double(\"inf\")"
+        } else {
+            "double(\"-inf\")"
+        }),
+        // Debug formatting always has a `.` or an exponent, so the number reads back as a double.
+        Literal::F64(x) => out.push_str(&format!("{:?}", x)),
+        Literal::Bool(b) => out.push_str(&b.to_string()),
+        Literal::String(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    // There are no escapes for these, and neither may appear unescaped.
+                    '\\' | '\'' => out.push_str(&format!("\\x{:02x}", c as u32)),
+                    c if c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Literal::Bytes(bs) => {
+            out.push_str("b\"");
+            for &b in bs {
+                match b {
+                    b'"' => out.push_str("\\\""),
+                    b'\n' => out.push_str("\\n"),
+                    b'\t' => out.push_str("\\t"),
+                    b'\\' | b'\'' => out.push_str(&format!("\\x{:02x}", b)),
+                    b' '..=b'~' => out.push(b as char),
+                    b => out.push_str(&format!("\\x{:02x}", b)),
+                }
+            }
+            out.push('"');
+        }
+        Literal::Null => out.push_str("null"),
+        Literal::List(_) | Literal::Map(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse, parse_with_spans};
+
+    /// Format `input`, checking that the result parses back to the same AST.
+    fn canonical(input: &str, width: usize) -> String {
+        let expr = parse(input).unwrap();
+        let formatted = format_to_width(&expr, width);
+        assert_eq!(
+            formatted,
+            format_to_width(&parse_with_spans(input).unwrap(), width)
+        );
+        assert_eq!(parse(&formatted), Ok(expr), "{}", formatted);
+        formatted
+    }
+
+    #[test]
+    fn spacing_and_parentheses() {
+        let one_line = |input| canonical(input, WIDTH);
+        assert_eq!(one_line("1+2*3"), "1 + 2 * 3");
+        assert_eq!(one_line("(1+2)*3"), "(1 + 2) * 3");
+        assert_eq!(one_line("1-(2-3)"), "1 - (2 - 3)");
+        assert_eq!(one_line("((1-2))-3"), "1 - 2 - 3");
+        assert_eq!(one_line("a||(b&&c)||(d||e)"), "a || b && c || (d || e)");
+        assert_eq!(one_line("!(a<b) == (c>=d)"), "!(a < b) == (c >= d)");
+        assert_eq!(one_line("(-x).y( 1 ,2 ,)"), "(-x).y(1, 2)");
+        assert_eq!(one_line("-x.y"), "-x.y");
+        assert_eq!(one_line("(a)?.b.c?.d"), "a?.b.c?.d");
+        assert_eq!(one_line("(-x)?.y"), "(-x)?.y");
+        assert_eq!(one_line("m[? (a?b:c) ][?0]"), "m[?a ? b : c][?0]");
+        assert_eq!(one_line("optional . of(1)"), "optional.of(1)");
+        assert_eq!(
+            one_line("(a?b:c)?(d?e:f):g?h:i"),
+            "(a ? b : c) ? d ? e : f : g ? h : i"
+        );
+        assert_eq!(
+            one_line("{(a?b:c):[1,],'k':2.50}"),
+            "{(a ? b : c): [1], \"k\": 2.5}"
+        );
+        assert_eq!(one_line("let x=1;let y=x;y"), "let x = 1;\nlet y = x;\ny");
+    }
+
+    #[test]
+    fn literals() {
+        let one_line = |input| canonical(input, WIDTH);
+        assert_eq!(one_line("0x1F + 1_000"), "31 + 1000");
+        assert_eq!(one_line("1e3 + 1.5e-7"), "1000.0 + 1.5e-7");
+        assert_eq!(one_line(r#"'say \"hi\"'"#), r#""say \"hi\"""#);
+        assert_eq!(one_line(r#""a\nb\x5c\x27\x01é""#), r#""a\nb\x5c\x27\x01é""#);
+        assert_eq!(one_line(r#"b'\xff\x00a\"'"#), r#"b"\xff\x00a\"""#);
+        assert_eq!(one_line("[true, null]"), "[true, null]");
+
+        let lit = |lit| format(&Expression::Lit(lit));
+        assert_eq!(lit(Literal::I64(-5)), "-5");
+        assert_eq!(lit(Literal::F64(f64::NEG_INFINITY)), "double(\"-inf\")");
+        let min = Expression::Member(
+            Box::new(Expression::Lit(Literal::I64(i64::MIN))),
+            crate::model::Identifier::new("x"),
+        );
+        assert_eq!(format(&min), "(-9223372036854775807 - 1).x");
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(
+            canonical("f(first, [second, third], {'k': fourth})", 20),
+            "f(\n    first,\n    [second, third],\n    {\"k\": fourth},\n)"
+        );
+        assert_eq!(
+            canonical("alpha > 1 && beta < 2 || gamma", 25),
+            "alpha > 1 && beta < 2\n    || gamma"
+        );
+        assert_eq!(
+            canonical("let x = 1; condition ? [1, 2, 3] : other", 20),
+            "let x = 1;\ncondition\n    ? [1, 2, 3]\n    : other"
+        );
+        assert_eq!(
+            canonical("x.method(aaaaaaaaaa, bbbbbbbbbb) + 1", 20),
+            "x.method(\n    aaaaaaaaaa,\n    bbbbbbbbbb,\n) + 1"
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use crate::conversions;
+use crate::methods;
+use crate::model::{Error, EvalResult, Identifier, Kind, Signature, Value};
+use crate::suggest;
+use crate::time;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+
+pub const FUNCTION_BOOL: &str = "bool";
+pub const FUNCTION_BYTES: &str = "bytes";
+pub const FUNCTION_CRC32: &str = "crc32";
+pub const FUNCTION_DOUBLE: &str = "double";
+pub const FUNCTION_DURATION: &str = "duration";
+pub const FUNCTION_INT: &str = "int";
+pub const FUNCTION_OPTIONAL_NONE: &str = "optional.none";
+pub const FUNCTION_OPTIONAL_OF: &str = "optional.of";
+pub const FUNCTION_OPTIONAL_OF_NON_ZERO_VALUE: &str = "optional.ofNonZeroValue";
+pub const FUNCTION_SHA1: &str = "sha1";
+pub const FUNCTION_SHA256: &str = "sha256";
+pub const FUNCTION_SIZE: &str = "size";
+pub const FUNCTION_STRING: &str = "string";
+pub const FUNCTION_TIMESTAMP: &str = "timestamp";
+pub const FUNCTION_UINT: &str = "uint";
+
+pub const SIGNATURES: &[Signature] = &[
+    Signature {
+        name: Cow::Borrowed(FUNCTION_INT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The integer itself."),
+        example: Cow::Borrowed("int(42)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_INT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::U64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The unsigned integer, if it fits in an int."),
+        example: Cow::Borrowed("int(uint(42))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_INT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The double truncated toward zero, if it fits in an int."),
+        example: Cow::Borrowed("int(-2.9)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_INT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("Parse a base-10 integer."),
+        example: Cow::Borrowed("int(\"42\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_INT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Timestamp)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("Seconds since the Unix epoch."),
+        example: Cow::Borrowed("int(timestamp(\"2024-01-01T00:00:00Z\"))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_UINT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::U64)]),
+        result: Kind::U64,
+        doc: Cow::Borrowed("The unsigned integer itself."),
+        example: Cow::Borrowed("uint(uint(42))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_UINT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::U64,
+        doc: Cow::Borrowed("The integer, if it is not negative."),
+        example: Cow::Borrowed("uint(42)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_UINT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::U64,
+        doc: Cow::Borrowed("The double truncated toward zero, if it fits in a uint."),
+        example: Cow::Borrowed("uint(2.9)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_UINT),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::U64,
+        doc: Cow::Borrowed("Parse a base-10 unsigned integer."),
+        example: Cow::Borrowed("uint(\"42\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_DOUBLE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("The double itself."),
+        example: Cow::Borrowed("double(1.5)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_DOUBLE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("The nearest double to the integer."),
+        example: Cow::Borrowed("double(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_DOUBLE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::U64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("The nearest double to the unsigned integer."),
+        example: Cow::Borrowed("double(uint(1))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_DOUBLE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("Parse a floating point number."),
+        example: Cow::Borrowed("double(\"1e3\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string itself."),
+        example: Cow::Borrowed("string(\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The integer in base 10."),
+        example: Cow::Borrowed("string(-42)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::U64)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The unsigned integer in base 10."),
+        example: Cow::Borrowed("string(uint(42))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The double in its shortest round-trip form."),
+        example: Cow::Borrowed("string(3.14)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bool)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("Either \"true\" or \"false\"."),
+        example: Cow::Borrowed("string(true)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bytes)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("Decode UTF-8 bytes."),
+        example: Cow::Borrowed("string(b\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Timestamp)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The timestamp in RFC 3339 format, in UTC."),
+        example: Cow::Borrowed("string(timestamp(\"2024-01-01T00:00:00Z\"))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_STRING),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Duration)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The duration in seconds, such as \"5400s\"."),
+        example: Cow::Borrowed("string(duration(\"90m\"))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_BYTES),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bytes)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed("The bytes themselves."),
+        example: Cow::Borrowed("bytes(b\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_BYTES),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed("The UTF-8 encoding of the string."),
+        example: Cow::Borrowed("bytes(\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_BOOL),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bool)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("The bool itself."),
+        example: Cow::Borrowed("bool(true)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_BOOL),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed(
+            "Parse \"true\"/\"false\" (in any common capitalization), \"t\"/\"f\", or \"1\"/\"0\".",
+        ),
+        example: Cow::Borrowed("bool(\"TRUE\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_DURATION),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Duration,
+        doc: Cow::Borrowed("Parse a duration such as \"1h30m\" or \"-1.5s\"."),
+        example: Cow::Borrowed("duration(\"1h30m\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_DURATION),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Duration)]),
+        result: Kind::Duration,
+        doc: Cow::Borrowed("The duration itself."),
+        example: Cow::Borrowed("duration(duration(\"1s\"))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_TIMESTAMP),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Timestamp,
+        doc: Cow::Borrowed("Parse an RFC 3339 timestamp such as \"2024-01-01T00:00:00Z\"."),
+        example: Cow::Borrowed("timestamp(\"2024-01-01T00:00:00Z\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_TIMESTAMP),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Timestamp)]),
+        result: Kind::Timestamp,
+        doc: Cow::Borrowed("The timestamp itself."),
+        example: Cow::Borrowed("timestamp(timestamp(\"2024-01-01T00:00:00Z\"))"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SIZE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::List)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of elements in the list."),
+        example: Cow::Borrowed("size([1, 2, 3])"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SIZE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of unicode code points in the string."),
+        example: Cow::Borrowed("size(\"¢\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SIZE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bytes)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of bytes."),
+        example: Cow::Borrowed("size(b\"\\xC2\\xA2\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SIZE),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Map)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of entries in the map."),
+        example: Cow::Borrowed("size({\"a\": 1})"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SHA256),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bytes)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed("The SHA-256 digest of the bytes, 32 bytes long."),
+        example: Cow::Borrowed("sha256(b\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SHA256),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed(
+            "The SHA-256 digest of the string's UTF-8 encoding. Format it with `%x` for hex.",
+        ),
+        example: Cow::Borrowed("sha256(\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SHA1),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bytes)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed(
+            "The SHA-1 digest of the bytes, 20 bytes long. SHA-1 is broken as a cryptographic \
+             hash, so use it only to match digests made elsewhere.",
+        ),
+        example: Cow::Borrowed("sha1(b\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_SHA1),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed("The SHA-1 digest of the string's UTF-8 encoding."),
+        example: Cow::Borrowed("sha1(\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_CRC32),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::Bytes)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed(
+            "The CRC-32 (IEEE) checksum of the bytes, as 4 bytes, most significant first. It \
+             catches accidental changes, not deliberate ones.",
+        ),
+        example: Cow::Borrowed("crc32(b\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_CRC32),
+        operand: None,
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed("The CRC-32 (IEEE) checksum of the string's UTF-8 encoding."),
+        example: Cow::Borrowed("crc32(\"abc\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_OPTIONAL_OF),
+        operand: None,
+        args: Cow::Borrowed(&[None]),
+        result: Kind::Optional,
+        doc: Cow::Borrowed("An optional holding the value."),
+        example: Cow::Borrowed("optional.of(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_OPTIONAL_OF_NON_ZERO_VALUE),
+        operand: None,
+        args: Cow::Borrowed(&[None]),
+        result: Kind::Optional,
+        doc: Cow::Borrowed(
+            "An optional holding the value, or an empty one if the value is its kind's zero value, \
+             e.g. `0`, `\"\"`, `[]` or `null`.",
+        ),
+        example: Cow::Borrowed("optional.ofNonZeroValue(\"\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(FUNCTION_OPTIONAL_NONE),
+        operand: None,
+        args: Cow::Borrowed(&[]),
+        result: Kind::Optional,
+        doc: Cow::Borrowed("An empty optional."),
+        example: Cow::Borrowed("optional.none()"),
+    },
+];
+
+fn arg_kinds(args: Vec<Value>) -> Vec<Kind> {
+    args.into_iter().map(|arg| arg.kind()).collect()
+}
+
+/// Call the built-in `function`. If there is no such function, the built-ins and `others` (e.g. the
+/// caller's custom functions) are searched for similar names to suggest.
+pub fn evaluate_function<'a>(
+    function: Identifier,
+    args: Vec<Value>,
+    others: impl IntoIterator<Item = &'a str>,
+) -> EvalResult {
+    match function.0.as_ref() {
+        FUNCTION_BOOL => evaluate_conversion(function, args, conversions::to_bool),
+        FUNCTION_BYTES => evaluate_conversion(function, args, conversions::to_bytes),
+        FUNCTION_CRC32 => evaluate_hash(function, args, |data| {
+            crc32fast::hash(data).to_be_bytes().to_vec()
+        }),
+        FUNCTION_DOUBLE => evaluate_conversion(function, args, conversions::to_double),
+        FUNCTION_DURATION => evaluate_function_duration(args),
+        FUNCTION_INT => evaluate_conversion(function, args, conversions::to_int),
+        FUNCTION_OPTIONAL_NONE => evaluate_function_optional_none(args),
+        FUNCTION_OPTIONAL_OF => evaluate_optional(function, args, |_| true),
+        FUNCTION_OPTIONAL_OF_NON_ZERO_VALUE => evaluate_optional(function, args, |v| !is_zero(v)),
+        FUNCTION_SHA1 => evaluate_hash(function, args, |data| Sha1::digest(data).to_vec()),
+        FUNCTION_SIZE => evaluate_function_size(args),
+        FUNCTION_SHA256 => evaluate_hash(function, args, |data| Sha256::digest(data).to_vec()),
+        FUNCTION_STRING => evaluate_conversion(function, args, conversions::to_string),
+        FUNCTION_TIMESTAMP => evaluate_function_timestamp(args),
+        FUNCTION_UINT => evaluate_conversion(function, args, conversions::to_uint),
+        _ => {
+            let builtins = SIGNATURES.iter().map(|s| s.name.as_ref());
+            let suggestions = suggest::similar(&function.0, builtins.chain(others));
+            Err(Error::NoFunction(function, suggestions))
+        }
+    }
+}
+
+fn evaluate_conversion(
+    function: Identifier,
+    args: Vec<Value>,
+    convert: fn(Value) -> EvalResult,
+) -> EvalResult {
+    if args.len() != 1 {
+        return Err(Error::NoFunctionWithSignature(function, arg_kinds(args)));
+    }
+    convert(args.into_iter().next().unwrap())
+}
+
+/// A hash of a string's UTF-8 encoding or of bytes, computed by `hash`.
+fn evaluate_hash(function: Identifier, args: Vec<Value>, hash: fn(&[u8]) -> Vec<u8>) -> EvalResult {
+    match args.as_slice() {
+        [Value::String(s)] => Ok(Value::Bytes(hash(s.as_bytes()).into())),
+        [Value::Bytes(b)] => Ok(Value::Bytes(hash(b).into())),
+        _ => Err(Error::NoFunctionWithSignature(function, arg_kinds(args))),
+    }
+}
+
+fn evaluate_function_optional_none(args: Vec<Value>) -> EvalResult {
+    match args.as_slice() {
+        [] => Ok(Value::Optional(None)),
+        _ => Err(Error::NoFunctionWithSignature(
+            Identifier::new(FUNCTION_OPTIONAL_NONE),
+            arg_kinds(args),
+        )),
+    }
+}
+
+/// An optional holding the one argument if `keep` accepts it, or else an empty one.
+fn evaluate_optional(
+    function: Identifier,
+    args: Vec<Value>,
+    keep: fn(&Value) -> bool,
+) -> EvalResult {
+    if args.len() != 1 {
+        return Err(Error::NoFunctionWithSignature(function, arg_kinds(args)));
+    }
+    Ok(Value::Optional(
+        args.into_iter().next().filter(keep).map(Box::new),
+    ))
+}
+
+/// Whether `value` is the zero value of its kind, as a field that isn't set would have.
+fn is_zero(value: &Value) -> bool {
+    match value {
+        Value::I64(i) => *i == 0,
+        Value::U64(u) => *u == 0,
+        Value::F64(f) => *f == 0.0,
+        Value::Bool(b) => !b,
+        Value::String(s) => s.is_empty(),
+        Value::Bytes(b) => b.is_empty(),
+        Value::List(elems) => elems.is_empty(),
+        Value::Map(fields) => fields.is_empty(),
+        Value::Timestamp(t) => t.seconds == 0 && t.nanos == 0,
+        Value::Duration(d) => *d == 0,
+        Value::Null => true,
+        Value::Optional(v) => v.is_none(),
+    }
+}
+
+/// `size(x)`, the same as `x.size()`.
+fn evaluate_function_size(args: Vec<Value>) -> EvalResult {
+    match args.as_slice() {
+        [value] => methods::size(value).map(Value::I64),
+        _ => None,
+    }
+    .ok_or_else(|| Error::NoFunctionWithSignature(Identifier::new(FUNCTION_SIZE), arg_kinds(args)))
+}
+
+fn evaluate_function_duration(args: Vec<Value>) -> EvalResult {
+    match args.as_slice() {
+        [Value::String(s)] => Ok(Value::Duration(time::parse_duration(s)?)),
+        [Value::Duration(d)] => Ok(Value::Duration(*d)),
+        _ => Err(Error::NoFunctionWithSignature(
+            Identifier::new(FUNCTION_DURATION),
+            arg_kinds(args),
+        )),
+    }
+}
+
+fn evaluate_function_timestamp(args: Vec<Value>) -> EvalResult {
+    match args.as_slice() {
+        [Value::String(s)] => Ok(Value::Timestamp(time::parse_timestamp(s)?)),
+        [Value::Timestamp(t)] => Ok(Value::Timestamp(*t)),
+        _ => Err(Error::NoFunctionWithSignature(
+            Identifier::new(FUNCTION_TIMESTAMP),
+            arg_kinds(args),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::EvalContext;
+    use crate::model::Expression;
+    use crate::parser::parse;
+
+    /// Each signature's example calls it with arguments of the declared kinds, and the function
+    /// returns a value of the declared result kind.
+    #[test]
+    fn signatures_match_dispatch() {
+        let ctx = EvalContext::default();
+        for sig in SIGNATURES {
+            let (name, args) = match parse(&sig.example) {
+                Ok(Expression::Function(ref mut name, ref mut args)) => {
+                    (std::mem::take(name), std::mem::take(args))
+                }
+                other => panic!("{} is not a function call: {:?}", sig.example, other),
+            };
+            assert_eq!(name.0, sig.name, "{}", sig.example);
+            assert_eq!(sig.operand, None, "{}", sig.example);
+            let args: Vec<Value> = args
+                .into_iter()
+                .map(|arg| ctx.evaluate(arg).expect("argument"))
+                .collect();
+            assert_eq!(args.len(), sig.args.len(), "{}", sig.example);
+            for (arg, kind) in args.iter().zip(sig.args.iter()) {
+                if let Some(kind) = kind {
+                    assert_eq!(arg.kind(), *kind, "{}", sig.example);
+                }
+            }
+            let result = evaluate_function(name, args, None).expect("result");
+            assert_eq!(result.kind(), sig.result, "{}", sig.example);
+        }
+    }
+
+    #[test]
+    fn size() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let inputs = [
+            (r#" [1, [2, 3]] "#, 2),
+            (r#" "a¢" "#, 2),
+            (r#" b"\xC2\xA2" "#, 2),
+            (r#" {"a": 1, "b": 2, "c": 3} "#, 3),
+            (r#" "" "#, 0),
+        ];
+        for (input, size) in &inputs {
+            let expected = Ok(Value::I64(*size));
+            assert_eq!(eval(&format!("size({})", input)), expected, "{}", input);
+            assert_eq!(eval(&format!("({}).size()", input)), expected, "{}", input);
+            assert_eq!(eval(&format!("({}).len()", input)), expected, "{}", input);
+        }
+        assert_eq!(
+            eval(r#" size(1) "#),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new("size"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" size("a", "b") "#),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new("size"),
+                vec![Kind::String, Kind::String]
+            ))
+        );
+        assert_eq!(
+            eval(r#" true.size() "#),
+            Err(Error::NoMethodOnType(Kind::Bool, Identifier::new("size")))
+        );
+    }
+
+    #[test]
+    fn hashes() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let hex = |input: &str| eval(&format!(r#" "%x".format([{}]) "#, input));
+        let s = |s: &str| Ok(Value::String(s.to_owned().into()));
+        assert_eq!(
+            hex(r#" sha256("abc") "#),
+            s("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(
+            hex(r#" sha256(b"") "#),
+            s("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(
+            hex(r#" sha1("abc") "#),
+            s("a9993e364706816aba3e25717850c26c9cd0d89d")
+        );
+        assert_eq!(hex(r#" crc32("123456789") "#), s("cbf43926"));
+        assert_eq!(hex(r#" crc32(b"") "#), s("00000000"));
+        // A string hashes as its UTF-8 encoding.
+        assert_eq!(
+            eval(r#" sha256("é") == sha256(b"\xc3\xa9") "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval(r#" sha1(1) "#),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new("sha1"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" crc32("a", "b") "#),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new("crc32"),
+                vec![Kind::String, Kind::String]
+            ))
+        );
+    }
+}
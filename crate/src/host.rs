@@ -0,0 +1,272 @@
+//! An import-based ABI for host-defined functions, so that embedders running the wasm module
+//! without wasm-bindgen's JS glue (e.g. under wasmtime or wasmer) can extend the evaluator.
+//!
+//! Everything crosses the boundary as UTF-8 JSON addressed by pointer and length. A buffer returned
+//! across the boundary is "framed": a 4-byte little-endian length, then that many bytes. Framed
+//! buffers are allocated with `cel_alloc` and released with `cel_dealloc` by whoever receives them.
+//!
+//! - The host registers a function by passing its JSON `Signature` to `cel_register_function`.
+//! - When an expression calls it, the guest calls the host's import
+//!   `cel_host.host_call(name_ptr, name_len, args_ptr, args_len)` with the arguments as a JSON
+//!   array, and the host returns a framed `{"Ok": value}` or `{"Err": "message"}`.
+//! - By default, calling a method that isn't built in fails. After `cel_set_unknown_methods(1)`,
+//!   `x.f(y)` calls the registered function `f` as `f(x, y)`; after `cel_set_unknown_methods(2)`,
+//!   it calls `host_call` for `f` with `[x, y]` whether or not `f` is registered.
+//! - `cel_eval(input_ptr, input_len)` evaluates an expression with the registered functions and
+//!   returns a framed `{"Ok": value}` or `{"Err": error}`.
+//!
+//! A panic in the guest is reported as a failure, `{"Err": {"Internal": message}}` or `false`,
+//! in builds that can catch it (see `panics`).
+
+use crate::interpreter::{CustomFunction, EvalContext, UnknownMethods};
+use crate::json::{from_json, to_json};
+use crate::model::{Error, EvalResult, Signature, Value};
+use crate::panics;
+use crate::parser;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+thread_local! {
+    static FUNCTIONS: RefCell<Vec<Signature>> = const { RefCell::new(Vec::new()) };
+    static UNKNOWN_METHODS: Cell<u32> = const { Cell::new(0) };
+}
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "cel_host")]
+extern "C" {
+    fn host_call(
+        name_ptr: *const u8,
+        name_len: usize,
+        args_ptr: *const u8,
+        args_len: usize,
+    ) -> *mut u8;
+}
+
+/// Call the host's `host_call` import, taking ownership of the framed buffer it returns.
+#[cfg(target_arch = "wasm32")]
+fn call_host(name: &str, args: &[u8]) -> Vec<u8> {
+    unsafe {
+        unframe(host_call(
+            name.as_ptr(),
+            name.len(),
+            args.as_ptr(),
+            args.len(),
+        ))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn call_host(_name: &str, _args: &[u8]) -> Vec<u8> {
+    br#"{"Err": "host functions are only available in wasm"}"#.to_vec()
+}
+
+/// A function implemented by the host's `host_call` import.
+pub fn host_function(signature: Signature) -> CustomFunction {
+    let name = signature.name.to_string();
+    CustomFunction {
+        signature,
+        implementation: Rc::new(move |args| invoke(call_host, &name, args)),
+    }
+}
+
+/// Encode `args`, pass them to `call`, and decode its response.
+fn invoke(call: impl Fn(&str, &[u8]) -> Vec<u8>, name: &str, args: Vec<Value>) -> EvalResult {
+    let args: serde_json::Value = args.into_iter().map(to_json).collect();
+    let response = call(name, args.to_string().as_bytes());
+    let response: Result<serde_json::Value, String> = serde_json::from_slice(&response)
+        .map_err(|err| Error::HostError(format!("invalid response from host: {}", err)))?;
+    match response {
+        Ok(value) => Ok(from_json(value)),
+        Err(message) => Err(Error::HostError(message)),
+    }
+}
+
+/// Allocate `len` bytes for the host to write into.
+#[no_mangle]
+pub extern "C" fn cel_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Release `len` bytes allocated by `cel_alloc`.
+///
+/// # Safety
+///
+/// `ptr` must come from `cel_alloc(len)` and not have been released already.
+#[no_mangle]
+pub unsafe extern "C" fn cel_dealloc(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Register the function described by the JSON `Signature` at `ptr`. Returns false if it doesn't
+/// parse, or if registering it panics.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cel_register_function(ptr: *const u8, len: usize) -> bool {
+    panics::guard("cel_register_function", || {
+        match serde_json::from_slice(std::slice::from_raw_parts(ptr, len)) {
+            Ok(signature) => {
+                FUNCTIONS.with(|fs| fs.borrow_mut().push(signature));
+                true
+            }
+            Err(_) => false,
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Choose what calling a method that isn't built in does: 0 fails, 1 calls the registered function
+/// of the same name, and 2 calls the host for any name. Returns false for other modes.
+#[no_mangle]
+pub extern "C" fn cel_set_unknown_methods(mode: u32) -> bool {
+    if mode > 2 {
+        return false;
+    }
+    UNKNOWN_METHODS.with(|m| m.set(mode));
+    true
+}
+
+/// What `cel_set_unknown_methods` chose, calling the host with `call`.
+fn unknown_methods(call: fn(&str, &[u8]) -> Vec<u8>) -> UnknownMethods {
+    match UNKNOWN_METHODS.with(Cell::get) {
+        1 => UnknownMethods::Functions,
+        2 => UnknownMethods::Handler(Rc::new(move |name, operand, args| {
+            invoke(
+                call,
+                &name.0,
+                std::iter::once(operand).chain(args).collect(),
+            )
+        })),
+        _ => UnknownMethods::Reject,
+    }
+}
+
+/// Evaluate the expression at `ptr` with the registered host functions, returning a framed result.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cel_eval(ptr: *const u8, len: usize) -> *mut u8 {
+    let input = String::from_utf8_lossy(std::slice::from_raw_parts(ptr, len));
+    let response = panics::guard_json("cel_eval", || eval(&input));
+    frame(&response.to_string().into_bytes())
+}
+
+fn eval(input: &str) -> serde_json::Value {
+    let expr = match parser::parse(input) {
+        Ok(expr) => expr,
+        Err(err) => return serde_json::json!({ "Err": format!("{:?}", err) }),
+    };
+    let mut ctx = EvalContext::default();
+    FUNCTIONS.with(|fs| {
+        for signature in fs.borrow().iter() {
+            ctx.register_function(host_function(signature.clone()));
+        }
+    });
+    ctx.set_unknown_methods(unknown_methods(call_host));
+    match ctx.evaluate(expr) {
+        Ok(value) => serde_json::json!({ "Ok": to_json(value) }),
+        Err(err) => serde_json::json!({ "Err": err }),
+    }
+}
+
+fn frame(bytes: &[u8]) -> *mut u8 {
+    let ptr = cel_alloc(bytes.len() + 4);
+    unsafe {
+        let buf = std::slice::from_raw_parts_mut(ptr, bytes.len() + 4);
+        buf[..4].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+        buf[4..].copy_from_slice(bytes);
+    }
+    ptr
+}
+
+/// Copy out and release a framed buffer.
+///
+/// # Safety
+///
+/// `ptr` must be a framed buffer from `cel_alloc` that is not used again.
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+unsafe fn unframe(ptr: *mut u8) -> Vec<u8> {
+    let mut len = [0; 4];
+    len.copy_from_slice(std::slice::from_raw_parts(ptr, 4));
+    let len = u32::from_le_bytes(len) as usize;
+    let bytes = std::slice::from_raw_parts(ptr.add(4), len).to_vec();
+    cel_dealloc(ptr, len + 4);
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Kind;
+    use std::borrow::Cow;
+
+    fn shout(name: &str, args: &[u8]) -> Vec<u8> {
+        let args: Vec<String> = serde_json::from_slice(args).unwrap();
+        assert_eq!(name, "shout");
+        serde_json::json!({ "Ok": format!("{}!", args[0]) })
+            .to_string()
+            .into_bytes()
+    }
+
+    #[test]
+    fn invoke_round_trips_json() {
+        let result = invoke(shout, "shout", vec![Value::String("hi".to_owned().into())]);
+        assert_eq!(result, Ok(Value::String("hi!".to_owned().into())));
+        let fail = |_: &str, _: &[u8]| br#"{"Err": "nope"}"#.to_vec();
+        assert_eq!(
+            invoke(fail, "f", vec![]),
+            Err(Error::HostError("nope".to_owned()))
+        );
+        let garbage = |_: &str, _: &[u8]| b"garbage".to_vec();
+        assert!(matches!(
+            invoke(garbage, "f", vec![]),
+            Err(Error::HostError(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_methods_call_host() {
+        let expr = crate::parser::parse(r#" "hi".shout() "#).unwrap();
+        let evaluate = || {
+            let mut ctx = EvalContext::default();
+            ctx.set_unknown_methods(unknown_methods(shout));
+            ctx.evaluate(expr.clone())
+        };
+        assert!(matches!(evaluate(), Err(Error::NoMethod(..))));
+        assert!(cel_set_unknown_methods(2));
+        assert_eq!(evaluate(), Ok(Value::String("hi!".to_owned().into())));
+        assert!(!cel_set_unknown_methods(3));
+        assert!(cel_set_unknown_methods(0));
+    }
+
+    #[test]
+    fn frames() {
+        let bytes = unsafe { unframe(frame(b"hello")) };
+        assert_eq!(bytes, b"hello");
+    }
+
+    #[test]
+    fn register_and_eval() {
+        let signature = Signature {
+            name: Cow::Borrowed("twice"),
+            operand: None,
+            args: Cow::Borrowed(&[Some(Kind::I64)]),
+            result: Kind::I64,
+            doc: Cow::Borrowed("Double an int."),
+            example: Cow::Borrowed("twice(2)"),
+        };
+        let json = serde_json::to_vec(&signature).unwrap();
+        assert!(unsafe { cel_register_function(json.as_ptr(), json.len()) });
+        assert!(!unsafe { cel_register_function(b"{".as_ptr(), 1) });
+        // Outside wasm there is no host to call.
+        assert_eq!(
+            eval("twice(2)"),
+            serde_json::json!({"Err": {"HostError": "host functions are only available in wasm"}})
+        );
+        assert_eq!(eval("1 + 1"), serde_json::json!({"Ok": 2}));
+    }
+}
@@ -0,0 +1,172 @@
+//! A table of the strings in a serialized payload, so that each is sent only once.
+//!
+//! Where interning is on, strings are replaced by their index into the table wherever the shape of
+//! the payload says a string goes: the content of a `String` value (`{"t": "String", "c": 3}`), a
+//! node's `name`, and so on. Map keys stay as they are, since JSON object keys must be strings.
+
+use crate::model::{EvalResult, Value};
+use crate::ordering;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, SerializeStruct, Serializer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The strings interned so far. Serializes as the list of them, in order of index.
+#[derive(Default)]
+pub struct Strings {
+    table: RefCell<(Vec<String>, HashMap<String, usize>)>,
+}
+
+impl Strings {
+    /// The index of `s`, adding it to the table if it isn't there yet.
+    pub fn intern(&self, s: &str) -> usize {
+        let mut table = self.table.borrow_mut();
+        let (list, indices) = &mut *table;
+        if let Some(&i) = indices.get(s) {
+            return i;
+        }
+        list.push(s.to_owned());
+        indices.insert(s.to_owned(), list.len() - 1);
+        list.len() - 1
+    }
+}
+
+impl Serialize for Strings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.table.borrow().0.serialize(serializer)
+    }
+}
+
+/// Serializes `value` as usual, but with its strings interned into `strings`.
+pub struct InternedValue<'a> {
+    pub value: &'a Value,
+    pub strings: &'a Strings,
+}
+
+impl Serialize for InternedValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let strings = self.strings;
+        match self.value {
+            Value::String(s) => {
+                let mut state = serializer.serialize_struct("Value", 2)?;
+                state.serialize_field("t", "String")?;
+                state.serialize_field("c", &strings.intern(s))?;
+                state.end()
+            }
+            Value::List(values) => {
+                let mut state = serializer.serialize_struct("Value", 2)?;
+                state.serialize_field("t", "List")?;
+                state.serialize_field("c", &InternedList { values, strings })?;
+                state.end()
+            }
+            Value::Map(entries) => {
+                let mut state = serializer.serialize_struct("Value", 2)?;
+                state.serialize_field("t", "Map")?;
+                state.serialize_field("c", &InternedMap { entries, strings })?;
+                state.end()
+            }
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+struct InternedList<'a> {
+    values: &'a [Value],
+    strings: &'a Strings,
+}
+
+impl Serialize for InternedList<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.values.len()))?;
+        for value in self.values {
+            seq.serialize_element(&InternedValue {
+                value,
+                strings: self.strings,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+struct InternedMap<'a> {
+    entries: &'a HashMap<String, Value>,
+    strings: &'a Strings,
+}
+
+impl Serialize for InternedMap<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.entries.len()))?;
+        for (key, value) in ordering::entries(self.entries) {
+            map.serialize_entry(
+                key,
+                &InternedValue {
+                    value,
+                    strings: self.strings,
+                },
+            )?;
+        }
+        map.end()
+    }
+}
+
+/// Serializes `result` as usual, but with the strings of a successful value interned. Errors are
+/// left as they are.
+pub struct InternedResult<'a> {
+    pub result: &'a EvalResult,
+    pub strings: &'a Strings,
+}
+
+impl Serialize for InternedResult<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.result {
+            Ok(value) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(
+                    "Ok",
+                    &InternedValue {
+                        value,
+                        strings: self.strings,
+                    },
+                )?;
+                map.end()
+            }
+            result => result.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interned_values() {
+        let strings = Strings::default();
+        let mut map = HashMap::new();
+        map.insert("k".to_owned(), Value::String("b".to_owned().into()));
+        let value = Value::List(vec![
+            Value::String("a".to_owned().into()),
+            Value::Map(map),
+            Value::String("a".to_owned().into()),
+            Value::I64(1),
+        ]);
+        let result = Ok(value);
+        let json = serde_json::to_value(InternedResult {
+            result: &result,
+            strings: &strings,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"Ok": {"t": "List", "c": [
+                {"t": "String", "c": 0},
+                {"t": "Map", "c": {"k": {"t": "String", "c": 1}}},
+                {"t": "String", "c": 0},
+                {"t": "I64", "c": 1},
+            ]}})
+        );
+        assert_eq!(
+            serde_json::to_value(&strings).unwrap(),
+            serde_json::json!(["a", "b"])
+        );
+    }
+}
@@ -1,284 +1,1106 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 
+use crate::functions;
 use crate::methods;
-use crate::model::{Error, EvalResult, Expression, Identifier, Literal, Op, Value};
+use crate::model::{
+    Error, ErrorPolicy, EvalResult, Expression, Identifier, Limit, LimitExceeded, Literal, Op,
+    Signature, Span, Value,
+};
+use crate::suggest;
+use crate::time;
+use crate::validation::{self, BindingError, BindingOptions};
 use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::rc::Rc;
 use std::sync::Mutex;
 
-#[derive(Default, Clone)]
+/// A host-defined global function, callable from expressions as `name(args...)`.
+///
+/// Custom functions are registered by Rust embedders; the wasm bindings evaluate with the built-ins
+/// only, so `list_signatures` there does not include them. Use `EvalContext::signatures` to list
+/// them alongside the built-ins.
+#[derive(Clone)]
+pub struct CustomFunction {
+    /// Describes the function for introspection; `signature.name` is the name it is called by.
+    pub signature: Signature,
+    pub implementation: Rc<dyn Fn(Vec<Value>) -> EvalResult>,
+}
+
+/// What calling a method that isn't built in does, so that hosts can add methods of their own
+/// without changing this crate.
+#[derive(Clone, Default)]
+pub enum UnknownMethods {
+    /// Fail with `Error::NoMethod`.
+    #[default]
+    Reject,
+    /// Call the custom function of the same name, with the receiver as its first argument, so that
+    /// `x.shout()` is `shout(x)`. Methods with no such function fail with `Error::NoMethod`.
+    Functions,
+    /// Call the handler with the method's name, its receiver and its arguments.
+    Handler(MethodHandler),
+}
+
+/// Implements methods that aren't built in, given a method's name, receiver and arguments.
+pub type MethodHandler = Rc<dyn Fn(&Identifier, Value, Vec<Value>) -> EvalResult>;
+
+#[derive(Clone)]
 pub struct EvalContext<'a> {
     parent: Option<&'a EvalContext<'a>>,
     pub binding: Option<(Identifier, EvalResult)>,
+    /// Bindings outside all of the others, e.g. the fields of a document (see `from_json`).
+    document: Rc<BTreeMap<String, Value>>,
     bytes_processed: Rc<Mutex<usize>>,
+    operations: Rc<Mutex<usize>>,
+    limits: EvalLimits,
+    functions: Rc<HashMap<String, CustomFunction>>,
+    unknown_methods: UnknownMethods,
+    error_policy: ErrorPolicy,
+    should_cancel: Option<Rc<dyn Fn() -> bool>>,
+}
+
+pub const BYTES_PROCESSED_LIMIT: usize = 1 << 20;
+pub const OPERATION_LIMIT: usize = 1 << 20;
+pub const DEPTH_LIMIT: usize = 250;
+/// How many operations to evaluate between calls to the cancellation callback, which may be slow
+/// (e.g. a call out to JS).
+const CANCELLATION_INTERVAL: usize = 256;
+
+/// Resource bounds for evaluation. Exceeding one fails the evaluation rather than letting it run
+/// away with memory or time.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct EvalLimits {
+    /// The total size of all intermediate values, in bytes. Exceeding it is `EvaluationTooLarge`,
+    /// at the value that crossed it.
+    pub max_bytes: usize,
+    /// How many subexpressions may be evaluated, however small their values. Exceeding it is
+    /// `TooManyOperations`.
+    pub max_ops: usize,
+    /// How deeply expressions may nest. Operands of a chain of binary operators like
+    /// `1 + 1 + ... + 1` all count as one level, however long the chain. Exceeding it is
+    /// `RecursionLimitExceeded`.
+    pub max_depth: usize,
+}
+
+/// What `EvalContext::default()` starts with on each thread, so that embedders can configure
+/// evaluation once rather than at every call site.
+#[derive(Clone, Default)]
+struct Defaults {
+    limits: EvalLimits,
+    functions: Rc<HashMap<String, CustomFunction>>,
+}
+
+thread_local! {
+    static DEFAULTS: RefCell<Defaults> = RefCell::new(Defaults::default());
+}
+
+/// Evaluate within `limits` in contexts later created on this thread by `EvalContext::default()`.
+/// `EvalContext::with_limits` still overrides them.
+pub fn set_default_limits(limits: EvalLimits) {
+    DEFAULTS.with(|d| d.borrow_mut().limits = limits);
+}
+
+/// Make `function` callable in contexts later created on this thread by `EvalContext::default()`,
+/// as if each had registered it.
+pub fn register_default_function(function: CustomFunction) {
+    DEFAULTS.with(|d| {
+        let functions = &mut d.borrow_mut().functions;
+        Rc::make_mut(functions).insert(function.signature.name.to_string(), function);
+    });
+}
+
+/// Forget the limits and functions set by `set_default_limits` and `register_default_function`.
+pub fn reset_defaults() {
+    DEFAULTS.with(|d| *d.borrow_mut() = Defaults::default());
+}
+
+impl Default for EvalContext<'_> {
+    fn default() -> Self {
+        let Defaults { limits, functions } = DEFAULTS.with(|d| d.borrow().clone());
+        EvalContext {
+            parent: None,
+            binding: None,
+            document: Rc::default(),
+            bytes_processed: Rc::default(),
+            operations: Rc::default(),
+            limits,
+            functions,
+            unknown_methods: UnknownMethods::default(),
+            error_policy: ErrorPolicy::default(),
+            should_cancel: None,
+        }
+    }
+}
+
+impl Default for EvalLimits {
+    fn default() -> EvalLimits {
+        EvalLimits {
+            max_bytes: BYTES_PROCESSED_LIMIT,
+            max_ops: OPERATION_LIMIT,
+            max_depth: DEPTH_LIMIT,
+        }
+    }
 }
 
-const BYTES_PROCESSED_LIMIT: usize = 1 << 20;
 impl<'a> EvalContext<'a> {
-    pub fn with_binding(&self, name: Identifier, result: EvalResult) -> EvalContext {
+    /// A context that evaluates within `limits` rather than the defaults.
+    pub fn with_limits(limits: EvalLimits) -> EvalContext<'a> {
+        EvalContext {
+            limits,
+            ..EvalContext::default()
+        }
+    }
+    /// A context with each field of `doc`, a JSON object, bound to its name, so that an
+    /// expression can refer to a record's fields directly. Fields are converted as
+    /// `validation::from_json` converts them, and fail as it does.
+    pub fn from_json(doc: &serde_json::Value) -> Result<EvalContext<'a>, BindingError> {
+        let fields = match doc {
+            serde_json::Value::Object(fields) => fields,
+            other => {
+                let kind = crate::json::from_json(other.clone()).kind();
+                return Err(BindingError::NotAnObject(kind));
+            }
+        };
+        let options = BindingOptions::default();
+        let document = fields
+            .iter()
+            .map(|(name, value)| {
+                let value = validation::from_json(name, value.clone(), &options)?;
+                Ok((name.clone(), value))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(EvalContext {
+            document: Rc::new(document),
+            ..EvalContext::default()
+        })
+    }
+    pub fn limits(&self) -> EvalLimits {
+        self.limits
+    }
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.error_policy
+    }
+    /// This context with each of `bindings` added to its document, a later binding of a name
+    /// replacing an earlier one. Unlike a chain of `with_binding`s, this takes no stack per
+    /// binding, and finds each in one lookup. Bindings made with `with_binding` shadow these.
+    pub fn with_bindings(
+        mut self,
+        bindings: impl IntoIterator<Item = (Identifier, Value)>,
+    ) -> EvalContext<'a> {
+        let document = Rc::make_mut(&mut self.document);
+        for (name, value) in bindings {
+            document.insert(name.0, value);
+        }
+        self
+    }
+    pub fn with_binding(&self, name: Identifier, result: EvalResult) -> EvalContext<'_> {
         EvalContext {
             parent: Some(self),
             binding: Some((name, result)),
+            document: self.document.clone(),
             bytes_processed: self.bytes_processed.clone(),
+            operations: self.operations.clone(),
+            limits: self.limits,
+            functions: self.functions.clone(),
+            unknown_methods: self.unknown_methods.clone(),
+            error_policy: self.error_policy,
+            should_cancel: self.should_cancel.clone(),
+        }
+    }
+    /// A context with the same functions, limits and error policy but no bindings, which counts
+    /// the work it does against the limits afresh.
+    pub(crate) fn detached<'b>(&self) -> EvalContext<'b> {
+        EvalContext {
+            parent: None,
+            binding: None,
+            document: Rc::default(),
+            bytes_processed: Rc::default(),
+            operations: Rc::default(),
+            limits: self.limits,
+            functions: self.functions.clone(),
+            unknown_methods: self.unknown_methods.clone(),
+            error_policy: self.error_policy,
+            should_cancel: self.should_cancel.clone(),
         }
     }
+    /// Whether calls to `name` go to a custom function rather than a built-in one.
+    pub(crate) fn is_custom_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+    /// Make `function` callable from expressions evaluated in this context. Custom functions shadow
+    /// built-in functions with the same name.
+    pub fn register_function(&mut self, function: CustomFunction) {
+        Rc::make_mut(&mut self.functions).insert(function.signature.name.to_string(), function);
+    }
+    /// Choose what calling a method that isn't built in does. By default, it fails.
+    pub fn set_unknown_methods(&mut self, unknown_methods: UnknownMethods) {
+        self.unknown_methods = unknown_methods;
+    }
+    /// Choose which error is reported when several operands of an expression fail.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+    /// Abort evaluation with `Error::Cancelled` once `should_cancel` returns true. It is polled
+    /// periodically, not before every operation. A deadline can be enforced by comparing against
+    /// the clock in `should_cancel`.
+    pub fn set_cancellation(&mut self, should_cancel: impl Fn() -> bool + 'static) {
+        self.should_cancel = Some(Rc::new(should_cancel));
+    }
+    /// Every built-in method and function overload, followed by the registered custom functions.
+    pub fn signatures(&self) -> Vec<Signature> {
+        let mut custom: Vec<Signature> = self
+            .functions
+            .values()
+            .map(|f| f.signature.clone())
+            .collect();
+        custom.sort_by(|a, b| a.name.cmp(&b.name));
+        crate::signatures()
+            .into_iter()
+            .cloned()
+            .chain(custom)
+            .collect()
+    }
     fn check_limits(&self) -> Result<(), Error> {
-        if *self.bytes_processed.lock().unwrap() > BYTES_PROCESSED_LIMIT {
-            return Err(Error::EvaluationTooLarge);
+        let bytes = *self.bytes_processed.lock().unwrap();
+        if bytes > self.limits.max_bytes {
+            return Err(self.too_large(bytes));
+        }
+        let operations = *self.operations.lock().unwrap();
+        if operations >= self.limits.max_ops {
+            return Err(Error::TooManyOperations);
+        }
+        if let Some(ref should_cancel) = self.should_cancel {
+            if operations.is_multiple_of(CANCELLATION_INTERVAL) && should_cancel() {
+                return Err(Error::Cancelled);
+            }
         }
         Ok(())
     }
-    pub fn evaluate(&'a self, expr: Expression) -> EvalResult {
+    /// Count one operation of the stack machine, failing if the limits are already exceeded.
+    pub(crate) fn charge_operation(&self) -> Result<(), Error> {
         self.check_limits()?;
-        let result = match expr {
+        *self.operations.lock().unwrap() += 1;
+        Ok(())
+    }
+    /// Count a value of `bytes` made by the stack machine, failing if that exceeds the byte limit.
+    pub(crate) fn charge_bytes(&self, bytes: usize) -> Result<(), Error> {
+        let mut processed = self.bytes_processed.lock().unwrap();
+        *processed += bytes;
+        if *processed > self.limits.max_bytes {
+            return Err(self.too_large(*processed));
+        }
+        Ok(())
+    }
+    fn too_large(&self, bytes: usize) -> Error {
+        Error::EvaluationTooLarge(LimitExceeded {
+            limit: Limit::Bytes,
+            threshold: self.limits.max_bytes,
+            consumed: bytes,
+        })
+    }
+    /// Evaluate `expr`. Subexpressions are tracked on an explicit work stack rather than by
+    /// recursion, so no expression is too deeply nested to evaluate without overflowing the native
+    /// stack; the depth limit is a resource limit like any other.
+    pub fn evaluate(&'a self, expr: Expression) -> EvalResult {
+        self.evaluate_with(expr, Vec::new())
+    }
+
+    /// Evaluate `expr` as if inside `let`s binding each of `scopes`, innermost last.
+    pub(crate) fn evaluate_with(
+        &'a self,
+        expr: Expression,
+        scopes: Vec<(Identifier, EvalResult)>,
+    ) -> EvalResult {
+        Machine {
+            ctx: self,
+            tasks: vec![Task::Eval(expr, 0)],
+            results: Vec::new(),
+            scopes,
+        }
+        .run()
+    }
+
+    pub(crate) fn call_function(&self, name: Identifier, args: Vec<Value>) -> EvalResult {
+        match self.functions.get(&name.0) {
+            Some(custom) => (custom.implementation)(args),
+            None => {
+                functions::evaluate_function(name, args, self.functions.keys().map(String::as_str))
+            }
+        }
+    }
+
+    pub(crate) fn call_method(
+        &self,
+        name: Identifier,
+        operand: Value,
+        args: Vec<Value>,
+    ) -> EvalResult {
+        if methods::is_builtin(&name.0) {
+            return methods::evaluate_method(name, operand, args);
+        }
+        match &self.unknown_methods {
+            UnknownMethods::Reject => methods::evaluate_method(name, operand, args),
+            UnknownMethods::Functions => match self.functions.get(&name.0) {
+                Some(custom) => {
+                    (custom.implementation)(std::iter::once(operand).chain(args).collect())
+                }
+                None => methods::evaluate_method(name, operand, args),
+            },
+            UnknownMethods::Handler(handler) => handler(&name, operand, args),
+        }
+    }
+
+    fn lookup_binding(&self, name: &Identifier) -> Option<EvalResult> {
+        let mut ctx = self;
+        loop {
+            if let Some((ref id, ref value)) = ctx.binding {
+                if id == name {
+                    return Some(value.clone());
+                }
+            }
+            match ctx.parent {
+                Some(parent) => ctx = parent,
+                None => return ctx.document.get(&name.0).cloned().map(Ok),
+            }
+        }
+    }
+
+    /// The value bound to `name`, or `NoSuchBinding` with the bound names it may have meant.
+    pub(crate) fn resolve(&self, name: Identifier) -> EvalResult {
+        match self.lookup_binding(&name) {
+            Some(result) => result,
+            None => {
+                let suggestions = suggest::similar(&name.0, self.binding_names());
+                Err(Error::NoSuchBinding(name, suggestions))
+            }
+        }
+    }
+
+    /// The name of every binding in scope, innermost first.
+    fn binding_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut ctx = Some(self);
+        while let Some(c) = ctx {
+            if let Some((ref id, _)) = c.binding {
+                names.push(id.0.as_str());
+            }
+            ctx = c.parent;
+        }
+        names.extend(self.document.keys().map(String::as_str));
+        names
+    }
+}
+
+/// A unit of pending work for `EvalContext::evaluate`.
+enum Task {
+    /// Evaluate the expression, nested at the given depth, and push its result.
+    Eval(Expression, usize),
+    /// Pop the result of an operand and carry on with the expression that was waiting for it.
+    Continue(Frame),
+    /// Count the size of the result on top of the stack against the bytes-processed limit.
+    Account,
+    /// Leave the innermost `let` scope.
+    PopScope,
+}
+
+/// An expression part-way through evaluation, waiting for the result of one of its operands.
+enum Frame {
+    /// Attribute any error to this part of the source.
+    Span(Span),
+    Unary(fn(Value) -> EvalResult),
+    Member(Identifier),
+    SafeMember(Identifier),
+    Ternary {
+        true_branch: Expression,
+        else_branch: Expression,
+        depth: usize,
+    },
+    Let {
+        id: Identifier,
+        body: Expression,
+        depth: usize,
+    },
+    BinaryLeft {
+        op: BinaryOp,
+        right: Expression,
+        depth: usize,
+    },
+    BinaryRight {
+        op: BinaryOp,
+        left: EvalResult,
+    },
+    /// `||` or `&&`, which only evaluate operands until one decides the result.
+    Logical {
+        op: Op,
+        rest: std::vec::IntoIter<Expression>,
+        seen: Vec<EvalResult>,
+        depth: usize,
+    },
+    /// Anything that needs all of its operands, left to right.
+    Operands {
+        target: Target,
+        rest: std::vec::IntoIter<Expression>,
+        seen: Vec<EvalResult>,
+        depth: usize,
+        /// The bytes charged so far for the elements of a list or map being built, which are
+        /// charged as they're added so that building one stops as soon as it's too large.
+        charged: usize,
+    },
+}
+
+enum Target {
+    List,
+    /// Alternating keys and values.
+    Map,
+    /// The operand, then the arguments.
+    Method(Identifier),
+    Function(Identifier),
+}
+
+/// The work and result stacks behind `EvalContext::evaluate`.
+struct Machine<'c, 'a> {
+    ctx: &'c EvalContext<'a>,
+    tasks: Vec<Task>,
+    results: Vec<EvalResult>,
+    /// The `let` bindings entered so far in this evaluation, innermost last.
+    scopes: Vec<(Identifier, EvalResult)>,
+}
+
+impl<'c, 'a> Machine<'c, 'a> {
+    fn run(mut self) -> EvalResult {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Eval(expr, depth) => self.eval(expr, depth),
+                Task::Continue(frame) => {
+                    let operand = self.results.pop().expect("operand");
+                    self.resume(frame, operand);
+                }
+                Task::Account => {
+                    if let Some(Ok(value)) = self.results.last() {
+                        let mut bytes = self.ctx.bytes_processed.lock().unwrap();
+                        *bytes += value.size();
+                        // Fail the value that crossed the limit, so the error is at its span.
+                        if *bytes > self.ctx.limits.max_bytes {
+                            let error = self.ctx.too_large(*bytes);
+                            drop(bytes);
+                            *self.results.last_mut().expect("result") = Err(error);
+                        }
+                    }
+                }
+                Task::PopScope => {
+                    self.scopes.pop();
+                }
+            }
+        }
+        self.results.pop().expect("result")
+    }
+
+    fn eval(&mut self, mut expr: Expression, depth: usize) {
+        // Spans are annotations rather than operations: they don't count against any limit.
+        if let Expression::Spanned(span, e) = &mut expr {
+            return self.wait(Frame::Span(*span), e.take(), depth);
+        }
+        if let Err(e) = self.ctx.check_limits() {
+            return self.results.push(Err(e));
+        }
+        if depth >= self.ctx.limits.max_depth {
+            return self.results.push(Err(Error::RecursionLimitExceeded));
+        }
+        *self.ctx.operations.lock().unwrap() += 1;
+        self.tasks.push(Task::Account);
+        let inner = depth + 1;
+        match &mut expr {
             Expression::LetBinding { id, value, body } => {
-                let value = self.evaluate(*value);
-                self.with_binding(id, value).evaluate(*body)
+                let body = body.take();
+                self.wait(
+                    Frame::Let {
+                        id: std::mem::take(id),
+                        body,
+                        depth: inner,
+                    },
+                    value.take(),
+                    inner,
+                );
             }
             Expression::Ternary {
                 condition,
                 true_branch,
                 else_branch,
-            } => match self.evaluate(*condition) {
-                Ok(Value::Bool(true)) => self.evaluate(*true_branch),
-                _ => self.evaluate(*else_branch),
-            },
-            Expression::Lit(lit) => self.evaluate_literal(lit),
-            Expression::Neg(e) => match self.evaluate(*e)? {
-                Value::I64(x) => Ok(Value::I64(-x)),
-                Value::F64(x) => Ok(Value::F64(-x)),
-                other => Err(Error::InvalidTypeForOperator(other.kind(), Op::Neg)),
-            },
-            Expression::Not(e) => match self.evaluate(*e)? {
-                Value::Bool(x) => Ok(Value::Bool(!x)),
-                other => Err(Error::InvalidTypeForOperator(other.kind(), Op::Not)),
-            },
-            Expression::Or(children) => {
-                let mut vs = Vec::new();
-                for child in children {
-                    match self.evaluate(child) {
-                        Ok(Value::Bool(true)) => return Ok(Value::Bool(true)),
-                        other => vs.push(other),
-                    };
-                }
-                for v in vs {
-                    match v? {
-                        Value::Bool(b) => assert!(!b),
-                        other => return Err(Error::InvalidTypeForOperator(other.kind(), Op::Or)),
-                    };
-                }
-                Ok(Value::Bool(false))
+            } => {
+                let frame = Frame::Ternary {
+                    true_branch: true_branch.take(),
+                    else_branch: else_branch.take(),
+                    depth: inner,
+                };
+                self.wait(frame, condition.take(), inner);
             }
-            Expression::And(children) => {
-                let mut vs = Vec::new();
-                for child in children {
-                    match self.evaluate(child) {
-                        Ok(Value::Bool(false)) => return Ok(Value::Bool(false)),
-                        other => vs.push(other),
-                    };
-                }
-                for v in vs {
-                    match v? {
-                        Value::Bool(b) => assert!(b),
-                        other => return Err(Error::InvalidTypeForOperator(other.kind(), Op::Or)),
-                    };
-                }
-                Ok(Value::Bool(true))
+            Expression::Lit(Literal::List(elems)) => {
+                self.operands(Target::List, std::mem::take(elems), inner)
             }
-            Expression::Eq(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                Ok(Value::Bool(a == b))
+            Expression::Lit(Literal::Map(kvs)) => {
+                let kvs = kvs.drain(..).flat_map(|(k, v)| vec![k, v]).collect();
+                self.operands(Target::Map, kvs, inner)
             }
-            Expression::Neq(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                Ok(Value::Bool(a != b))
+            Expression::Lit(lit) => {
+                let lit = std::mem::replace(lit, Literal::Null);
+                self.results.push(Ok(scalar(lit)))
             }
-            Expression::Lt(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match a.partial_cmp(&b) {
-                    None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Lt)),
-                    Some(ord) => Ok(Value::Bool(ord == Ordering::Less)),
-                }
+            Expression::Neg(e) => self.wait(Frame::Unary(neg), e.take(), inner),
+            Expression::Not(e) => self.wait(Frame::Unary(not), e.take(), inner),
+            Expression::Member(e, name) => {
+                self.wait(Frame::Member(std::mem::take(name)), e.take(), inner)
             }
-            Expression::Lte(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match a.partial_cmp(&b) {
-                    None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Lte)),
-                    Some(ord) => Ok(Value::Bool(ord == Ordering::Less || a == b)),
-                }
+            Expression::SafeMember(e, name) => {
+                self.wait(Frame::SafeMember(std::mem::take(name)), e.take(), inner)
             }
-            Expression::Gte(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match a.partial_cmp(&b) {
-                    None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Gte)),
-                    Some(ord) => Ok(Value::Bool(ord == Ordering::Greater || a == b)),
-                }
+            Expression::Or(children) => self.logical(Op::Or, std::mem::take(children), inner),
+            Expression::And(children) => self.logical(Op::And, std::mem::take(children), inner),
+            Expression::Binding(name) => {
+                let result = self.lookup(std::mem::take(name));
+                self.results.push(result);
             }
-            Expression::Gt(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match a.partial_cmp(&b) {
-                    None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Gt)),
-                    Some(ord) => Ok(Value::Bool(ord == Ordering::Greater)),
-                }
+            Expression::Method(e, name, args) => {
+                let operands = std::iter::once(e.take()).chain(args.drain(..)).collect();
+                self.operands(Target::Method(std::mem::take(name)), operands, inner)
             }
-            Expression::Add(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match (a, b) {
-                    (Value::I64(a), Value::I64(b)) => Ok(Value::I64(a + b)),
-                    (Value::F64(a), Value::F64(b)) => Ok(Value::F64(a + b)),
-                    (Value::String(a), Value::String(b)) => {
-                        Ok(Value::String(a.chars().chain(b.chars()).collect()))
-                    }
-                    (Value::Bytes(a), Value::Bytes(b)) => {
-                        Ok(Value::Bytes(a.into_iter().chain(b.into_iter()).collect()))
-                    }
-                    (Value::List(a), Value::List(b)) => {
-                        Ok(Value::List(a.into_iter().chain(b.into_iter()).collect()))
-                    }
-                    (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Plus)),
-                }
+            Expression::Function(name, args) => {
+                let target = Target::Function(std::mem::take(name));
+                self.operands(target, std::mem::take(args), inner)
             }
-            Expression::Sub(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match (a, b) {
-                    (Value::I64(a), Value::I64(b)) => Ok(Value::I64(a - b)),
-                    (a, b) => Err(Error::InvalidTypesForOperator(
-                        a.kind(),
-                        b.kind(),
-                        Op::Minus,
-                    )),
-                }
+            _ => {
+                let (left, op, right) = split_binary(&mut expr).expect("binary operator");
+                // A chain like `1 + 2 - 3` nests to the left; it counts as one level, not one per
+                // operator.
+                let left_depth = if is_binary(&left) { depth } else { inner };
+                self.wait(
+                    Frame::BinaryLeft {
+                        op,
+                        right,
+                        depth: inner,
+                    },
+                    left,
+                    left_depth,
+                );
             }
-            Expression::Mul(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match (a, b) {
-                    (Value::I64(a), Value::I64(b)) => Ok(Value::I64(a * b)),
-                    (Value::F64(a), Value::F64(b)) => Ok(Value::F64(a * b)),
-                    (a, b) => Err(Error::InvalidTypesForOperator(
-                        a.kind(),
-                        b.kind(),
-                        Op::Times,
-                    )),
-                }
+        }
+    }
+
+    /// Evaluate `operand`, then resume `frame` with its result.
+    fn wait(&mut self, frame: Frame, operand: Expression, depth: usize) {
+        self.tasks.push(Task::Continue(frame));
+        self.tasks.push(Task::Eval(operand, depth));
+    }
+
+    fn operands(&mut self, target: Target, operands: Vec<Expression>, depth: usize) {
+        let mut rest = operands.into_iter();
+        match rest.next() {
+            Some(first) => {
+                let seen = Vec::new();
+                self.wait(
+                    Frame::Operands {
+                        target,
+                        rest,
+                        seen,
+                        depth,
+                        charged: 0,
+                    },
+                    first,
+                    depth,
+                );
             }
-            Expression::Div(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match (a, b) {
-                    (Value::I64(a), Value::I64(b)) => {
-                        if b != 0 {
-                            Ok(Value::I64(a / b))
-                        } else {
-                            Err(Error::DivisionByZero)
-                        }
-                    }
-                    (Value::F64(a), Value::F64(b)) => {
-                        if b != 0.0 {
-                            Ok(Value::F64(a / b))
-                        } else {
-                            Err(Error::DivisionByZero)
-                        }
-                    }
-                    (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Div)),
-                }
+            None => self.finish(target, Vec::new(), 0),
+        }
+    }
+
+    fn logical(&mut self, op: Op, operands: Vec<Expression>, depth: usize) {
+        let mut rest = operands.into_iter();
+        let seen = Vec::new();
+        match rest.next() {
+            Some(first) => self.wait(
+                Frame::Logical {
+                    op,
+                    rest,
+                    seen,
+                    depth,
+                },
+                first,
+                depth,
+            ),
+            None => self.resume_logical(op, rest, seen, depth),
+        }
+    }
+
+    fn resume(&mut self, frame: Frame, operand: EvalResult) {
+        let policy = self.ctx.error_policy;
+        match frame {
+            Frame::Span(span) => self.results.push(operand.map_err(|e| locate(span, e))),
+            Frame::Unary(f) => self.results.push(operand.and_then(f)),
+            Frame::Member(name) => self.results.push(operand.and_then(|v| member(v, name))),
+            Frame::SafeMember(name) => self
+                .results
+                .push(operand.and_then(|v| safe_member(v, name))),
+            Frame::Ternary {
+                true_branch,
+                else_branch,
+                depth,
+            } => {
+                let branch = match operand {
+                    Ok(Value::Bool(true)) => true_branch,
+                    _ => else_branch,
+                };
+                self.tasks.push(Task::Eval(branch, depth));
             }
-            Expression::Mod(a, b) => {
-                let a = self.evaluate(*a)?;
-                let b = self.evaluate(*b)?;
-                match (a, b) {
-                    (Value::I64(a), Value::I64(b)) => Ok(Value::I64(a % b)),
-                    (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Mod)),
-                }
+            Frame::Let { id, body, depth } => {
+                self.scopes.push((id, operand));
+                self.tasks.push(Task::PopScope);
+                self.tasks.push(Task::Eval(body, depth));
             }
-            Expression::Binding(name) => self.lookup_binding(name),
-            Expression::Member(e, name) => {
-                let e = self.evaluate(*e)?;
-                match e {
-                    Value::Map(mut fields) => {
-                        Ok(fields.remove(&name.0).ok_or(Error::NoSuchMember(name))?)
+            Frame::BinaryLeft { op, right, depth } => match operand {
+                Err(e) if policy == ErrorPolicy::Leftmost => self.results.push(Err(e)),
+                left => self.wait(Frame::BinaryRight { op, left }, right, depth),
+            },
+            Frame::BinaryRight { op, left } => self.results.push(match (left, operand) {
+                (Ok(a), Ok(b)) => op(a, b),
+                (Err(e), Ok(_)) | (Ok(_), Err(e)) => Err(e),
+                (Err(a), Err(b)) => Err(policy.combine(a, b)),
+            }),
+            Frame::Logical {
+                op,
+                rest,
+                mut seen,
+                depth,
+            } => {
+                // `true` decides `||`, and `false` decides `&&`, even if other operands failed.
+                let decisive = op == Op::Or;
+                if let Ok(Value::Bool(b)) = operand {
+                    if b == decisive {
+                        return self.results.push(Ok(Value::Bool(decisive)));
                     }
-                    other => Err(Error::InvalidTypeForOperator(
-                        other.kind(),
-                        Op::Member(name),
-                    )),
                 }
+                seen.push(operand);
+                self.resume_logical(op, rest, seen, depth);
             }
-            Expression::Method(e, name, args) => {
-                let e = self.evaluate(*e)?;
-                let args = args
-                    .into_iter()
-                    .map(|a| self.evaluate(a))
-                    .collect::<Result<Vec<_>, _>>()?;
-                methods::evaluate_method(name, e, args)
+            Frame::Operands {
+                target,
+                mut rest,
+                mut seen,
+                depth,
+                mut charged,
+            } => {
+                if let (Target::List | Target::Map, Ok(value)) = (&target, &operand) {
+                    let mut bytes = self.ctx.bytes_processed.lock().unwrap();
+                    let size = value.size();
+                    *bytes += size;
+                    charged += size;
+                    if *bytes > self.ctx.limits.max_bytes {
+                        let error = self.ctx.too_large(*bytes);
+                        drop(bytes);
+                        return self.results.push(Err(error));
+                    }
+                }
+                let stop = operand.is_err() && policy == ErrorPolicy::Leftmost;
+                seen.push(operand);
+                match rest.next() {
+                    Some(next) if !stop => self.wait(
+                        Frame::Operands {
+                            target,
+                            rest,
+                            seen,
+                            depth,
+                            charged,
+                        },
+                        next,
+                        depth,
+                    ),
+                    _ => self.finish(target, seen, charged),
+                }
             }
+        }
+    }
+
+    fn resume_logical(
+        &mut self,
+        op: Op,
+        mut rest: std::vec::IntoIter<Expression>,
+        seen: Vec<EvalResult>,
+        depth: usize,
+    ) {
+        if let Some(next) = rest.next() {
+            return self.wait(
+                Frame::Logical {
+                    op,
+                    rest,
+                    seen,
+                    depth,
+                },
+                next,
+                depth,
+            );
+        }
+        let decisive = op == Op::Or;
+        let checked = seen.into_iter().map(|result| match result? {
+            Value::Bool(_) => Ok(()),
+            other => Err(Error::InvalidTypeForOperator(other.kind(), op.clone())),
+        });
+        let result = self.ctx.error_policy.collect(checked);
+        self.results.push(result.map(|_| Value::Bool(!decisive)));
+    }
+
+    /// Combine the results of every operand that was evaluated. Under `ErrorPolicy::Leftmost`,
+    /// evaluation stops at the first error, so `results` may be cut short.
+    ///
+    /// `charged` bytes are given back, since a list or map is charged for in full once it's built.
+    fn finish(&mut self, target: Target, results: Vec<EvalResult>, charged: usize) {
+        *self.ctx.bytes_processed.lock().unwrap() -= charged;
+        let policy = self.ctx.error_policy;
+        let result = match target {
+            Target::List => policy.collect(results).map(Value::List),
+            Target::Map => make_map(results, policy),
+            Target::Method(name) => policy.collect(results).and_then(|mut args| {
+                let operand = args.remove(0);
+                self.ctx.call_method(name, operand, args)
+            }),
+            Target::Function(name) => policy
+                .collect(results)
+                .and_then(|args| self.ctx.call_function(name, args)),
         };
+        self.results.push(result);
+    }
 
-        if let Ok(ref value) = result {
-            *self.bytes_processed.lock().unwrap() += value.size();
-        }
-        result
-    }
-
-    fn evaluate_literal(&self, lit: Literal) -> EvalResult {
-        match lit {
-            Literal::Null => Ok(Value::Null),
-            Literal::String(v) => Ok(Value::String(v)),
-            Literal::Bytes(v) => Ok(Value::Bytes(v)),
-            Literal::Bool(v) => Ok(Value::Bool(v)),
-            Literal::I64(v) => Ok(Value::I64(v)),
-            Literal::F64(v) => Ok(Value::F64(v)),
-            Literal::List(elems) => {
-                let vs = elems
-                    .into_iter()
-                    .map(|e| self.evaluate(e))
-                    .collect::<Result<Vec<_>, _>>()?;
-                Ok(Value::List(vs))
-            }
-            Literal::Map(kvs) => {
-                let mut m = HashMap::new();
-                for (k, v) in kvs {
-                    let k = self.evaluate(k)?;
-                    let v = self.evaluate(v)?;
-                    match k {
-                        Value::String(k) => {
-                            if m.insert(k.clone(), v).is_some() {
-                                return Err(Error::DuplicateMapKey(k));
-                            }
-                        }
-                        other => return Err(Error::InvalidMapKey(other.kind())),
+    fn lookup(&self, name: Identifier) -> EvalResult {
+        let local = self.scopes.iter().rev().find(|(id, _)| *id == name);
+        match local.map(|(_, result)| result.clone()) {
+            Some(result) => result,
+            None => match self.ctx.lookup_binding(&name) {
+                Some(result) => result,
+                None => {
+                    let local = self.scopes.iter().rev().map(|(id, _)| id.0.as_str());
+                    let names = local.chain(self.ctx.binding_names());
+                    let suggestions = suggest::similar(&name.0, names);
+                    Err(Error::NoSuchBinding(name, suggestions))
+                }
+            },
+        }
+    }
+}
+
+fn scalar(lit: Literal) -> Value {
+    match lit {
+        Literal::Null => Value::Null,
+        Literal::String(v) => Value::String(v.into()),
+        Literal::Bytes(v) => Value::Bytes(v.into()),
+        Literal::Bool(v) => Value::Bool(v),
+        Literal::I64(v) => Value::I64(v),
+        Literal::F64(v) => Value::F64(v),
+        Literal::List(_) | Literal::Map(_) => unreachable!("evaluated as operands"),
+    }
+}
+
+/// Build a map from alternating keys and values, reporting problems in source order.
+fn make_map(results: Vec<EvalResult>, policy: ErrorPolicy) -> EvalResult {
+    let mut m = HashMap::new();
+    let mut results = results.into_iter();
+    let entries = std::iter::from_fn(|| {
+        let k = results.next()?;
+        // Only a failed key can be missing its value.
+        let v = results.next().unwrap_or(Ok(Value::Null));
+        Some(policy.collect(vec![k, v]).and_then(|mut kv| {
+            let v = kv.pop().expect("value");
+            match kv.pop().expect("key") {
+                Value::String(k) => {
+                    let k = Rc::unwrap_or_clone(k);
+                    if m.insert(k.clone(), v).is_some() {
+                        return Err(Error::DuplicateMapKey(k));
                     }
+                    Ok(())
                 }
-                Ok(Value::Map(m))
+                other => Err(Error::InvalidMapKey(other.kind())),
             }
+        }))
+    });
+    policy.collect(entries)?;
+    Ok(Value::Map(m))
+}
+
+/// Attribute `error` to `span`, unless it already came from somewhere more specific.
+fn locate(span: Span, error: Error) -> Error {
+    match error {
+        Error::At(..) => error,
+        Error::Multiple(errors) => {
+            Error::Multiple(errors.into_iter().map(|e| locate(span, e)).collect())
         }
+        error => Error::At(span, Box::new(error)),
+    }
+}
+
+pub(crate) fn is_binary(expr: &Expression) -> bool {
+    if let Expression::Spanned(_, e) = expr {
+        return is_binary(e);
     }
+    matches!(
+        expr,
+        Expression::Eq(..)
+            | Expression::Neq(..)
+            | Expression::Lt(..)
+            | Expression::Lte(..)
+            | Expression::Gte(..)
+            | Expression::Gt(..)
+            | Expression::Add(..)
+            | Expression::Sub(..)
+            | Expression::Mul(..)
+            | Expression::Div(..)
+            | Expression::Mod(..)
+            | Expression::OptionalIndex(..)
+    )
+}
+
+type BinaryOp = fn(Value, Value) -> EvalResult;
+
+/// If `expr` is a binary operator, take its operands (leaving a placeholder behind).
+fn split_binary(expr: &mut Expression) -> Option<(Expression, BinaryOp, Expression)> {
+    let op: BinaryOp = match expr {
+        Expression::Eq(..) => eq,
+        Expression::Neq(..) => neq,
+        Expression::Lt(..) => lt,
+        Expression::Lte(..) => lte,
+        Expression::Gte(..) => gte,
+        Expression::Gt(..) => gt,
+        Expression::Add(..) => add,
+        Expression::Sub(..) => sub,
+        Expression::Mul(..) => mul,
+        Expression::Div(..) => div,
+        Expression::Mod(..) => rem,
+        Expression::OptionalIndex(..) => optional_index,
+        _ => return None,
+    };
+    match expr {
+        Expression::Eq(a, b)
+        | Expression::Neq(a, b)
+        | Expression::Lt(a, b)
+        | Expression::Lte(a, b)
+        | Expression::Gte(a, b)
+        | Expression::Gt(a, b)
+        | Expression::Add(a, b)
+        | Expression::Sub(a, b)
+        | Expression::Mul(a, b)
+        | Expression::Div(a, b)
+        | Expression::Mod(a, b)
+        | Expression::OptionalIndex(a, b) => Some((a.take(), op, b.take())),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn eq(a: Value, b: Value) -> EvalResult {
+    Ok(Value::Bool(a == b))
+}
+
+pub(crate) fn neq(a: Value, b: Value) -> EvalResult {
+    Ok(Value::Bool(a != b))
+}
+
+pub(crate) fn neg(v: Value) -> EvalResult {
+    match v {
+        Value::I64(x) => Ok(Value::I64(x.checked_neg().ok_or(Error::IntegerOverflow)?)),
+        Value::F64(x) => Ok(Value::F64(-x)),
+        Value::Duration(x) => Ok(Value::Duration(
+            x.checked_neg().ok_or(Error::DurationOutOfRange)?,
+        )),
+        other => Err(Error::InvalidTypeForOperator(other.kind(), Op::Neg)),
+    }
+}
+
+pub(crate) fn not(v: Value) -> EvalResult {
+    match v {
+        Value::Bool(x) => Ok(Value::Bool(!x)),
+        other => Err(Error::InvalidTypeForOperator(other.kind(), Op::Not)),
+    }
+}
 
-    fn lookup_binding(&self, name: Identifier) -> EvalResult {
-        if let Some((ref id, ref value)) = self.binding {
-            if *id == name {
-                return value.clone();
+pub(crate) fn member(v: Value, name: Identifier) -> EvalResult {
+    match v {
+        Value::Map(mut fields) => Ok(fields.remove(&name.0).ok_or(Error::NoSuchMember(name))?),
+        other => Err(Error::InvalidTypeForOperator(
+            other.kind(),
+            Op::Member(name),
+        )),
+    }
+}
+
+/// `v?.name`, which is null where `v.name` would fail for want of a map or a member.
+pub(crate) fn safe_member(v: Value, name: Identifier) -> EvalResult {
+    match v {
+        Value::Null => Ok(Value::Null),
+        Value::Map(mut fields) => Ok(fields.remove(&name.0).unwrap_or(Value::Null)),
+        other => Err(Error::InvalidTypeForOperator(
+            other.kind(),
+            Op::SafeMember(name),
+        )),
+    }
+}
+
+/// `a[?k]`: an optional holding the element of a list or the entry of a map, if there is one.
+pub(crate) fn optional_index(a: Value, k: Value) -> EvalResult {
+    let found = match (a, k) {
+        (Value::Optional(None), _) => None,
+        (Value::Optional(Some(a)), k) => return optional_index(*a, k),
+        (Value::Map(mut fields), Value::String(k)) => fields.remove(k.as_str()),
+        (Value::List(mut elems), Value::I64(i)) => usize::try_from(i)
+            .ok()
+            .filter(|&i| i < elems.len())
+            .map(|i| elems.swap_remove(i)),
+        (Value::List(mut elems), Value::U64(i)) => usize::try_from(i)
+            .ok()
+            .filter(|&i| i < elems.len())
+            .map(|i| elems.swap_remove(i)),
+        (a, k) => {
+            return Err(Error::InvalidTypesForOperator(
+                a.kind(),
+                k.kind(),
+                Op::OptionalIndex,
+            ))
+        }
+    };
+    Ok(Value::Optional(found.map(Box::new)))
+}
+
+pub(crate) fn lt(a: Value, b: Value) -> EvalResult {
+    match a.partial_cmp(&b) {
+        None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Lt)),
+        Some(ord) => Ok(Value::Bool(ord == Ordering::Less)),
+    }
+}
+
+pub(crate) fn lte(a: Value, b: Value) -> EvalResult {
+    match a.partial_cmp(&b) {
+        None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Lte)),
+        Some(ord) => Ok(Value::Bool(ord == Ordering::Less || a == b)),
+    }
+}
+
+pub(crate) fn gte(a: Value, b: Value) -> EvalResult {
+    match a.partial_cmp(&b) {
+        None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Gte)),
+        Some(ord) => Ok(Value::Bool(ord == Ordering::Greater || a == b)),
+    }
+}
+
+pub(crate) fn gt(a: Value, b: Value) -> EvalResult {
+    match a.partial_cmp(&b) {
+        None => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Gt)),
+        Some(ord) => Ok(Value::Bool(ord == Ordering::Greater)),
+    }
+}
+
+pub(crate) fn add(a: Value, b: Value) -> EvalResult {
+    match (a, b) {
+        (Value::I64(a), Value::I64(b)) => {
+            Ok(Value::I64(a.checked_add(b).ok_or(Error::IntegerOverflow)?))
+        }
+        (Value::F64(a), Value::F64(b)) => Ok(Value::F64(a + b)),
+        (Value::String(a), Value::String(b)) => {
+            let mut s = Rc::unwrap_or_clone(a);
+            s.push_str(&b);
+            Ok(Value::String(s.into()))
+        }
+        (Value::Bytes(a), Value::Bytes(b)) => {
+            let mut bytes = Rc::unwrap_or_clone(a);
+            bytes.extend_from_slice(&b);
+            Ok(Value::Bytes(bytes.into()))
+        }
+        (Value::List(a), Value::List(b)) => Ok(Value::List(a.into_iter().chain(b).collect())),
+        (Value::Timestamp(a), Value::Duration(b)) | (Value::Duration(b), Value::Timestamp(a)) => {
+            Ok(Value::Timestamp(time::timestamp_from_nanos(
+                time::timestamp_to_nanos(a) + i128::from(b),
+            )?))
+        }
+        (Value::Duration(a), Value::Duration(b)) => Ok(Value::Duration(
+            a.checked_add(b).ok_or(Error::DurationOutOfRange)?,
+        )),
+        (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Plus)),
+    }
+}
+
+pub(crate) fn sub(a: Value, b: Value) -> EvalResult {
+    match (a, b) {
+        (Value::I64(a), Value::I64(b)) => {
+            Ok(Value::I64(a.checked_sub(b).ok_or(Error::IntegerOverflow)?))
+        }
+        (Value::Timestamp(a), Value::Timestamp(b)) => Ok(Value::Duration(
+            i64::try_from(time::timestamp_to_nanos(a) - time::timestamp_to_nanos(b))
+                .map_err(|_| Error::DurationOutOfRange)?,
+        )),
+        (Value::Timestamp(a), Value::Duration(b)) => Ok(Value::Timestamp(
+            time::timestamp_from_nanos(time::timestamp_to_nanos(a) - i128::from(b))?,
+        )),
+        (Value::Duration(a), Value::Duration(b)) => Ok(Value::Duration(
+            a.checked_sub(b).ok_or(Error::DurationOutOfRange)?,
+        )),
+        (a, b) => Err(Error::InvalidTypesForOperator(
+            a.kind(),
+            b.kind(),
+            Op::Minus,
+        )),
+    }
+}
+
+pub(crate) fn mul(a: Value, b: Value) -> EvalResult {
+    match (a, b) {
+        (Value::I64(a), Value::I64(b)) => {
+            Ok(Value::I64(a.checked_mul(b).ok_or(Error::IntegerOverflow)?))
+        }
+        (Value::F64(a), Value::F64(b)) => Ok(Value::F64(a * b)),
+        (a, b) => Err(Error::InvalidTypesForOperator(
+            a.kind(),
+            b.kind(),
+            Op::Times,
+        )),
+    }
+}
+
+pub(crate) fn div(a: Value, b: Value) -> EvalResult {
+    match (a, b) {
+        (Value::I64(a), Value::I64(b)) => {
+            if b != 0 {
+                Ok(Value::I64(a.checked_div(b).ok_or(Error::IntegerOverflow)?))
+            } else {
+                Err(Error::DivisionByZero)
+            }
+        }
+        (Value::F64(a), Value::F64(b)) => {
+            if b != 0.0 {
+                Ok(Value::F64(a / b))
+            } else {
+                Err(Error::DivisionByZero)
             }
         }
-        if let Some(parent) = self.parent {
-            return parent.lookup_binding(name);
+        (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Div)),
+    }
+}
+
+pub(crate) fn rem(a: Value, b: Value) -> EvalResult {
+    match (a, b) {
+        (Value::I64(a), Value::I64(b)) => {
+            if b != 0 {
+                Ok(Value::I64(a.checked_rem(b).ok_or(Error::IntegerOverflow)?))
+            } else {
+                Err(Error::DivisionByZero)
+            }
         }
-        Err(Error::NoSuchBinding(name))
+        (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Mod)),
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::model::{Error, EvalResult, Identifier, Kind, Op, Value};
-    use crate::parser::parse;
+    use super::{CustomFunction, EvalContext, EvalLimits, UnknownMethods, BYTES_PROCESSED_LIMIT};
+    use crate::model::{
+        Error, ErrorPolicy, EvalResult, Expression, Identifier, Kind, Limit, LimitExceeded,
+        Literal, Op, Signature, Span, Value,
+    };
+    use crate::parser::{parse, parse_with_spans};
+    use std::borrow::Cow;
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     fn evaluate(input: &str) -> EvalResult {
         super::EvalContext::default().evaluate(parse(input).expect("parse"))
@@ -374,6 +1196,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn float_powf() {
         let input = r#" 3.1415926.pow(3.1415926) "#;
         assert_eq!(
@@ -383,6 +1206,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn float_powi() {
         let input = r#" 3.1415926.pow(2) "#;
         assert_eq!(evaluate(input), Ok(Value::F64(3.1415926f64.powf(2.0))));
@@ -406,8 +1230,8 @@ mod test {
         assert_eq!(
             evaluate(input),
             Ok(Value::List(vec![
-                Value::String("a".to_owned()),
-                Value::String("b".to_owned())
+                Value::String("a".to_owned().into()),
+                Value::String("b".to_owned().into())
             ]))
         );
     }
@@ -474,6 +1298,102 @@ mod test {
         assert_eq!(evaluate(input), Err(Error::DivisionByZero));
     }
 
+    #[test]
+    fn modulo() {
+        assert_eq!(evaluate(r#" 7 % 3 "#), Ok(Value::I64(1)));
+        assert_eq!(evaluate(r#" -7 % 3 "#), Ok(Value::I64(-1)));
+        assert_eq!(evaluate(r#" 2 * 7 % 4 "#), Ok(Value::I64(2)));
+    }
+
+    #[test]
+    fn eval_error_modulo_by_zero() {
+        assert_eq!(evaluate(r#" 1 % 0 "#), Err(Error::DivisionByZero));
+    }
+
+    #[test]
+    fn leftmost_error_wins() {
+        assert_eq!(
+            evaluate(r#" (1 / 0) + ("a" + 1) "#),
+            Err(Error::DivisionByZero)
+        );
+        assert_eq!(
+            evaluate(r#" ("a" + 1) + (1 / 0) "#),
+            Err(Error::InvalidTypesForOperator(
+                Kind::String,
+                Kind::I64,
+                Op::Plus
+            ))
+        );
+        assert_eq!(
+            evaluate(r#" [1 % 0, 1 + "a"] "#),
+            Err(Error::DivisionByZero)
+        );
+        assert_eq!(
+            evaluate(r#" false || 1 / 0 || 1 % 0 "#),
+            Err(Error::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn errors_have_spans() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse_with_spans(input).unwrap());
+        let at = |start, end, e| Err(Error::At(Span { start, end }, Box::new(e)));
+        assert_eq!(eval("1 + 2 / 0"), at(4, 9, Error::DivisionByZero));
+        assert_eq!(
+            eval(r#"[1, "a"] + x.y"#),
+            at(11, 12, evaluate("x").unwrap_err())
+        );
+        assert_eq!(
+            eval(r#"{"a": 1}.b"#),
+            at(0, 10, Error::NoSuchMember(Identifier::new("b")))
+        );
+        assert_eq!(eval("(1 + 2) * 3"), Ok(Value::I64(9)));
+    }
+
+    #[test]
+    fn merged_errors() {
+        let mut ctx = EvalContext::default();
+        ctx.set_error_policy(ErrorPolicy::Merged);
+        let eval = |input: &str| ctx.evaluate(parse(input).expect("parse"));
+        assert_eq!(
+            eval(r#" 1/0 == (true + 1) "#),
+            Err(Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::InvalidTypesForOperator(Kind::Bool, Kind::I64, Op::Plus),
+            ]))
+        );
+        assert_eq!(
+            eval(r#" [1 % 0, 1, {"a": 1, 2: 3, "a": 4}] "#),
+            Err(Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::InvalidMapKey(Kind::I64),
+                Error::DuplicateMapKey("a".to_owned()),
+            ]))
+        );
+        assert_eq!(eval(r#" 1 + (2 / 0) "#), Err(Error::DivisionByZero));
+        assert_eq!(eval(r#" true || 1 / 0 || 1 % 0 "#), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval(r#" 1 / 0 && "a" "#),
+            Err(Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::InvalidTypeForOperator(Kind::String, Op::And),
+            ]))
+        );
+    }
+
+    #[test]
+    fn map_entries_in_source_order() {
+        assert_eq!(
+            evaluate(r#" {"a": 1, 2: 3, "a": 4} "#),
+            Err(Error::InvalidMapKey(Kind::I64))
+        );
+        assert_eq!(
+            evaluate(r#" {"a": 1, "a": 2, 3: 4} "#),
+            Err(Error::DuplicateMapKey("a".to_owned()))
+        );
+        assert_eq!(evaluate(r#" {1: 1 / 0} "#), Err(Error::DivisionByZero));
+    }
+
     #[test]
     fn or_true_with_error() {
         let input = r#" true || 1 / 0 "#;
@@ -539,7 +1459,7 @@ mod test {
         let input = r#" { foo: "bar" }.len() "#;
         assert_eq!(
             evaluate(input),
-            Err(Error::NoSuchBinding(Identifier::new("foo")))
+            Err(Error::NoSuchBinding(Identifier::new("foo"), vec![]))
         );
     }
 
@@ -588,6 +1508,43 @@ mod test {
         );
     }
 
+    #[test]
+    fn safe_member() {
+        let ctx = EvalContext::default();
+        let ctx = ctx.with_binding(
+            Identifier::new("user"),
+            evaluate(r#" {"name": "bob", "address": {"city": null}} "#),
+        );
+        let eval = |input: &str| ctx.evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" user?.name "#), evaluate(r#" "bob" "#));
+        assert_eq!(eval(r#" user?.email "#), Ok(Value::Null));
+        assert_eq!(eval(r#" user?.address?.city?.zip "#), Ok(Value::Null));
+        assert_eq!(eval(r#" user?.phone?.home?.number "#), Ok(Value::Null));
+        assert_eq!(eval(r#" user.address?.city "#), Ok(Value::Null));
+        // Each `?.` guards only its own step.
+        assert_eq!(
+            eval(r#" user?.phone.home "#),
+            Err(Error::InvalidTypeForOperator(
+                Kind::Null,
+                Op::Member(Identifier::new("home"))
+            ))
+        );
+        // It doesn't hide values of the wrong kind.
+        assert_eq!(
+            eval(r#" user?.name?.first "#),
+            Err(Error::InvalidTypeForOperator(
+                Kind::String,
+                Op::SafeMember(Identifier::new("first"))
+            ))
+        );
+        assert_eq!(
+            eval(r#" user?.email == null ? "none" : user.email "#),
+            evaluate(r#" "none" "#)
+        );
+        // A ternary still needs no spaces.
+        assert_eq!(eval(r#" true?{"a": 1}.a:2 "#), Ok(Value::I64(1)));
+    }
+
     #[test]
     fn let_binding_smoke() {
         let input = r#" let x = 42; x "#;
@@ -644,6 +1601,523 @@ mod test {
         assert_eq!(evaluate(input), evaluate(r#" { "a": "foo" } "#));
     }
 
+    #[test]
+    fn timestamp_minus_timestamp() {
+        let input = r#" timestamp("2024-01-01T01:30:00Z") - timestamp("2024-01-01T00:00:00Z") "#;
+        assert_eq!(evaluate(input), evaluate(r#" duration("1h30m") "#));
+    }
+
+    #[test]
+    fn timestamp_plus_duration() {
+        let input = r#" timestamp("2024-01-01T00:00:00Z") + duration("36h") "#;
+        assert_eq!(
+            evaluate(input),
+            evaluate(r#" timestamp("2024-01-02T12:00:00Z") "#)
+        );
+        let input = r#" duration("36h") + timestamp("2024-01-01T00:00:00Z") "#;
+        assert_eq!(
+            evaluate(input),
+            evaluate(r#" timestamp("2024-01-02T12:00:00Z") "#)
+        );
+    }
+
+    #[test]
+    fn timestamp_minus_duration() {
+        let input = r#" timestamp("2024-01-01T00:00:00Z") - duration("1s") "#;
+        assert_eq!(
+            evaluate(input),
+            evaluate(r#" timestamp("2023-12-31T23:59:59Z") "#)
+        );
+    }
+
+    #[test]
+    fn duration_arithmetic() {
+        let input = r#" duration("1h") - duration("30m") + -duration("15m") "#;
+        assert_eq!(evaluate(input), evaluate(r#" duration("15m") "#));
+    }
+
+    #[test]
+    fn timestamp_comparison() {
+        let input =
+            r#" timestamp("2024-01-01T00:00:00Z") < timestamp("2024-01-01T00:00:00.001Z") "#;
+        assert_eq!(evaluate(input), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn timestamp_full_range() {
+        let input = r#" timestamp("1600-01-01T00:00:00Z") + duration("24h") "#;
+        assert_eq!(
+            evaluate(input),
+            evaluate(r#" timestamp("1600-01-02T00:00:00Z") "#)
+        );
+        let input = r#" string(timestamp("9999-12-31T23:59:59Z")) "#;
+        assert_eq!(
+            evaluate(input),
+            Ok(Value::String("9999-12-31T23:59:59Z".to_owned().into()))
+        );
+        let input = r#" timestamp("9999-12-31T23:59:59Z") + duration("1s") "#;
+        assert_eq!(evaluate(input), Err(Error::TimestampOutOfRange));
+        let input = r#" timestamp("9999-12-31T23:59:59Z") - timestamp("0001-01-01T00:00:00Z") "#;
+        assert_eq!(evaluate(input), Err(Error::DurationOutOfRange));
+    }
+
+    #[test]
+    fn timestamp_plus_timestamp() {
+        let input = r#" timestamp("2024-01-01T00:00:00Z") + timestamp("2024-01-01T00:00:00Z") "#;
+        assert_eq!(
+            evaluate(input),
+            Err(Error::InvalidTypesForOperator(
+                Kind::Timestamp,
+                Kind::Timestamp,
+                Op::Plus,
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_timestamp() {
+        let input = r#" timestamp("yesterday") "#;
+        assert_eq!(
+            evaluate(input),
+            Err(Error::InvalidTimestamp("yesterday".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unknown_function() {
+        let input = r#" frobnicate(1) "#;
+        assert_eq!(
+            evaluate(input),
+            Err(Error::NoFunction(Identifier::new("frobnicate"), vec![]))
+        );
+    }
+
+    #[test]
+    fn unknown_function_suggestions() {
+        assert_eq!(
+            evaluate(r#" timestmp("2024-01-01T00:00:00Z") "#),
+            Err(Error::NoFunction(
+                Identifier::new("timestmp"),
+                vec![Identifier::new("timestamp")]
+            ))
+        );
+        let mut ctx = EvalContext::default();
+        ctx.register_function(shout());
+        assert_eq!(
+            ctx.evaluate(parse(r#" shoot("a") "#).expect("parse")),
+            Err(Error::NoFunction(
+                Identifier::new("shoot"),
+                vec![Identifier::new("shout")]
+            ))
+        );
+    }
+
+    #[test]
+    fn unknown_binding_suggestions() {
+        let input = r#" let request = 1; let response = 2; reqest + 1 "#;
+        assert_eq!(
+            evaluate(input),
+            Err(Error::NoSuchBinding(
+                Identifier::new("reqest"),
+                vec![Identifier::new("request")]
+            ))
+        );
+    }
+
+    #[test]
+    fn json_document() {
+        use crate::validation::BindingError;
+        use serde_json::json;
+
+        let doc = json!({"user": {"name": "ada", "age": 36}, "limit": 40, "admin": false});
+        let ctx = EvalContext::from_json(&doc).unwrap();
+        let expr = parse(r#" !admin && user.age < limit ? user.name : "no" "#).unwrap();
+        assert_eq!(
+            ctx.evaluate(expr),
+            Ok(Value::String("ada".to_owned().into()))
+        );
+        // Bindings shadow the document's fields.
+        let ctx = ctx.with_binding(Identifier::new("limit"), Ok(Value::I64(30)));
+        assert_eq!(
+            ctx.evaluate(parse(r#" user.age < limit "#).unwrap()),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            ctx.evaluate(parse(r#" limt "#).unwrap()),
+            Err(Error::NoSuchBinding(
+                Identifier::new("limt"),
+                vec![Identifier::new("limit")]
+            ))
+        );
+
+        assert_eq!(
+            EvalContext::from_json(&json!([1])).err(),
+            Some(BindingError::NotAnObject(Kind::List))
+        );
+        let deep = (0..100).fold(json!(1), |v, _| json!([v]));
+        assert_eq!(
+            EvalContext::from_json(&json!({ "deep": deep })).err(),
+            Some(BindingError::TooDeep(format!("deep{}", "[0]".repeat(64))))
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(evaluate(r#" int("42") + 1 "#), Ok(Value::I64(43)));
+        assert_eq!(evaluate(r#" uint(42) "#), Ok(Value::U64(42)));
+        assert_eq!(evaluate(r#" double(1) / 2.0 "#), Ok(Value::F64(0.5)));
+        assert_eq!(
+            evaluate(r#" string(3.14) "#),
+            Ok(Value::String("3.14".to_owned().into()))
+        );
+        assert_eq!(
+            evaluate(r#" bytes("abc") "#),
+            Ok(Value::Bytes(b"abc".to_vec().into()))
+        );
+        assert_eq!(evaluate(r#" bool("true") "#), Ok(Value::Bool(true)));
+        assert_eq!(
+            evaluate(r#" string(timestamp("2024-01-01T00:00:00Z") + duration("90m")) "#),
+            Ok(Value::String("2024-01-01T01:30:00Z".to_owned().into()))
+        );
+        assert_eq!(
+            evaluate(r#" string(duration("1.5s")) "#),
+            Ok(Value::String("1.5s".to_owned().into()))
+        );
+        assert_eq!(
+            evaluate(r#" string(1e300) "#),
+            Ok(Value::String("1e+300".to_owned().into()))
+        );
+    }
+
+    #[test]
+    fn conversion_errors() {
+        assert_eq!(
+            evaluate(r#" int("forty-two") "#),
+            Err(Error::InvalidConversion(Kind::I64, "forty-two".to_owned()))
+        );
+        assert_eq!(
+            evaluate(r#" uint(-1) "#),
+            Err(Error::ConversionOutOfRange(Kind::I64, Kind::U64))
+        );
+        assert_eq!(
+            evaluate(r#" int(1, 2) "#),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new("int"),
+                vec![Kind::I64, Kind::I64]
+            ))
+        );
+    }
+
+    fn shout() -> CustomFunction {
+        CustomFunction {
+            signature: Signature {
+                name: Cow::Borrowed("shout"),
+                operand: None,
+                args: Cow::Borrowed(&[Some(Kind::String)]),
+                result: Kind::String,
+                doc: Cow::Borrowed("Append an exclamation point."),
+                example: Cow::Borrowed("shout(\"hello\")"),
+            },
+            implementation: Rc::new(|args| match args.as_slice() {
+                [Value::String(s)] => Ok(Value::String(format!("{}!", s).into())),
+                _ => Err(Error::NoFunctionWithSignature(
+                    Identifier::new("shout"),
+                    args.iter().map(Value::kind).collect(),
+                )),
+            }),
+        }
+    }
+
+    #[test]
+    fn custom_function() {
+        let mut ctx = EvalContext::default();
+        ctx.register_function(shout());
+        let expr = parse(r#" let x = "hi"; shout(x) "#).expect("parse");
+        assert_eq!(
+            ctx.evaluate(expr),
+            Ok(Value::String("hi!".to_owned().into()))
+        );
+    }
+
+    #[test]
+    fn custom_function_signatures() {
+        let mut ctx = EvalContext::default();
+        ctx.register_function(shout());
+        let signatures = ctx.signatures();
+        assert_eq!(signatures.last(), Some(&shout().signature));
+        assert_eq!(signatures.len(), crate::signatures().len() + 1);
+    }
+
+    #[test]
+    fn unknown_methods() {
+        let expr = || parse(r#" "hi".shout() + "hi".len() "#).unwrap();
+        let mut ctx = EvalContext::default();
+        ctx.register_function(shout());
+        assert!(matches!(
+            ctx.evaluate(expr()),
+            Err(Error::NoMethod(ref name, _)) if name.0 == "shout"
+        ));
+        ctx.set_unknown_methods(UnknownMethods::Functions);
+        assert_eq!(
+            ctx.evaluate(parse(r#" "hi".shout() "#).unwrap()),
+            Ok(Value::String("hi!".to_owned().into()))
+        );
+        assert!(matches!(
+            ctx.evaluate(parse(r#" "hi".whisper() "#).unwrap()),
+            Err(Error::NoMethod(..))
+        ));
+        // Built-in methods can't be overridden.
+        ctx.set_unknown_methods(UnknownMethods::Handler(Rc::new(|name, operand, args| {
+            Ok(Value::String(
+                format!("{:?}.{}({})", operand.kind(), name.0, args.len()).into(),
+            ))
+        })));
+        assert_eq!(
+            ctx.evaluate(parse(r#" "hi".whisper(1, 2) + string("hi".len()) "#).unwrap()),
+            Ok(Value::String("String.whisper(2)2".to_owned().into()))
+        );
+    }
+
+    #[test]
+    fn thread_defaults() {
+        super::register_default_function(shout());
+        super::set_default_limits(EvalLimits {
+            max_depth: 3,
+            ..EvalLimits::default()
+        });
+        let expr = || parse(r#" shout("hi") "#).unwrap();
+        assert_eq!(
+            EvalContext::default().evaluate(expr()),
+            Ok(Value::String("hi!".to_owned().into()))
+        );
+        assert_eq!(EvalContext::default().limits().max_depth, 3);
+        // Per-context configuration overrides the defaults.
+        let ctx = EvalContext::with_limits(EvalLimits::default());
+        assert_eq!(ctx.limits(), EvalLimits::default());
+        assert_eq!(
+            ctx.evaluate(expr()),
+            Ok(Value::String("hi!".to_owned().into()))
+        );
+        // Other threads are unaffected.
+        let elsewhere = std::thread::spawn(|| EvalContext::default().limits()).join();
+        assert_eq!(elsewhere.unwrap(), EvalLimits::default());
+        super::reset_defaults();
+        assert!(EvalContext::default().evaluate(expr()).is_err());
+    }
+
+    #[test]
+    fn signature_examples_evaluate() {
+        for sig in crate::signatures() {
+            let result = evaluate(&sig.example);
+            assert!(result.is_ok(), "{} evaluated to {:?}", sig.example, result);
+        }
+    }
+
+    #[test]
+    fn integer_overflow() {
+        assert_eq!(
+            evaluate(r#" 9223372036854775807 + 1 "#),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(
+            evaluate(r#" -9223372036854775807 - 2 "#),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(
+            evaluate(r#" 4611686018427387904 * 2 "#),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(
+            evaluate(r#" let min = -9223372036854775807 - 1; -min "#),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(
+            evaluate(r#" (-9223372036854775807 - 1) / -1 "#),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(evaluate(r#" 2.pow(63) "#), Err(Error::IntegerOverflow));
+        assert_eq!(evaluate(r#" 2.pow(-1) "#), Err(Error::NegativeExponent));
+        assert_eq!(evaluate(r#" 2.0.pow(-1) "#), Ok(Value::F64(0.5)));
+    }
+
+    #[test]
+    fn integer_limits() {
+        assert_eq!(
+            evaluate(r#" 9223372036854775806 + 1 "#),
+            Ok(Value::I64(i64::MAX))
+        );
+        assert_eq!(
+            evaluate(r#" -2.pow(62) - 2.pow(62) "#),
+            Ok(Value::I64(i64::MIN))
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let input = format!("{}1{}", "[(".repeat(40), ")]".repeat(40));
+        assert!(evaluate(&input).is_ok());
+    }
+
+    #[test]
+    fn long_operator_chain() {
+        let input = format!("0{}", " + 1".repeat(5000));
+        assert_eq!(evaluate(&input), Ok(Value::I64(5000)));
+        let input = format!("1{}", " * 2 / 2 == 1 == true".repeat(1000));
+        assert_eq!(evaluate(&input), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn long_operator_chain_errors() {
+        let mut ctx = EvalContext::default();
+        let input = format!("1 / 0{}", " + 1 % 0".repeat(3));
+        assert_eq!(
+            ctx.evaluate(parse(&input).unwrap()),
+            Err(Error::DivisionByZero)
+        );
+        ctx.set_error_policy(ErrorPolicy::Merged);
+        assert_eq!(
+            ctx.evaluate(parse(&input).unwrap()),
+            Err(Error::Multiple(vec![Error::DivisionByZero; 4]))
+        );
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let input = format!("{}1", "-".repeat(5000));
+        assert_eq!(evaluate(&input), Err(Error::RecursionLimitExceeded));
+    }
+
+    #[test]
+    fn no_native_recursion() {
+        // Every one of the intermediate values counts against the byte limit.
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_depth: usize::MAX,
+            max_bytes: usize::MAX,
+            ..EvalLimits::default()
+        });
+        let mut expr = Expression::Lit(Literal::I64(1));
+        for _ in 0..100_000 {
+            expr = Expression::Neg(Box::new(expr));
+        }
+        assert_eq!(ctx.evaluate(expr), Ok(Value::I64(1)));
+    }
+
+    #[test]
+    fn operation_limit() {
+        let limits = EvalLimits {
+            max_ops: 5,
+            ..EvalLimits::default()
+        };
+        let ctx = EvalContext::with_limits(limits);
+        // Each operator and each operand is one operation.
+        assert_eq!(ctx.evaluate(parse("1 + 2 + 3").unwrap()), Ok(Value::I64(6)));
+        let ctx = EvalContext::with_limits(limits);
+        assert_eq!(
+            ctx.evaluate(parse("[1, 2, 3, 4, 5]").unwrap()),
+            Err(Error::TooManyOperations)
+        );
+    }
+
+    #[test]
+    fn cancellation() {
+        let polls = Rc::new(Cell::new(0));
+        let mut ctx = EvalContext::default();
+        let counter = polls.clone();
+        ctx.set_cancellation(move || {
+            counter.set(counter.get() + 1);
+            counter.get() > 2
+        });
+        let input = format!("0{}", " + 1".repeat(1000));
+        assert_eq!(ctx.evaluate(parse(&input).unwrap()), Err(Error::Cancelled));
+        assert_eq!(polls.get(), 3);
+
+        let mut ctx = EvalContext::default();
+        ctx.set_cancellation(|| false);
+        assert_eq!(ctx.evaluate(parse(&input).unwrap()), Ok(Value::I64(1000)));
+    }
+
+    #[test]
+    fn configurable_byte_limit() {
+        let eval = |max_bytes: usize| {
+            let ctx = EvalContext::with_limits(EvalLimits {
+                max_bytes,
+                ..EvalLimits::default()
+            });
+            ctx.evaluate(parse(r#" "abc" + "def" + "ghi" "#).unwrap())
+        };
+        assert!(eval(1 << 10).is_ok());
+        // `"abc"` and `"def"` are 59 bytes each.
+        let exceeded = Error::EvaluationTooLarge(LimitExceeded {
+            limit: Limit::Bytes,
+            threshold: 100,
+            consumed: 118,
+        });
+        assert_eq!(eval(100), Err(exceeded.clone()));
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_bytes: 100,
+            ..EvalLimits::default()
+        });
+        let input = parse_with_spans(r#""abc" + "def" + "ghi""#).unwrap();
+        assert_eq!(
+            ctx.evaluate(input),
+            Err(Error::At(Span { start: 8, end: 13 }, Box::new(exceeded)))
+        );
+    }
+
+    #[test]
+    fn list_literal_limit() {
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_bytes: 20_000,
+            ..EvalLimits::default()
+        });
+        // Each element is 1056 bytes, charged once when it's looked up and once when it's added.
+        let list = format!("[{}]", vec!["s"; 100].join(", "));
+        let input = format!("let s = '{}'; {}", "x".repeat(1000), list);
+        let start = input.len() - list.len();
+        match ctx.evaluate(parse_with_spans(&input).unwrap()) {
+            Err(Error::At(span, e)) => {
+                // The list stops being built as soon as it's too large, rather than once it has
+                // every element.
+                assert_eq!(
+                    span,
+                    Span {
+                        start,
+                        end: input.len()
+                    }
+                );
+                assert!(matches!(
+                    *e,
+                    Error::EvaluationTooLarge(LimitExceeded { consumed, .. })
+                        if consumed < 20_000 + 2 * 1056
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+        // One that fits is charged for no more than before: the string, each lookup of it, then
+        // the list as the value of both itself and the `let`.
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_bytes: 1056 + 10 * 1056 + 2 * (56 + 10 * 1056),
+            ..EvalLimits::default()
+        });
+        let input = format!("let s = '{}'; [{}]", "x".repeat(1000), ["s"; 10].join(", "));
+        assert!(ctx.evaluate(parse(&input).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn configurable_depth_limit() {
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_depth: 10,
+            ..EvalLimits::default()
+        });
+        let nested = |n: usize| parse(&format!("{}1{}", "[".repeat(n), "]".repeat(n))).unwrap();
+        assert!(ctx.evaluate(nested(9)).is_ok());
+        assert_eq!(ctx.evaluate(nested(10)), Err(Error::RecursionLimitExceeded));
+        // The limit applies to nesting, not to the number of operators.
+        assert!(ctx
+            .evaluate(parse(&format!("1{}", " + 1".repeat(100))).unwrap())
+            .is_ok());
+    }
+
     #[test]
     fn value_size_explosion() {
         // 16 ** 8 == 2 ** 32 values, should _definitely_ overflow
@@ -659,6 +2133,13 @@ mod test {
         let x = x + x + x + x + x + x + x + x + x + x + x + x + x + x + x + x;
         x
         "#;
-        assert_eq!(evaluate(input), Err(Error::EvaluationTooLarge));
+        assert!(matches!(
+            evaluate(input),
+            Err(Error::EvaluationTooLarge(LimitExceeded {
+                limit: Limit::Bytes,
+                threshold: BYTES_PROCESSED_LIMIT,
+                ..
+            }))
+        ));
     }
 }
@@ -0,0 +1,151 @@
+//! Plain JSON representations of values, for embedders that exchange data as JSON rather than
+//! through the tagged serde representation of `Value`.
+//!
+//! `Value` converts from `serde_json::Value` with `from_json`, and back with `TryFrom`, which only
+//! fails for doubles that JSON can't represent. `to_json` is the lossy conversion for display.
+
+use crate::model::Value;
+use crate::time;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Numbers that fit in an `i64` become ints, others that fit in a `u64` uints, and all others
+/// doubles. Nothing becomes bytes, a timestamp, or a duration.
+pub fn from_json(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Value::I64(i),
+            (None, Some(u)) => Value::U64(u),
+            (None, None) => Value::F64(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::String(s.into()),
+        serde_json::Value::Array(vs) => Value::List(vs.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(fields) => {
+            Value::Map(fields.into_iter().map(|(k, v)| (k, from_json(v))).collect())
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Value {
+        from_json(value)
+    }
+}
+
+/// A value with no JSON representation: a double that is NaN or infinite.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct NotJson(pub f64);
+
+/// Bytes become base64 strings, and timestamps and durations their string forms, as in CEL's own
+/// JSON mapping. Doubles that aren't finite fail rather than becoming null.
+impl TryFrom<Value> for serde_json::Value {
+    type Error = NotJson;
+    fn try_from(value: Value) -> Result<serde_json::Value, NotJson> {
+        Ok(match value {
+            Value::F64(f) => serde_json::Number::from_f64(f)
+                .map(serde_json::Value::Number)
+                .ok_or(NotJson(f))?,
+            Value::Bytes(bs) => serde_json::Value::String(base64(&bs)),
+            Value::List(vs) => vs
+                .into_iter()
+                .map(serde_json::Value::try_from)
+                .collect::<Result<_, _>>()?,
+            Value::Map(fields) => serde_json::Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| Ok((k, serde_json::Value::try_from(v)?)))
+                    .collect::<Result<_, NotJson>>()?,
+            ),
+            other => to_json(other),
+        })
+    }
+}
+
+/// Standard, padded base64.
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Bytes become an array of numbers, and timestamps and durations their string forms. Doubles
+/// that JSON can't represent (NaN and the infinities) become null, as do empty optionals; others
+/// become the value they hold.
+pub fn to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(b),
+        Value::I64(i) => i.into(),
+        Value::U64(u) => u.into(),
+        Value::F64(f) => serde_json::Number::from_f64(f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::String(s) => serde_json::Value::String(Rc::unwrap_or_clone(s)),
+        Value::Bytes(bs) => bs.iter().copied().map(serde_json::Value::from).collect(),
+        Value::List(vs) => vs.into_iter().map(to_json).collect(),
+        Value::Map(fields) => {
+            serde_json::Value::Object(fields.into_iter().map(|(k, v)| (k, to_json(v))).collect())
+        }
+        Value::Timestamp(t) => serde_json::Value::String(time::format_timestamp(t)),
+        Value::Duration(d) => serde_json::Value::String(time::format_duration(d)),
+        Value::Optional(v) => v.map_or(serde_json::Value::Null, |v| to_json(*v)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let doc = json!({"a": [1, 2.5, "x", null, true], "b": {}});
+        assert_eq!(to_json(from_json(doc.clone())), doc);
+    }
+
+    #[test]
+    fn lossy_kinds() {
+        assert_eq!(
+            to_json(Value::Bytes(b"hi".to_vec().into())),
+            json!([104, 105])
+        );
+        assert_eq!(to_json(Value::F64(f64::NAN)), json!(null));
+        assert_eq!(to_json(Value::Duration(1_500_000_000)), json!("1.5s"));
+    }
+
+    #[test]
+    fn conversions() {
+        let doc = json!({"a": [1, 2.5, "x", null, true], "b": {"c": 18446744073709551615u64}});
+        let value = Value::from(doc.clone());
+        assert_eq!(serde_json::Value::try_from(value), Ok(doc));
+
+        let bytes = |bs: &[u8]| serde_json::Value::try_from(Value::Bytes(bs.to_vec().into()));
+        assert_eq!(bytes(b""), Ok(json!("")));
+        assert_eq!(bytes(b"h"), Ok(json!("aA==")));
+        assert_eq!(bytes(b"hi"), Ok(json!("aGk=")));
+        assert_eq!(bytes(b"hi?"), Ok(json!("aGk/")));
+        assert_eq!(bytes(&[0xfb, 0xff]), Ok(json!("+/8=")));
+
+        let nested = Value::List(vec![Value::F64(1.0), Value::F64(f64::INFINITY)]);
+        assert_eq!(
+            serde_json::Value::try_from(nested),
+            Err(NotJson(f64::INFINITY))
+        );
+        assert!(serde_json::Value::try_from(Value::F64(f64::NAN)).is_err());
+    }
+}
@@ -1,94 +1,1063 @@
+use crate::checker::strip_span;
+use crate::intern::{InternedResult, Strings};
 use crate::interpreter::EvalContext;
-use crate::model::{EvalResult, Expression, Op, Value};
+use crate::model::{
+    ErrorPolicy, EvalResult, Expression, Identifier, Literal, Op, Signature, Span, Value,
+};
 use serde::Serialize;
-use wasm_bindgen::prelude::*;
+use std::rc::Rc;
 
+pub mod backend;
+pub mod bundle;
+pub mod canonical;
+pub mod checker;
+mod conversions;
+pub mod cost;
+pub mod features;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+mod functions;
+pub mod host;
+mod intern;
 pub mod interpreter;
+pub mod json;
+pub mod messages;
 mod methods;
 pub mod model;
-mod ordering;
+pub mod optimize;
+pub mod ordering;
+mod panics;
+pub mod parsed_expr;
 pub mod parser;
+pub mod program;
+pub mod register;
+pub mod residual;
+pub mod satisfiability;
 pub mod stack;
+mod suggest;
+mod time;
+pub mod validation;
+pub mod wasi;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-/// Parse `input` into an AST, then serialize it as JSON.
-#[wasm_bindgen]
-pub fn parse_to_ast(input: String) -> JsValue {
-    match parser::parse(&input) {
-        Ok(parsed) => JsValue::from_serde(&parsed).expect("serialize"),
-        Err(err) => JsValue::from_str(&format!("{:?}", err)),
+#[cfg(feature = "wasm")]
+pub use crate::wasm::*;
+
+/// Every overload of every built-in method and function.
+pub fn signatures() -> Vec<&'static Signature> {
+    methods::SIGNATURES
+        .iter()
+        .chain(functions::SIGNATURES)
+        .collect()
+}
+
+/// Write the nested JSON of a tree, `{<fields>, "children": [...]}` for each node, where `node`
+/// gives a node's other fields, already written as `"key":value` pairs, and its children, or
+/// `None` for a node written without them. Nodes are visited in pre-order.
+///
+/// The tree is walked with a stack of its own, rather than serialized by recursion, since a chain
+/// of operators like `1 + 1 + ...` is as deep as it is long.
+fn write_tree<N>(root: N, mut node: impl FnMut(&N) -> (String, Option<Vec<N>>)) -> String {
+    enum Step<N> {
+        Open(N),
+        Text(&'static str),
     }
+    let mut out = String::new();
+    let mut steps = vec![Step::Open(root)];
+    while let Some(step) = steps.pop() {
+        let n = match step {
+            Step::Open(n) => n,
+            Step::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+        };
+        let (fields, children) = node(&n);
+        out.push('{');
+        out.push_str(&fields);
+        match children {
+            Some(children) => {
+                if !fields.is_empty() {
+                    out.push(',');
+                }
+                out.push_str("\"children\":[");
+                steps.push(Step::Text("]}"));
+                for (i, child) in children.into_iter().enumerate().rev() {
+                    steps.push(Step::Open(child));
+                    if i > 0 {
+                        steps.push(Step::Text(","));
+                    }
+                }
+            }
+            None => out.push('}'),
+        }
+    }
+    out
 }
 
-/// Parse `input` into an AST, evaluate it fully, then serialize the resulting `EvaluatedAst` as JSON.
-#[wasm_bindgen]
-pub fn process(input: String) -> JsValue {
-    let ast = match parser::parse(&input) {
-        Ok(parsed) => parsed,
-        Err(err) => return JsValue::from_str(&format!("{:?}", err)),
-    };
+/// Append `"key":value` to the fields of a node being written by `write_tree`.
+fn field(fields: &mut String, key: &str, value: &impl Serialize) {
+    if !fields.is_empty() {
+        fields.push(',');
+    }
+    fields.push_str(&serde_json::to_string(key).expect("serialize"));
+    fields.push(':');
+    fields.push_str(&serde_json::to_string(value).expect("serialize"));
+}
 
-    JsValue::from_serde(&explore(&EvalContext::default(), ast)).expect("serialize")
+/// `expr` as JSON with the metadata described on `parse_to_ast`, interning names and string
+/// literals in `strings` if given.
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+fn ast_json(expr: &Expression, strings: Option<&Strings>) -> String {
+    let mut next_id = 0;
+    write_tree(
+        (None, expr),
+        |&(role, expr): &(Option<&'static str>, &Expression)| {
+            let id = next_id;
+            next_id += 1;
+            let expr = strip_span(expr);
+            let op = expr.op();
+            let children: Vec<_> = expr
+                .children()
+                .into_iter()
+                .map(|(role, child)| (Some(role), child))
+                .collect();
+            let mut fields = String::new();
+            field(&mut fields, "id", &id);
+            if let Some(role) = role {
+                field(&mut fields, "role", &role);
+            }
+            field(&mut fields, "op", &op);
+            field(&mut fields, "precedence", &op.precedence());
+            field(&mut fields, "arity", &children.len());
+            if let Expression::Binding(id) | Expression::LetBinding { id, .. } = expr {
+                match strings {
+                    Some(strings) => field(&mut fields, "name", &strings.intern(&id.0)),
+                    None => field(&mut fields, "name", &id.0),
+                }
+            }
+            match (expr, strings) {
+                (Expression::Lit(Literal::List(_)), _) | (Expression::Lit(Literal::Map(_)), _) => {}
+                (Expression::Lit(Literal::String(s)), Some(strings)) => field(
+                    &mut fields,
+                    "literal",
+                    &serde_json::json!({ "String": strings.intern(s) }),
+                ),
+                (Expression::Lit(lit), _) => field(&mut fields, "literal", lit),
+                _ => {}
+            }
+            (fields, Some(children))
+        },
+    )
 }
 
-fn explore(ctx: &EvalContext, expr: Expression) -> EvaluatedAst {
-    let value = ctx.evaluate(expr.clone());
+/// The values bound by the `let`s around a node, innermost last.
+type Scopes = Rc<Vec<(Identifier, EvalResult)>>;
+
+/// Evaluate `expr` and each of its subexpressions, recording every result in `ast`. Returns the
+/// index of the node for `expr`.
+///
+/// Each subexpression is evaluated once: a node is evaluated with its children standing in for
+/// their already recorded results, so that it is combined from them just as in a full evaluation.
+/// Subexpressions that a full evaluation wouldn't reach, like the right side of `true || x`, are
+/// recorded as skipped rather than evaluated.
+///
+/// The tree is walked with a stack of nodes part-way through exploration rather than by recursion,
+/// since a chain of operators like `1 + 1 + ...` is as deep as it is long.
+fn explore(ctx: &EvalContext, expr: Expression, ast: &mut EvaluatedAst) -> usize {
+    let mut frames: Vec<Exploring> = Vec::new();
+    let mut next = Some((expr, Scopes::default(), false));
+    loop {
+        let (node, wrappers) = match next.take() {
+            Some((expr, scopes, skipped)) => match begin(ctx, expr, scopes, skipped, ast) {
+                Begun::Leaf(node, wrappers) => (node, wrappers),
+                Begun::Frame(frame) => {
+                    frames.push(*frame);
+                    continue;
+                }
+            },
+            None => {
+                let frame = frames.last_mut().expect("a node being explored");
+                if let Some(child) = frame.pending.next() {
+                    next = Some((child, frame.scopes.clone(), frame.operands.skipping));
+                    continue;
+                }
+                frames.pop().unwrap().finish(ctx, ast)
+            }
+        };
+        match frames.last_mut() {
+            Some(parent) => {
+                let stand_in = parent.operands.record(ast, node, wrappers);
+                parent.stand_ins.push(stand_in);
+            }
+            None => return node,
+        }
+    }
+}
+
+/// What `begin` made of a node: the index of the node, if it has no children to explore first,
+/// along with the spans around it; or else the node, waiting for its children.
+enum Begun {
+    Leaf(usize, Vec<Span>),
+    Frame(Box<Exploring>),
+}
+
+/// A node whose children are being explored.
+struct Exploring {
+    id: usize,
+    scopes: Scopes,
+    /// The innermost span around the node, if it was parsed with spans.
+    span: Option<Span>,
+    /// The spans around the node in its parent, outermost first, for its stand-in to keep.
+    wrappers: Vec<Span>,
+    operands: Operands,
+    /// The children not yet explored.
+    pending: std::vec::IntoIter<Expression>,
+    /// The stand-ins for the children explored so far.
+    stand_ins: Vec<Expression>,
+    rebuild: residual::Rebuild,
+}
+
+/// Start exploring `expr`. The nodes of a `let` are numbered but, other than its body, not
+/// recorded: its value is evaluated as a whole and bound for the body, which stands for the `let`.
+fn begin(
+    ctx: &EvalContext,
+    expr: Expression,
+    mut scopes: Scopes,
+    skipped: bool,
+    ast: &mut EvaluatedAst,
+) -> Begun {
+    let mut wrappers = Vec::new();
+    let mut expr = expr;
+    while let Expression::Spanned(span, e) = &mut expr {
+        wrappers.push(*span);
+        expr = e.take();
+    }
+    let mut span = wrappers.last().copied();
+    let mut id = ast.next_id;
+    ast.next_id += 1;
+    while let Expression::LetBinding {
+        id: name,
+        value,
+        body,
+    } = &mut expr
+    {
+        ast.next_id += node_count(value);
+        if !skipped {
+            let value = ctx.evaluate_with(value.take(), scopes.to_vec());
+            let mut inner = scopes.to_vec();
+            inner.push((std::mem::take(name), value));
+            scopes = Rc::new(inner);
+        }
+        expr = body.take();
+        while let Expression::Spanned(s, e) = &mut expr {
+            span = Some(*s);
+            expr = e.take();
+        }
+        id = ast.next_id;
+        ast.next_id += 1;
+    }
     let op = expr.op();
-    let children = match expr {
-        Expression::Ternary {
-            condition,
-            true_branch,
-            else_branch,
-        } => vec![
-            explore(ctx, *condition),
-            explore(ctx, *true_branch),
-            explore(ctx, *else_branch),
-        ],
-        Expression::LetBinding { id, value, body } => {
-            let value = ctx.evaluate(*value);
-            let child_ctx = ctx.with_binding(id, value);
-            return explore(&child_ctx, *body);
-        }
-        Expression::Or(cs) => cs.into_iter().map(|c| explore(ctx, c)).collect(),
-        Expression::And(cs) => cs.into_iter().map(|c| explore(ctx, c)).collect(),
-        Expression::Eq(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Neq(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Lt(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Lte(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Gte(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Gt(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Add(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Sub(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Mul(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Div(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Mod(a, b) => vec![explore(ctx, *a), explore(ctx, *b)],
-        Expression::Neg(a) => vec![explore(ctx, *a)],
-        Expression::Not(a) => vec![explore(ctx, *a)],
-        Expression::Member(a, _) => vec![explore(ctx, *a)],
-        Expression::Method(a, _, args) => {
-            let mut cs = vec![explore(ctx, *a)];
-            for arg in args {
-                cs.push(explore(ctx, arg));
-            }
-            cs
-        }
-        Expression::Lit(_) => vec![],
-        Expression::Binding(id) => vec![EvaluatedAst {
-            op: Op::Lookup,
-            result: Ok(Value::String(id.0)),
-            children: vec![],
-        }],
+    let roles = expr.children().into_iter().map(|(role, _)| role).collect();
+    let mut operands = Operands::new(op, roles, ctx.error_policy(), skipped);
+    let (children, rebuild) = match expr {
+        Expression::Binding(ref mut name) => {
+            let name = std::mem::take(name);
+            let lookup = ast.push(EvaluatedNode {
+                id: None,
+                role: None,
+                op: Op::Lookup,
+                result: Some(Ok(Value::String(name.0.clone().into()))),
+                skipped,
+                span: None,
+                children: vec![],
+            });
+            operands.children.push(lookup);
+            (
+                Vec::new(),
+                Box::new(move |_| Expression::Binding(name)) as residual::Rebuild,
+            )
+        }
+        expr => residual::split(expr),
     };
+    let frame = Exploring {
+        id,
+        scopes,
+        span,
+        wrappers,
+        operands,
+        pending: children.into_iter(),
+        stand_ins: Vec::new(),
+        rebuild,
+    };
+    if frame.pending.len() > 0 {
+        return Begun::Frame(Box::new(frame));
+    }
+    let (node, wrappers) = frame.finish(ctx, ast);
+    Begun::Leaf(node, wrappers)
+}
+
+impl Exploring {
+    /// Evaluate the node, its children having been explored, and record it. Returns its index and
+    /// the spans around it.
+    fn finish(self, ctx: &EvalContext, ast: &mut EvaluatedAst) -> (usize, Vec<Span>) {
+        let operands = self.operands;
+        let result = if operands.skipped {
+            None
+        } else {
+            let expr = (self.rebuild)(self.stand_ins);
+            let mut scopes = self.scopes.to_vec();
+            scopes.extend(operands.results);
+            Some(ctx.evaluate_with(expr, scopes))
+        };
+        let node = ast.push(EvaluatedNode {
+            id: Some(self.id),
+            role: None,
+            op: operands.op,
+            result,
+            skipped: operands.skipped,
+            span: self.span,
+            children: operands.children,
+        });
+        (node, self.wrappers)
+    }
+}
 
-    EvaluatedAst {
-        op,
-        result: value,
-        children,
+/// How many nodes `expr` has, not counting spans.
+fn node_count(expr: &Expression) -> usize {
+    let mut count = 0;
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        count += 1;
+        stack.extend(
+            strip_span(expr)
+                .children()
+                .into_iter()
+                .map(|(_, child)| child),
+        );
     }
+    count
 }
 
-#[derive(Serialize)]
+/// The children of a node being explored, and their results.
+struct Operands {
+    /// The operator of the node being explored.
+    op: Op,
+    /// The role of each child in the node, as in `Expression::children`.
+    roles: Vec<&'static str>,
+    policy: ErrorPolicy,
+    /// Whether the node being explored goes unevaluated, and so all of its children.
+    skipped: bool,
+    /// Whether the next child goes unevaluated.
+    skipping: bool,
+    children: Vec<usize>,
+    /// The results of the children that were evaluated, bound to names that can't be written in an
+    /// expression.
+    results: Vec<(Identifier, EvalResult)>,
+}
+
+impl Operands {
+    fn new(op: Op, roles: Vec<&'static str>, policy: ErrorPolicy, skipped: bool) -> Operands {
+        Operands {
+            op,
+            roles,
+            policy,
+            skipped,
+            skipping: skipped,
+            children: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Whether the child after the one at `index` goes unevaluated, given whether that one was and
+    /// its result if so. This follows the interpreter: a ternary evaluates one branch, `||` and
+    /// `&&` stop at the first operand that decides them, and other operators, under
+    /// `ErrorPolicy::Leftmost`, stop at the first error.
+    fn skips_after(&self, index: usize, skipped: bool, result: Option<&EvalResult>) -> bool {
+        match (&self.op, index) {
+            _ if self.skipped => true,
+            (Op::Ternary, 0) => !matches!(result, Some(Ok(Value::Bool(true)))),
+            (Op::Ternary, _) => !skipped,
+            (Op::Or, _) => skipped || matches!(result, Some(Ok(Value::Bool(true)))),
+            (Op::And, _) => skipped || matches!(result, Some(Ok(Value::Bool(false)))),
+            _ => skipped || self.policy == ErrorPolicy::Leftmost && matches!(result, Some(Err(_))),
+        }
+    }
+
+    /// Record `child`, the node for the next child of the node being explored, and return an
+    /// expression that stands for its result: a binding of the result, within `wrappers`, the
+    /// spans that were around the child.
+    fn record(&mut self, ast: &mut EvaluatedAst, child: usize, wrappers: Vec<Span>) -> Expression {
+        let index = self.children.len();
+        let skipped = self.skipping;
+        ast.nodes[child].role = self.roles.get(index).copied();
+        let id = Identifier(format!("#{}", index));
+        let result = ast.nodes[child].result.clone();
+        self.skipping = self.skips_after(index, skipped, result.as_ref());
+        self.children.push(child);
+        if let Some(result) = result {
+            self.results.push((id.clone(), result));
+        }
+        wrappers
+            .into_iter()
+            .rev()
+            .fold(Expression::Binding(id), |e, span| {
+                Expression::Spanned(span, Box::new(e))
+            })
+    }
+}
+
+/// Every evaluated node of an expression, stored flat in one arena rather than as a tree of boxes.
+/// Children are always pushed before their parent, so the root is the last node.
+#[derive(Default)]
 pub struct EvaluatedAst {
+    nodes: Vec<EvaluatedNode>,
+    /// The id of the next node of the parsed expression to be explored.
+    next_id: usize,
+    max_levels: Option<usize>,
+    intern_strings: bool,
+}
+
+/// What to record while building an `EvaluatedAst`.
+pub struct ExploreOptions {
+    /// Whether to keep the values of successful subexpressions, not just the root's.
+    pub intermediate_results: bool,
+    /// How many levels of the tree to serialize at once. Deeper subtrees are replaced by a node
+    /// id to fetch them by, with `EvaluatedAst::subtree`.
+    pub max_levels: Option<usize>,
+    /// Whether to serialize `{root, strings}`, with the contents of string values in the tree
+    /// replaced by their index into `strings`.
+    pub intern_strings: bool,
+}
+
+impl Default for ExploreOptions {
+    fn default() -> ExploreOptions {
+        ExploreOptions {
+            intermediate_results: true,
+            max_levels: None,
+            intern_strings: false,
+        }
+    }
+}
+
+/// A subexpression that decided the result of an `EvaluatedAst`, as `EvaluatedAst::explain` finds.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct Cause {
+    /// The node's id, as in the output of `parse_to_ast`.
+    pub id: Option<usize>,
+    pub op: Op,
+    pub span: Option<Span>,
+    pub result: EvalResult,
+}
+
+struct EvaluatedNode {
+    /// The node's id, as in the output of `parse_to_ast`. Lookups, which stand for no node of the
+    /// parsed expression, have none.
+    id: Option<usize>,
+    /// The node's role in its parent, as in the output of `parse_to_ast`.
+    role: Option<&'static str>,
     op: Op,
-    result: EvalResult,
-    children: Vec<EvaluatedAst>,
+    /// Omitted for successful subexpressions unless `ExploreOptions::intermediate_results` is set,
+    /// and for skipped ones.
+    result: Option<EvalResult>,
+    /// Whether a full evaluation wouldn't have evaluated the subexpression. Serializes as a
+    /// `result` of `"Skipped"`.
+    skipped: bool,
+    /// Where in the source the subexpression was parsed from, if it was parsed with spans.
+    span: Option<Span>,
+    children: Vec<usize>,
+}
+
+impl EvaluatedAst {
+    /// Evaluate `expr` and all of its subexpressions in `ctx`.
+    pub fn new(ctx: &EvalContext, expr: Expression) -> EvaluatedAst {
+        EvaluatedAst::with_options(ctx, expr, ExploreOptions::default())
+    }
+
+    pub fn with_options(
+        ctx: &EvalContext,
+        expr: Expression,
+        options: ExploreOptions,
+    ) -> EvaluatedAst {
+        let mut ast = EvaluatedAst {
+            nodes: Vec::new(),
+            next_id: 0,
+            max_levels: options.max_levels,
+            intern_strings: options.intern_strings,
+        };
+        let root = explore(ctx, expr, &mut ast);
+        if !options.intermediate_results {
+            for (i, node) in ast.nodes.iter_mut().enumerate() {
+                if i != root && matches!(node.result, Some(Ok(_))) {
+                    node.result = None;
+                }
+            }
+        }
+        ast
+    }
+
+    fn push(&mut self, node: EvaluatedNode) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// The fewest subexpressions that decided the root's result, in source order. `||` and `&&`
+    /// are explained by the operand that decided them, if one did, and otherwise by all of them; a
+    /// `!` by its operand; and a ternary by its condition and the branch it took. Any other node,
+    /// like a comparison, explains itself.
+    ///
+    /// This needs the results of the subexpressions: for a tree built without
+    /// `ExploreOptions::intermediate_results`, the root explains itself.
+    pub fn explain(&self) -> Vec<Cause> {
+        let mut causes = Vec::new();
+        // Nodes to explain, the next last, so that causes are found in source order.
+        let mut pending = vec![self.nodes.len() - 1];
+        while let Some(index) = pending.pop() {
+            match self.deciding(index) {
+                Some(deciding) => pending.extend(deciding.into_iter().rev()),
+                None => {
+                    let node = &self.nodes[index];
+                    causes.push(Cause {
+                        id: node.id,
+                        op: node.op.clone(),
+                        span: node.span,
+                        result: node.result.clone().expect("result"),
+                    });
+                }
+            }
+        }
+        causes
+    }
+
+    /// The children that decided the result of the node at `index`, or `None` if it explains
+    /// itself.
+    fn deciding(&self, index: usize) -> Option<Vec<usize>> {
+        let node = &self.nodes[index];
+        let bool_result = |i: usize| match self.nodes[i].result {
+            Some(Ok(Value::Bool(b))) => Some(b),
+            _ => None,
+        };
+        let all_known = |children: &[usize]| {
+            children
+                .iter()
+                .all(|&child| self.nodes[child].result.is_some())
+        };
+        let deciding: Vec<usize> = match (&node.op, bool_result(index)) {
+            // `true` decides an `||`, and `false` an `&&`, whatever the other operands did.
+            (Op::Or, Some(true)) | (Op::And, Some(false)) => node
+                .children
+                .iter()
+                .copied()
+                .find(|&child| bool_result(child) == bool_result(index))
+                .into_iter()
+                .collect(),
+            (Op::Or, Some(b)) | (Op::And, Some(b))
+                if node
+                    .children
+                    .iter()
+                    .all(|&child| bool_result(child) == Some(b)) =>
+            {
+                node.children.clone()
+            }
+            (Op::Not, _) if all_known(&node.children) => node.children.clone(),
+            (Op::Ternary, _) => {
+                let taken: Vec<usize> = node
+                    .children
+                    .iter()
+                    .copied()
+                    .filter(|&child| !self.nodes[child].skipped)
+                    .collect();
+                if all_known(&taken) {
+                    taken
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        };
+        if deciding.is_empty() {
+            None
+        } else {
+            Some(deciding)
+        }
+    }
+
+    /// The tree as JSON: the nested `{id, role, op, result, span, children: [...]}` of each node.
+    pub fn to_json(&self) -> String {
+        self.subtree_json(self.nodes.len() - 1)
+    }
+
+    /// The subtree rooted at the node with id `id`, as JSON the same way as the whole tree.
+    pub fn subtree(&self, id: usize) -> Option<String> {
+        let root = self.nodes.iter().position(|node| node.id == Some(id))?;
+        Some(self.subtree_json(root))
+    }
+
+    /// The subtree rooted at `root`, along with its string table if the strings are interned.
+    fn subtree_json(&self, root: usize) -> String {
+        if !self.intern_strings {
+            return self.tree_json(root, None);
+        }
+        let strings = Strings::default();
+        // The root goes first, to fill in the table.
+        let root = self.tree_json(root, Some(&strings));
+        let strings = serde_json::to_string(&strings).expect("serialize");
+        format!("{{\"root\":{},\"strings\":{}}}", root, strings)
+    }
+
+    fn tree_json(&self, root: usize, strings: Option<&Strings>) -> String {
+        write_tree((root, self.max_levels), |&(index, levels)| {
+            let node = &self.nodes[index];
+            let mut fields = String::new();
+            if let Some(id) = node.id {
+                field(&mut fields, "id", &id);
+            }
+            if let Some(role) = node.role {
+                field(&mut fields, "role", &role);
+            }
+            field(&mut fields, "op", &node.op);
+            match (&node.result, strings) {
+                _ if node.skipped => field(&mut fields, "result", &"Skipped"),
+                (Some(result), Some(strings)) => {
+                    field(&mut fields, "result", &InternedResult { result, strings })
+                }
+                (Some(result), None) => field(&mut fields, "result", result),
+                (None, _) => {}
+            }
+            if let Some(span) = node.span {
+                field(&mut fields, "span", &span);
+            }
+            // `levels` counts how many more levels to write, this one included.
+            if levels.is_some_and(|n| n <= 1) && !node.children.is_empty() {
+                field(&mut fields, "expand", &node.id);
+                return (fields, None);
+            }
+            let levels = levels.map(|n| n - 1);
+            let children = node.children.iter().map(|&i| (i, levels)).collect();
+            (fields, Some(children))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn explore_json(input: &str) -> serde_json::Value {
+        explore_json_with(input, ExploreOptions::default())
+    }
+
+    fn explore_json_with(input: &str, options: ExploreOptions) -> serde_json::Value {
+        let expr = parser::parse(input).unwrap();
+        let ast = EvaluatedAst::with_options(&EvalContext::default(), expr, options);
+        serde_json::from_str::<serde_json::Value>(&ast.to_json()).unwrap()
+    }
+
+    #[test]
+    fn ast_metadata() {
+        let expr = parser::parse(r#" let x = 1; x > 0 ? -x : [x] "#).unwrap();
+        let json = serde_json::from_str::<serde_json::Value>(&ast_json(&expr, None)).unwrap();
+        let lookup = |id: usize, role: &str| {
+            serde_json::json!({
+                "id": id, "role": role, "op": {"t": "Lookup"}, "precedence": 9, "arity": 0,
+                "name": "x", "children": [],
+            })
+        };
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "id": 0, "op": {"t": "LetBinding"}, "precedence": 0, "arity": 2, "name": "x",
+                "children": [
+                    {
+                        "id": 1, "role": "value", "op": {"t": "Lit"}, "precedence": 9,
+                        "arity": 0, "literal": {"I64": 1}, "children": [],
+                    },
+                    {
+                        "id": 2, "role": "body", "op": {"t": "Ternary"}, "precedence": 1,
+                        "arity": 3,
+                        "children": [
+                            {
+                                "id": 3, "role": "condition", "op": {"t": "Gt"}, "precedence": 4,
+                                "arity": 2,
+                                "children": [
+                                    lookup(4, "left"),
+                                    {
+                                        "id": 5, "role": "right", "op": {"t": "Lit"},
+                                        "precedence": 9, "arity": 0, "literal": {"I64": 0},
+                                        "children": [],
+                                    },
+                                ],
+                            },
+                            {
+                                "id": 6, "role": "true_branch", "op": {"t": "Neg"},
+                                "precedence": 7, "arity": 1, "children": [lookup(7, "operand")],
+                            },
+                            {
+                                "id": 8, "role": "else_branch", "op": {"t": "Lit"},
+                                "precedence": 9, "arity": 1, "children": [lookup(9, "element")],
+                            },
+                        ],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn serializes_as_tree() {
+        assert_eq!(
+            explore_json(" 1 + 2 "),
+            serde_json::json!({
+                "id": 0,
+                "op": {"t": "Plus"},
+                "result": {"Ok": {"t": "I64", "c": 3}},
+                "children": [
+                    {
+                        "id": 1, "role": "left", "op": {"t": "Lit"},
+                        "result": {"Ok": {"t": "I64", "c": 1}}, "children": [],
+                    },
+                    {
+                        "id": 2, "role": "right", "op": {"t": "Lit"},
+                        "result": {"Ok": {"t": "I64", "c": 2}}, "children": [],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn children_in_source_order() {
+        let json = explore_json(" [1, 2].len() == 2 || x ");
+        let ops: Vec<&serde_json::Value> = json["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| &c["op"]["t"])
+            .collect();
+        assert_eq!(ops, vec!["Eq", "Lookup"]);
+        assert_eq!(json["children"][1]["children"][0]["op"]["t"], "Lookup");
+    }
+
+    #[test]
+    fn without_intermediate_results() {
+        let options = ExploreOptions {
+            intermediate_results: false,
+            ..ExploreOptions::default()
+        };
+        let json = explore_json_with(" 1 + 2 * (3 / 0) ", options);
+        assert_eq!(json["result"]["Err"], "DivisionByZero");
+        let one = &json["children"][0];
+        assert_eq!(one["op"]["t"], "Lit");
+        assert!(one.get("result").is_none());
+        let quotient = &json["children"][1]["children"][1];
+        assert_eq!(quotient["result"]["Err"], "DivisionByZero");
+
+        let options = ExploreOptions {
+            intermediate_results: false,
+            ..ExploreOptions::default()
+        };
+        assert_eq!(
+            explore_json_with(" 1 + 2 ", options)["result"]["Ok"]["c"],
+            3
+        );
+    }
+
+    #[test]
+    fn limited_levels() {
+        let expr = parser::parse(" (1 + 2) * 3 ").unwrap();
+        let options = ExploreOptions {
+            max_levels: Some(2),
+            ..ExploreOptions::default()
+        };
+        let ast = EvaluatedAst::with_options(&EvalContext::default(), expr, options);
+        let json = serde_json::from_str::<serde_json::Value>(&ast.to_json()).unwrap();
+        assert_eq!(json["result"]["Ok"]["c"], 9);
+        let sum = &json["children"][0];
+        assert_eq!(sum["result"]["Ok"]["c"], 3);
+        assert!(sum.get("children").is_none());
+        let three = &json["children"][1];
+        assert_eq!(three["children"], serde_json::json!([]));
+
+        let id = sum["expand"].as_u64().unwrap() as usize;
+        let subtree = serde_json::from_str::<serde_json::Value>(&ast.subtree(id).unwrap()).unwrap();
+        assert_eq!(subtree["result"]["Ok"]["c"], 3);
+        assert_eq!(subtree["children"][1]["result"]["Ok"]["c"], 2);
+        assert!(ast.subtree(100).is_none());
+    }
+
+    #[test]
+    fn evaluates_each_node_once() {
+        use crate::interpreter::CustomFunction;
+        use std::borrow::Cow;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut ctx = EvalContext::default();
+        ctx.register_function(CustomFunction {
+            signature: Signature {
+                name: Cow::Borrowed("tick"),
+                operand: None,
+                args: Cow::Borrowed(&[]),
+                result: model::Kind::I64,
+                doc: Cow::Borrowed("Count calls."),
+                example: Cow::Borrowed("tick()"),
+            },
+            implementation: Rc::new(move |_| {
+                counter.set(counter.get() + 1);
+                Ok(Value::I64(counter.get()))
+            }),
+        });
+        let input = "((tick() + 1) * 2 > 0) == (tick() < 0 || 1 / 0 == 1)";
+        let ast = EvaluatedAst::new(&ctx, parser::parse_with_spans(input).unwrap());
+        assert_eq!(calls.get(), 2);
+        let json = serde_json::from_str::<serde_json::Value>(&ast.to_json()).unwrap();
+        let or = &json["children"][1];
+        assert_eq!(or["children"][0]["result"]["Ok"]["c"], false);
+        // Errors are attributed to the same spans as in a full evaluation.
+        let full = ctx.evaluate(parser::parse_with_spans(input).unwrap());
+        assert!(matches!(full, Err(model::Error::At(..))));
+        assert_eq!(json["result"], serde_json::json!(full));
+    }
+
+    #[test]
+    fn skipped_nodes() {
+        let skipped = serde_json::json!("Skipped");
+        let json = explore_json("true || 1 / 0 == 1");
+        assert_eq!(json["result"]["Ok"]["c"], true);
+        assert_eq!(json["children"][1]["result"], skipped);
+        assert_eq!(json["children"][1]["children"][0]["result"], skipped);
+
+        // Every node under an unevaluated branch is skipped, even where it would otherwise choose.
+        let json = explore_json("false ? x : (true ? 1 : y)");
+        assert_eq!(json["result"]["Ok"]["c"], 1);
+        assert_eq!(json["children"][1]["result"], skipped);
+        assert_eq!(json["children"][1]["children"][0]["result"], skipped);
+        let else_branch = &json["children"][2]["children"];
+        assert_eq!(else_branch[1]["result"]["Ok"]["c"], 1);
+        assert_eq!(else_branch[2]["result"], skipped);
+        let json = explore_json("true ? 1 : (true ? 2 : 3)");
+        assert_eq!(json["children"][2]["children"][1]["result"], skipped);
+
+        // Under the default error policy, operands after the first error are skipped.
+        let options = ExploreOptions {
+            intermediate_results: false,
+            ..ExploreOptions::default()
+        };
+        let json = explore_json_with("(1 / 0) + f(2, 3)", options);
+        assert!(!json["children"][0]["result"]["Err"].is_null());
+        assert_eq!(json["children"][1]["result"], skipped);
+        let json = explore_json("f(1 / 0, 3)");
+        assert_eq!(json["children"][1]["result"], skipped);
+        let json = explore_json("1 + 2 == 3 && false");
+        assert_eq!(json["children"][1]["result"]["Ok"]["c"], false);
+    }
+
+    #[test]
+    fn explanations() {
+        fn explain(input: &str, x: i64) -> Vec<(&str, EvalResult)> {
+            let ctx = EvalContext::default();
+            let ctx = ctx.with_binding(Identifier::new("x"), Ok(Value::I64(x)));
+            let ast = EvaluatedAst::new(&ctx, parser::parse_with_spans(input).unwrap());
+            ast.explain()
+                .into_iter()
+                .map(|cause| {
+                    let span = cause.span.unwrap();
+                    (&input[span.start..span.end], cause.result)
+                })
+                .collect()
+        }
+        let t = Ok(Value::Bool(true));
+        let f = Ok(Value::Bool(false));
+        // The first operand that decided an `||` or `&&`, else all of them.
+        let input = "x > 1 && (x < 3 || x == 7) && x != 5";
+        assert_eq!(explain(input, 0), vec![("x > 1", f.clone())]);
+        assert_eq!(
+            explain(input, 5),
+            vec![("x < 3", f.clone()), ("x == 7", f.clone())]
+        );
+        assert_eq!(
+            explain(input, 2),
+            vec![
+                ("x > 1", t.clone()),
+                ("x < 3", t.clone()),
+                ("x != 5", t.clone())
+            ]
+        );
+        // Negations, and a ternary's condition and the branch it took.
+        assert_eq!(
+            explain("!(x > 1) ? x == 2 : x == 3", 3),
+            vec![("x > 1", t.clone()), ("x == 3", t.clone())]
+        );
+        // An error explains itself.
+        let causes = explain("x / 0 > 1 || x > 1", 0);
+        assert_eq!(causes.len(), 1);
+        assert_eq!(causes[0].0, "x / 0 > 1 || x > 1");
+        assert!(causes[0].1.is_err());
+    }
+
+    #[test]
+    fn node_spans() {
+        let input = "let y = 2; (1 + y) * 3 > 0 ? 'a'.size() : -y";
+        let expr = parser::parse_with_spans(input).unwrap();
+        let json =
+            serde_json::from_str(&EvaluatedAst::new(&EvalContext::default(), expr).to_json())
+                .unwrap();
+        let text = |node: &serde_json::Value| {
+            let start = node["span"]["start"].as_u64().unwrap() as usize;
+            let end = node["span"]["end"].as_u64().unwrap() as usize;
+            &input[start..end]
+        };
+        assert_eq!(text(&json), "(1 + y) * 3 > 0 ? 'a'.size() : -y");
+        let condition = &json["children"][0];
+        assert_eq!(text(condition), "(1 + y) * 3 > 0");
+        assert_eq!(text(&condition["children"][0]), "(1 + y) * 3");
+        assert_eq!(text(&condition["children"][0]["children"][0]), "1 + y");
+        assert_eq!(text(&condition["children"][0]["children"][1]), "3");
+        assert_eq!(text(&json["children"][1]), "'a'.size()");
+        assert_eq!(text(&json["children"][1]["children"][0]), "'a'");
+        assert_eq!(text(&json["children"][2]), "-y");
+
+        assert!(explore_json("1 + 2")["span"].is_null());
+    }
+
+    #[test]
+    fn stable_ids() {
+        // Every node of the evaluated tree has the id of the node it came from in the parsed one.
+        fn ids(node: &serde_json::Value, by_id: &mut HashMap<u64, serde_json::Value>) {
+            if let Some(id) = node["id"].as_u64() {
+                by_id.insert(id, node["op"].clone());
+            }
+            for child in node["children"].as_array().unwrap() {
+                ids(child, by_id);
+            }
+        }
+        let input = "let y = [1, {'a': 2}]; y.size() > 1 && (false ? [y] : -f(1, 2))";
+        let (mut parsed, mut evaluated) = (HashMap::new(), HashMap::new());
+        let expr = parser::parse(input).unwrap();
+        ids(
+            &serde_json::from_str::<serde_json::Value>(&ast_json(&expr, None)).unwrap(),
+            &mut parsed,
+        );
+        let ast = EvaluatedAst::new(
+            &EvalContext::default(),
+            parser::parse_with_spans(input).unwrap(),
+        );
+        ids(
+            &serde_json::from_str::<serde_json::Value>(&ast.to_json()).unwrap(),
+            &mut evaluated,
+        );
+        assert_eq!(parsed.len(), 19);
+        assert!(!evaluated.is_empty());
+        for (id, op) in &evaluated {
+            assert_eq!(parsed.get(id), Some(op), "node {}", id);
+        }
+
+        let id = evaluated.iter().find(|(_, op)| op["t"] == "Neg").unwrap().0;
+        let subtree =
+            serde_json::from_str::<serde_json::Value>(&ast.subtree(*id as usize).unwrap()).unwrap();
+        assert_eq!(subtree["op"]["t"], "Neg");
+    }
+
+    #[test]
+    fn child_roles() {
+        let json = explore_json("1 > 0 ? [2, {'k': 3}] : 4");
+        let roles = |node: &serde_json::Value| -> Vec<serde_json::Value> {
+            let children = node["children"].as_array().unwrap();
+            children.iter().map(|child| child["role"].clone()).collect()
+        };
+        assert_eq!(
+            roles(&json),
+            vec!["condition", "true_branch", "else_branch"]
+        );
+        assert_eq!(json["children"][2]["result"], "Skipped");
+        let list = &json["children"][1];
+        assert_eq!(roles(list), vec!["element", "element"]);
+        assert_eq!(list["children"][0]["result"]["Ok"]["c"], 2);
+        let map = &list["children"][1];
+        assert_eq!(roles(map), vec!["key", "value"]);
+        assert_eq!(map["children"][1]["result"]["Ok"]["c"], 3);
+
+        // Elements after a failing one are skipped, as in a full evaluation.
+        let json = explore_json("[1 / 0, 2]");
+        assert!(!json["result"]["Err"].is_null());
+        assert_eq!(json["children"][1]["result"], "Skipped");
+        let json = explore_json("{'a': 1, 'a': 2}");
+        assert_eq!(
+            json["result"]["Err"],
+            serde_json::json!(model::Error::DuplicateMapKey("a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn interned_strings() {
+        let options = ExploreOptions {
+            intern_strings: true,
+            ..ExploreOptions::default()
+        };
+        let json = explore_json_with(r#" ["ab", "ab"] + ["cd"] "#, options);
+        assert_eq!(json["strings"], serde_json::json!(["ab", "cd"]));
+        let root = &json["root"];
+        assert_eq!(
+            root["result"]["Ok"]["c"],
+            serde_json::json!([
+                {"t": "String", "c": 0},
+                {"t": "String", "c": 0},
+                {"t": "String", "c": 1},
+            ])
+        );
+        assert_eq!(
+            root["children"][1]["result"]["Ok"]["c"],
+            serde_json::json!([{"t": "String", "c": 1}])
+        );
+
+        let strings = Strings::default();
+        let expr = parser::parse("let x = 'ab'; x == 'ab'").unwrap();
+        let ast =
+            serde_json::from_str::<serde_json::Value>(&ast_json(&expr, Some(&strings))).unwrap();
+        assert_eq!(ast["name"], 0);
+        assert_eq!(
+            ast["children"][0]["literal"],
+            serde_json::json!({"String": 1})
+        );
+        assert_eq!(ast["children"][1]["children"][0]["name"], 0);
+        assert_eq!(
+            serde_json::to_value(&strings).unwrap(),
+            serde_json::json!(["x", "ab"])
+        );
+    }
+
+    /// The parser counts a chain of operators as one level of nesting, however long it is, so
+    /// nothing that walks the tree may recurse per node.
+    #[test]
+    fn long_chains() {
+        let n = 5000;
+        let input = vec!["1"; n].join(" + ");
+        let expr = parser::parse_with_spans(&input).unwrap();
+        assert_eq!(ast_json(&expr, None).matches(r#""arity":2"#).count(), n - 1);
+        // Every node is evaluated separately, and they all count against one byte budget.
+        let ctx = EvalContext::with_limits(crate::interpreter::EvalLimits {
+            max_bytes: usize::MAX,
+            ..Default::default()
+        });
+        let ast = EvaluatedAst::new(&ctx, expr);
+        assert_eq!(
+            ast.nodes.last().unwrap().result,
+            Some(Ok(Value::I64(n as i64)))
+        );
+        let json = ast.to_json();
+        assert_eq!(json.matches(r#""op":{"t":"Plus"}"#).count(), n - 1);
+        // The leftmost `1` is nested under every `+`.
+        assert!(json.contains(r#"{"id":4999,"role":"left","op":{"t":"Lit"}"#));
+
+        let input = format!("{}true", "!".repeat(n));
+        let ast = EvaluatedAst::new(&EvalContext::default(), parser::parse(&input).unwrap());
+        let causes = ast.explain();
+        assert_eq!(causes.len(), 1);
+        assert_eq!(causes[0].op, Op::Lit);
+    }
 }
@@ -0,0 +1,446 @@
+//! Human-readable messages for evaluation errors, rendered from a catalog of templates keyed by
+//! stable error codes, so that hosts can show errors in the user's language rather than parsing
+//! English text.
+//!
+//! Each error has a `code`, like `"division_by_zero"`, and named `params`, like the kinds of the
+//! operands. A template refers to params by name in braces, e.g. `"{op} can't be applied to
+//! {kind}"`. `ENGLISH` lists every code with the params it has; neither codes nor param names
+//! change from one version to the next. Kinds are given by their CEL names, like `int`, and
+//! operators by their symbols.
+
+use crate::model::{Error, Identifier, Kind, Limit, Op, Span};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// The English template for each error code.
+pub const ENGLISH: &[(&str, &str)] = &[
+    ("no_method", "no method named {method}"),
+    ("no_method_on_type", "{kind} has no method named {method}"),
+    (
+        "no_method_with_signature",
+        "{kind} has no method {method} taking ({args})",
+    ),
+    ("no_function", "no function named {function}"),
+    (
+        "no_function_with_signature",
+        "no function {function} taking ({args})",
+    ),
+    (
+        "invalid_type_for_operator",
+        "{op} can't be applied to {kind}",
+    ),
+    (
+        "invalid_types_for_operator",
+        "{op} can't be applied to {left} and {right}",
+    ),
+    ("division_by_zero", "division by zero"),
+    ("no_such_binding", "{name} is not bound"),
+    ("no_such_member", "no member named {member}"),
+    (
+        "index_out_of_range",
+        "the index {index} is out of range for a length of {length}",
+    ),
+    (
+        "invalid_range",
+        "the range from {start} to {end} ends before it starts",
+    ),
+    ("invalid_map_key", "{kind} can't be a map key"),
+    (
+        "invalid_list_element",
+        "{method} can't be applied to a list holding a {kind}",
+    ),
+    ("empty_list", "{method} can't be applied to an empty list"),
+    ("duplicate_map_key", "the key {key} appears more than once"),
+    (
+        "evaluation_too_large",
+        "the evaluation used {consumed} {limit}, over its limit of {threshold}",
+    ),
+    (
+        "too_many_operations",
+        "the evaluation took too many operations",
+    ),
+    ("cancelled", "the evaluation was cancelled"),
+    ("host_error", "a host function failed: {message}"),
+    (
+        "recursion_limit_exceeded",
+        "the expression nests too deeply",
+    ),
+    ("invalid_timestamp", "{text} is not a valid timestamp"),
+    ("invalid_duration", "{text} is not a valid duration"),
+    ("timestamp_out_of_range", "the timestamp is out of range"),
+    ("duration_out_of_range", "the duration is out of range"),
+    ("integer_overflow", "integer overflow"),
+    (
+        "negative_exponent",
+        "an integer can't be raised to a negative power",
+    ),
+    (
+        "negative_argument",
+        "{method} can't take a negative argument, {value}",
+    ),
+    (
+        "conversion_out_of_range",
+        "the {from} is out of range for {to}",
+    ),
+    ("invalid_conversion", "{text} can't be converted to {kind}"),
+    ("invalid_regex", "invalid regular expression: {message}"),
+    (
+        "format_arity",
+        "the format has {verbs} verbs but was given {args} arguments",
+    ),
+    ("invalid_format", "{verb} is not a format verb"),
+    ("invalid_format_argument", "{verb} can't format a {kind}"),
+    ("multiple", "{count} errors:"),
+    ("internal", "internal error: {message}"),
+];
+
+impl Error {
+    /// The stable code of the error. An error at a span has the code of the error there.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NoMethod(..) => "no_method",
+            Error::NoMethodOnType(..) => "no_method_on_type",
+            Error::NoMethodWithSignature(..) => "no_method_with_signature",
+            Error::NoFunction(..) => "no_function",
+            Error::NoFunctionWithSignature(..) => "no_function_with_signature",
+            Error::InvalidTypeForOperator(..) => "invalid_type_for_operator",
+            Error::InvalidTypesForOperator(..) => "invalid_types_for_operator",
+            Error::DivisionByZero => "division_by_zero",
+            Error::NoSuchBinding(..) => "no_such_binding",
+            Error::NoSuchMember(..) => "no_such_member",
+            Error::InvalidMapKey(..) => "invalid_map_key",
+            Error::InvalidListElement(..) => "invalid_list_element",
+            Error::EmptyList(..) => "empty_list",
+            Error::IndexOutOfRange(..) => "index_out_of_range",
+            Error::InvalidRange(..) => "invalid_range",
+            Error::DuplicateMapKey(..) => "duplicate_map_key",
+            Error::EvaluationTooLarge(..) => "evaluation_too_large",
+            Error::TooManyOperations => "too_many_operations",
+            Error::Cancelled => "cancelled",
+            Error::HostError(..) => "host_error",
+            Error::At(_, e) => e.code(),
+            Error::RecursionLimitExceeded => "recursion_limit_exceeded",
+            Error::InvalidTimestamp(..) => "invalid_timestamp",
+            Error::InvalidDuration(..) => "invalid_duration",
+            Error::TimestampOutOfRange => "timestamp_out_of_range",
+            Error::DurationOutOfRange => "duration_out_of_range",
+            Error::IntegerOverflow => "integer_overflow",
+            Error::NegativeExponent => "negative_exponent",
+            Error::NegativeArgument(..) => "negative_argument",
+            Error::ConversionOutOfRange(..) => "conversion_out_of_range",
+            Error::InvalidConversion(..) => "invalid_conversion",
+            Error::InvalidRegex(..) => "invalid_regex",
+            Error::FormatArity(..) => "format_arity",
+            Error::InvalidFormat(..) => "invalid_format",
+            Error::InvalidFormatArgument(..) => "invalid_format_argument",
+            Error::Multiple(..) => "multiple",
+            Error::Internal(..) => "internal",
+        }
+    }
+
+    /// The params of the error, by name. Lists, like the similarly-named methods that `no_method`
+    /// suggests as `suggestions`, are separated by commas.
+    pub fn params(&self) -> BTreeMap<&'static str, String> {
+        let names = |ids: &[Identifier]| {
+            ids.iter()
+                .map(|id| id.0.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let kinds = |kinds: &[Kind]| {
+            kinds
+                .iter()
+                .map(|&k| kind_name(k))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let params: Vec<(&'static str, String)> = match self {
+            Error::NoMethod(method, similar) => {
+                vec![
+                    ("method", method.0.clone()),
+                    ("suggestions", names(similar)),
+                ]
+            }
+            Error::NoMethodOnType(kind, method) => vec![
+                ("kind", kind_name(*kind).to_owned()),
+                ("method", method.0.clone()),
+            ],
+            Error::NoMethodWithSignature(kind, method, args) => vec![
+                ("kind", kind_name(*kind).to_owned()),
+                ("method", method.0.clone()),
+                ("args", kinds(args)),
+            ],
+            Error::NoFunction(function, similar) => vec![
+                ("function", function.0.clone()),
+                ("suggestions", names(similar)),
+            ],
+            Error::NoFunctionWithSignature(function, args) => {
+                vec![("function", function.0.clone()), ("args", kinds(args))]
+            }
+            Error::InvalidTypeForOperator(kind, op) => {
+                vec![("kind", kind_name(*kind).to_owned()), ("op", op_symbol(op))]
+            }
+            Error::InvalidTypesForOperator(left, right, op) => vec![
+                ("left", kind_name(*left).to_owned()),
+                ("right", kind_name(*right).to_owned()),
+                ("op", op_symbol(op)),
+            ],
+            Error::NoSuchBinding(name, similar) => {
+                vec![("name", name.0.clone()), ("suggestions", names(similar))]
+            }
+            Error::NoSuchMember(member) => vec![("member", member.0.clone())],
+            Error::InvalidMapKey(kind) => vec![("kind", kind_name(*kind).to_owned())],
+            Error::EmptyList(method) => vec![("method", method.0.clone())],
+            Error::NegativeArgument(method, value) => {
+                vec![("method", method.0.clone()), ("value", value.to_string())]
+            }
+            Error::IndexOutOfRange(index, length) => {
+                vec![("index", index.to_string()), ("length", length.to_string())]
+            }
+            Error::InvalidRange(start, end) => {
+                vec![("start", start.to_string()), ("end", end.to_string())]
+            }
+            Error::InvalidListElement(method, kind) => vec![
+                ("method", method.0.clone()),
+                ("kind", kind_name(*kind).to_owned()),
+            ],
+            Error::DuplicateMapKey(key) => vec![("key", key.clone())],
+            Error::HostError(message) | Error::InvalidRegex(message) | Error::Internal(message) => {
+                vec![("message", message.clone())]
+            }
+            Error::At(_, e) => return e.params(),
+            Error::InvalidTimestamp(text) | Error::InvalidDuration(text) => {
+                vec![("text", text.clone())]
+            }
+            Error::ConversionOutOfRange(from, to) => vec![
+                ("from", kind_name(*from).to_owned()),
+                ("to", kind_name(*to).to_owned()),
+            ],
+            Error::InvalidConversion(kind, text) => vec![
+                ("kind", kind_name(*kind).to_owned()),
+                ("text", text.clone()),
+            ],
+            Error::FormatArity(verbs, args) => {
+                vec![("verbs", verbs.to_string()), ("args", args.to_string())]
+            }
+            Error::InvalidFormat(verb) => vec![("verb", verb.clone())],
+            Error::InvalidFormatArgument(verb, kind) => vec![
+                ("verb", verb.clone()),
+                ("kind", kind_name(*kind).to_owned()),
+            ],
+            Error::EvaluationTooLarge(exceeded) => vec![
+                ("limit", limit_name(exceeded.limit).to_owned()),
+                ("threshold", exceeded.threshold.to_string()),
+                ("consumed", exceeded.consumed.to_string()),
+            ],
+            Error::Multiple(errors) => vec![("count", errors.len().to_string())],
+            Error::DivisionByZero
+            | Error::TooManyOperations
+            | Error::Cancelled
+            | Error::RecursionLimitExceeded
+            | Error::TimestampOutOfRange
+            | Error::DurationOutOfRange
+            | Error::IntegerOverflow
+            | Error::NegativeExponent => vec![],
+        };
+        params.into_iter().collect()
+    }
+
+    /// The innermost span the error is at, if any.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::At(span, e) => e.span().or(Some(*span)),
+            _ => None,
+        }
+    }
+}
+
+/// The CEL name of `kind`.
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::I64 => "int",
+        Kind::U64 => "uint",
+        Kind::F64 => "double",
+        Kind::Bool => "bool",
+        Kind::String => "string",
+        Kind::Bytes => "bytes",
+        Kind::List => "list",
+        Kind::Map => "map",
+        Kind::Timestamp => "timestamp",
+        Kind::Duration => "duration",
+        Kind::Null => "null_type",
+        Kind::Optional => "optional_type",
+    }
+}
+
+/// What `limit` is counted in.
+fn limit_name(limit: Limit) -> &'static str {
+    match limit {
+        Limit::Bytes => "bytes",
+        Limit::Clauses => "clauses",
+    }
+}
+
+/// `op` as it's written.
+fn op_symbol(op: &Op) -> String {
+    let symbol = match op {
+        Op::Not => "!",
+        Op::Neg | Op::Minus => "-",
+        Op::Plus => "+",
+        Op::Times => "*",
+        Op::Div => "/",
+        Op::Mod => "%",
+        Op::Or => "||",
+        Op::And => "&&",
+        Op::Eq => "==",
+        Op::Neq => "!=",
+        Op::Lte => "<=",
+        Op::Lt => "<",
+        Op::Gt => ">",
+        Op::Gte => ">=",
+        Op::Lit => "literal",
+        Op::Lookup => "lookup",
+        Op::Member(id) => return format!(".{}", id.0),
+        Op::SafeMember(id) => return format!("?.{}", id.0),
+        Op::OptionalIndex => "[?]",
+        Op::Method(id) => return format!(".{}()", id.0),
+        Op::Function(id) => return format!("{}()", id.0),
+        Op::LetBinding => "let",
+        Op::Ternary => "?:",
+    };
+    symbol.to_owned()
+}
+
+/// Templates for rendering errors, by code: the English ones, other than those a host has
+/// translated.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Catalog {
+    templates: HashMap<String, String>,
+}
+
+/// An error as a host shows it: its code and params, the message rendered from them, and the span
+/// it is at.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct LocalizedError {
+    pub code: &'static str,
+    pub params: BTreeMap<&'static str, String>,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Default for Catalog {
+    fn default() -> Catalog {
+        Catalog::english()
+    }
+}
+
+impl Catalog {
+    pub fn english() -> Catalog {
+        let templates = ENGLISH
+            .iter()
+            .map(|&(code, template)| (code.to_owned(), template.to_owned()))
+            .collect();
+        Catalog { templates }
+    }
+
+    /// Render errors with `code` from `template` rather than the English one.
+    pub fn translate(&mut self, code: &str, template: &str) {
+        self.templates.insert(code.to_owned(), template.to_owned());
+    }
+
+    /// The message for `error`. Params a template doesn't know of are left in braces as they are.
+    /// Several errors render as the template for `multiple`, then each of their messages on a line
+    /// of its own.
+    pub fn render(&self, error: &Error) -> String {
+        let template = self
+            .templates
+            .get(error.code())
+            .map_or(error.code(), String::as_str);
+        let mut message = template.to_owned();
+        for (name, value) in error.params() {
+            message = message.replace(&format!("{{{}}}", name), &value);
+        }
+        if let Error::Multiple(errors) = error {
+            for e in errors {
+                message.push('\n');
+                message.push_str(&self.render(e));
+            }
+        }
+        message
+    }
+
+    pub fn localize(&self, error: &Error) -> LocalizedError {
+        LocalizedError {
+            code: error.code(),
+            params: error.params(),
+            message: self.render(error),
+            span: error.span(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::EvalContext;
+    use crate::model::LimitExceeded;
+    use crate::parser::parse_with_spans;
+
+    fn error(input: &str) -> Error {
+        EvalContext::default()
+            .evaluate(parse_with_spans(input).unwrap())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn english() {
+        let catalog = Catalog::default();
+        assert_eq!(
+            catalog.render(&error("1 + 'a'")),
+            "+ can't be applied to int and string"
+        );
+        assert_eq!(catalog.render(&error("x")), "x is not bound");
+        assert_eq!(
+            catalog.render(&Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::NoSuchMember(Identifier::new("y")),
+            ])),
+            "2 errors:\ndivision by zero\nno member named y"
+        );
+        assert_eq!(
+            catalog.render(&Error::EvaluationTooLarge(LimitExceeded {
+                limit: Limit::Bytes,
+                threshold: 100,
+                consumed: 118,
+            })),
+            "the evaluation used 118 bytes, over its limit of 100"
+        );
+
+        let localized = catalog.localize(&error("[1].contians(1)"));
+        assert_eq!(localized.code, "no_method");
+        assert_eq!(localized.params["suggestions"], "contains");
+        assert_eq!(localized.span, Some(Span { start: 0, end: 15 }));
+
+        // Every error has a template, and every template's params are the error's.
+        let catalog_codes: Vec<&str> = ENGLISH.iter().map(|&(code, _)| code).collect();
+        for input in ["1 / 0", "true - 1", "{1: 2, 1: 3}", "int('x')", "2.pow(-1)"] {
+            let err = error(input);
+            assert!(catalog_codes.contains(&err.code()), "{}", input);
+            assert!(!catalog.render(&err).contains('{'), "{}", input);
+        }
+    }
+
+    #[test]
+    fn translated() {
+        let mut catalog = Catalog::english();
+        catalog.translate(
+            "invalid_types_for_operator",
+            "{op} ne s'applique pas à {left} et {right}",
+        );
+        assert_eq!(
+            catalog.render(&error("1 + 'a'")),
+            "+ ne s'applique pas à int et string"
+        );
+        assert_eq!(catalog.render(&Error::DivisionByZero), "division by zero");
+    }
+}
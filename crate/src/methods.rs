@@ -1,21 +1,797 @@
-use crate::model::{Error, EvalResult, Identifier, Kind, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
-const METHOD_CONTAINS: &str = "contains";
-const METHOD_KEYS: &str = "keys";
-const METHOD_LEN: &str = "len";
-const METHOD_POW: &str = "pow";
+use std::rc::Rc;
+
+use crate::conversions;
+use crate::format;
+use crate::model::{Error, EvalResult, Identifier, Kind, Op, Signature, Value};
+use crate::ordering;
+use crate::residual;
+use crate::suggest;
+use regex::RegexBuilder;
+
+pub const METHOD_ABS: &str = "abs";
+pub const METHOD_AT: &str = "at";
+pub const METHOD_CEIL: &str = "ceil";
+pub const METHOD_CHAR_AT: &str = "charAt";
+pub const METHOD_CONTAINS: &str = "contains";
+pub const METHOD_CONTAINS_KEY: &str = "containsKey";
+pub const METHOD_DISTINCT: &str = "distinct";
+pub const METHOD_ENDS_WITH: &str = "endsWith";
+pub const METHOD_FLATTEN: &str = "flatten";
+pub const METHOD_FLOOR: &str = "floor";
+pub const METHOD_FORMAT: &str = "format";
+pub const METHOD_GET: &str = "get";
+pub const METHOD_HAS_VALUE: &str = "hasValue";
+pub const METHOD_INDEX_OF: &str = "indexOf";
+pub const METHOD_JOIN: &str = "join";
+pub const METHOD_KEYS: &str = "keys";
+pub const METHOD_LAST_INDEX_OF: &str = "lastIndexOf";
+pub const METHOD_LEN: &str = "len";
+pub const METHOD_LOWER_ASCII: &str = "lowerAscii";
+pub const METHOD_MATCHES: &str = "matches";
+pub const METHOD_MAX: &str = "max";
+pub const METHOD_MIN: &str = "min";
+pub const METHOD_OR_VALUE: &str = "orValue";
+pub const METHOD_PATCH: &str = "patch";
+pub const METHOD_POW: &str = "pow";
+pub const METHOD_REVERSE: &str = "reverse";
+pub const METHOD_ROUND: &str = "round";
+pub const METHOD_SIZE: &str = "size";
+pub const METHOD_SLICE: &str = "slice";
+pub const METHOD_SORT: &str = "sort";
+pub const METHOD_SPLIT: &str = "split";
+pub const METHOD_SQRT: &str = "sqrt";
+pub const METHOD_STARTS_WITH: &str = "startsWith";
+pub const METHOD_SUBSTRING: &str = "substring";
+pub const METHOD_SUM: &str = "sum";
+pub const METHOD_TO_UTF8: &str = "toUtf8";
+pub const METHOD_TO_UTF8_LOSSY: &str = "toUtf8Lossy";
+pub const METHOD_TRIM: &str = "trim";
+pub const METHOD_TRIM_END: &str = "trimEnd";
+pub const METHOD_TRIM_START: &str = "trimStart";
+pub const METHOD_UPPER_ASCII: &str = "upperAscii";
+
+pub const SIGNATURES: &[Signature] = &[
+    Signature {
+        name: Cow::Borrowed(METHOD_ABS),
+        operand: Some(Kind::I64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The absolute value of the int, failing for the least int, whose absolute value is \
+             too large. A uint is its own absolute value.",
+        ),
+        example: Cow::Borrowed("(-3).abs()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_ABS),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("The absolute value of the double."),
+        example: Cow::Borrowed("(-2.5).abs()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_AT),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The byte at the index, from 0 to 255."),
+        example: Cow::Borrowed("b\"\\x89PNG\".at(0)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_CEIL),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("The least integer no less than the double, as a double."),
+        example: Cow::Borrowed("1.2.ceil()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_CHAR_AT),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The code point at the index, or an empty string at the end of the string."),
+        example: Cow::Borrowed("\"a¢b\".charAt(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_CONTAINS),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[None]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("Whether the list has an element equal to the argument."),
+        example: Cow::Borrowed("[1, 2, 3].contains(2)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_CONTAINS),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("Whether the argument appears in the string."),
+        example: Cow::Borrowed("\"foobar\".contains(\"oob\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_CONTAINS_KEY),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("Whether the map has the key."),
+        example: Cow::Borrowed("{\"a\": null}.containsKey(\"a\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_DISTINCT),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The elements of the list without those equal to one before them, in order.",
+        ),
+        example: Cow::Borrowed("[1, 2, 1, 3, 2].distinct()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_ENDS_WITH),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("Whether the string ends with the argument."),
+        example: Cow::Borrowed("\"foobar\".endsWith(\"bar\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_FLATTEN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The list with each element that is a list replaced by its elements, in order.",
+        ),
+        example: Cow::Borrowed("[1, [2, [3]], []].flatten()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_FLATTEN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The list flattened as many times as the argument says, or until it has no lists in \
+             it.",
+        ),
+        example: Cow::Borrowed("[1, [2, [3]], []].flatten(2)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_FLOOR),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("The greatest integer no greater than the double, as a double."),
+        example: Cow::Borrowed("1.8.floor()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_FORMAT),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::List)]),
+        result: Kind::String,
+        doc: Cow::Borrowed(
+            "The string with each verb replaced by the next element of the list: `%s` any value, \
+             `%d` an integer, `%f` a number, with six digits after the point or as many as in \
+             `%.2f`, `%b` an integer in binary, `%x` or `%X` an integer, string or bytes in hex. \
+             `%%` is a percent sign.",
+        ),
+        example: Cow::Borrowed("\"%s has %d points\".format([\"bob\", 10])"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_GET),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[Some(Kind::String), None]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The value of the map at the key, or the second argument if the map has no such key.",
+        ),
+        example: Cow::Borrowed("{\"a\": 1}.get(\"b\", 0)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_HAS_VALUE),
+        operand: Some(Kind::Optional),
+        args: Cow::Borrowed(&[]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("Whether the optional holds a value."),
+        example: Cow::Borrowed("{\"a\": 1}[?\"a\"].hasValue()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_INDEX_OF),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[None]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The index of the first element equal to the argument, or -1."),
+        example: Cow::Borrowed("[1, 2, 1].indexOf(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_INDEX_OF),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The index of the code point where the argument first appears in the string, or -1."),
+        example: Cow::Borrowed("\"a¢b¢\".indexOf(\"¢\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_INDEX_OF),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String), Some(Kind::I64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The index of the code point where the argument first appears in the string at or after the second argument, or -1."),
+        example: Cow::Borrowed("\"a¢b¢\".indexOf(\"¢\", 2)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_JOIN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The strings of the list, one after another."),
+        example: Cow::Borrowed("[\"a\", \"b\"].join()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_JOIN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The strings of the list, with the argument between each two."),
+        example: Cow::Borrowed("[\"a\", \"b\"].join(\"-\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_KEYS),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[]),
+        result: Kind::List,
+        doc: Cow::Borrowed("The keys of the map, in sorted order."),
+        example: Cow::Borrowed("{\"b\": 1, \"a\": 2}.keys()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_LAST_INDEX_OF),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[None]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The index of the last element equal to the argument, or -1."),
+        example: Cow::Borrowed("[1, 2, 1].lastIndexOf(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_LEN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of elements in the list."),
+        example: Cow::Borrowed("[1, 2, 3].len()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_LEN),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of unicode code points in the string."),
+        example: Cow::Borrowed("\"¢\".len()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_LEN),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of bytes."),
+        example: Cow::Borrowed("b\"\\xC2\\xA2\".len()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_LEN),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of entries in the map."),
+        example: Cow::Borrowed("{\"a\": 1}.len()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_LOWER_ASCII),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string with ASCII letters in lower case, and others unchanged."),
+        example: Cow::Borrowed("\"Hello, Wörld\".lowerAscii()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MATCHES),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed(
+            "Whether the regular expression, in RE2 syntax, matches some part of the string. Use \
+             `^` and `$` to match all of it.",
+        ),
+        example: Cow::Borrowed("\"abc123\".matches(\"[a-z]+[0-9]+\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MAX),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The greatest element of a list of ints, or of uints. The list can't be empty, and \
+             its elements must all be of one kind.",
+        ),
+        example: Cow::Borrowed("[3, 1, 2].max()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MAX),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The greatest element of a list of doubles, taking NaN to be greater than any other. \
+             The list can't be empty.",
+        ),
+        example: Cow::Borrowed("[0.5, -1.0].max()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MAX),
+        operand: Some(Kind::I64),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The greater of the int and the argument, or of two uints.",
+        ),
+        example: Cow::Borrowed("3.max(5)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MAX),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The greater of the double and the argument, taking NaN to be greater than any other.",
+        ),
+        example: Cow::Borrowed("0.5.max(-1.0)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MIN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The least element of a list of ints, or of uints. The list can't be empty, and \
+             its elements must all be of one kind.",
+        ),
+        example: Cow::Borrowed("[3, 1, 2].min()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MIN),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The least element of a list of doubles, taking NaN to be greater than any other. \
+             The list can't be empty.",
+        ),
+        example: Cow::Borrowed("[0.5, -1.0].min()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MIN),
+        operand: Some(Kind::I64),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The lesser of the int and the argument, or of two uints.",
+        ),
+        example: Cow::Borrowed("3.min(5)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_MIN),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The lesser of the double and the argument, taking NaN to be greater than any other.",
+        ),
+        example: Cow::Borrowed("0.5.min(-1.0)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_OR_VALUE),
+        operand: Some(Kind::Optional),
+        args: Cow::Borrowed(&[None]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The value the optional holds, or the argument if it holds none."),
+        example: Cow::Borrowed("{\"a\": 1}[?\"b\"].orValue(0)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_PATCH),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[Some(Kind::Map)]),
+        result: Kind::Map,
+        doc: Cow::Borrowed(
+            "The map with the entries of the argument merged in: where both have a map under a \
+             key, the two are merged the same way, and otherwise the argument's value replaces the \
+             map's.",
+        ),
+        example: Cow::Borrowed("{\"a\": {\"x\": 1, \"y\": 2}}.patch({\"a\": {\"y\": 3}})"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_PATCH),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[Some(Kind::Map), Some(Kind::Bool)]),
+        result: Kind::Map,
+        doc: Cow::Borrowed(
+            "As `patch`, but if the second argument is true, where both have a list under a key \
+             the argument's is appended to the map's rather than replacing it.",
+        ),
+        example: Cow::Borrowed("{\"a\": [1]}.patch({\"a\": [2]}, true)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_POW),
+        operand: Some(Kind::I64),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("Raise the integer to a non-negative integer power."),
+        example: Cow::Borrowed("2.pow(10)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_POW),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("Raise the double to an integer power."),
+        example: Cow::Borrowed("2.0.pow(-1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_POW),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[Some(Kind::F64)]),
+        result: Kind::F64,
+        doc: Cow::Borrowed("Raise the double to a double power."),
+        example: Cow::Borrowed("2.0.pow(0.5)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_REVERSE),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::List,
+        doc: Cow::Borrowed("The elements of the list in reverse order."),
+        example: Cow::Borrowed("[1, 2, 3].reverse()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_ROUND),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The integer nearest the double, as a double, rounding halves away from zero.",
+        ),
+        example: Cow::Borrowed("2.5.round()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SIZE),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of elements in the list."),
+        example: Cow::Borrowed("[1, 2, 3].size()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SIZE),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of unicode code points in the string."),
+        example: Cow::Borrowed("\"¢\".size()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SIZE),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of bytes."),
+        example: Cow::Borrowed("b\"\\xC2\\xA2\".size()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SIZE),
+        operand: Some(Kind::Map),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed("The number of entries in the map."),
+        example: Cow::Borrowed("{\"a\": 1}.size()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SLICE),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The elements of the list from the index on. A negative index counts back from the \
+             end, and one past either end stops there.",
+        ),
+        example: Cow::Borrowed("[1, 2, 3, 4].slice(-2)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SLICE),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[Some(Kind::I64), Some(Kind::I64)]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The elements of the list from the first index up to the second. A negative index \
+             counts back from the end, and one past either end stops there, so the result is \
+             empty rather than an error when the second comes before the first.",
+        ),
+        example: Cow::Borrowed("[1, 2, 3, 4].slice(1, -1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SLICE),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed("The bytes from the index on, counting as `slice` on a list does."),
+        example: Cow::Borrowed("b\"\\x89PNG\".slice(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SLICE),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[Some(Kind::I64), Some(Kind::I64)]),
+        result: Kind::Bytes,
+        doc: Cow::Borrowed(
+            "The bytes from the first index up to the second, counting as `slice` on a list does.",
+        ),
+        example: Cow::Borrowed("b\"\\x89PNG\".slice(0, 2)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SORT),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The elements of the list in ascending order, as `<` orders them, with NaNs last. The \
+             elements must all be of one kind that `<` applies to.",
+        ),
+        example: Cow::Borrowed("[\"b\", \"c\", \"a\"].sort()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SPLIT),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::List,
+        doc: Cow::Borrowed(
+            "The parts of the string between occurrences of the argument, or each of its \
+             characters if the argument is empty.",
+        ),
+        example: Cow::Borrowed("\"a,b,c\".split(\",\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SQRT),
+        operand: Some(Kind::I64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The square root of the int, or uint, as a double. It's NaN for a negative int.",
+        ),
+        example: Cow::Borrowed("2.sqrt()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SQRT),
+        operand: Some(Kind::F64),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The square root of the double. It's NaN for a negative double.",
+        ),
+        example: Cow::Borrowed("2.25.sqrt()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_STARTS_WITH),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::String)]),
+        result: Kind::Bool,
+        doc: Cow::Borrowed("Whether the string starts with the argument."),
+        example: Cow::Borrowed("\"foobar\".startsWith(\"foo\")"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SUBSTRING),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::I64)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string from the code point at the index."),
+        example: Cow::Borrowed("\"a¢b\".substring(1)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SUBSTRING),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[Some(Kind::I64), Some(Kind::I64)]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string from the code point at the first index up to the one at the second."),
+        example: Cow::Borrowed("\"a¢b\".substring(1, 2)"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SUM),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::I64,
+        doc: Cow::Borrowed(
+            "The sum of a list of ints, or of uints, failing if it overflows. The list can't be \
+             empty, and its elements must all be of one kind.",
+        ),
+        example: Cow::Borrowed("[1, 2, 3].sum()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_SUM),
+        operand: Some(Kind::List),
+        args: Cow::Borrowed(&[]),
+        result: Kind::F64,
+        doc: Cow::Borrowed(
+            "The sum of a list of doubles, added in order. The list can't be empty.",
+        ),
+        example: Cow::Borrowed("[0.5, 0.25].sum()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_TO_UTF8),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The bytes decoded as UTF-8, failing if they aren't valid UTF-8."),
+        example: Cow::Borrowed("b\"caf\\xc3\\xa9\".toUtf8()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_TO_UTF8_LOSSY),
+        operand: Some(Kind::Bytes),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed(
+            "The bytes decoded as UTF-8, with each sequence that isn't valid UTF-8 replaced by \
+             U+FFFD, the replacement character.",
+        ),
+        example: Cow::Borrowed("b\"caf\\xc3\".toUtf8Lossy()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_TRIM),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string without whitespace at either end."),
+        example: Cow::Borrowed("\" a b \\n\".trim()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_TRIM_END),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string without whitespace at its end."),
+        example: Cow::Borrowed("\" a b \\n\".trimEnd()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_TRIM_START),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string without whitespace at its start."),
+        example: Cow::Borrowed("\" a b \\n\".trimStart()"),
+    },
+    Signature {
+        name: Cow::Borrowed(METHOD_UPPER_ASCII),
+        operand: Some(Kind::String),
+        args: Cow::Borrowed(&[]),
+        result: Kind::String,
+        doc: Cow::Borrowed("The string with ASCII letters in upper case, and others unchanged."),
+        example: Cow::Borrowed("\"Hello, wörld\".upperAscii()"),
+    },
+];
 
 fn arg_kinds(args: Vec<Value>) -> Vec<Kind> {
     args.into_iter().map(|arg| arg.kind()).collect()
 }
 
+/// Whether `name` is a built-in method.
+pub fn is_builtin(name: &str) -> bool {
+    SIGNATURES.iter().any(|s| s.name == name)
+}
+
 pub fn evaluate_method(method: Identifier, operand: Value, args: Vec<Value>) -> EvalResult {
     match method.0.as_ref() {
+        METHOD_ABS => evaluate_method_abs(operand, args),
+        METHOD_AT => evaluate_method_at(operand, args),
+        METHOD_CEIL => double_map(METHOD_CEIL, operand, args, f64::ceil),
+        METHOD_CHAR_AT => evaluate_method_char_at(operand, args),
         METHOD_CONTAINS => evaluate_method_contains(operand, args),
+        METHOD_CONTAINS_KEY => evaluate_method_contains_key(operand, args),
+        METHOD_DISTINCT => evaluate_method_distinct(operand, args),
+        METHOD_ENDS_WITH => {
+            string_predicate(METHOD_ENDS_WITH, operand, args, |s, p| s.ends_with(p))
+        }
+        METHOD_FLATTEN => evaluate_method_flatten(operand, args),
+        METHOD_FLOOR => double_map(METHOD_FLOOR, operand, args, f64::floor),
+        METHOD_FORMAT => evaluate_method_format(operand, args),
+        METHOD_GET => evaluate_method_get(operand, args),
+        METHOD_HAS_VALUE => evaluate_method_has_value(operand, args),
+        METHOD_INDEX_OF => evaluate_method_index_of(operand, args),
+        METHOD_JOIN => evaluate_method_join(operand, args),
         METHOD_KEYS => evaluate_method_keys(operand, args),
-        METHOD_LEN => evaluate_method_len(operand, args),
+        METHOD_LAST_INDEX_OF => evaluate_method_last_index_of(operand, args),
+        METHOD_LEN => evaluate_method_size(METHOD_LEN, operand, args),
+        METHOD_LOWER_ASCII => {
+            string_map(METHOD_LOWER_ASCII, operand, args, str::to_ascii_lowercase)
+        }
+        METHOD_MATCHES => evaluate_method_matches(operand, args),
+        METHOD_MAX => evaluate_method_extremum(METHOD_MAX, operand, args),
+        METHOD_MIN => evaluate_method_extremum(METHOD_MIN, operand, args),
+        METHOD_OR_VALUE => evaluate_method_or_value(operand, args),
+        METHOD_PATCH => evaluate_method_patch(operand, args),
         METHOD_POW => evaluate_method_pow(operand, args),
-        _ => Err(Error::NoMethod(method)),
+        METHOD_REVERSE => evaluate_method_reverse(operand, args),
+        METHOD_ROUND => double_map(METHOD_ROUND, operand, args, f64::round),
+        METHOD_SIZE => evaluate_method_size(METHOD_SIZE, operand, args),
+        METHOD_SLICE => evaluate_method_slice(operand, args),
+        METHOD_SORT => evaluate_method_sort(operand, args),
+        METHOD_SPLIT => evaluate_method_split(operand, args),
+        METHOD_SQRT => evaluate_method_sqrt(operand, args),
+        METHOD_STARTS_WITH => {
+            string_predicate(METHOD_STARTS_WITH, operand, args, |s, p| s.starts_with(p))
+        }
+        METHOD_SUBSTRING => evaluate_method_substring(operand, args),
+        METHOD_SUM => evaluate_method_sum(operand, args),
+        METHOD_TO_UTF8 => evaluate_method_to_utf8(METHOD_TO_UTF8, operand, args),
+        METHOD_TO_UTF8_LOSSY => evaluate_method_to_utf8(METHOD_TO_UTF8_LOSSY, operand, args),
+        METHOD_TRIM => string_map(METHOD_TRIM, operand, args, |s| s.trim().to_owned()),
+        METHOD_TRIM_END => string_map(METHOD_TRIM_END, operand, args, |s| s.trim_end().to_owned()),
+        METHOD_TRIM_START => string_map(METHOD_TRIM_START, operand, args, |s| {
+            s.trim_start().to_owned()
+        }),
+        METHOD_UPPER_ASCII => {
+            string_map(METHOD_UPPER_ASCII, operand, args, str::to_ascii_uppercase)
+        }
+        _ => {
+            let suggestions =
+                suggest::similar(&method.0, SIGNATURES.iter().map(|s| s.name.as_ref()));
+            Err(Error::NoMethod(method, suggestions))
+        }
+    }
+}
+
+/// `index` as a position among `len` code points, elements or bytes, the last being just past the
+/// end.
+fn position(index: i64, len: usize) -> Result<usize, Error> {
+    usize::try_from(index)
+        .ok()
+        .filter(|&i| i <= len)
+        .ok_or(Error::IndexOutOfRange(index, len))
+}
+
+fn evaluate_method_at(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::Bytes(bytes), [Value::I64(index)]) => usize::try_from(*index)
+            .ok()
+            .and_then(|i| bytes.get(i))
+            .map(|&b| Value::I64(i64::from(b)))
+            .ok_or(Error::IndexOutOfRange(*index, bytes.len())),
+        (Value::Bytes(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::Bytes,
+            Identifier::new(METHOD_AT),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_AT),
+        )),
+    }
+}
+
+fn evaluate_method_char_at(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(s), [Value::I64(index)]) => {
+            let index = position(*index, s.chars().count())?;
+            let c = s.chars().nth(index).map(String::from).unwrap_or_default();
+            Ok(Value::String(c.into()))
+        }
+        (Value::String(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(METHOD_CHAR_AT),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_CHAR_AT),
+        )),
     }
 }
 
@@ -27,9 +803,9 @@ fn evaluate_method_contains(operand: Value, args: Vec<Value>) -> EvalResult {
             arg_kinds(args),
         ));
     }
-    let arg = args.into_iter().next().unwrap();
     match operand {
-        Value::List(elems) => Ok(Value::Bool(elems.contains(&arg))),
+        Value::List(elems) => Ok(Value::Bool(elems.contains(&args[0]))),
+        Value::String(_) => string_predicate(METHOD_CONTAINS, operand, args, |s, p| s.contains(p)),
         other => Err(Error::NoMethodOnType(
             other.kind(),
             Identifier::new(METHOD_CONTAINS),
@@ -37,8 +813,411 @@ fn evaluate_method_contains(operand: Value, args: Vec<Value>) -> EvalResult {
     }
 }
 
+fn evaluate_method_contains_key(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::Map(fields), [Value::String(key)]) => {
+            Ok(Value::Bool(fields.contains_key(key.as_str())))
+        }
+        (Value::Map(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::Map,
+            Identifier::new(METHOD_CONTAINS_KEY),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_CONTAINS_KEY),
+        )),
+    }
+}
+
+/// `toUtf8`, which fails as `string` does on bytes that aren't UTF-8, or `toUtf8Lossy`.
+fn evaluate_method_to_utf8(method: &str, operand: Value, args: Vec<Value>) -> EvalResult {
+    match operand {
+        Value::Bytes(bytes) if args.is_empty() => {
+            if method == METHOD_TO_UTF8_LOSSY {
+                Ok(Value::String(
+                    String::from_utf8_lossy(&bytes).into_owned().into(),
+                ))
+            } else {
+                conversions::to_string(Value::Bytes(bytes))
+            }
+        }
+        Value::Bytes(_) => Err(Error::NoMethodWithSignature(
+            Kind::Bytes,
+            Identifier::new(method),
+            arg_kinds(args),
+        )),
+        other => Err(Error::NoMethodOnType(other.kind(), Identifier::new(method))),
+    }
+}
+
+/// A method of a string that takes another, like `startsWith`, tested by `f`.
+fn string_predicate(
+    method: &str,
+    operand: Value,
+    args: Vec<Value>,
+    f: fn(&str, &str) -> bool,
+) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(s), [Value::String(arg)]) => Ok(Value::Bool(f(&s, arg))),
+        (Value::String(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(method),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(other.kind(), Identifier::new(method))),
+    }
+}
+
+/// A method of a string that takes nothing and makes another, like `trim`, made by `f`.
+fn string_map(method: &str, operand: Value, args: Vec<Value>, f: fn(&str) -> String) -> EvalResult {
+    match operand {
+        Value::String(s) if args.is_empty() => Ok(Value::String(f(&s).into())),
+        Value::String(_) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(method),
+            arg_kinds(args),
+        )),
+        other => Err(Error::NoMethodOnType(other.kind(), Identifier::new(method))),
+    }
+}
+
+fn evaluate_method_distinct(operand: Value, args: Vec<Value>) -> EvalResult {
+    match operand {
+        Value::List(elems) if args.is_empty() => {
+            let mut distinct: Vec<Value> = Vec::with_capacity(elems.len());
+            for elem in elems {
+                if !distinct.contains(&elem) {
+                    distinct.push(elem);
+                }
+            }
+            Ok(Value::List(distinct))
+        }
+        Value::List(_) => Err(Error::NoMethodWithSignature(
+            Kind::List,
+            Identifier::new(METHOD_DISTINCT),
+            arg_kinds(args),
+        )),
+        other => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_DISTINCT),
+        )),
+    }
+}
+
+fn evaluate_method_flatten(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::List(elems), []) => Ok(Value::List(flatten(elems, 1))),
+        (Value::List(_), [Value::I64(depth)]) if *depth < 0 => Err(Error::NegativeArgument(
+            Identifier::new(METHOD_FLATTEN),
+            *depth,
+        )),
+        (Value::List(elems), [Value::I64(depth)]) => Ok(Value::List(flatten(elems, *depth))),
+        (Value::List(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::List,
+            Identifier::new(METHOD_FLATTEN),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_FLATTEN),
+        )),
+    }
+}
+
+/// `elems` with the lists in it replaced by their elements, `depth` times over.
+fn flatten(elems: Vec<Value>, depth: i64) -> Vec<Value> {
+    if depth == 0 {
+        return elems;
+    }
+    let mut flat = Vec::with_capacity(elems.len());
+    for elem in elems {
+        match elem {
+            Value::List(inner) => flat.extend(flatten(inner, depth - 1)),
+            other => flat.push(other),
+        }
+    }
+    flat
+}
+
+/// The most digits a `format` may ask for after the point, as in `%.100f`.
+pub const FORMAT_MAX_PRECISION: usize = 100;
+
+fn evaluate_method_format(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(template), [Value::List(values)]) => {
+            let pieces = parse_format(&template)?;
+            let verbs = pieces
+                .iter()
+                .filter(|p| matches!(p, Piece::Verb(..)))
+                .count();
+            if verbs != values.len() {
+                return Err(Error::FormatArity(verbs, values.len()));
+            }
+            let mut values = values.iter();
+            let mut formatted = String::new();
+            for piece in pieces {
+                match piece {
+                    Piece::Text(text) => formatted.push_str(text),
+                    Piece::Verb(written, precision, verb) => {
+                        let value = values.next().cloned().unwrap_or(Value::Null);
+                        formatted.push_str(&format_value(written, precision, verb, value)?);
+                    }
+                }
+            }
+            Ok(Value::String(formatted.into()))
+        }
+        (Value::String(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(METHOD_FORMAT),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_FORMAT),
+        )),
+    }
+}
+
+/// Part of a `format`: text to copy, or a verb as written, with its precision if it has one.
+enum Piece<'a> {
+    Text(&'a str),
+    Verb(&'a str, Option<usize>, char),
+}
+
+fn parse_format(template: &str) -> Result<Vec<Piece<'_>>, Error> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('%') {
+        pieces.push(Piece::Text(&rest[..at]));
+        let spec = &rest[at + 1..];
+        let digits = spec
+            .strip_prefix('.')
+            .map(|s| &s[..s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len()]);
+        let verb_at = digits.map_or(0, |d| 1 + d.len());
+        let verb = spec[verb_at..].chars().next();
+        let end = at + 1 + verb_at + verb.map_or(0, char::len_utf8);
+        let written = &rest[at..end];
+        let invalid = || Error::InvalidFormat(written.to_owned());
+        let precision = match digits {
+            Some(d) => Some(
+                d.parse()
+                    .ok()
+                    .filter(|&p| p <= FORMAT_MAX_PRECISION)
+                    .ok_or_else(invalid)?,
+            ),
+            None => None,
+        };
+        pieces.push(match (verb, precision) {
+            (Some('%'), None) => Piece::Text("%"),
+            (Some(verb @ 'f'), _) => Piece::Verb(written, precision, verb),
+            (Some(verb @ ('s' | 'd' | 'b' | 'x' | 'X')), None) => Piece::Verb(written, None, verb),
+            _ => return Err(invalid()),
+        });
+        rest = &rest[end..];
+    }
+    pieces.push(Piece::Text(rest));
+    Ok(pieces)
+}
+
+/// `value` as the verb `written` formats it.
+fn format_value(
+    written: &str,
+    precision: Option<usize>,
+    verb: char,
+    value: Value,
+) -> Result<String, Error> {
+    let hex = |bytes: &[u8]| -> String {
+        bytes
+            .iter()
+            .map(|b| match verb {
+                'X' => format!("{:02X}", b),
+                _ => format!("{:02x}", b),
+            })
+            .collect()
+    };
+    let signed = |i: i64, digits: String| {
+        if i < 0 {
+            format!("-{}", digits)
+        } else {
+            digits
+        }
+    };
+    Ok(match (verb, value) {
+        ('s', value @ (Value::List(_) | Value::Map(_) | Value::Null | Value::Optional(_))) => {
+            format::format(&residual::literal(value))
+        }
+        ('s', value) => match conversions::to_string(value)? {
+            Value::String(s) => Rc::unwrap_or_clone(s),
+            other => format::format(&residual::literal(other)),
+        },
+        ('d', Value::I64(i)) => i.to_string(),
+        ('d', Value::U64(u)) => u.to_string(),
+        ('f', Value::I64(i)) => format!("{:.*}", precision.unwrap_or(6), i as f64),
+        ('f', Value::U64(u)) => format!("{:.*}", precision.unwrap_or(6), u as f64),
+        // CEL's names for the doubles that have no digits.
+        ('f', Value::F64(f)) if f.is_nan() => "NaN".to_owned(),
+        ('f', Value::F64(f)) if f.is_infinite() => {
+            if f > 0.0 { "Infinity" } else { "-Infinity" }.to_owned()
+        }
+        ('f', Value::F64(f)) => format!("{:.*}", precision.unwrap_or(6), f),
+        ('b', Value::I64(i)) => signed(i, format!("{:b}", i.unsigned_abs())),
+        ('b', Value::U64(u)) => format!("{:b}", u),
+        ('x', Value::I64(i)) => signed(i, format!("{:x}", i.unsigned_abs())),
+        ('X', Value::I64(i)) => signed(i, format!("{:X}", i.unsigned_abs())),
+        ('x', Value::U64(u)) => format!("{:x}", u),
+        ('X', Value::U64(u)) => format!("{:X}", u),
+        ('x', Value::String(s)) | ('X', Value::String(s)) => hex(s.as_bytes()),
+        ('x', Value::Bytes(b)) | ('X', Value::Bytes(b)) => hex(&b),
+        (_, other) => {
+            return Err(Error::InvalidFormatArgument(
+                written.to_owned(),
+                other.kind(),
+            ))
+        }
+    })
+}
+
+fn evaluate_method_get(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::Map(mut fields), [Value::String(key), default]) => Ok(fields
+            .remove(key.as_str())
+            .unwrap_or_else(|| default.clone())),
+        (Value::Map(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::Map,
+            Identifier::new(METHOD_GET),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_GET),
+        )),
+    }
+}
+
+fn evaluate_method_has_value(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::Optional(v), []) => Ok(Value::Bool(v.is_some())),
+        (Value::Optional(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::Optional,
+            Identifier::new(METHOD_HAS_VALUE),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_HAS_VALUE),
+        )),
+    }
+}
+
+fn evaluate_method_or_value(operand: Value, mut args: Vec<Value>) -> EvalResult {
+    match (operand, args.len()) {
+        (Value::Optional(Some(v)), 1) => Ok(*v),
+        (Value::Optional(None), 1) => Ok(args.remove(0)),
+        (Value::Optional(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::Optional,
+            Identifier::new(METHOD_OR_VALUE),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_OR_VALUE),
+        )),
+    }
+}
+
+fn evaluate_method_index_of(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(s), [Value::String(needle)]) => index_of(&s, needle, 0),
+        (Value::String(s), [Value::String(needle), Value::I64(from)]) => {
+            index_of(&s, needle, *from)
+        }
+        (Value::List(elems), [needle]) => Ok(element_index(elems.iter().position(|e| e == needle))),
+        (operand @ Value::String(_), _) | (operand @ Value::List(_), _) => {
+            Err(Error::NoMethodWithSignature(
+                operand.kind(),
+                Identifier::new(METHOD_INDEX_OF),
+                arg_kinds(args),
+            ))
+        }
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_INDEX_OF),
+        )),
+    }
+}
+
+fn evaluate_method_last_index_of(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::List(elems), [needle]) => {
+            Ok(element_index(elems.iter().rposition(|e| e == needle)))
+        }
+        (Value::List(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::List,
+            Identifier::new(METHOD_LAST_INDEX_OF),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_LAST_INDEX_OF),
+        )),
+    }
+}
+
+/// The index of an element that was found, or -1.
+fn element_index(index: Option<usize>) -> Value {
+    Value::I64(index.map_or(-1, |i| i as i64))
+}
+
+/// The index of the code point where `needle` first appears in `s` at or after `from`, or -1.
+fn index_of(s: &str, needle: &str, from: i64) -> EvalResult {
+    let from = position(from, s.chars().count())?;
+    let offset = s.char_indices().nth(from).map_or(s.len(), |(at, _)| at);
+    let index = match s[offset..].find(needle) {
+        Some(at) => (from + s[offset..offset + at].chars().count()) as i64,
+        None => -1,
+    };
+    Ok(Value::I64(index))
+}
+
+fn evaluate_method_join(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::List(elems), []) => join(&elems, ""),
+        (Value::List(elems), [Value::String(separator)]) => join(&elems, separator),
+        (Value::List(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::List,
+            Identifier::new(METHOD_JOIN),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_JOIN),
+        )),
+    }
+}
+
+fn join(elems: &[Value], separator: &str) -> EvalResult {
+    let mut joined = String::new();
+    for (i, elem) in elems.iter().enumerate() {
+        let s = match elem {
+            Value::String(s) => s,
+            other => {
+                return Err(Error::InvalidListElement(
+                    Identifier::new(METHOD_JOIN),
+                    other.kind(),
+                ))
+            }
+        };
+        if i > 0 {
+            joined.push_str(separator);
+        }
+        joined.push_str(s);
+    }
+    Ok(Value::String(joined.into()))
+}
+
 fn evaluate_method_keys(operand: Value, args: Vec<Value>) -> EvalResult {
-    if args.len() != 0 {
+    if !args.is_empty() {
         return Err(Error::NoMethodWithSignature(
             operand.kind(),
             Identifier::new(METHOD_KEYS),
@@ -47,11 +1226,11 @@ fn evaluate_method_keys(operand: Value, args: Vec<Value>) -> EvalResult {
     }
     match operand {
         Value::Map(fields) => {
-            let mut keys: Vec<String> = fields.keys().cloned().collect();
-            keys.sort();
-            Ok(Value::List(
-                keys.into_iter().map(|s| Value::String(s)).collect(),
-            ))
+            let keys = ordering::into_entries(fields)
+                .into_iter()
+                .map(|(k, _)| Value::String(k.into()))
+                .collect();
+            Ok(Value::List(keys))
         }
         other => Err(Error::NoMethodOnType(
             other.kind(),
@@ -60,26 +1239,435 @@ fn evaluate_method_keys(operand: Value, args: Vec<Value>) -> EvalResult {
     }
 }
 
-fn evaluate_method_len(operand: Value, args: Vec<Value>) -> EvalResult {
-    if args.len() != 0 {
+/// `size`, or its alias `len`.
+fn evaluate_method_size(method: &str, operand: Value, args: Vec<Value>) -> EvalResult {
+    if !args.is_empty() {
         return Err(Error::NoMethodWithSignature(
             operand.kind(),
-            Identifier::new(METHOD_LEN),
+            Identifier::new(method),
             arg_kinds(args),
         ));
     }
+    match size(&operand) {
+        Some(size) => Ok(Value::I64(size)),
+        None => Err(Error::NoMethodOnType(
+            operand.kind(),
+            Identifier::new(method),
+        )),
+    }
+}
+
+/// The number of elements, code points, bytes or entries in `value`, for the `size` method and
+/// function.
+pub(crate) fn size(value: &Value) -> Option<i64> {
+    let size = match value {
+        Value::List(elems) => elems.len(),
+        Value::String(s) => s.chars().count(),
+        Value::Bytes(b) => b.len(),
+        Value::Map(fields) => fields.len(),
+        _ => return None,
+    };
+    Some(size as i64)
+}
+
+/// How large, in bytes, the pattern of a `matches` may compile to, and how deeply it may nest, so
+/// that an untrusted expression can't exhaust memory compiling or running it.
+pub const REGEX_SIZE_LIMIT: usize = 1 << 20;
+pub const REGEX_NEST_LIMIT: u32 = 64;
+
+fn evaluate_method_matches(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(s), [Value::String(pattern)]) => {
+            let regex = RegexBuilder::new(pattern)
+                .size_limit(REGEX_SIZE_LIMIT)
+                .dfa_size_limit(REGEX_SIZE_LIMIT)
+                .nest_limit(REGEX_NEST_LIMIT)
+                .build()
+                .map_err(|err| Error::InvalidRegex(err.to_string()))?;
+            Ok(Value::Bool(regex.is_match(&s)))
+        }
+        (Value::String(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(METHOD_MATCHES),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_MATCHES),
+        )),
+    }
+}
+
+fn evaluate_method_reverse(operand: Value, args: Vec<Value>) -> EvalResult {
     match operand {
-        Value::List(elems) => Ok(Value::I64(elems.len() as i64)),
-        Value::String(s) => Ok(Value::I64(s.chars().count() as i64)),
-        Value::Bytes(b) => Ok(Value::I64(b.len() as i64)),
-        Value::Map(fields) => Ok(Value::I64(fields.len() as i64)),
+        Value::List(mut elems) if args.is_empty() => {
+            elems.reverse();
+            Ok(Value::List(elems))
+        }
+        Value::List(_) => Err(Error::NoMethodWithSignature(
+            Kind::List,
+            Identifier::new(METHOD_REVERSE),
+            arg_kinds(args),
+        )),
         other => Err(Error::NoMethodOnType(
             other.kind(),
-            Identifier::new(METHOD_LEN),
+            Identifier::new(METHOD_REVERSE),
         )),
     }
 }
 
+fn evaluate_method_slice(operand: Value, args: Vec<Value>) -> EvalResult {
+    let (start, end) = match (&operand, args.as_slice()) {
+        (Value::List(_), [Value::I64(start)]) | (Value::Bytes(_), [Value::I64(start)]) => {
+            (*start, None)
+        }
+        (Value::List(_), [Value::I64(start), Value::I64(end)])
+        | (Value::Bytes(_), [Value::I64(start), Value::I64(end)]) => (*start, Some(*end)),
+        (Value::List(_), _) | (Value::Bytes(_), _) => {
+            return Err(Error::NoMethodWithSignature(
+                operand.kind(),
+                Identifier::new(METHOD_SLICE),
+                arg_kinds(args),
+            ))
+        }
+        (other, _) => {
+            return Err(Error::NoMethodOnType(
+                other.kind(),
+                Identifier::new(METHOD_SLICE),
+            ))
+        }
+    };
+    match operand {
+        Value::Bytes(bytes) => {
+            let (from, to) = bounds(start, end, bytes.len());
+            Ok(Value::Bytes(bytes[from..to].to_vec().into()))
+        }
+        Value::List(elems) => {
+            let (from, to) = bounds(start, end, elems.len());
+            Ok(Value::List(
+                elems.into_iter().skip(from).take(to - from).collect(),
+            ))
+        }
+        other => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_SLICE),
+        )),
+    }
+}
+
+/// The positions a `slice` from `start` up to `end`, or the end, takes among `len` elements, the
+/// second no less than the first.
+fn bounds(start: i64, end: Option<i64>, len: usize) -> (usize, usize) {
+    let from = clamp(start, len);
+    let to = end.map_or(len, |end| clamp(end, len)).max(from);
+    (from, to)
+}
+
+/// `index` as a position among `len` elements, counting back from the end if it's negative, and
+/// stopping at either end.
+fn clamp(index: i64, len: usize) -> usize {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let index = if index < 0 {
+        len.saturating_add(index)
+    } else {
+        index
+    };
+    // Between 0 and `len`, which came from a `usize`.
+    index.clamp(0, len) as usize
+}
+
+fn evaluate_method_sort(operand: Value, args: Vec<Value>) -> EvalResult {
+    let mut elems = match operand {
+        Value::List(elems) if args.is_empty() => elems,
+        Value::List(_) => {
+            return Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new(METHOD_SORT),
+                arg_kinds(args),
+            ))
+        }
+        other => {
+            return Err(Error::NoMethodOnType(
+                other.kind(),
+                Identifier::new(METHOD_SORT),
+            ))
+        }
+    };
+    if let Some(first) = elems.first() {
+        let kind = first.kind();
+        if let Kind::List | Kind::Map = kind {
+            return Err(Error::InvalidListElement(
+                Identifier::new(METHOD_SORT),
+                kind,
+            ));
+        }
+        if let Some(other) = elems.iter().find(|e| e.kind() != kind) {
+            return Err(Error::InvalidTypesForOperator(kind, other.kind(), Op::Lt));
+        }
+    }
+    elems.sort_by(sort_order);
+    Ok(Value::List(elems))
+}
+
+/// The order `sort` puts values of one kind in. Only NaNs aren't ordered by `<`, and go last.
+fn sort_order(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::F64(a), Value::F64(b)) => a
+            .partial_cmp(b)
+            .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan())),
+        (a, b) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// The elements of the list `operand` that `method`, `min`, `max` or `sum`, aggregates: a
+/// non-empty list of numbers of one kind. Numbers of different kinds fail as they would under
+/// `op`, since neither is converted to the other.
+fn numbers(method: &str, operand: Value, args: Vec<Value>, op: Op) -> Result<Vec<Value>, Error> {
+    let elems = match operand {
+        Value::List(elems) if args.is_empty() => elems,
+        Value::List(_) => {
+            return Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new(method),
+                arg_kinds(args),
+            ))
+        }
+        other => return Err(Error::NoMethodOnType(other.kind(), Identifier::new(method))),
+    };
+    let kind = match elems.first() {
+        Some(first) => first.kind(),
+        None => return Err(Error::EmptyList(Identifier::new(method))),
+    };
+    if let Some(other) = elems
+        .iter()
+        .find(|e| !matches!(e.kind(), Kind::I64 | Kind::U64 | Kind::F64))
+    {
+        return Err(Error::InvalidListElement(
+            Identifier::new(method),
+            other.kind(),
+        ));
+    }
+    if let Some(other) = elems.iter().find(|e| e.kind() != kind) {
+        return Err(Error::InvalidTypesForOperator(kind, other.kind(), op));
+    }
+    Ok(elems)
+}
+
+/// `min` or `max`, the first or last element in `sort` order.
+/// `min` or `max`, of a list or of a number and another of its kind.
+fn evaluate_method_extremum(method: &str, operand: Value, args: Vec<Value>) -> EvalResult {
+    let elems = match operand {
+        Value::I64(_) | Value::U64(_) | Value::F64(_) => match args.as_slice() {
+            [other] if other.kind() == operand.kind() => vec![operand, other.clone()],
+            _ => {
+                return Err(Error::NoMethodWithSignature(
+                    operand.kind(),
+                    Identifier::new(method),
+                    arg_kinds(args),
+                ))
+            }
+        },
+        operand => numbers(method, operand, args, Op::Lt)?,
+    }
+    .into_iter();
+    let extremum = if method == METHOD_MAX {
+        elems.max_by(sort_order)
+    } else {
+        elems.min_by(sort_order)
+    };
+    extremum.ok_or_else(|| Error::EmptyList(Identifier::new(method)))
+}
+
+fn evaluate_method_sum(operand: Value, args: Vec<Value>) -> EvalResult {
+    let mut elems = numbers(METHOD_SUM, operand, args, Op::Plus)?.into_iter();
+    let first = elems
+        .next()
+        .ok_or_else(|| Error::EmptyList(Identifier::new(METHOD_SUM)))?;
+    elems.try_fold(first, |sum, e| match (sum, e) {
+        (Value::I64(a), Value::I64(b)) => a
+            .checked_add(b)
+            .map(Value::I64)
+            .ok_or(Error::IntegerOverflow),
+        (Value::U64(a), Value::U64(b)) => a
+            .checked_add(b)
+            .map(Value::U64)
+            .ok_or(Error::IntegerOverflow),
+        (Value::F64(a), Value::F64(b)) => Ok(Value::F64(a + b)),
+        (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Plus)),
+    })
+}
+
+fn evaluate_method_split(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(s), [Value::String(separator)]) => {
+            let part = |p: &str| Value::String(p.to_owned().into());
+            let parts = if separator.is_empty() {
+                s.chars()
+                    .map(|c| part(c.encode_utf8(&mut [0; 4])))
+                    .collect()
+            } else {
+                s.split(separator.as_str()).map(part).collect()
+            };
+            Ok(Value::List(parts))
+        }
+        (Value::String(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(METHOD_SPLIT),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_SPLIT),
+        )),
+    }
+}
+
+fn evaluate_method_substring(operand: Value, args: Vec<Value>) -> EvalResult {
+    match (operand, args.as_slice()) {
+        (Value::String(s), [Value::I64(start)]) => substring(&s, *start, None),
+        (Value::String(s), [Value::I64(start), Value::I64(end)]) => {
+            substring(&s, *start, Some(*end))
+        }
+        (Value::String(_), _) => Err(Error::NoMethodWithSignature(
+            Kind::String,
+            Identifier::new(METHOD_SUBSTRING),
+            arg_kinds(args),
+        )),
+        (other, _) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_SUBSTRING),
+        )),
+    }
+}
+
+/// The code points of `s` from `start` up to `end`, or to the end of `s`.
+fn substring(s: &str, start: i64, end: Option<i64>) -> EvalResult {
+    let len = s.chars().count();
+    let from = position(start, len)?;
+    let to = match end {
+        Some(end) => position(end, len)?,
+        None => len,
+    };
+    if to < from {
+        return Err(Error::InvalidRange(start, to as i64));
+    }
+    let part: String = s.chars().skip(from).take(to - from).collect();
+    Ok(Value::String(part.into()))
+}
+
+fn evaluate_method_patch(operand: Value, args: Vec<Value>) -> EvalResult {
+    let mut args = args.into_iter();
+    match (operand, args.next(), args.next(), args.next()) {
+        (Value::Map(base), Some(Value::Map(overlay)), concat, None) => {
+            let concat_lists = match concat {
+                None => false,
+                Some(Value::Bool(b)) => b,
+                Some(other) => {
+                    return Err(Error::NoMethodWithSignature(
+                        Kind::Map,
+                        Identifier::new(METHOD_PATCH),
+                        vec![Kind::Map, other.kind()],
+                    ))
+                }
+            };
+            Ok(Value::Map(patch(base, overlay, concat_lists)))
+        }
+        (Value::Map(_), first, second, third) => Err(Error::NoMethodWithSignature(
+            Kind::Map,
+            Identifier::new(METHOD_PATCH),
+            arg_kinds(
+                first
+                    .into_iter()
+                    .chain(second)
+                    .chain(third)
+                    .chain(args)
+                    .collect(),
+            ),
+        )),
+        (other, ..) => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_PATCH),
+        )),
+    }
+}
+
+/// `base` with `overlay` merged in, recursively where both have maps under a key. Lists under the
+/// same key are concatenated if `concat_lists`, and otherwise, like any other values, `overlay`'s
+/// replaces `base`'s.
+fn patch(
+    mut base: HashMap<String, Value>,
+    overlay: HashMap<String, Value>,
+    concat_lists: bool,
+) -> HashMap<String, Value> {
+    for (key, value) in overlay {
+        let merged = match (base.remove(&key), value) {
+            (Some(Value::Map(a)), Value::Map(b)) => Value::Map(patch(a, b, concat_lists)),
+            (Some(Value::List(mut a)), Value::List(b)) if concat_lists => {
+                a.extend(b);
+                Value::List(a)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, merged);
+    }
+    base
+}
+
+fn evaluate_method_abs(operand: Value, args: Vec<Value>) -> EvalResult {
+    match operand {
+        Value::I64(i) if args.is_empty() => i
+            .checked_abs()
+            .map(Value::I64)
+            .ok_or(Error::IntegerOverflow),
+        Value::U64(u) if args.is_empty() => Ok(Value::U64(u)),
+        Value::F64(f) if args.is_empty() => Ok(Value::F64(f.abs())),
+        Value::I64(_) | Value::U64(_) | Value::F64(_) => Err(Error::NoMethodWithSignature(
+            operand.kind(),
+            Identifier::new(METHOD_ABS),
+            arg_kinds(args),
+        )),
+        other => Err(Error::NoMethodOnType(
+            other.kind(),
+            Identifier::new(METHOD_ABS),
+        )),
+    }
+}
+
+/// A method of a double that takes no arguments, like `ceil`, computed by `f`.
+fn double_map(method: &str, operand: Value, args: Vec<Value>, f: fn(f64) -> f64) -> EvalResult {
+    match operand {
+        Value::F64(x) if args.is_empty() => Ok(Value::F64(f(x))),
+        Value::F64(_) => Err(Error::NoMethodWithSignature(
+            Kind::F64,
+            Identifier::new(method),
+            arg_kinds(args),
+        )),
+        other => Err(Error::NoMethodOnType(other.kind(), Identifier::new(method))),
+    }
+}
+
+fn evaluate_method_sqrt(operand: Value, args: Vec<Value>) -> EvalResult {
+    let x = match operand {
+        Value::I64(i) if args.is_empty() => i as f64,
+        Value::U64(u) if args.is_empty() => u as f64,
+        Value::F64(f) if args.is_empty() => f,
+        Value::I64(_) | Value::U64(_) | Value::F64(_) => {
+            return Err(Error::NoMethodWithSignature(
+                operand.kind(),
+                Identifier::new(METHOD_SQRT),
+                arg_kinds(args),
+            ))
+        }
+        other => {
+            return Err(Error::NoMethodOnType(
+                other.kind(),
+                Identifier::new(METHOD_SQRT),
+            ))
+        }
+    };
+    Ok(Value::F64(x.sqrt()))
+}
+
 fn evaluate_method_pow(operand: Value, args: Vec<Value>) -> EvalResult {
     if args.len() != 1 {
         return Err(Error::NoMethodWithSignature(
@@ -91,7 +1679,12 @@ fn evaluate_method_pow(operand: Value, args: Vec<Value>) -> EvalResult {
     let arg = args.into_iter().next().unwrap();
     match operand {
         Value::I64(base) => match arg {
-            Value::I64(exp) => Ok(Value::I64(i64::pow(base, exp as u32))),
+            Value::I64(exp) if exp < 0 => Err(Error::NegativeExponent),
+            Value::I64(exp) => u32::try_from(exp)
+                .ok()
+                .and_then(|exp| base.checked_pow(exp))
+                .map(Value::I64)
+                .ok_or(Error::IntegerOverflow),
             other => Err(Error::NoMethodWithSignature(
                 Kind::I64,
                 Identifier::new(METHOD_POW),
@@ -113,3 +1706,858 @@ fn evaluate_method_pow(operand: Value, args: Vec<Value>) -> EvalResult {
         )),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::EvalContext;
+    use crate::model::Expression;
+    use crate::parser::parse;
+
+    #[test]
+    fn no_method_suggestions() {
+        assert_eq!(
+            evaluate_method(Identifier::new("contians"), Value::Null, vec![]),
+            Err(Error::NoMethod(
+                Identifier::new("contians"),
+                vec![Identifier::new("contains")]
+            ))
+        );
+    }
+
+    #[test]
+    fn patch() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let base = r#"{"a": {"x": 1, "y": [1]}, "b": 1, "c": {"d": 1}}"#;
+        let overlay = r#"{"a": {"y": [2], "z": 3}, "b": {"e": 1}, "c": 2}"#;
+        assert_eq!(
+            eval(&format!("{}.patch({})", base, overlay)),
+            eval(r#"{"a": {"x": 1, "y": [2], "z": 3}, "b": {"e": 1}, "c": 2}"#)
+        );
+        assert_eq!(
+            eval(&format!("{}.patch({}, true)", base, overlay)),
+            eval(r#"{"a": {"x": 1, "y": [1, 2], "z": 3}, "b": {"e": 1}, "c": 2}"#)
+        );
+        let no_signature = |args| {
+            Err(Error::NoMethodWithSignature(
+                Kind::Map,
+                Identifier::new("patch"),
+                args,
+            ))
+        };
+        assert_eq!(eval("{}.patch(1)"), no_signature(vec![Kind::I64]));
+        assert_eq!(
+            eval("{}.patch({}, 1)"),
+            no_signature(vec![Kind::Map, Kind::I64])
+        );
+        assert_eq!(eval("{}.patch()"), no_signature(vec![]));
+        assert_eq!(
+            eval("[].patch({})"),
+            Err(Error::NoMethodOnType(Kind::List, Identifier::new("patch")))
+        );
+    }
+
+    #[test]
+    fn string_predicates() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(
+            eval(r#" "foobar".startsWith("foo") "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval(r#" "foobar".startsWith("bar") "#),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(eval(r#" "foobar".endsWith("bar") "#), Ok(Value::Bool(true)));
+        assert_eq!(eval(r#" "foobar".endsWith("") "#), Ok(Value::Bool(true)));
+        assert_eq!(eval(r#" "foobar".contains("oob") "#), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval(r#" "foobar".contains("obo") "#),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(eval(r#" "¢x".startsWith("¢") "#), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval(r#" "foobar".contains(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::String,
+                Identifier::new("contains"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "foobar".startsWith("f", "o") "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::String,
+                Identifier::new("startsWith"),
+                vec![Kind::String, Kind::String]
+            ))
+        );
+        assert_eq!(
+            eval(r#" [1].endsWith("1") "#),
+            Err(Error::NoMethodOnType(
+                Kind::List,
+                Identifier::new("endsWith")
+            ))
+        );
+    }
+
+    #[test]
+    fn case_and_trim() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let string = |s: &str| Ok(Value::String(s.to_owned().into()));
+        assert_eq!(
+            eval(r#" "Hello, Wörld".lowerAscii() "#),
+            string("hello, wörld")
+        );
+        assert_eq!(
+            eval(r#" "Hello, wörld".upperAscii() "#),
+            string("HELLO, WöRLD")
+        );
+        assert_eq!(
+            eval(r#" "ADMIN".lowerAscii() == "admin".lowerAscii() "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(eval(r#" "\t a b \n".trim() "#), string("a b"));
+        assert_eq!(eval(r#" "\t a b \n".trimStart() "#), string("a b \n"));
+        assert_eq!(eval(r#" "\t a b \n".trimEnd() "#), string("\t a b"));
+        assert_eq!(eval(r#" "\u3000a".trim() "#), string("a"));
+        assert_eq!(
+            eval(r#" "a".trim(" ") "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::String,
+                Identifier::new("trim"),
+                vec![Kind::String]
+            ))
+        );
+        assert_eq!(
+            eval(r#" 1.upperAscii() "#),
+            Err(Error::NoMethodOnType(
+                Kind::I64,
+                Identifier::new("upperAscii")
+            ))
+        );
+    }
+
+    #[test]
+    fn aggregations() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" [3, 1, 2].min() "#), Ok(Value::I64(1)));
+        assert_eq!(eval(r#" [3, 1, 2].max() "#), Ok(Value::I64(3)));
+        assert_eq!(eval(r#" [3, 1, 2].sum() "#), Ok(Value::I64(6)));
+        assert_eq!(eval(r#" [uint(3), uint(4)].sum() "#), Ok(Value::U64(7)));
+        assert_eq!(eval(r#" [uint(3), uint(4)].min() "#), Ok(Value::U64(3)));
+        assert_eq!(eval(r#" [0.5, -1.0].min() "#), Ok(Value::F64(-1.0)));
+        assert_eq!(eval(r#" [0.5, 0.25].sum() "#), Ok(Value::F64(0.75)));
+        assert_eq!(eval(r#" [7].max() "#), Ok(Value::I64(7)));
+        // NaN is greatest, as `sort` orders it.
+        assert_eq!(
+            eval(r#" [1.0, double("nan"), -1.0].min() "#),
+            Ok(Value::F64(-1.0))
+        );
+        assert!(matches!(
+            eval(r#" [1.0, double("nan"), -1.0].max() "#),
+            Ok(Value::F64(f)) if f.is_nan()
+        ));
+        assert_eq!(
+            eval(r#" [9223372036854775807, 1].sum() "#),
+            Err(Error::IntegerOverflow)
+        );
+        for method in &["min", "max", "sum"] {
+            assert_eq!(
+                eval(&format!("[].{}()", method)),
+                Err(Error::EmptyList(Identifier::new(method)))
+            );
+            assert_eq!(
+                eval(&format!(r#" [1, "2"].{}() "#, method)),
+                Err(Error::InvalidListElement(
+                    Identifier::new(method),
+                    Kind::String
+                ))
+            );
+        }
+        // Numbers of different kinds aren't converted to one.
+        assert_eq!(
+            eval(r#" [1, 2.0].max() "#),
+            Err(Error::InvalidTypesForOperator(Kind::I64, Kind::F64, Op::Lt))
+        );
+        assert_eq!(
+            eval(r#" [1, uint(2)].sum() "#),
+            Err(Error::InvalidTypesForOperator(
+                Kind::I64,
+                Kind::U64,
+                Op::Plus
+            ))
+        );
+        assert_eq!(
+            eval(r#" [1].sum(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new("sum"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" {"a": 1}.max() "#),
+            Err(Error::NoMethodOnType(Kind::Map, Identifier::new("max")))
+        );
+    }
+
+    #[test]
+    fn bytes_at_and_slice() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" b"\x89PNG".at(0) "#), Ok(Value::I64(0x89)));
+        assert_eq!(eval(r#" b"\x89PNG".at(3) "#), Ok(Value::I64(71)));
+        assert_eq!(
+            eval(r#" b"\x89PNG".at(4) "#),
+            Err(Error::IndexOutOfRange(4, 4))
+        );
+        assert_eq!(
+            eval(r#" b"\x89PNG".at(-1) "#),
+            Err(Error::IndexOutOfRange(-1, 4))
+        );
+        assert_eq!(
+            eval(r#" b"\x89PNG".slice(0, 2) == b"\x89P" "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(eval(r#" b"\x89PNG".slice(1) "#), eval(r#" b"PNG" "#));
+        assert_eq!(eval(r#" b"\x89PNG".slice(-2, 10) "#), eval(r#" b"NG" "#));
+        assert_eq!(eval(r#" b"\x89PNG".slice(3, 1) "#), eval(r#" b"" "#));
+        assert_eq!(
+            eval(r#" b"abc".at("0") "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Bytes,
+                Identifier::new("at"),
+                vec![Kind::String]
+            ))
+        );
+        assert_eq!(
+            eval(r#" b"abc".slice() "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Bytes,
+                Identifier::new("slice"),
+                vec![]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "abc".at(0) "#),
+            Err(Error::NoMethodOnType(Kind::String, Identifier::new("at")))
+        );
+    }
+
+    #[test]
+    fn to_utf8() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let s = |s: &str| Ok(Value::String(s.to_owned().into()));
+        assert_eq!(eval(r#" b"caf\xc3\xa9".toUtf8() "#), s("café"));
+        assert_eq!(eval(r#" b"caf\xc3\xa9".toUtf8Lossy() "#), s("café"));
+        assert_eq!(eval(r#" b"".toUtf8() "#), s(""));
+        assert_eq!(
+            eval(r#" b"caf\xc3".toUtf8() "#),
+            Err(Error::InvalidConversion(
+                Kind::String,
+                "caf\u{FFFD}".to_owned()
+            ))
+        );
+        assert_eq!(eval(r#" b"caf\xc3".toUtf8Lossy() "#), s("caf\u{FFFD}"));
+        assert_eq!(
+            eval(r#" b"\xff\xfea\xc3".toUtf8Lossy() "#),
+            s("\u{FFFD}\u{FFFD}a\u{FFFD}")
+        );
+        assert_eq!(
+            eval(r#" b"a".toUtf8(true) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Bytes,
+                Identifier::new("toUtf8"),
+                vec![Kind::Bool]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "a".toUtf8Lossy() "#),
+            Err(Error::NoMethodOnType(
+                Kind::String,
+                Identifier::new("toUtf8Lossy")
+            ))
+        );
+    }
+
+    #[test]
+    fn math() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" (-3).abs() "#), Ok(Value::I64(3)));
+        assert_eq!(eval(r#" -3.abs() "#), Ok(Value::I64(-3)));
+        assert_eq!(eval(r#" uint(3).abs() "#), Ok(Value::U64(3)));
+        assert_eq!(eval(r#" (-2.5).abs() "#), Ok(Value::F64(2.5)));
+        assert_eq!(
+            eval(r#" (-9223372036854775807 - 1).abs() "#),
+            Err(Error::IntegerOverflow)
+        );
+        assert_eq!(eval(r#" 1.2.ceil() "#), Ok(Value::F64(2.0)));
+        assert_eq!(eval(r#" (-1.2).ceil() "#), Ok(Value::F64(-1.0)));
+        assert_eq!(eval(r#" 1.8.floor() "#), Ok(Value::F64(1.0)));
+        assert_eq!(eval(r#" (-1.2).floor() "#), Ok(Value::F64(-2.0)));
+        assert_eq!(eval(r#" 2.5.round() "#), Ok(Value::F64(3.0)));
+        assert_eq!(eval(r#" (-2.5).round() "#), Ok(Value::F64(-3.0)));
+        assert_eq!(eval(r#" 2.4.round() "#), Ok(Value::F64(2.0)));
+        assert_eq!(eval(r#" 2.25.sqrt() "#), Ok(Value::F64(1.5)));
+        assert_eq!(eval(r#" 4.sqrt() "#), Ok(Value::F64(2.0)));
+        assert_eq!(eval(r#" uint(9).sqrt() "#), Ok(Value::F64(3.0)));
+        assert!(matches!(eval(r#" (-4).sqrt() "#), Ok(Value::F64(f)) if f.is_nan()));
+        assert_eq!(eval(r#" 3.max(5) "#), Ok(Value::I64(5)));
+        assert_eq!(eval(r#" 3.min(5) "#), Ok(Value::I64(3)));
+        assert_eq!(eval(r#" uint(3).min(uint(5)) "#), Ok(Value::U64(3)));
+        assert_eq!(eval(r#" 0.5.min(-1.0) "#), Ok(Value::F64(-1.0)));
+        assert_eq!(eval(r#" 0.5.min(double("nan")) "#), Ok(Value::F64(0.5)));
+        assert_eq!(
+            eval(r#" 3.max(5.0) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::I64,
+                Identifier::new("max"),
+                vec![Kind::F64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" 3.ceil() "#),
+            Err(Error::NoMethodOnType(Kind::I64, Identifier::new("ceil")))
+        );
+        assert_eq!(
+            eval(r#" 1.5.round(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::F64,
+                Identifier::new("round"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "3".abs() "#),
+            Err(Error::NoMethodOnType(Kind::String, Identifier::new("abs")))
+        );
+    }
+
+    #[test]
+    fn slice() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(1) "#), eval(r#" [2, 3, 4] "#));
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(1, 3) "#), eval(r#" [2, 3] "#));
+        assert_eq!(
+            eval(r#" [1, 2, 3, 4].slice(0, 4) "#),
+            eval(r#" [1, 2, 3, 4] "#)
+        );
+        // Negative indexes count back from the end.
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(-2) "#), eval(r#" [3, 4] "#));
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(1, -1) "#), eval(r#" [2, 3] "#));
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(-3, -2) "#), eval(r#" [2] "#));
+        // Indexes past either end stop there.
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(-10, 2) "#), eval(r#" [1, 2] "#));
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(2, 10) "#), eval(r#" [3, 4] "#));
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(10) "#), eval(r#" [] "#));
+        assert_eq!(
+            eval(r#" [1, 2].slice(-9223372036854775807 - 1, 9223372036854775807) "#),
+            eval(r#" [1, 2] "#)
+        );
+        // An end before the start leaves nothing.
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(3, 1) "#), eval(r#" [] "#));
+        assert_eq!(eval(r#" [1, 2, 3, 4].slice(-1, -2) "#), eval(r#" [] "#));
+        assert_eq!(eval(r#" [].slice(0, 1) "#), eval(r#" [] "#));
+        assert_eq!(
+            eval(r#" [1].slice(0.0) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new("slice"),
+                vec![Kind::F64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "abc".slice(1) "#),
+            Err(Error::NoMethodOnType(
+                Kind::String,
+                Identifier::new("slice")
+            ))
+        );
+    }
+
+    #[test]
+    fn split_and_join() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(
+            eval(r#" "a,b,c".split(",") "#),
+            eval(r#" ["a", "b", "c"] "#)
+        );
+        assert_eq!(
+            eval(r#" ",a,,".split(",") "#),
+            eval(r#" ["", "a", "", ""] "#)
+        );
+        assert_eq!(eval(r#" "a::b".split("::") "#), eval(r#" ["a", "b"] "#));
+        assert_eq!(eval(r#" "".split(",") "#), eval(r#" [""] "#));
+        assert_eq!(eval(r#" "a¢".split("") "#), eval(r#" ["a", "¢"] "#));
+        assert_eq!(
+            eval(r#" ["a", "b"].join("-") "#),
+            Ok(Value::String("a-b".to_owned().into()))
+        );
+        assert_eq!(
+            eval(r#" ["a", "b"].join() "#),
+            Ok(Value::String("ab".to_owned().into()))
+        );
+        assert_eq!(
+            eval(r#" [].join(",") "#),
+            Ok(Value::String(String::new().into()))
+        );
+        assert_eq!(
+            eval(r#" "a,b".split(",").join(",") == "a,b" "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval(r#" ["a", 1].join(",") "#),
+            Err(Error::InvalidListElement(
+                Identifier::new("join"),
+                Kind::I64
+            ))
+        );
+        assert_eq!(
+            eval(r#" ["a"].join(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new("join"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "a".split() "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::String,
+                Identifier::new("split"),
+                vec![]
+            ))
+        );
+    }
+
+    #[test]
+    fn code_points() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let string = |s: &str| Ok(Value::String(s.to_owned().into()));
+        assert_eq!(eval(r#" "a¢b".charAt(1) "#), string("¢"));
+        assert_eq!(eval(r#" "a¢b".charAt(3) "#), string(""));
+        assert_eq!(
+            eval(r#" "a¢b".charAt(4) "#),
+            Err(Error::IndexOutOfRange(4, 3))
+        );
+        assert_eq!(
+            eval(r#" "a¢b".charAt(-1) "#),
+            Err(Error::IndexOutOfRange(-1, 3))
+        );
+        assert_eq!(eval(r#" "a¢b".substring(1) "#), string("¢b"));
+        assert_eq!(eval(r#" "a¢b".substring(1, 2) "#), string("¢"));
+        assert_eq!(eval(r#" "a¢b".substring(3, 3) "#), string(""));
+        assert_eq!(
+            eval(r#" "a¢b".substring(2, 1) "#),
+            Err(Error::InvalidRange(2, 1))
+        );
+        assert_eq!(
+            eval(r#" "a¢b".substring(0, 5) "#),
+            Err(Error::IndexOutOfRange(5, 3))
+        );
+        assert_eq!(eval(r#" "a¢b¢".indexOf("¢") "#), Ok(Value::I64(1)));
+        assert_eq!(eval(r#" "a¢b¢".indexOf("b¢") "#), Ok(Value::I64(2)));
+        assert_eq!(eval(r#" "a¢b¢".indexOf("¢", 2) "#), Ok(Value::I64(3)));
+        assert_eq!(eval(r#" "a¢b¢".indexOf("c") "#), Ok(Value::I64(-1)));
+        assert_eq!(eval(r#" "a¢b¢".indexOf("", 4) "#), Ok(Value::I64(4)));
+        assert_eq!(
+            eval(r#" "a".indexOf("a", 2) "#),
+            Err(Error::IndexOutOfRange(2, 1))
+        );
+        assert_eq!(
+            eval(r#" "abc".substring(0, 1 + 1) + "abc".charAt(2) "#),
+            string("abc")
+        );
+        assert_eq!(
+            eval(r#" "a".substring("b") "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::String,
+                Identifier::new("substring"),
+                vec![Kind::String]
+            ))
+        );
+    }
+
+    #[test]
+    fn format() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        let string = |s: &str| Ok(Value::String(s.to_owned().into()));
+        assert_eq!(
+            eval(r#" "user %s has %d points".format(["bob", 10]) "#),
+            string("user bob has 10 points")
+        );
+        assert_eq!(
+            eval(r#" "%f %.2f %.0f %f".format([1.5, 2, 2.5, double("-inf")]) "#),
+            string("1.500000 2.00 2 -Infinity")
+        );
+        assert_eq!(
+            eval(r#" "%b %b %x %X %x %x".format([5, -5, 255, 255, "hi", b"\x01"]) "#),
+            string("101 -101 ff FF 6869 01")
+        );
+        assert_eq!(
+            eval(r#" "%s|%s|%s|%s|%s".format([1.5, true, null, [1, "a"], {"k": uint("2")}]) "#),
+            string(r#"1.5|true|null|[1, "a"]|{"k": uint("2")}"#)
+        );
+        assert_eq!(eval(r#" "100%% ¢%s".format(["!"]) "#), string("100% ¢!"));
+        assert_eq!(eval(r#" "".format([]) "#), string(""));
+        assert_eq!(
+            eval(r#" "%s and %s".format(["a"]) "#),
+            Err(Error::FormatArity(2, 1))
+        );
+        assert_eq!(
+            eval(r#" "%s".format(["a", "b"]) "#),
+            Err(Error::FormatArity(1, 2))
+        );
+        assert_eq!(
+            eval(r#" "%d".format(["a"]) "#),
+            Err(Error::InvalidFormatArgument("%d".to_owned(), Kind::String))
+        );
+        assert_eq!(
+            eval(r#" "%.2f".format(["a"]) "#),
+            Err(Error::InvalidFormatArgument(
+                "%.2f".to_owned(),
+                Kind::String
+            ))
+        );
+        for verb in &["%q", "%", "%.2d", "%.f", "%.101f", "%¢"] {
+            assert_eq!(
+                eval(&format!(r#" "a{}".format([1]) "#, verb)),
+                Err(Error::InvalidFormat((*verb).to_owned())),
+                "{}",
+                verb
+            );
+        }
+    }
+
+    #[test]
+    fn sort_and_reverse() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" [3, 1, 2].sort() "#), eval(r#" [1, 2, 3] "#));
+        assert_eq!(
+            eval(r#" ["b", "B", "a"].sort() "#),
+            eval(r#" ["B", "a", "b"] "#)
+        );
+        assert_eq!(
+            eval(r#" [true, false].sort() "#),
+            eval(r#" [false, true] "#)
+        );
+        assert_eq!(eval(r#" [].sort() "#), eval(r#" [] "#));
+        match eval(r#" [2.0, double("nan"), -1.0].sort() "#) {
+            Ok(Value::List(vs)) => {
+                assert_eq!(vs[..2], [Value::F64(-1.0), Value::F64(2.0)]);
+                assert!(matches!(vs[2], Value::F64(f) if f.is_nan()));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(eval(r#" [1, 2, 3].reverse() "#), eval(r#" [3, 2, 1] "#));
+        assert_eq!(eval(r#" [[1], "a"].reverse() "#), eval(r#" ["a", [1]] "#));
+        assert_eq!(
+            eval(r#" [1, "a"].sort() "#),
+            Err(Error::InvalidTypesForOperator(
+                Kind::I64,
+                Kind::String,
+                Op::Lt
+            ))
+        );
+        assert_eq!(
+            eval(r#" [1, 2.0].sort() "#),
+            Err(Error::InvalidTypesForOperator(Kind::I64, Kind::F64, Op::Lt))
+        );
+        assert_eq!(
+            eval(r#" [[2], [1]].sort() "#),
+            Err(Error::InvalidListElement(
+                Identifier::new("sort"),
+                Kind::List
+            ))
+        );
+        assert_eq!(
+            eval(r#" "ab".reverse() "#),
+            Err(Error::NoMethodOnType(
+                Kind::String,
+                Identifier::new("reverse")
+            ))
+        );
+    }
+
+    #[test]
+    fn distinct_and_flatten() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(
+            eval(r#" [1, 2, 1, 3, 2].distinct() "#),
+            eval(r#" [1, 2, 3] "#)
+        );
+        assert_eq!(
+            eval(r#" [[1], {"a": 1}, [1], {"a": 1}, "1", 1].distinct() "#),
+            eval(r#" [[1], {"a": 1}, "1", 1] "#)
+        );
+        assert_eq!(eval(r#" [].distinct() "#), eval(r#" [] "#));
+        assert_eq!(
+            eval(r#" [1, [2, [3, [4]]], []].flatten() "#),
+            eval(r#" [1, 2, [3, [4]]] "#)
+        );
+        assert_eq!(
+            eval(r#" [1, [2, [3, [4]]], []].flatten(2) "#),
+            eval(r#" [1, 2, 3, [4]] "#)
+        );
+        assert_eq!(
+            eval(r#" [1, [2, [3, [4]]], []].flatten(10) "#),
+            eval(r#" [1, 2, 3, 4] "#)
+        );
+        assert_eq!(eval(r#" [[1]].flatten(0) "#), eval(r#" [[1]] "#));
+        assert_eq!(
+            eval(r#" [[1]].flatten(-1) "#),
+            Err(Error::NegativeArgument(Identifier::new("flatten"), -1))
+        );
+        assert_eq!(
+            eval(r#" [[1]].flatten("1") "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new("flatten"),
+                vec![Kind::String]
+            ))
+        );
+    }
+
+    #[test]
+    fn contains_key() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(
+            eval(r#" {"a": 1}.containsKey("a") "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval(r#" {"a": null}.containsKey("a") "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval(r#" {"a": 1}.containsKey("b") "#),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(eval(r#" {}.containsKey("") "#), Ok(Value::Bool(false)));
+        assert_eq!(
+            eval(r#" {"a": 1}.containsKey(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Map,
+                Identifier::new("containsKey"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" ["a"].containsKey("a") "#),
+            Err(Error::NoMethodOnType(
+                Kind::List,
+                Identifier::new("containsKey")
+            ))
+        );
+    }
+
+    #[test]
+    fn optionals() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" {"a": 1}[?"a"].orValue(0) "#), Ok(Value::I64(1)));
+        assert_eq!(eval(r#" {"a": 1}[?"b"].orValue(0) "#), Ok(Value::I64(0)));
+        assert_eq!(eval(r#" [1, 2][?1].hasValue() "#), Ok(Value::Bool(true)));
+        assert_eq!(eval(r#" [1, 2][?2].hasValue() "#), Ok(Value::Bool(false)));
+        assert_eq!(eval(r#" [1, 2][?-1].hasValue() "#), Ok(Value::Bool(false)));
+        // Indexing an optional indexes what it holds, if anything.
+        assert_eq!(
+            eval(r#" {"a": {"b": [3]}}[?"a"][?"b"][?0].orValue(0) "#),
+            Ok(Value::I64(3))
+        );
+        assert_eq!(
+            eval(r#" {"a": {}}[?"a"][?"b"][?0].orValue(0) "#),
+            Ok(Value::I64(0))
+        );
+        assert_eq!(
+            eval(r#" optional.of(null).hasValue() "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(eval(r#" optional.none().orValue("x") "#), eval(r#" "x" "#));
+        for zero in &[
+            "0", "uint(0)", "0.0", "false", "''", "b''", "[]", "{}", "null",
+        ] {
+            let input = format!("optional.ofNonZeroValue({})", zero);
+            assert_eq!(eval(&input), Ok(Value::Optional(None)), "{}", input);
+        }
+        assert_eq!(
+            eval(r#" optional.ofNonZeroValue([0]) "#),
+            Ok(Value::Optional(Some(Box::new(Value::List(vec![
+                Value::I64(0)
+            ])))))
+        );
+        assert_eq!(
+            eval(r#" optional.of(1) == optional.of(1) && optional.of(1) != optional.none() "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval(r#" "%s, %s".format([optional.of([1]), optional.none()]) "#),
+            eval(r#" "optional.of([1]), optional.none()" "#)
+        );
+        assert_eq!(
+            eval(r#" {"a": 1}[?1] "#),
+            Err(Error::InvalidTypesForOperator(
+                Kind::Map,
+                Kind::I64,
+                Op::OptionalIndex
+            ))
+        );
+        assert_eq!(
+            eval(r#" "abc"[?0] "#),
+            Err(Error::InvalidTypesForOperator(
+                Kind::String,
+                Kind::I64,
+                Op::OptionalIndex
+            ))
+        );
+        assert_eq!(
+            eval(r#" 1.orValue(0) "#),
+            Err(Error::NoMethodOnType(Kind::I64, Identifier::new("orValue")))
+        );
+        assert_eq!(
+            eval(r#" optional.none().hasValue(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Optional,
+                Identifier::new("hasValue"),
+                vec![Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" optional.of() "#),
+            Err(Error::NoFunctionWithSignature(
+                Identifier::new("optional.of"),
+                vec![]
+            ))
+        );
+    }
+
+    #[test]
+    fn get() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" {"a": 1}.get("a", 0) "#), Ok(Value::I64(1)));
+        assert_eq!(eval(r#" {"a": 1}.get("b", 0) "#), Ok(Value::I64(0)));
+        assert_eq!(eval(r#" {"a": null}.get("a", 0) "#), Ok(Value::Null));
+        assert_eq!(eval(r#" {}.get("a", [1]) "#), eval(r#" [1] "#));
+        assert_eq!(
+            eval(r#" {"a": {"b": 2}}.get("a", {}).get("b", 0) "#),
+            Ok(Value::I64(2))
+        );
+        assert_eq!(
+            eval(r#" {"a": 1}.get("a") "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Map,
+                Identifier::new("get"),
+                vec![Kind::String]
+            ))
+        );
+        assert_eq!(
+            eval(r#" {"a": 1}.get(1, 0) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::Map,
+                Identifier::new("get"),
+                vec![Kind::I64, Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" [1].get("a", 0) "#),
+            Err(Error::NoMethodOnType(Kind::List, Identifier::new("get")))
+        );
+    }
+
+    #[test]
+    fn list_index_of() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(eval(r#" [1, 2, 1].indexOf(1) "#), Ok(Value::I64(0)));
+        assert_eq!(eval(r#" [1, 2, 1].lastIndexOf(1) "#), Ok(Value::I64(2)));
+        assert_eq!(
+            eval(r#" [1, [2], {"a": 3}].indexOf([2]) "#),
+            Ok(Value::I64(1))
+        );
+        assert_eq!(
+            eval(r#" [1, [2], {"a": 3}].lastIndexOf({"a": 3}) "#),
+            Ok(Value::I64(2))
+        );
+        // Absent, or equal only after a conversion.
+        assert_eq!(eval(r#" [1, 2].indexOf(3) "#), Ok(Value::I64(-1)));
+        assert_eq!(eval(r#" [1, 2].lastIndexOf("1") "#), Ok(Value::I64(-1)));
+        assert_eq!(eval(r#" [].indexOf(null) "#), Ok(Value::I64(-1)));
+        assert_eq!(
+            eval(r#" [1].indexOf(1, 0) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::List,
+                Identifier::new("indexOf"),
+                vec![Kind::I64, Kind::I64]
+            ))
+        );
+        assert_eq!(
+            eval(r#" "abc".lastIndexOf("c") "#),
+            Err(Error::NoMethodOnType(
+                Kind::String,
+                Identifier::new("lastIndexOf")
+            ))
+        );
+    }
+
+    #[test]
+    fn matches() {
+        let eval = |input: &str| EvalContext::default().evaluate(parse(input).unwrap());
+        assert_eq!(
+            eval(r#" "abc123".matches("[a-z]+[0-9]+") "#),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(eval(r#" "abc123".matches("c1") "#), Ok(Value::Bool(true)));
+        assert_eq!(eval(r#" "abc123".matches("^c1") "#), Ok(Value::Bool(false)));
+        assert_eq!(eval(r#" "¢".matches("^.$") "#), Ok(Value::Bool(true)));
+        assert!(matches!(
+            eval(r#" "abc".matches("[a-") "#),
+            Err(Error::InvalidRegex(_))
+        ));
+        // Patterns that would compile to more than the limit, or nest too deeply, are refused.
+        assert!(matches!(
+            eval(r#" "abc".matches("a{1000}{1000}") "#),
+            Err(Error::InvalidRegex(_))
+        ));
+        let nested = format!("{}a{}", "(".repeat(100), ")".repeat(100));
+        assert!(matches!(
+            eval(&format!(r#" "a".matches("{}") "#, nested)),
+            Err(Error::InvalidRegex(_))
+        ));
+        assert_eq!(
+            eval(r#" "abc".matches(1) "#),
+            Err(Error::NoMethodWithSignature(
+                Kind::String,
+                Identifier::new("matches"),
+                vec![Kind::I64]
+            ))
+        );
+    }
+
+    /// Each signature's example calls it with arguments of the declared kinds, and the method
+    /// returns a value of the declared result kind.
+    #[test]
+    fn signatures_match_dispatch() {
+        let ctx = EvalContext::default();
+        for sig in SIGNATURES {
+            let (operand, name, args) = match parse(&sig.example) {
+                Ok(Expression::Method(ref mut operand, ref mut name, ref mut args)) => {
+                    (operand.take(), std::mem::take(name), std::mem::take(args))
+                }
+                other => panic!("{} is not a method call: {:?}", sig.example, other),
+            };
+            assert_eq!(name.0, sig.name, "{}", sig.example);
+            let operand = ctx.evaluate(operand).expect("operand");
+            assert_eq!(Some(operand.kind()), sig.operand, "{}", sig.example);
+            let args: Vec<Value> = args
+                .into_iter()
+                .map(|arg| ctx.evaluate(arg).expect("argument"))
+                .collect();
+            assert_eq!(args.len(), sig.args.len(), "{}", sig.example);
+            for (arg, kind) in args.iter().zip(sig.args.iter()) {
+                if let Some(kind) = kind {
+                    assert_eq!(arg.kind(), *kind, "{}", sig.example);
+                }
+            }
+            let result = evaluate_method(name, operand, args).expect("result");
+            assert_eq!(result.kind(), sig.result, "{}", sig.example);
+        }
+    }
+}
@@ -1,8 +1,12 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Clone, Serialize)]
+/// Cloning and dropping are iterative rather than derived, so that no expression is too deep to
+/// clone or drop, however long a chain of operators it has.
+#[derive(Debug, PartialEq, Serialize)]
 pub enum Expression {
     LetBinding {
         id: Identifier,
@@ -30,9 +34,26 @@ pub enum Expression {
     Neg(Box<Expression>),
     Not(Box<Expression>),
     Member(Box<Expression>, Identifier),
+    /// `a?.b`: the member of a map, or null if the map has no such member or `a` is null.
+    SafeMember(Box<Expression>, Identifier),
+    /// `a[?k]`: the element of a list or entry of a map, as an optional that is empty if there is
+    /// none, or if `a` is an empty optional.
+    OptionalIndex(Box<Expression>, Box<Expression>),
     Method(Box<Expression>, Identifier, Vec<Expression>),
+    Function(Identifier, Vec<Expression>),
     Lit(Literal),
     Binding(Identifier),
+    /// The expression, and where in the source it was parsed from. Only `parse_with_spans`
+    /// produces these.
+    Spanned(Span, Box<Expression>),
+}
+
+/// A range of byte offsets into the source of an expression.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 impl Expression {
@@ -56,26 +77,413 @@ impl Expression {
             Expression::Neg(_) => Op::Neg,
             Expression::Not(_) => Op::Not,
             Expression::Member(_, id) => Op::Member(id.clone()),
+            Expression::SafeMember(_, id) => Op::SafeMember(id.clone()),
+            Expression::OptionalIndex(..) => Op::OptionalIndex,
             Expression::Method(_, id, _) => Op::Method(id.clone()),
+            Expression::Function(id, _) => Op::Function(id.clone()),
             Expression::Lit(_) => Op::Lit,
             Expression::Binding(_) => Op::Lookup,
+            Expression::Spanned(_, e) => e.op(),
+        }
+    }
+
+    /// `self.op().precedence()`, without copying the name of a member, method or function.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Expression::Member(..) | Expression::SafeMember(..) | Expression::Method(..) => 8,
+            Expression::Function(..) => 9,
+            Expression::Spanned(_, e) => e.precedence(),
+            e => e.op().precedence(),
+        }
+    }
+
+    /// The direct subexpressions, in source order, each labeled with the role it plays, e.g.
+    /// `"condition"` for the first operand of a ternary.
+    pub fn children(&self) -> Vec<(&'static str, &Expression)> {
+        match self {
+            Expression::LetBinding { value, body, .. } => vec![("value", value), ("body", body)],
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => vec![
+                ("condition", condition),
+                ("true_branch", true_branch),
+                ("else_branch", else_branch),
+            ],
+            Expression::Or(cs) | Expression::And(cs) => cs.iter().map(|c| ("operand", c)).collect(),
+            Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::Gt(a, b)
+            | Expression::Add(a, b)
+            | Expression::Sub(a, b)
+            | Expression::Mul(a, b)
+            | Expression::Div(a, b)
+            | Expression::Mod(a, b) => vec![("left", a), ("right", b)],
+            Expression::OptionalIndex(a, k) => vec![("operand", a), ("index", k)],
+            Expression::Neg(a)
+            | Expression::Not(a)
+            | Expression::Member(a, _)
+            | Expression::SafeMember(a, _) => {
+                vec![("operand", a)]
+            }
+            Expression::Method(a, _, args) => std::iter::once(("receiver", &**a))
+                .chain(args.iter().map(|arg| ("argument", arg)))
+                .collect(),
+            Expression::Function(_, args) => args.iter().map(|arg| ("argument", arg)).collect(),
+            Expression::Lit(Literal::List(elems)) => elems.iter().map(|e| ("element", e)).collect(),
+            Expression::Lit(Literal::Map(kvs)) => kvs
+                .iter()
+                .flat_map(|(k, v)| vec![("key", k), ("value", v)])
+                .collect(),
+            Expression::Lit(_) | Expression::Binding(_) => vec![],
+            Expression::Spanned(_, e) => e.children(),
+        }
+    }
+
+    /// The names the expression looks up that no `let` in it binds, in order of first use: those a
+    /// host must bind to evaluate it. A `let`'s value is outside its scope, so `x` is free in
+    /// `let x = x + 1; x`.
+    pub fn free_variables(&self) -> Vec<Identifier> {
+        let mut free = Vec::new();
+        self.collect_free(&mut Vec::new(), &mut free);
+        free
+    }
+
+    fn collect_free<'a>(&'a self, bound: &mut Vec<&'a Identifier>, free: &mut Vec<Identifier>) {
+        match self {
+            Expression::Binding(id) => {
+                if !bound.contains(&id) && !free.contains(id) {
+                    free.push(id.clone());
+                }
+            }
+            Expression::LetBinding { id, value, body } => {
+                value.collect_free(bound, free);
+                bound.push(id);
+                body.collect_free(bound, free);
+                bound.pop();
+            }
+            Expression::Spanned(_, e) => e.collect_free(bound, free),
+            e => {
+                for (_, child) in e.children() {
+                    child.collect_free(bound, free);
+                }
+            }
+        }
+    }
+
+    /// A stable 128-bit hash of the expression's canonical form (see `canonical`), the same for
+    /// expressions that differ only in layout or in the order of commutative operands.
+    pub fn fingerprint(&self) -> u128 {
+        crate::canonical::fingerprint(self)
+    }
+
+    /// A Graphviz DOT graph of the expression: a node for each subexpression, labeled with its
+    /// operator, name or literal value, and an edge to each of its children, labeled with the
+    /// child's role.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph {\n    node [shape=box];\n");
+        self.write_dot(&mut 0, &mut out);
+        out.push_str("}\n");
+        out
+    }
+
+    /// Write the node for this expression, numbered `next`, and those of its subexpressions, which
+    /// are numbered from `next + 1` in pre-order. Returns this node's number.
+    fn write_dot(&self, next: &mut usize, out: &mut String) -> usize {
+        if let Expression::Spanned(_, e) = self {
+            return e.write_dot(next, out);
+        }
+        let id = *next;
+        *next += 1;
+        let label = self.dot_label().replace('\\', "\\\\").replace('"', "\\\"");
+        out.push_str(&format!("    n{} [label=\"{}\"];\n", id, label));
+        for (role, child) in self.children() {
+            let child = child.write_dot(next, out);
+            out.push_str(&format!(
+                "    n{} -> n{} [label=\"{}\"];\n",
+                id, child, role
+            ));
+        }
+        id
+    }
+
+    /// What the node for this expression in `to_dot`'s graph shows.
+    fn dot_label(&self) -> String {
+        let symbol = match self {
+            Expression::LetBinding { id, .. } => return format!("let {}", id.0),
+            Expression::Ternary { .. } => "?:",
+            Expression::Or(_) => "||",
+            Expression::And(_) => "&&",
+            Expression::Eq(_, _) => "==",
+            Expression::Neq(_, _) => "!=",
+            Expression::Lt(_, _) => "<",
+            Expression::Lte(_, _) => "<=",
+            Expression::Gte(_, _) => ">=",
+            Expression::Gt(_, _) => ">",
+            Expression::Add(_, _) => "+",
+            Expression::Sub(_, _) => "-",
+            Expression::Mul(_, _) => "*",
+            Expression::Div(_, _) => "/",
+            Expression::Mod(_, _) => "%",
+            Expression::Neg(_) => "-",
+            Expression::Not(_) => "!",
+            Expression::Member(_, id) => return format!(".{}", id.0),
+            Expression::SafeMember(_, id) => return format!("?.{}", id.0),
+            Expression::OptionalIndex(..) => "[?]",
+            Expression::Method(_, id, _) => return format!(".{}()", id.0),
+            Expression::Function(id, _) => return format!("{}()", id.0),
+            Expression::Lit(Literal::List(_)) => "[]",
+            Expression::Lit(Literal::Map(_)) => "{}",
+            Expression::Lit(_) => return crate::format::format(self),
+            Expression::Binding(id) => return id.0.clone(),
+            Expression::Spanned(_, e) => return e.dot_label(),
+        };
+        symbol.to_owned()
+    }
+
+    /// Move the expression out, leaving `null` in its place. Patterns can't move the fields out of
+    /// an `Expression`, since it implements `Drop`, so they take them instead.
+    pub(crate) fn take(&mut self) -> Expression {
+        std::mem::replace(self, Expression::Lit(Literal::Null))
+    }
+
+    /// Whether the expression has no subexpressions.
+    fn is_leaf(&self) -> bool {
+        match self {
+            Expression::Lit(Literal::List(elems)) => elems.is_empty(),
+            Expression::Lit(Literal::Map(kvs)) => kvs.is_empty(),
+            Expression::Lit(_) | Expression::Binding(_) => true,
+            _ => false,
+        }
+    }
+
+    /// A copy of this node alone, with `null` in place of each of its direct subexpressions.
+    fn shell(&self) -> Expression {
+        let hole = || Box::new(Expression::Lit(Literal::Null));
+        let holes = |n: usize| (0..n).map(|_| Expression::Lit(Literal::Null)).collect();
+        match self {
+            Expression::LetBinding { id, .. } => Expression::LetBinding {
+                id: id.clone(),
+                value: hole(),
+                body: hole(),
+            },
+            Expression::Ternary { .. } => Expression::Ternary {
+                condition: hole(),
+                true_branch: hole(),
+                else_branch: hole(),
+            },
+            Expression::Or(cs) => Expression::Or(holes(cs.len())),
+            Expression::And(cs) => Expression::And(holes(cs.len())),
+            Expression::Eq(..) => Expression::Eq(hole(), hole()),
+            Expression::Neq(..) => Expression::Neq(hole(), hole()),
+            Expression::Lt(..) => Expression::Lt(hole(), hole()),
+            Expression::Lte(..) => Expression::Lte(hole(), hole()),
+            Expression::Gte(..) => Expression::Gte(hole(), hole()),
+            Expression::Gt(..) => Expression::Gt(hole(), hole()),
+            Expression::Add(..) => Expression::Add(hole(), hole()),
+            Expression::Sub(..) => Expression::Sub(hole(), hole()),
+            Expression::Mul(..) => Expression::Mul(hole(), hole()),
+            Expression::Div(..) => Expression::Div(hole(), hole()),
+            Expression::Mod(..) => Expression::Mod(hole(), hole()),
+            Expression::OptionalIndex(..) => Expression::OptionalIndex(hole(), hole()),
+            Expression::Neg(_) => Expression::Neg(hole()),
+            Expression::Not(_) => Expression::Not(hole()),
+            Expression::Member(_, id) => Expression::Member(hole(), id.clone()),
+            Expression::SafeMember(_, id) => Expression::SafeMember(hole(), id.clone()),
+            Expression::Method(_, id, args) => {
+                Expression::Method(hole(), id.clone(), holes(args.len()))
+            }
+            Expression::Function(id, args) => Expression::Function(id.clone(), holes(args.len())),
+            Expression::Lit(Literal::List(elems)) => {
+                Expression::Lit(Literal::List(holes(elems.len())))
+            }
+            Expression::Lit(Literal::Map(kvs)) => Expression::Lit(Literal::Map(
+                kvs.iter()
+                    .map(|_| {
+                        (
+                            Expression::Lit(Literal::Null),
+                            Expression::Lit(Literal::Null),
+                        )
+                    })
+                    .collect(),
+            )),
+            Expression::Lit(literal) => Expression::Lit(literal.clone()),
+            Expression::Binding(id) => Expression::Binding(id.clone()),
+            Expression::Spanned(span, _) => Expression::Spanned(*span, hole()),
+        }
+    }
+
+    /// Call `f` with each direct subexpression, in source order, the one a span wraps included.
+    fn subexpressions<'a>(&'a self, mut f: impl FnMut(&'a Expression)) {
+        match self {
+            Expression::LetBinding { value, body, .. } => {
+                f(value);
+                f(body);
+            }
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => {
+                f(condition);
+                f(true_branch);
+                f(else_branch);
+            }
+            Expression::Or(cs) | Expression::And(cs) | Expression::Function(_, cs) => {
+                cs.iter().for_each(f)
+            }
+            Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::Gt(a, b)
+            | Expression::Add(a, b)
+            | Expression::Sub(a, b)
+            | Expression::Mul(a, b)
+            | Expression::Div(a, b)
+            | Expression::Mod(a, b)
+            | Expression::OptionalIndex(a, b) => {
+                f(a);
+                f(b);
+            }
+            Expression::Neg(a)
+            | Expression::Not(a)
+            | Expression::Member(a, _)
+            | Expression::SafeMember(a, _)
+            | Expression::Spanned(_, a) => f(a),
+            Expression::Method(a, _, args) => {
+                f(a);
+                args.iter().for_each(f);
+            }
+            Expression::Lit(Literal::List(elems)) => elems.iter().for_each(f),
+            Expression::Lit(Literal::Map(kvs)) => {
+                for (k, v) in kvs {
+                    f(k);
+                    f(v);
+                }
+            }
+            Expression::Lit(_) | Expression::Binding(_) => {}
+        }
+    }
+
+    /// Move each direct subexpression that has subexpressions of its own into `into`.
+    fn take_subexpressions(&mut self, into: &mut Vec<Expression>) {
+        self.subexpressions_mut(|e| {
+            if !e.is_leaf() {
+                into.push(e.take());
+            }
+        });
+    }
+
+    /// Like `subexpressions`, but mutably.
+    fn subexpressions_mut<'a>(&'a mut self, mut f: impl FnMut(&'a mut Expression)) {
+        match self {
+            Expression::LetBinding { value, body, .. } => {
+                f(value);
+                f(body);
+            }
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => {
+                f(condition);
+                f(true_branch);
+                f(else_branch);
+            }
+            Expression::Or(cs) | Expression::And(cs) | Expression::Function(_, cs) => {
+                cs.iter_mut().for_each(f)
+            }
+            Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::Gt(a, b)
+            | Expression::Add(a, b)
+            | Expression::Sub(a, b)
+            | Expression::Mul(a, b)
+            | Expression::Div(a, b)
+            | Expression::Mod(a, b)
+            | Expression::OptionalIndex(a, b) => {
+                f(a);
+                f(b);
+            }
+            Expression::Neg(a)
+            | Expression::Not(a)
+            | Expression::Member(a, _)
+            | Expression::SafeMember(a, _)
+            | Expression::Spanned(_, a) => f(a),
+            Expression::Method(a, _, args) => {
+                f(a);
+                args.iter_mut().for_each(f);
+            }
+            Expression::Lit(Literal::List(elems)) => elems.iter_mut().for_each(f),
+            Expression::Lit(Literal::Map(kvs)) => {
+                for (k, v) in kvs {
+                    f(k);
+                    f(v);
+                }
+            }
+            Expression::Lit(_) | Expression::Binding(_) => {}
+        }
+    }
+}
+
+impl Clone for Expression {
+    fn clone(&self) -> Expression {
+        let mut root = self.shell();
+        // Pairs of a subexpression and the shell of its copy, whose subexpressions are still null.
+        let mut pending = vec![(self, &mut root)];
+        while let Some((original, copy)) = pending.pop() {
+            let mut originals = Vec::new();
+            original.subexpressions(|e| originals.push(e));
+            let mut originals = originals.into_iter();
+            copy.subexpressions_mut(|slot| {
+                let original = originals.next().expect("subexpression");
+                *slot = original.shell();
+                pending.push((original, slot));
+            });
+        }
+        root
+    }
+}
+
+impl Drop for Expression {
+    fn drop(&mut self) {
+        // Take the subexpressions out before dropping each node, so that no drop recurses.
+        let mut pending = Vec::new();
+        self.take_subexpressions(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.take_subexpressions(&mut pending);
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
 pub enum Kind {
     I64,
+    U64,
     F64,
     Bool,
     String,
     Bytes,
     List,
     Map,
+    Timestamp,
+    Duration,
     Null,
+    Optional,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
 #[serde(tag = "t", content = "c")]
 pub enum Op {
     Not,
@@ -96,35 +504,66 @@ pub enum Op {
     Lit,
     Lookup,
     Member(Identifier),
+    SafeMember(Identifier),
+    OptionalIndex,
     Method(Identifier),
+    Function(Identifier),
     LetBinding,
     Ternary,
 }
 
+impl Op {
+    /// How tightly the operator binds its operands, from 0 for `let` up to 9 for operands that
+    /// need no parentheses, like literals. An operand whose operator has lower precedence than its
+    /// parent's must be parenthesized.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Op::LetBinding => 0,
+            Op::Ternary => 1,
+            Op::Or => 2,
+            Op::And => 3,
+            Op::Eq | Op::Neq | Op::Lt | Op::Lte | Op::Gt | Op::Gte => 4,
+            Op::Plus | Op::Minus => 5,
+            Op::Times | Op::Div | Op::Mod => 6,
+            Op::Not | Op::Neg => 7,
+            Op::Member(_) | Op::SafeMember(_) | Op::OptionalIndex | Op::Method(_) => 8,
+            Op::Lit | Op::Lookup | Op::Function(_) => 9,
+        }
+    }
+}
+
 impl Value {
     pub fn kind(&self) -> Kind {
         match *self {
             Value::I64(_) => Kind::I64,
+            Value::U64(_) => Kind::U64,
             Value::F64(_) => Kind::F64,
             Value::Bool(_) => Kind::Bool,
             Value::String(_) => Kind::String,
             Value::Bytes(_) => Kind::Bytes,
             Value::List(_) => Kind::List,
             Value::Map(_) => Kind::Map,
+            Value::Timestamp(_) => Kind::Timestamp,
+            Value::Duration(_) => Kind::Duration,
             Value::Null => Kind::Null,
+            Value::Optional(_) => Kind::Optional,
         }
     }
 
     pub fn size(&self) -> usize {
         let transitive = match self {
             Value::I64(_) => 0,
+            Value::U64(_) => 0,
             Value::F64(_) => 0,
             Value::Bool(_) => 0,
             Value::String(s) => s.len(),
             Value::Bytes(b) => b.len(),
+            Value::Timestamp(_) => 0,
+            Value::Duration(_) => 0,
             Value::Null => 0,
             Value::List(children) => children.iter().map(|v| v.size()).sum(),
             Value::Map(children) => children.iter().map(|(k, v)| k.len() + v.size()).sum(),
+            Value::Optional(v) => v.as_ref().map_or(0, |v| v.size()),
         };
         std::mem::size_of_val(self) + transitive
     }
@@ -143,34 +582,196 @@ pub enum Literal {
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
 #[serde(tag = "t", content = "c")]
 pub enum Value {
     I64(i64),
+    U64(u64),
     F64(f64),
     Bool(bool),
-    String(String),
-    Bytes(Vec<u8>),
+    /// Strings and bytes are shared rather than copied when a binding is looked up, or a value is
+    /// passed along unchanged.
+    String(Rc<String>),
+    Bytes(Rc<Vec<u8>>),
     List(Vec<Value>),
+    /// Entries are serialized in order of key; see `ordering`.
+    #[serde(serialize_with = "crate::ordering::serialize_map")]
     Map(HashMap<String, Value>),
+    Timestamp(Timestamp),
+    /// Nanoseconds.
+    Duration(i64),
     Null,
+    /// A value that may be absent, as CEL's optional types extension defines: see
+    /// `optional.of` and `m[?k]`.
+    Optional(Option<Box<Value>>),
+}
+
+/// A point in time between 0001-01-01 and 9999-12-31 (UTC), as CEL defines timestamps.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Serialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch.
+    pub seconds: i64,
+    /// Nanoseconds past `seconds`, in `0..1_000_000_000`.
+    pub nanos: u32,
 }
 
 #[derive(Debug, Eq, PartialEq, Serialize, Clone)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
 pub enum Error {
-    NoMethod(Identifier),
+    /// The unknown method, and similarly-named methods that do exist.
+    NoMethod(Identifier, Vec<Identifier>),
     NoMethodOnType(Kind, Identifier),
     NoMethodWithSignature(Kind, Identifier, Vec<Kind>),
+    /// The unknown function, and similarly-named functions that do exist.
+    NoFunction(Identifier, Vec<Identifier>),
+    NoFunctionWithSignature(Identifier, Vec<Kind>),
     InvalidTypeForOperator(Kind, Op),
     InvalidTypesForOperator(Kind, Kind, Op),
     DivisionByZero,
-    NoSuchBinding(Identifier),
+    /// The unknown binding, and similarly-named bindings that are in scope.
+    NoSuchBinding(Identifier, Vec<Identifier>),
     NoSuchMember(Identifier),
     InvalidMapKey(Kind),
+    /// A method of lists called on a list with an element of a kind it can't take, e.g. `join` on
+    /// a list with an int in it.
+    InvalidListElement(Identifier, Kind),
+    /// A method of lists with no result for an empty list, e.g. `max`.
+    EmptyList(Identifier),
+    /// An index, and the length of what it was into.
+    IndexOutOfRange(i64, usize),
+    /// A range, e.g. of a `substring`, whose end comes before its start.
+    InvalidRange(i64, i64),
     DuplicateMapKey(String),
-    EvaluationTooLarge,
+    /// A limit on the size of the evaluation was crossed. Under a span, the span is that of the
+    /// innermost spanned subexpression whose value crossed it.
+    EvaluationTooLarge(LimitExceeded),
+    TooManyOperations,
+    /// The evaluation's cancellation callback asked it to stop.
+    Cancelled,
+    /// A host-defined function failed.
+    HostError(String),
+    /// The error, and the source of the innermost spanned subexpression it came from.
+    At(Span, Box<Error>),
+    RecursionLimitExceeded,
+    InvalidTimestamp(String),
+    InvalidDuration(String),
+    TimestampOutOfRange,
+    DurationOutOfRange,
+    IntegerOverflow,
+    /// An integer raised to a negative power, whose result is not an integer.
+    NegativeExponent,
+    /// A method given a negative count, e.g. a `flatten` depth, and the count.
+    NegativeArgument(Identifier, i64),
+    ConversionOutOfRange(Kind, Kind),
+    InvalidConversion(Kind, String),
+    /// A pattern that isn't a regular expression, or compiles to one over the size limit, and why.
+    InvalidRegex(String),
+    /// A `format` with the given number of verbs, given the other number of arguments.
+    FormatArity(usize, usize),
+    /// A `%` in a `format` that isn't followed by a verb it knows, as written.
+    InvalidFormat(String),
+    /// A verb of a `format`, as written, and the kind of its argument, which it can't format.
+    InvalidFormatArgument(String, Kind),
+    /// Every error from operands that failed, in source order. Only reported under
+    /// `ErrorPolicy::Merged`.
+    Multiple(Vec<Error>),
+    /// A bug rather than a problem with the expression: a panic caught at a wasm export, or a
+    /// malformed program given to the stack machine.
+    Internal(String),
+}
+
+/// Which limit an evaluation crossed, what it was set to, and how much had been used by the time
+/// it was crossed.
+#[derive(Debug, Eq, PartialEq, Serialize, Copy, Clone)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub threshold: usize,
+    pub consumed: usize,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize, Copy, Clone)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
+pub enum Limit {
+    /// `EvalLimits::max_bytes`, the total size of all intermediate values.
+    Bytes,
+    /// `residual::MAX_CLAUSES`, the number of clauses a residual filter may have.
+    Clauses,
+}
+
+/// Which error to report when more than one operand of an expression fails, e.g. `1/0 == (true + 1)`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ErrorPolicy {
+    /// Stop at the leftmost failing operand and report its error.
+    #[default]
+    Leftmost,
+    /// Evaluate every operand and report all of their errors as an `Error::Multiple`.
+    Merged,
+}
+
+impl ErrorPolicy {
+    /// The error to report when `a` and then `b` both failed.
+    pub fn combine(self, a: Error, b: Error) -> Error {
+        match self {
+            ErrorPolicy::Leftmost => a,
+            ErrorPolicy::Merged => {
+                let mut errors = Vec::new();
+                for e in [a, b] {
+                    match e {
+                        Error::Multiple(es) => errors.extend(es),
+                        e => errors.push(e),
+                    }
+                }
+                Error::Multiple(errors)
+            }
+        }
+    }
+
+    /// Gather `results` in order. Under `Leftmost` this stops consuming `results` at the first error.
+    pub fn collect<T>(
+        self,
+        results: impl IntoIterator<Item = Result<T, Error>>,
+    ) -> Result<Vec<T>, Error> {
+        match self {
+            ErrorPolicy::Leftmost => results.into_iter().collect(),
+            ErrorPolicy::Merged => {
+                let mut values = Vec::new();
+                let mut error: Option<Error> = None;
+                for result in results {
+                    match (result, error.take()) {
+                        (Ok(v), None) => values.push(v),
+                        (Ok(_), Some(e)) => error = Some(e),
+                        (Err(e), None) => error = Some(e),
+                        (Err(e), Some(prev)) => error = Some(self.combine(prev, e)),
+                    }
+                }
+                match error {
+                    Some(e) => Err(e),
+                    None => Ok(values),
+                }
+            }
+        }
+    }
+}
+
+/// One overload of a built-in method or function, for editors building autocompletion and docs.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
+pub struct Signature {
+    pub name: Cow<'static, str>,
+    /// The receiver kind for methods; `None` for global functions.
+    pub operand: Option<Kind>,
+    /// Argument kinds; `None` accepts a value of any kind.
+    pub args: Cow<'static, [Option<Kind>]>,
+    pub result: Kind,
+    pub doc: Cow<'static, str>,
+    /// A short expression showing the overload in use.
+    pub example: Cow<'static, str>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "wasm", derive(tsify::Tsify))]
 pub struct Identifier(pub String);
 impl Identifier {
     pub fn new(name: &str) -> Identifier {
@@ -191,39 +792,164 @@ pub type EvalResult = Result<Value, Error>;
 mod test {
     use super::*;
 
+    #[test]
+    fn dot_graph() {
+        let input = r#"let s = "a\""; f(s, [1]) && !s.y"#;
+        let dot = crate::parser::parse(input).unwrap().to_dot();
+        assert_eq!(
+            dot,
+            crate::parser::parse_with_spans(input).unwrap().to_dot()
+        );
+        let expected = r#"digraph {
+    node [shape=box];
+    n0 [label="let s"];
+    n1 [label="\"a\\\"\""];
+    n0 -> n1 [label="value"];
+    n2 [label="&&"];
+    n3 [label="f()"];
+    n4 [label="s"];
+    n3 -> n4 [label="argument"];
+    n5 [label="[]"];
+    n6 [label="1"];
+    n5 -> n6 [label="element"];
+    n3 -> n5 [label="argument"];
+    n2 -> n3 [label="operand"];
+    n7 [label="!"];
+    n8 [label=".y"];
+    n9 [label="s"];
+    n8 -> n9 [label="operand"];
+    n7 -> n8 [label="operand"];
+    n2 -> n7 [label="operand"];
+    n0 -> n2 [label="body"];
+}
+"#;
+        assert_eq!(dot, expected);
+    }
+
+    #[test]
+    fn free_variables() {
+        let free = |input: &str| -> Vec<String> {
+            crate::parser::parse_with_spans(input)
+                .unwrap()
+                .free_variables()
+                .into_iter()
+                .map(|id| id.0)
+                .collect()
+        };
+        assert_eq!(free("b.x + a + b.y"), vec!["b", "a"]);
+        assert_eq!(free("let x = x + 1; let y = 2; x * y * z"), vec!["x", "z"]);
+        assert_eq!(free("let y = 2; [y].contains(size(z))"), vec!["z"]);
+        assert!(free("1 + 2").is_empty());
+    }
+
+    #[test]
+    fn precedence_matches_op() {
+        let expr =
+            crate::parser::parse_with_spans("f(x.y, x.m(1), -x, [1] + {}, a ? b : c)").unwrap();
+        let mut stack = vec![&expr];
+        while let Some(expr) = stack.pop() {
+            assert_eq!(expr.precedence(), expr.op().precedence(), "{:?}", expr);
+            stack.extend(expr.children().into_iter().map(|(_, child)| child));
+        }
+    }
+
+    #[test]
+    fn leftmost_keeps_first_error() {
+        let results = vec![
+            Ok(1),
+            Err(Error::DivisionByZero),
+            Err(Error::IntegerOverflow),
+        ];
+        assert_eq!(
+            ErrorPolicy::Leftmost.collect(results),
+            Err(Error::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn merged_flattens_errors() {
+        let results = vec![
+            Err(Error::DivisionByZero),
+            Ok(1),
+            Err(Error::Multiple(vec![
+                Error::IntegerOverflow,
+                Error::DurationOutOfRange,
+            ])),
+        ];
+        assert_eq!(
+            ErrorPolicy::Merged.collect(results),
+            Err(Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::IntegerOverflow,
+                Error::DurationOutOfRange,
+            ]))
+        );
+    }
+
+    #[test]
+    fn merged_single_error_is_unwrapped() {
+        let results = vec![Ok(1), Err(Error::DivisionByZero)];
+        assert_eq!(
+            ErrorPolicy::Merged.collect(results),
+            Err(Error::DivisionByZero)
+        );
+    }
+
     #[test]
     fn sizeof_primitive() {
-        assert_eq!(Value::Null.size(), 64);
-        assert_eq!(Value::Bool(true).size(), 64);
-        assert_eq!(Value::I64(42).size(), 64);
-        assert_eq!(Value::F64(2.78).size(), 64);
+        assert_eq!(Value::Null.size(), 56);
+        assert_eq!(Value::Bool(true).size(), 56);
+        assert_eq!(Value::I64(42).size(), 56);
+        assert_eq!(Value::U64(42).size(), 56);
+        assert_eq!(Value::F64(2.78).size(), 56);
+    }
+
+    #[test]
+    fn long_chains() {
+        let mut expr = Expression::Lit(Literal::I64(1));
+        for _ in 0..100_000 {
+            expr = Expression::Neg(Box::new(expr));
+        }
+        let copy = expr.clone();
+        drop(expr);
+        let mut depth = 0;
+        let mut e = &copy;
+        while let Expression::Neg(inner) = e {
+            depth += 1;
+            e = inner;
+        }
+        assert_eq!((depth, e), (100_000, &Expression::Lit(Literal::I64(1))));
+
+        let input = vec!["[1]"; 20_000].join(" + ");
+        let expr = crate::parser::parse_with_spans(&input).unwrap();
+        drop(expr.clone());
     }
 
     #[test]
     fn sizeof_string() {
-        let v = Value::String("asdf".to_owned());
-        assert_eq!(v.size(), 64 + 4);
+        let v = Value::String("asdf".to_owned().into());
+        assert_eq!(v.size(), 56 + 4);
     }
 
     #[test]
     fn sizeof_bytes() {
-        let v = Value::Bytes("asdf".as_bytes().to_owned());
-        assert_eq!(v.size(), 64 + 4);
+        let v = Value::Bytes("asdf".as_bytes().to_owned().into());
+        assert_eq!(v.size(), 56 + 4);
     }
 
     #[test]
     fn sizeof_list() {
         let v = Value::List(vec![
-            Value::String("asdf".to_owned()),
+            Value::String("asdf".to_owned().into()),
             Value::Null,
             Value::List(vec![]),
         ]);
-        assert_eq!(v.size(), 4 * 64 + 4);
+        assert_eq!(v.size(), 4 * 56 + 4);
     }
 
     #[test]
     fn sizeof_map() {
         let v = Value::Map(vec![("a".to_owned(), Value::Null)].into_iter().collect());
-        assert_eq!(v.size(), 2 * 64 + 1);
+        assert_eq!(v.size(), 2 * 56 + 1);
     }
 }
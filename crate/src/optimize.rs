@@ -0,0 +1,299 @@
+//! Rewrites of an expression that evaluate to the same result with less work, for hosts that
+//! compile an expression once and evaluate it many times.
+//!
+//! `fold_constants` evaluates ahead of time the subexpressions that don't depend on any binding,
+//! e.g. `2 * 3 + x` becomes `6 + x`. A subexpression that fails, like `1 / 0`, is left to fail when
+//! the expression is evaluated, so that folding never changes the result, errors included.
+//!
+//! `eliminate_dead_branches` removes what literal conditions decide, e.g. `true ? a : b` becomes
+//! `a`, for expressions generated from templates that fill in feature flags as literals. `optimize`
+//! does both.
+
+use crate::checker::strip_span;
+use crate::interpreter::EvalContext;
+use crate::methods;
+use crate::model::{Expression, Identifier, Literal, Value};
+use crate::residual::{literal, split};
+use std::mem;
+
+/// `expr` with each constant subexpression replaced by its value, and each `let` of a constant
+/// replaced by its value wherever it is used. Spans are kept.
+///
+/// The result evaluates the same as `expr` in any context with the same functions as `ctx`. Calls
+/// to custom functions, and to methods that aren't built in, aren't folded, since they may not always
+/// return the same result.
+pub fn fold_constants(ctx: &EvalContext, expr: Expression) -> Expression {
+    Folder {
+        ctx,
+        scopes: Vec::new(),
+    }
+    .fold(expr)
+    .0
+}
+
+/// `expr` with its constants folded, then its dead branches eliminated.
+pub fn optimize(ctx: &EvalContext, expr: Expression) -> Expression {
+    eliminate_dead_branches(fold_constants(ctx, expr))
+}
+
+/// `expr` without the ternary branches and `||` and `&&` operands that literal `true`s and
+/// `false`s decide: `true ? a : b` becomes `a`, `x || true` becomes `true`, and `false || x || y`
+/// becomes `x || y`. Spans are kept.
+///
+/// A literal stays beside a lone operand that might not be a bool, since the interpreter checks
+/// that each operand is one: `x || false` fails if `x` is an int, but `x` alone doesn't.
+pub fn eliminate_dead_branches(mut expr: Expression) -> Expression {
+    match &mut expr {
+        // A ternary that becomes one of its branches takes the branch's span.
+        Expression::Spanned(span, e) => match eliminate_dead_branches(e.take()) {
+            e @ Expression::Spanned(..) => e,
+            e => Expression::Spanned(*span, Box::new(e)),
+        },
+        Expression::Ternary {
+            condition,
+            true_branch,
+            else_branch,
+        } => {
+            let condition = eliminate_dead_branches(condition.take());
+            match literal_bool(&condition) {
+                Some(true) => eliminate_dead_branches(true_branch.take()),
+                Some(false) => eliminate_dead_branches(else_branch.take()),
+                None => Expression::Ternary {
+                    condition: Box::new(condition),
+                    true_branch: Box::new(eliminate_dead_branches(true_branch.take())),
+                    else_branch: Box::new(eliminate_dead_branches(else_branch.take())),
+                },
+            }
+        }
+        Expression::Or(operands) => logical(true, mem::take(operands)),
+        Expression::And(operands) => logical(false, mem::take(operands)),
+        Expression::LetBinding { id, value, body } => Expression::LetBinding {
+            id: mem::take(id),
+            value: Box::new(eliminate_dead_branches(value.take())),
+            body: Box::new(eliminate_dead_branches(body.take())),
+        },
+        _ => {
+            let (operands, rebuild) = split(expr);
+            rebuild(operands.into_iter().map(eliminate_dead_branches).collect())
+        }
+    }
+}
+
+/// `||` (if `is_or`) or `&&` of `operands`, without those that literals decide.
+fn logical(is_or: bool, operands: Vec<Expression>) -> Expression {
+    let operands: Vec<Expression> = operands.into_iter().map(eliminate_dead_branches).collect();
+    if operands.iter().any(|e| literal_bool(e) == Some(is_or)) {
+        return Expression::Lit(Literal::Bool(is_or));
+    }
+    let others: Vec<&Expression> = operands
+        .iter()
+        .filter(|e| literal_bool(e).is_none())
+        .collect();
+    // A lone other operand keeps one of the literals, to be checked to be a bool, unless it can
+    // only be one.
+    let mut spare = match others.as_slice() {
+        [] => return Expression::Lit(Literal::Bool(!is_or)),
+        [other] => !is_bool(other),
+        _ => false,
+    };
+    let mut kept = Vec::new();
+    for operand in operands {
+        if literal_bool(&operand).is_none() {
+            kept.push(operand);
+        } else if spare {
+            kept.push(operand);
+            spare = false;
+        }
+    }
+    if kept.len() == 1 {
+        kept.pop().unwrap()
+    } else if is_or {
+        Expression::Or(kept)
+    } else {
+        Expression::And(kept)
+    }
+}
+
+/// Whether `expr` evaluates to a bool whenever it doesn't fail.
+fn is_bool(expr: &Expression) -> bool {
+    matches!(
+        strip_span(expr),
+        Expression::Or(_)
+            | Expression::And(_)
+            | Expression::Not(_)
+            | Expression::Eq(..)
+            | Expression::Neq(..)
+            | Expression::Lt(..)
+            | Expression::Lte(..)
+            | Expression::Gte(..)
+            | Expression::Gt(..)
+    )
+}
+
+/// The value of `expr` if it is a literal bool.
+fn literal_bool(expr: &Expression) -> Option<bool> {
+    match strip_span(expr) {
+        Expression::Lit(Literal::Bool(b)) => Some(*b),
+        _ => None,
+    }
+}
+
+struct Folder<'a> {
+    ctx: &'a EvalContext<'a>,
+    /// What enclosing `let`s bound their names to, if it's constant.
+    scopes: Vec<(Identifier, Option<Value>)>,
+}
+
+impl Folder<'_> {
+    /// Fold `expr`, returning the folded expression and its value if it's constant.
+    fn fold(&mut self, mut expr: Expression) -> (Expression, Option<Value>) {
+        match &mut expr {
+            Expression::Spanned(span, e) => {
+                let (e, value) = self.fold(e.take());
+                (Expression::Spanned(*span, Box::new(e)), value)
+            }
+            Expression::Binding(id) => {
+                match self.scopes.iter().rev().find(|(name, _)| name == id) {
+                    Some((_, Some(value))) => (literal(value.clone()), Some(value.clone())),
+                    _ => (expr, None),
+                }
+            }
+            Expression::LetBinding { id, value, body } => {
+                let id = mem::take(id);
+                let (value, constant) = self.fold(value.take());
+                let inlined = constant.is_some();
+                self.scopes.push((id.clone(), constant));
+                let (body, result) = self.fold(body.take());
+                self.scopes.pop();
+                if inlined {
+                    return (body, result);
+                }
+                let expr = Expression::LetBinding {
+                    id,
+                    value: Box::new(value),
+                    body: Box::new(body),
+                };
+                (expr, None)
+            }
+            _ => {
+                let custom = match &expr {
+                    Expression::Function(id, _) => self.ctx.is_custom_function(&id.0),
+                    // Methods that aren't built in may be the host's.
+                    Expression::Method(_, id, _) => !methods::is_builtin(&id.0),
+                    _ => false,
+                };
+                let (operands, rebuild) = split(expr);
+                let mut constant = !custom;
+                let operands = operands
+                    .into_iter()
+                    .map(|operand| {
+                        let (operand, value) = self.fold(operand);
+                        constant &= value.is_some();
+                        operand
+                    })
+                    .collect();
+                let expr = rebuild(operands);
+                if !constant {
+                    return (expr, None);
+                }
+                // Each evaluation counts against the limits on its own, not against `ctx`'s.
+                let ctx = self.ctx.detached();
+                match ctx.evaluate(expr.clone()) {
+                    Ok(value) => (literal(value.clone()), Some(value)),
+                    Err(_) => (expr, None),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::format;
+    use crate::interpreter::CustomFunction;
+    use crate::model::{Kind, Signature};
+    use crate::parser::{parse, parse_with_spans};
+    use std::borrow::Cow;
+    use std::rc::Rc;
+
+    fn fold(input: &str) -> Expression {
+        let ctx = EvalContext::default();
+        let folded = fold_constants(&ctx, parse(input).unwrap());
+        let spanned = fold_constants(&ctx, parse_with_spans(input).unwrap());
+        assert_eq!(format(&spanned), format(&folded));
+        folded
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(fold("2 * 3 + x"), parse("6 + x").unwrap());
+        assert_eq!(fold(r#""a" + "b" == s"#), parse(r#""ab" == s"#).unwrap());
+        assert_eq!(
+            fold("[1 + 1, x, {'k': 'v'.len()}]"),
+            parse("[2, x, {'k': 1}]").unwrap()
+        );
+        assert_eq!(
+            fold("let n = 2; let m = n * n; x < m && n > 1"),
+            parse("x < 4 && true").unwrap()
+        );
+        assert_eq!(
+            fold("let y = x; let x = 1; y + x"),
+            parse("let y = x; y + 1").unwrap()
+        );
+        assert_eq!(fold("uint(2 + 1)"), parse("uint('3')").unwrap());
+    }
+
+    #[test]
+    fn leaves_errors() {
+        assert_eq!(fold("1 / 0 + x"), parse("1 / 0 + x").unwrap());
+        assert_eq!(fold("x + (2 - 1) / 0"), parse("x + 1 / 0").unwrap());
+        assert_eq!(fold("[1, 'a' - 1]"), parse("[1, 'a' - 1]").unwrap());
+    }
+
+    #[test]
+    fn leaves_custom_functions() {
+        let mut ctx = EvalContext::default();
+        ctx.register_function(CustomFunction {
+            signature: Signature {
+                name: Cow::Borrowed("roll"),
+                operand: None,
+                args: Cow::Borrowed(&[Some(Kind::I64)]),
+                result: Kind::I64,
+                doc: Cow::Borrowed(""),
+                example: Cow::Borrowed(""),
+            },
+            implementation: Rc::new(|args| Ok(args[0].clone())),
+        });
+        let folded = fold_constants(&ctx, parse("roll(1 + 1) + int(2.0)").unwrap());
+        assert_eq!(folded, parse("roll(2) + 2").unwrap());
+    }
+
+    #[test]
+    fn dead_branches() {
+        let eliminate = |input: &str| {
+            let eliminated = eliminate_dead_branches(parse(input).unwrap());
+            let spanned = eliminate_dead_branches(parse_with_spans(input).unwrap());
+            assert_eq!(format(&spanned), format(&eliminated));
+            eliminated
+        };
+        assert_eq!(eliminate("true ? a : b"), parse("a").unwrap());
+        assert_eq!(eliminate("[false ? a : b.c]"), parse("[b.c]").unwrap());
+        assert_eq!(eliminate("x.y(1) || true"), parse("true").unwrap());
+        assert_eq!(eliminate("false || x || y"), parse("x || y").unwrap());
+        assert_eq!(eliminate("x && true && true"), parse("x && true").unwrap());
+        assert_eq!(eliminate("true && x == 1"), parse("x == 1").unwrap());
+        assert_eq!(eliminate("true && (false || true)"), parse("true").unwrap());
+        assert_eq!(
+            eliminate("let f = true; f ? (false ? 1 : x) : y"),
+            parse("let f = true; f ? x : y").unwrap()
+        );
+
+        let ctx = EvalContext::default();
+        let flags = "let beta = false; beta && x > 1 || !beta && x > 2";
+        assert_eq!(
+            optimize(&ctx, parse(flags).unwrap()),
+            parse("x > 2").unwrap()
+        );
+    }
+}
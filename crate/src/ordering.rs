@@ -1,16 +1,92 @@
+//! How values are ordered: by comparison operators, and the entries of maps wherever their order
+//! shows.
+//!
+//! A map's entries are always in order of key, comparing keys by their UTF-8 bytes (and so by code
+//! point): in `keys()`, in serialized values, and in residual expressions. This doesn't depend on
+//! how the map was built, or on the platform, so results that list a map's entries are the same
+//! from one run to the next. Anything that iterates over a map must go through `entries` or
+//! `into_entries`.
+
 use crate::model::Value;
+use serde::Serializer;
 use std::cmp::{Ordering, PartialOrd};
+use std::collections::HashMap;
 
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
         match (self, other) {
             (Value::Null, Value::Null) => Some(Ordering::Equal),
             (Value::I64(a), Value::I64(b)) => Some(Ord::cmp(&a, &b)),
-            (Value::F64(a), Value::F64(b)) => f64::partial_cmp(&a, &b),
+            (Value::U64(a), Value::U64(b)) => Some(Ord::cmp(a, b)),
+            (Value::F64(a), Value::F64(b)) => f64::partial_cmp(a, b),
             (Value::Bool(a), Value::Bool(b)) => Some(Ord::cmp(&a, &b)),
             (Value::String(ref a), Value::String(ref b)) => Some(Ord::cmp(&a, &b)),
             (Value::Bytes(ref a), Value::Bytes(ref b)) => Some(Ord::cmp(&a, &b)),
+            (Value::Timestamp(a), Value::Timestamp(b)) => Some(Ord::cmp(a, b)),
+            (Value::Duration(a), Value::Duration(b)) => Some(Ord::cmp(a, b)),
             _ => None,
         }
     }
 }
+
+/// The order of map keys.
+pub fn compare_keys(a: &str, b: &str) -> Ordering {
+    a.as_bytes().cmp(b.as_bytes())
+}
+
+/// The entries of `map`, in order of key.
+pub fn entries(map: &HashMap<String, Value>) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+    entries
+}
+
+/// The entries of `map`, in order of key.
+pub fn into_entries(map: HashMap<String, Value>) -> Vec<(String, Value)> {
+    let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+    entries
+}
+
+/// Serialize `map` with its entries in order of key.
+pub(crate) fn serialize_map<S: Serializer>(
+    map: &HashMap<String, Value>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(entries(map))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::methods;
+    use crate::model::Identifier;
+
+    #[test]
+    fn map_order() {
+        let keys = ["b", "a", "\u{e9}", "B", "ab", "", "\u{1f600}", "\u{ff5e}"];
+        let map: HashMap<String, Value> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), Value::I64(i as i64)))
+            .collect();
+        let expected = ["", "B", "a", "ab", "b", "\u{e9}", "\u{ff5e}", "\u{1f600}"];
+        let ordered: Vec<&str> = entries(&map).into_iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(ordered, expected);
+
+        let json = serde_json::to_string(&Value::Map(map.clone())).unwrap();
+        let positions: Vec<usize> = expected
+            .iter()
+            .map(|k| json.find(&format!("{:?}:", k)).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", json);
+        let keys: Vec<Value> = expected
+            .iter()
+            .map(|k| Value::String(k.to_string().into()))
+            .collect();
+        assert_eq!(
+            methods::evaluate_method(Identifier::new("keys"), Value::Map(map), vec![]),
+            Ok(Value::List(keys))
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Reporting panics at the crate's entry points as errors, on targets that can unwind them, so that
+//! a bug that panics on one input fails that call rather than taking down the host.
+//!
+//! `guard` runs an entry point's body, catching any panic and reporting it as an `InternalError`
+//! naming the entry point, with the panic's message and where it happened. Callers pass it on as
+//! an `Error::Internal`, as if evaluation had failed. The location comes from a panic hook that
+//! `guard` installs the first time it runs; it calls whichever hook was installed before it.
+//!
+//! This is no boundary when built with `panic = "abort"`, which is the only strategy
+//! `wasm32-unknown-unknown` supports: nothing can be caught, so `guard` just runs the body, and a
+//! panic traps the wasm instance as it would without it.
+
+use crate::model::{Error, EvalResult};
+use serde::Serialize;
+use std::fmt;
+#[cfg(panic = "unwind")]
+use std::{
+    cell::RefCell,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+/// A panic in the crate, caught at the export that was called.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InternalError {
+    /// The export that was called.
+    pub entry: &'static str,
+    pub message: String,
+    /// The file, line and column that panicked, if known.
+    pub location: Option<String>,
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "{} panicked at {}: {}",
+                self.entry, location, self.message
+            ),
+            None => write!(f, "{} panicked: {}", self.entry, self.message),
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Error {
+        Error::Internal(err.to_string())
+    }
+}
+
+#[cfg(panic = "unwind")]
+thread_local! {
+    /// Where the last panic on this thread happened.
+    static LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[cfg(panic = "unwind")]
+static HOOK: Once = Once::new();
+
+/// Run `f`, the body of the entry point `entry`, reporting a panic in it as an `InternalError`.
+///
+/// State that `f` was changing when it panicked may be left half-changed. The entry points only
+/// change their own arguments, e.g. a `Bindings` being set, so nothing else is affected.
+#[cfg(panic = "unwind")]
+pub fn guard<T>(entry: &'static str, f: impl FnOnce() -> T) -> Result<T, InternalError> {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+            LOCATION.with(|l| *l.borrow_mut() = location);
+            previous(info);
+        }));
+    });
+    LOCATION.with(|l| l.borrow_mut().take());
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            (*s).to_owned()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_owned()
+        };
+        InternalError {
+            entry,
+            message,
+            location: LOCATION.with(|l| l.borrow_mut().take()),
+        }
+    })
+}
+
+/// Like `guard`, for an entry point that answers in JSON: a panic is answered as the failed
+/// evaluation `{"Err": {"Internal": message}}`.
+pub fn guard_json(entry: &'static str, f: impl FnOnce() -> serde_json::Value) -> serde_json::Value {
+    guard(entry, f).unwrap_or_else(|err| {
+        let result: EvalResult = Err(err.into());
+        serde_json::json!(result)
+    })
+}
+
+/// Run `f`. A build that aborts on panic can't catch one, so this never fails.
+#[cfg(not(panic = "unwind"))]
+pub fn guard<T>(_entry: &'static str, f: impl FnOnce() -> T) -> Result<T, InternalError> {
+    Ok(f())
+}
+
+#[cfg(all(test, panic = "unwind"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn catches_panics() {
+        assert_eq!(guard("ok", || 1), Ok(1));
+        let err = guard("boom", || -> i32 { panic!("boom {}", 1) }).unwrap_err();
+        assert_eq!(err.entry, "boom");
+        assert_eq!(err.message, "boom 1");
+        assert!(err.location.unwrap().starts_with("src/panics.rs:"));
+        let err = Error::from(InternalError {
+            location: None,
+            ..err
+        });
+        assert_eq!(err, Error::Internal("boom panicked: boom 1".to_owned()));
+        // A caught panic doesn't affect the next call.
+        assert_eq!(guard("ok", || "fine"), Ok("fine"));
+    }
+
+    #[test]
+    fn answers_panics_in_json() {
+        assert_eq!(
+            guard_json("ok", || serde_json::json!({"Ok": 1})),
+            serde_json::json!({"Ok": 1})
+        );
+        let response = guard_json("boom", || panic!("boom"));
+        let message = response["Err"]["Internal"].as_str().unwrap();
+        assert!(message.starts_with("boom panicked at src/panics.rs:"));
+    }
+}
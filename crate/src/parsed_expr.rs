@@ -0,0 +1,414 @@
+//! Expressions in the shape of cel-spec's `google.api.expr.v1alpha1.ParsedExpr`, as proto3 JSON, so
+//! that they can be checked by other CEL implementations or stored where the canonical form is
+//! expected.
+//!
+//! Operators become calls to the functions cel-spec names them by, e.g. `_+_` and `_?_:_`, and
+//! chains of `||` and `&&` nest to the left. CEL has no `let`: each becomes the comprehension that
+//! the `cel.bind` macro expands to, binding the name as the accumulator, and no `?.`: `a?.b` becomes
+//! `a == null || !has(a.b) ? null : a.b`, with `a` repeated. Expression ids number the
+//! nodes in pre-order from 1. When the expression was parsed with spans, `sourceInfo.positions`
+//! maps each id to the code point offset its node starts at.
+
+use crate::json::base64;
+use crate::model::{Expression, Literal};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// `expr`, parsed from `source`, as a `ParsedExpr`.
+pub fn to_parsed_expr(expr: &Expression, source: &str) -> Value {
+    let mut builder = Builder {
+        next_id: 0,
+        positions: BTreeMap::new(),
+        source,
+    };
+    let expr = builder.expr(expr, None);
+    let line_offsets: Vec<usize> = source
+        .chars()
+        .enumerate()
+        .filter(|&(_, c)| c == '\n')
+        .map(|(i, _)| i + 1)
+        .collect();
+    let positions: Map<String, Value> = builder
+        .positions
+        .into_iter()
+        .map(|(id, offset)| (id.to_string(), offset.into()))
+        .collect();
+    json!({
+        "expr": expr,
+        "sourceInfo": {
+            "lineOffsets": line_offsets,
+            "positions": positions,
+        },
+    })
+}
+
+struct Builder<'s> {
+    next_id: u64,
+    /// The code point offset of each node with a span.
+    positions: BTreeMap<u64, usize>,
+    source: &'s str,
+}
+
+impl Builder<'_> {
+    /// An `Expr` with a fresh id and the given kind, e.g. `identExpr`.
+    fn node(&mut self, start: Option<usize>, kind: &str, body: Value) -> Value {
+        self.next_id += 1;
+        if let Some(start) = start {
+            let offset = self.source.get(..start).map_or(0, |s| s.chars().count());
+            self.positions.insert(self.next_id, offset);
+        }
+        let mut node = Map::new();
+        // Proto3 JSON writes int64s as strings.
+        node.insert("id".to_owned(), self.next_id.to_string().into());
+        node.insert(kind.to_owned(), body);
+        Value::Object(node)
+    }
+
+    /// A call of `function` with `args`, as a method of `target` if there is one. Like every node,
+    /// it takes its id before its operands do.
+    fn call(
+        &mut self,
+        start: Option<usize>,
+        function: &str,
+        target: Option<&Expression>,
+        args: Vec<&Expression>,
+    ) -> Value {
+        let mut node = self.node(start, "callExpr", Value::Null);
+        let mut call = Map::new();
+        if let Some(target) = target {
+            call.insert("target".to_owned(), self.expr(target, None));
+        }
+        call.insert("function".to_owned(), function.into());
+        if !args.is_empty() {
+            let args: Vec<Value> = args.into_iter().map(|a| self.expr(a, None)).collect();
+            call.insert("args".to_owned(), args.into());
+        }
+        node["callExpr"] = Value::Object(call);
+        node
+    }
+
+    /// `operand.field`, or `has(operand.field)` if `test_only`.
+    fn select(
+        &mut self,
+        start: Option<usize>,
+        operand: &Expression,
+        field: &str,
+        test_only: bool,
+    ) -> Value {
+        let mut node = self.node(start, "selectExpr", Value::Null);
+        let operand = self.expr(operand, None);
+        node["selectExpr"] = if test_only {
+            json!({ "operand": operand, "field": field, "testOnly": true })
+        } else {
+            json!({ "operand": operand, "field": field })
+        };
+        node
+    }
+
+    /// `||` or `&&` of `vs`, nested to the left, each call starting where the chain does.
+    fn chain(&mut self, start: Option<usize>, function: &str, vs: &[Expression]) -> Value {
+        match vs.split_last() {
+            Some((last, rest)) if !rest.is_empty() => {
+                let mut node = self.node(start, "callExpr", Value::Null);
+                let left = if rest.len() == 1 {
+                    self.expr(&rest[0], None)
+                } else {
+                    self.chain(start, function, rest)
+                };
+                let right = self.expr(last, None);
+                node["callExpr"] = json!({ "function": function, "args": [left, right] });
+                node
+            }
+            _ => self.expr(&vs[0], start),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression, start: Option<usize>) -> Value {
+        let (function, a, b) = match expr {
+            Expression::Spanned(span, e) => return self.expr(e, Some(span.start)),
+            Expression::LetBinding { id, value, body } => {
+                let mut node = self.node(start, "comprehensionExpr", Value::Null);
+                let iter_range = self.node(None, "listExpr", json!({}));
+                let accu_init = self.expr(value, None);
+                let loop_condition = self.node(None, "constExpr", json!({ "boolValue": false }));
+                let loop_step = self.node(None, "identExpr", json!({ "name": id.0 }));
+                let result = self.expr(body, None);
+                node["comprehensionExpr"] = json!({
+                    "iterVar": "#unused",
+                    "iterRange": iter_range,
+                    "accuVar": id.0,
+                    "accuInit": accu_init,
+                    "loopCondition": loop_condition,
+                    "loopStep": loop_step,
+                    "result": result,
+                });
+                return node;
+            }
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => {
+                let args = vec![&**condition, &**true_branch, &**else_branch];
+                return self.call(start, "_?_:_", None, args);
+            }
+            Expression::Or(vs) => return self.chain(start, "_||_", vs),
+            Expression::And(vs) => return self.chain(start, "_&&_", vs),
+            Expression::Eq(a, b) => ("_==_", a, b),
+            Expression::Neq(a, b) => ("_!=_", a, b),
+            Expression::Lt(a, b) => ("_<_", a, b),
+            Expression::Lte(a, b) => ("_<=_", a, b),
+            Expression::Gte(a, b) => ("_>=_", a, b),
+            Expression::Gt(a, b) => ("_>_", a, b),
+            Expression::Add(a, b) => ("_+_", a, b),
+            Expression::Sub(a, b) => ("_-_", a, b),
+            Expression::Mul(a, b) => ("_*_", a, b),
+            Expression::Div(a, b) => ("_/_", a, b),
+            Expression::Mod(a, b) => ("_%_", a, b),
+            Expression::OptionalIndex(a, b) => ("_[?_]", a, b),
+            Expression::Neg(a) => return self.call(start, "-_", None, vec![a]),
+            Expression::Not(a) => return self.call(start, "!_", None, vec![a]),
+            Expression::Member(a, name) => return self.select(start, a, &name.0, false),
+            Expression::SafeMember(a, name) => {
+                let mut node = self.node(start, "callExpr", Value::Null);
+                let mut or = self.node(None, "callExpr", Value::Null);
+                let is_null =
+                    self.call(None, "_==_", None, vec![a, &Expression::Lit(Literal::Null)]);
+                let mut not = self.node(None, "callExpr", Value::Null);
+                let has = self.select(None, a, &name.0, true);
+                not["callExpr"] = json!({ "function": "!_", "args": [has] });
+                or["callExpr"] = json!({ "function": "_||_", "args": [is_null, not] });
+                let absent = self.literal(&Literal::Null, None);
+                let member = self.select(None, a, &name.0, false);
+                node["callExpr"] = json!({ "function": "_?_:_", "args": [or, absent, member] });
+                return node;
+            }
+            Expression::Method(a, name, args) => {
+                return self.call(start, &name.0, Some(a), args.iter().collect())
+            }
+            Expression::Function(name, args) => {
+                return self.call(start, &name.0, None, args.iter().collect())
+            }
+            Expression::Lit(lit) => return self.literal(lit, start),
+            Expression::Binding(id) => {
+                return self.node(start, "identExpr", json!({ "name": id.0 }))
+            }
+        };
+        self.call(start, function, None, vec![a, b])
+    }
+
+    fn literal(&mut self, lit: &Literal, start: Option<usize>) -> Value {
+        let constant = match lit {
+            Literal::Null => json!({ "nullValue": "NULL_VALUE" }),
+            Literal::Bool(b) => json!({ "boolValue": b }),
+            Literal::I64(i) => json!({ "int64Value": i.to_string() }),
+            Literal::F64(f) if f.is_finite() => json!({ "doubleValue": f }),
+            // Proto3 JSON's names for the doubles that JSON has no numbers for.
+            Literal::F64(f) if f.is_nan() => json!({ "doubleValue": "NaN" }),
+            Literal::F64(f) if *f > 0.0 => json!({ "doubleValue": "Infinity" }),
+            Literal::F64(_) => json!({ "doubleValue": "-Infinity" }),
+            Literal::String(s) => json!({ "stringValue": s }),
+            Literal::Bytes(bs) => json!({ "bytesValue": base64(bs) }),
+            Literal::List(vs) => {
+                let mut node = self.node(start, "listExpr", Value::Null);
+                let elements: Vec<Value> = vs.iter().map(|v| self.expr(v, None)).collect();
+                node["listExpr"] = if elements.is_empty() {
+                    json!({})
+                } else {
+                    json!({ "elements": elements })
+                };
+                return node;
+            }
+            Literal::Map(entries) => {
+                let mut node = self.node(start, "structExpr", Value::Null);
+                let entries: Vec<Value> = entries
+                    .iter()
+                    .map(|(k, v)| {
+                        self.next_id += 1;
+                        let id = self.next_id;
+                        let key = self.expr(k, None);
+                        let value = self.expr(v, None);
+                        json!({ "id": id.to_string(), "mapKey": key, "value": value })
+                    })
+                    .collect();
+                node["structExpr"] = if entries.is_empty() {
+                    json!({})
+                } else {
+                    json!({ "entries": entries })
+                };
+                return node;
+            }
+        };
+        self.node(start, "constExpr", constant)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse, parse_with_spans};
+
+    #[test]
+    fn operators_and_calls() {
+        let input = "a.b + f(1)\n|| !x.size()";
+        let parsed = to_parsed_expr(&parse_with_spans(input).unwrap(), input);
+        assert_eq!(
+            parsed,
+            json!({
+                "expr": {
+                    "id": "1",
+                    "callExpr": {
+                        "function": "_||_",
+                        "args": [
+                            {
+                                "id": "2",
+                                "callExpr": {
+                                    "function": "_+_",
+                                    "args": [
+                                        {
+                                            "id": "3",
+                                            "selectExpr": {
+                                                "operand": {"id": "4", "identExpr": {"name": "a"}},
+                                                "field": "b",
+                                            },
+                                        },
+                                        {
+                                            "id": "5",
+                                            "callExpr": {
+                                                "function": "f",
+                                                "args": [
+                                                    {"id": "6", "constExpr": {"int64Value": "1"}},
+                                                ],
+                                            },
+                                        },
+                                    ],
+                                },
+                            },
+                            {
+                                "id": "7",
+                                "callExpr": {
+                                    "function": "!_",
+                                    "args": [
+                                        {
+                                            "id": "8",
+                                            "callExpr": {
+                                                "target": {"id": "9", "identExpr": {"name": "x"}},
+                                                "function": "size",
+                                            },
+                                        },
+                                    ],
+                                },
+                            },
+                        ],
+                    },
+                },
+                "sourceInfo": {
+                    "lineOffsets": [11],
+                    "positions": {
+                        "1": 0, "2": 0, "3": 0, "4": 0, "5": 6, "6": 8, "7": 14, "8": 15, "9": 15,
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn literals_and_lets() {
+        let input = r#" let y = [null, b"hi"]; {"k": y} "#;
+        let parsed = to_parsed_expr(&parse(input).unwrap(), input);
+        assert_eq!(
+            parsed["expr"],
+            json!({
+                "id": "1",
+                "comprehensionExpr": {
+                    "iterVar": "#unused",
+                    "iterRange": {"id": "2", "listExpr": {}},
+                    "accuVar": "y",
+                    "accuInit": {
+                        "id": "3",
+                        "listExpr": {
+                            "elements": [
+                                {"id": "4", "constExpr": {"nullValue": "NULL_VALUE"}},
+                                {"id": "5", "constExpr": {"bytesValue": "aGk="}},
+                            ],
+                        },
+                    },
+                    "loopCondition": {"id": "6", "constExpr": {"boolValue": false}},
+                    "loopStep": {"id": "7", "identExpr": {"name": "y"}},
+                    "result": {
+                        "id": "8",
+                        "structExpr": {
+                            "entries": [
+                                {
+                                    "id": "9",
+                                    "mapKey": {"id": "10", "constExpr": {"stringValue": "k"}},
+                                    "value": {"id": "11", "identExpr": {"name": "y"}},
+                                },
+                            ],
+                        },
+                    },
+                },
+            })
+        );
+        assert_eq!(parsed["sourceInfo"]["positions"], json!({}));
+    }
+
+    #[test]
+    fn safe_members() {
+        let input = "a?.b";
+        let parsed = to_parsed_expr(&parse(input).unwrap(), input);
+        assert_eq!(
+            parsed["expr"],
+            json!({
+                "id": "1",
+                "callExpr": {
+                    "function": "_?_:_",
+                    "args": [
+                        {
+                            "id": "2",
+                            "callExpr": {
+                                "function": "_||_",
+                                "args": [
+                                    {
+                                        "id": "3",
+                                        "callExpr": {
+                                            "function": "_==_",
+                                            "args": [
+                                                {"id": "4", "identExpr": {"name": "a"}},
+                                                {"id": "5", "constExpr": {"nullValue": "NULL_VALUE"}},
+                                            ],
+                                        },
+                                    },
+                                    {
+                                        "id": "6",
+                                        "callExpr": {
+                                            "function": "!_",
+                                            "args": [
+                                                {
+                                                    "id": "7",
+                                                    "selectExpr": {
+                                                        "operand": {"id": "8", "identExpr": {"name": "a"}},
+                                                        "field": "b",
+                                                        "testOnly": true,
+                                                    },
+                                                },
+                                            ],
+                                        },
+                                    },
+                                ],
+                            },
+                        },
+                        {"id": "9", "constExpr": {"nullValue": "NULL_VALUE"}},
+                        {
+                            "id": "10",
+                            "selectExpr": {
+                                "operand": {"id": "11", "identExpr": {"name": "a"}},
+                                "field": "b",
+                            },
+                        },
+                    ],
+                },
+            })
+        );
+    }
+}
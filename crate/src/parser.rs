@@ -1,11 +1,13 @@
-use crate::model::{Expression, Identifier, Literal};
+use crate::model::{Expression, Identifier, Literal, Span};
 
+use pest::error::{ErrorVariant, InputLocation};
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::num::IntErrorKind;
 
 #[derive(Parser)]
 #[grammar = "cel.pest"]
@@ -15,7 +17,15 @@ struct CelParser;
 pub enum ParseError {
     Pest(String),
     IllegalInt(String),
+    /// An integer literal, as written, that doesn't fit in an int.
+    IntLiteralOutOfRange(String),
     IllegalFloat(String),
+    /// Brackets or ternaries nest deeper than the given limit.
+    NestingTooDeep(usize),
+    /// A reserved word used as an identifier, e.g. the name of a binding, member, or function.
+    ReservedWord(String),
+    /// A `\u` escape, as written, that isn't a character, e.g. the surrogate `\uD800`.
+    InvalidEscape(String),
 }
 
 impl<T: Debug> From<pest::error::Error<T>> for ParseError {
@@ -36,12 +46,275 @@ impl From<std::num::ParseFloatError> for ParseError {
 
 pub type ParseResult<T> = Result<T, ParseError>;
 
+/// How deeply brackets and ternaries may nest. Both parsing and evaluation recurse on nesting, so
+/// this keeps adversarial input from overflowing the stack.
+pub const NESTING_LIMIT: usize = 100;
+
 pub fn parse(input: &str) -> ParseResult<Expression> {
+    parse_inner(input, false)
+}
+
+/// Like `parse`, but every subexpression other than a `let` is wrapped in an `Expression::Spanned`
+/// recording where it came from, so that evaluation errors and renderings of the evaluation can
+/// point at it.
+pub fn parse_with_spans(input: &str) -> ParseResult<Expression> {
+    parse_inner(input, true)
+}
+
+fn parse_inner(input: &str, spans: bool) -> ParseResult<Expression> {
+    check_nesting(input)?;
+    if let Some(offset) = stray_nul(input) {
+        return Err(ParseError::Pest(format!(
+            "unexpected NUL at offset {}",
+            offset
+        )));
+    }
     let mut parsed = CelParser::parse(Rule::TopLevel, input)?;
-    extract_top_level(parsed.next().unwrap())
+    extract_top_level(parsed.next().unwrap(), spans)
 }
 
-fn extract_top_level(pair: Pair<Rule>) -> ParseResult<Expression> {
+/// If recording spans, wrap `expr` with the byte range `start..end` of the input it came from.
+fn spanned(expr: Expression, start: usize, end: usize, spans: bool) -> Expression {
+    if spans {
+        Expression::Spanned(Span { start, end }, Box::new(expr))
+    } else {
+        expr
+    }
+}
+
+/// A syntax error, and the byte offset into the input where it was found.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub offset: usize,
+    pub error: ParseError,
+}
+
+/// What `parse_recovering` makes of its input.
+#[derive(Debug, PartialEq)]
+pub struct Recovered {
+    /// The AST, if one could be built. Operands that were missing, or that had to be skipped, are
+    /// bindings with an empty name.
+    pub expr: Option<Expression>,
+    /// Every syntax error, in the order they were found.
+    pub errors: Vec<Diagnostic>,
+}
+
+/// How many syntax errors `parse_recovering` reports before giving up on building an AST.
+pub const MAX_DIAGNOSTICS: usize = 32;
+
+/// Parse `input`, recovering from syntax errors rather than stopping at the first one, for editors
+/// that want to show them all.
+///
+/// After each error the input is patched and parsed again: a missing operand is filled with a hole,
+/// unclosed brackets at the end are closed, and anything else unexpected is skipped up to the next
+/// comma, operator, or closing bracket.
+pub fn parse_recovering(input: &str) -> Recovered {
+    let mut errors = Vec::new();
+    if let Err(error) = check_nesting(input) {
+        errors.push(Diagnostic { offset: 0, error });
+        return Recovered { expr: None, errors };
+    }
+    if let Some(offset) = stray_nul(input) {
+        let error = ParseError::Pest(format!("unexpected NUL at offset {}", offset));
+        errors.push(Diagnostic { offset, error });
+        return Recovered { expr: None, errors };
+    }
+    let mut text = input.to_owned();
+    // The offset into `input` of each byte of `text`, and of its end.
+    let mut origin: Vec<usize> = (0..=input.len()).collect();
+    // Where holes have been tried already, as offsets into `input`, in case they didn't help.
+    let mut holes = Vec::new();
+    while errors.len() < MAX_DIAGNOSTICS {
+        let err = match CelParser::parse(Rule::TopLevel, &text) {
+            Ok(mut parsed) => {
+                let expr = match extract_top_level(parsed.next().unwrap(), false) {
+                    Ok(expr) => Some(expr),
+                    Err(error) => {
+                        errors.push(Diagnostic { offset: 0, error });
+                        None
+                    }
+                };
+                return Recovered { expr, errors };
+            }
+            Err(err) => err,
+        };
+        let pos = match err.location {
+            InputLocation::Pos(pos) => pos,
+            InputLocation::Span((start, _)) => start,
+        };
+        // Patching one error often uncovers another in the same place; report just the first.
+        if errors.last().map(|d: &Diagnostic| d.offset) != Some(origin[pos]) {
+            errors.push(Diagnostic {
+                offset: origin[pos],
+                error: ParseError::Pest(format!("{:?}", err.variant)),
+            });
+        }
+        let allow_hole = !holes.contains(&origin[pos]);
+        let (start, end, patch) = repair(&text, pos, &err.variant, allow_hole);
+        if patch == "\0" {
+            holes.push(origin[pos]);
+        }
+        if (start, end, patch.as_str()) == (pos, pos, "") {
+            break;
+        }
+        text.replace_range(start..end, &patch);
+        let at = origin[start];
+        origin.splice(start..end, std::iter::repeat_n(at, patch.len()));
+    }
+    Recovered { expr: None, errors }
+}
+
+/// How to patch `text` after a syntax error at `pos`: replace `start..end` with the returned text.
+/// A hole is only tried if `allow_hole`, i.e. one hasn't already failed to help here.
+fn repair(
+    text: &str,
+    pos: usize,
+    variant: &ErrorVariant<Rule>,
+    allow_hole: bool,
+) -> (usize, usize, String) {
+    let rest = &text[pos..];
+    let expects_operand = allow_hole
+        && match variant {
+            ErrorVariant::ParsingError { positives, .. } => positives.iter().any(|rule| {
+                matches!(
+                    rule,
+                    Rule::Literal
+                        | Rule::FunctionCall
+                        | Rule::Identifier
+                        | Rule::UnaryOp
+                        | Rule::Operand
+                        | Rule::Unary
+                        | Rule::Member
+                        | Rule::MapField
+                )
+            }),
+            ErrorVariant::CustomError { .. } => false,
+        };
+    if expects_operand && !rest.trim_start().starts_with(|c: char| c.is_alphanumeric()) {
+        return (pos, pos, "\0".to_owned());
+    }
+    if rest.trim().is_empty() {
+        return (pos, pos, unclosed(&text[..pos]));
+    }
+    (pos, pos + skip(rest), String::new())
+}
+
+/// The closing quote and brackets for those left open in `text`, innermost first.
+fn unclosed(text: &str) -> String {
+    let (chars, quote) = outside_strings(text);
+    let mut open = Vec::new();
+    for (_, c) in chars {
+        match c {
+            '(' => open.push(")"),
+            '[' => open.push("]"),
+            '{' => open.push("}"),
+            ')' | ']' | '}' => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    open.extend(quote);
+    open.into_iter().rev().collect()
+}
+
+/// How many bytes of `rest` to skip: at least one token, then up to the next comma, operator, or
+/// closing bracket outside of any brackets opened along the way.
+fn skip(rest: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in outside_strings(rest).0 {
+        if depth == 0 && i > 0 && ",:;?)]}+-*/%=!<>&|".contains(c) {
+            return i;
+        }
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+/// The offset of the first NUL outside a string literal. The grammar accepts those as holes, which
+/// only `parse_recovering` should produce.
+fn stray_nul(input: &str) -> Option<usize> {
+    let (chars, _) = outside_strings(input);
+    chars.into_iter().find(|&(_, c)| c == '\0').map(|(i, _)| i)
+}
+
+/// The characters of `input` outside of string literals, with their offsets, and the quote of the
+/// string left open at the end, if any. This is only a rough lexer, for looking over input that may
+/// not parse.
+fn outside_strings(input: &str) -> (Vec<(usize, char)>, Option<&'static str>) {
+    let mut outside = Vec::new();
+    let mut quote: Option<&'static str> = None;
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        let rest = &input[i..];
+        match quote {
+            Some(_) if c == '\\' => {
+                i += 1;
+                if let Some(escaped) = input[i..].chars().next() {
+                    i += escaped.len_utf8();
+                }
+                continue;
+            }
+            Some(q) if rest.starts_with(q) => {
+                quote = None;
+                i += q.len();
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                quote = ["\"\"\"", "\'\'\'", "\"", "\'"]
+                    .iter()
+                    .copied()
+                    .find(|q| rest.starts_with(q));
+                match quote {
+                    Some(q) => {
+                        i += q.len();
+                        continue;
+                    }
+                    None => outside.push((i, c)),
+                }
+            }
+        }
+        i += c.len_utf8();
+    }
+    (outside, quote)
+}
+
+/// Reject input that nests too deeply before handing it to the (recursive) grammar.
+fn check_nesting(input: &str) -> ParseResult<()> {
+    // The number of unclosed ternaries within each unclosed bracket.
+    let mut ternaries = vec![0];
+    let mut depth = 0;
+    for (_, c) in outside_strings(input).0 {
+        match c {
+            '(' | '[' | '{' => {
+                ternaries.push(0);
+                depth += 1;
+            }
+            ')' | ']' | '}' if ternaries.len() > 1 => {
+                depth -= ternaries.pop().unwrap() + 1;
+            }
+            '?' => {
+                *ternaries.last_mut().unwrap() += 1;
+                depth += 1;
+            }
+            ',' | ';' => {
+                depth -= std::mem::replace(ternaries.last_mut().unwrap(), 0);
+            }
+            _ => {}
+        }
+        if depth > NESTING_LIMIT {
+            return Err(ParseError::NestingTooDeep(NESTING_LIMIT));
+        }
+    }
+    Ok(())
+}
+
+fn extract_top_level(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::TopLevel);
     let mut pairs = pair.into_inner();
 
@@ -50,9 +323,9 @@ fn extract_top_level(pair: Pair<Rule>) -> ParseResult<Expression> {
         let p = pairs.next().unwrap();
         match p.as_rule() {
             Rule::LetBinding => {
-                bindings.push(extract_binding(p)?);
+                bindings.push(extract_binding(p, spans)?);
             }
-            _ => break extract_expression(p)?,
+            _ => break extract_expression(p, spans)?,
         }
     };
 
@@ -65,71 +338,79 @@ fn extract_top_level(pair: Pair<Rule>) -> ParseResult<Expression> {
         }))
 }
 
-fn extract_binding(pair: Pair<Rule>) -> ParseResult<(Identifier, Expression)> {
+fn extract_binding(pair: Pair<Rule>, spans: bool) -> ParseResult<(Identifier, Expression)> {
     assert_eq!(pair.as_rule(), Rule::LetBinding);
     let mut pairs = pair.into_inner();
-    let id = extract_identifier(pairs.next().unwrap());
-    let value = extract_expression(pairs.next().unwrap())?;
+    let id = extract_identifier(pairs.next().unwrap())?;
+    let value = extract_expression(pairs.next().unwrap(), spans)?;
     Ok((id, value))
 }
 
-fn extract_expression(pair: Pair<Rule>) -> ParseResult<Expression> {
-    match pair.as_rule() {
-        Rule::Ternary => extract_ternary(pair),
-        Rule::Disjunction => extract_disjunction(pair),
-        _ => unreachable!(),
-    }
+fn extract_expression(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
+    extract_ternary(pair, spans)
 }
 
-fn extract_ternary(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_ternary(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Ternary);
+    let start = pair.as_span().start();
     let mut pairs = pair.into_inner();
-    let condition = extract_disjunction(pairs.next().unwrap())?;
-    let true_branch = extract_expression(pairs.next().unwrap())?;
-    let else_branch = extract_expression(pairs.next().unwrap())?;
-    Ok(Expression::Ternary {
+    let condition = extract_disjunction(pairs.next().unwrap(), spans)?;
+    let true_branch = match pairs.next() {
+        Some(p) => extract_expression(p, spans)?,
+        None => return Ok(condition),
+    };
+    let else_branch = pairs.next().unwrap();
+    let end = end_of(&else_branch);
+    let else_branch = extract_expression(else_branch, spans)?;
+    let ternary = Expression::Ternary {
         condition: Box::new(condition),
         true_branch: Box::new(true_branch),
         else_branch: Box::new(else_branch),
-    })
+    };
+    Ok(spanned(ternary, start, end, spans))
 }
 
-fn extract_disjunction(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_disjunction(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Disjunction);
+    let (start, end) = (pair.as_span().start(), end_of(&pair));
     let mut exprs: Vec<Expression> = pair
         .into_inner()
-        .map(extract_conjunction)
+        .map(|p| extract_conjunction(p, spans))
         .collect::<ParseResult<_>>()?;
     if exprs.len() == 1 {
         Ok(exprs.swap_remove(0))
     } else {
-        Ok(Expression::Or(exprs))
+        let or = Expression::Or(exprs);
+        Ok(spanned(or, start, end, spans))
     }
 }
 
-fn extract_conjunction(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_conjunction(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Conjunction);
+    let (start, end) = (pair.as_span().start(), end_of(&pair));
     let mut exprs: Vec<Expression> = pair
         .into_inner()
-        .map(extract_relation)
+        .map(|p| extract_relation(p, spans))
         .collect::<ParseResult<_>>()?;
     if exprs.len() == 1 {
         Ok(exprs.swap_remove(0))
     } else {
-        Ok(Expression::And(exprs))
+        let and = Expression::And(exprs);
+        Ok(spanned(and, start, end, spans))
     }
 }
 
-fn extract_relation(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_relation(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Relation);
+    let (start, end) = (pair.as_span().start(), end_of(&pair));
     let mut pairs = pair.into_inner();
-    let a = extract_addition(pairs.next().unwrap())?;
+    let a = extract_addition(pairs.next().unwrap(), spans)?;
     let outer = match pairs.next() {
         None => a,
         Some(op) => {
             assert_eq!(op.as_rule(), Rule::RelOp);
-            let b = extract_addition(pairs.next().unwrap())?;
-            match op.as_str() {
+            let b = extract_addition(pairs.next().unwrap(), spans)?;
+            let relation = match op.as_str() {
                 "==" => Expression::Eq(Box::new(a), Box::new(b)),
                 "!=" => Expression::Neq(Box::new(a), Box::new(b)),
                 "<" => Expression::Lt(Box::new(a), Box::new(b)),
@@ -137,170 +418,276 @@ fn extract_relation(pair: Pair<Rule>) -> ParseResult<Expression> {
                 ">=" => Expression::Gte(Box::new(a), Box::new(b)),
                 ">" => Expression::Gt(Box::new(a), Box::new(b)),
                 _ => unreachable!(),
-            }
+            };
+            spanned(relation, start, end, spans)
         }
     };
     Ok(outer)
 }
 
-fn extract_addition(pair: Pair<Rule>) -> ParseResult<Expression> {
+/// Where `pair` ends in the input, leaving out the whitespace that repetitions at its end skip past.
+fn end_of(pair: &Pair<Rule>) -> usize {
+    pair.as_span().start() + pair.as_str().trim_end().len()
+}
+
+fn extract_addition(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Addition);
+    let start = pair.as_span().start();
     let mut pairs = pair.into_inner();
-    let mut a = extract_multiplication(pairs.next().unwrap())?;
+    let mut a = extract_multiplication(pairs.next().unwrap(), spans)?;
     while let Some(op) = pairs.next() {
         assert_eq!(op.as_rule(), Rule::AddOp);
-        let b = extract_multiplication(pairs.next().unwrap())?;
+        let b = pairs.next().unwrap();
+        let end = end_of(&b);
+        let b = extract_multiplication(b, spans)?;
         a = match op.as_str() {
             "+" => Expression::Add(Box::new(a), Box::new(b)),
             "-" => Expression::Sub(Box::new(a), Box::new(b)),
             _ => unreachable!(),
-        }
+        };
+        a = spanned(a, start, end, spans);
     }
     Ok(a)
 }
 
-fn extract_multiplication(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_multiplication(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Multiplication);
+    let start = pair.as_span().start();
     let mut pairs = pair.into_inner();
-    let mut a = extract_unary(pairs.next().unwrap())?;
+    let mut a = extract_unary(pairs.next().unwrap(), spans)?;
     while let Some(op) = pairs.next() {
         assert_eq!(op.as_rule(), Rule::MulOp);
-        let b = extract_unary(pairs.next().unwrap())?;
+        let b = pairs.next().unwrap();
+        let end = end_of(&b);
+        let b = extract_unary(b, spans)?;
         a = match op.as_str() {
             "*" => Expression::Mul(Box::new(a), Box::new(b)),
             "/" => Expression::Div(Box::new(a), Box::new(b)),
             "%" => Expression::Mod(Box::new(a), Box::new(b)),
             _ => unreachable!(),
-        }
+        };
+        a = spanned(a, start, end, spans);
     }
     Ok(a)
 }
 
-fn extract_unary(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_unary(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Unary);
-    let mut pairs = pair.into_inner();
-    let a = pairs.next().unwrap();
-    match a.as_rule() {
-        Rule::Member => extract_member(a),
-        Rule::UnaryOp => {
-            assert_eq!(a.as_rule(), Rule::UnaryOp);
-            match a.as_str() {
-                "-" => Ok(Expression::Neg(Box::new(extract_unary(
-                    pairs.next().unwrap(),
-                )?))),
-                "!" => Ok(Expression::Not(Box::new(extract_unary(
-                    pairs.next().unwrap(),
-                )?))),
-                _ => unreachable!(),
-            }
-        }
-        _ => unreachable!(),
+    let end = end_of(&pair);
+    let mut pairs: Vec<Pair<Rule>> = pair.into_inner().collect();
+    let mut a = extract_member(pairs.pop().unwrap(), spans)?;
+    // Operators apply innermost (rightmost) first, e.g. `-!x` is `-(!x)`.
+    for op in pairs.into_iter().rev() {
+        assert_eq!(op.as_rule(), Rule::UnaryOp);
+        let start = op.as_span().start();
+        a = match op.as_str() {
+            "-" => Expression::Neg(Box::new(a)),
+            "!" => Expression::Not(Box::new(a)),
+            _ => unreachable!(),
+        };
+        a = spanned(a, start, end, spans);
     }
+    Ok(a)
 }
 
-fn extract_member(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_member(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Member);
+    let start = pair.as_span().start();
     let mut pairs = pair.into_inner();
-    let mut a = extract_operand(pairs.next().unwrap())?;
+    let mut a = extract_operand(pairs.next().unwrap(), spans)?;
 
-    while let Some(pair) = pairs.next() {
+    for pair in pairs {
+        let end = end_of(&pair);
         match pair.as_rule() {
             Rule::MethodCall => {
-                let (id, args) = extract_method_call(pair)?;
+                let (id, args) = extract_method_call(pair, spans)?;
                 a = Expression::Method(Box::new(a), id, args);
             }
             Rule::MemberRef => {
-                let id = extract_member_ref(pair);
+                let id = extract_member_ref(pair)?;
                 a = Expression::Member(Box::new(a), id);
             }
+            Rule::SafeMemberRef => {
+                let id = extract_member_ref(pair)?;
+                a = Expression::SafeMember(Box::new(a), id);
+            }
+            Rule::OptionalIndex => {
+                let index = extract_expression(pair.into_inner().next().unwrap(), spans)?;
+                a = Expression::OptionalIndex(Box::new(a), Box::new(index));
+            }
             _ => unreachable!(),
         };
+        a = spanned(a, start, end, spans);
     }
 
     Ok(a)
 }
 
-fn extract_operand(pair: Pair<Rule>) -> ParseResult<Expression> {
+fn extract_operand(pair: Pair<Rule>, spans: bool) -> ParseResult<Expression> {
     assert_eq!(pair.as_rule(), Rule::Operand);
-    let a = pair.into_inner().next().unwrap();
-    match a.as_rule() {
-        Rule::Literal => Ok(Expression::Lit(extract_literal(a)?)),
-        Rule::Identifier => Ok(Expression::Binding(extract_identifier(a))),
-        _ => extract_expression(a),
-    }
+    let a = match pair.into_inner().next() {
+        Some(a) => a,
+        // A hole, which is silent.
+        None => return Ok(Expression::Binding(Identifier::new(""))),
+    };
+    let (start, end) = (a.as_span().start(), end_of(&a));
+    // Parenthesized expressions are spanned by what's inside the parentheses.
+    let expr = match a.as_rule() {
+        Rule::Literal => Expression::Lit(extract_literal(a, spans)?),
+        Rule::FunctionCall => {
+            let (id, args) = extract_function_call(a, spans)?;
+            Expression::Function(id, args)
+        }
+        Rule::Identifier => Expression::Binding(extract_identifier(a)?),
+        _ => return extract_expression(a, spans),
+    };
+    Ok(spanned(expr, start, end, spans))
 }
 
-fn extract_method_call(pair: Pair<Rule>) -> ParseResult<(Identifier, Vec<Expression>)> {
+fn extract_method_call(
+    pair: Pair<Rule>,
+    spans: bool,
+) -> ParseResult<(Identifier, Vec<Expression>)> {
     assert_eq!(pair.as_rule(), Rule::MethodCall);
     let mut pairs = pair.into_inner();
     Ok((
-        extract_identifier(pairs.next().unwrap()),
-        extract_args(pairs.next().unwrap())?,
+        extract_identifier(pairs.next().unwrap())?,
+        extract_args(pairs.next().unwrap(), spans)?,
     ))
 }
 
-fn extract_member_ref(pair: Pair<Rule>) -> Identifier {
-    assert_eq!(pair.as_rule(), Rule::MemberRef);
+fn extract_function_call(
+    pair: Pair<Rule>,
+    spans: bool,
+) -> ParseResult<(Identifier, Vec<Expression>)> {
+    assert_eq!(pair.as_rule(), Rule::FunctionCall);
+    let mut pairs = pair.into_inner().peekable();
+    let namespace = match pairs.peek() {
+        Some(p) if p.as_rule() == Rule::Namespace => Some(pairs.next().unwrap().as_str()),
+        _ => None,
+    };
+    let mut id = extract_identifier(pairs.next().unwrap())?;
+    if let Some(namespace) = namespace {
+        id = Identifier(format!("{}.{}", namespace, id.0));
+    }
+    Ok((id, extract_args(pairs.next().unwrap(), spans)?))
+}
+
+fn extract_member_ref(pair: Pair<Rule>) -> ParseResult<Identifier> {
+    assert!(matches!(
+        pair.as_rule(),
+        Rule::MemberRef | Rule::SafeMemberRef
+    ));
     extract_identifier(pair.into_inner().next().unwrap())
 }
 
-fn extract_identifier(pair: Pair<Rule>) -> Identifier {
+/// Words that can't be identifiers: the literals and `in`, as well as those CEL reserves for host
+/// languages, and `let`.
+const RESERVED_WORDS: &[&str] = &[
+    "false",
+    "in",
+    "null",
+    "true",
+    "as",
+    "break",
+    "const",
+    "continue",
+    "else",
+    "for",
+    "function",
+    "if",
+    "import",
+    "let",
+    "loop",
+    "package",
+    "namespace",
+    "return",
+    "var",
+    "void",
+    "while",
+];
+
+fn extract_identifier(pair: Pair<Rule>) -> ParseResult<Identifier> {
     assert_eq!(pair.as_rule(), Rule::Identifier);
-    pair.as_str().parse().expect("parse identifier")
+    if RESERVED_WORDS.contains(&pair.as_str()) {
+        return Err(ParseError::ReservedWord(pair.as_str().to_owned()));
+    }
+    Ok(pair.as_str().parse().expect("parse identifier"))
 }
 
-fn extract_args(pair: Pair<Rule>) -> ParseResult<Vec<Expression>> {
+fn extract_args(pair: Pair<Rule>, spans: bool) -> ParseResult<Vec<Expression>> {
     assert_eq!(pair.as_rule(), Rule::Args);
-    pair.into_inner().map(extract_expression).collect()
+    pair.into_inner()
+        .map(|p| extract_expression(p, spans))
+        .collect()
+}
+
+fn extract_int(pair: Pair<Rule>) -> ParseResult<i64> {
+    let digits = pair.as_str().replace("_", "");
+    let parsed = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    parsed.map_err(|err: std::num::ParseIntError| match err.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+            ParseError::IntLiteralOutOfRange(pair.as_str().to_owned())
+        }
+        _ => err.into(),
+    })
 }
 
-fn extract_literal(pair: Pair<Rule>) -> ParseResult<Literal> {
+fn extract_literal(pair: Pair<Rule>, spans: bool) -> ParseResult<Literal> {
     assert_eq!(pair.as_rule(), Rule::Literal);
     let pair = pair.into_inner().next().unwrap();
     match pair.as_rule() {
-        Rule::StringLiteral => Ok(Literal::String(extract_string(pair))),
-        Rule::BytesLiteral => Ok(Literal::Bytes(extract_bytes(pair))),
+        Rule::StringLiteral => Ok(Literal::String(extract_string(pair)?)),
+        Rule::BytesLiteral => Ok(Literal::Bytes(extract_bytes(pair)?)),
         Rule::FloatLiteral => Ok(Literal::F64(pair.as_str().replace("_", "").parse()?)),
-        Rule::IntLiteral => Ok(Literal::I64(pair.as_str().replace("_", "").parse()?)),
-        Rule::ListLiteral => extract_list(pair),
-        Rule::MapLiteral => extract_map(pair),
+        Rule::IntLiteral => Ok(Literal::I64(extract_int(pair)?)),
+        Rule::ListLiteral => extract_list(pair, spans),
+        Rule::MapLiteral => extract_map(pair, spans),
         Rule::BoolLiteral => Ok(Literal::Bool(pair.as_str().parse().unwrap())),
         Rule::NullLiteral => Ok(Literal::Null),
         _ => unreachable!(),
     }
 }
 
-fn extract_string(pair: Pair<Rule>) -> String {
+fn extract_string(pair: Pair<Rule>) -> ParseResult<String> {
     assert_eq!(pair.as_rule(), Rule::StringLiteral);
     let mut buf = String::new();
     for p in pair.into_inner() {
-        match unescape_sequence(&p) {
+        match unescape_sequence(&p)? {
             Unescaped::Byte(b) => buf.push(b as char),
             Unescaped::Unicode(ch) => buf.push(ch),
         };
     }
-    buf
+    Ok(buf)
 }
 
-fn extract_bytes(pair: Pair<Rule>) -> Vec<u8> {
+fn extract_bytes(pair: Pair<Rule>) -> ParseResult<Vec<u8>> {
     assert_eq!(pair.as_rule(), Rule::BytesLiteral);
     let mut buf = Vec::new();
     for p in pair.into_inner() {
-        match unescape_sequence(&p) {
+        match unescape_sequence(&p)? {
             Unescaped::Byte(b) => buf.push(b),
             Unescaped::Unicode(ch) => buf.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
         };
     }
-    buf
+    Ok(buf)
 }
 
 enum Unescaped {
     Byte(u8),
     Unicode(char),
 }
-fn unescape_sequence(pair: &Pair<Rule>) -> Unescaped {
-    match pair.as_rule() {
-        Rule::CharLiteral => Unescaped::Unicode(pair.as_str().chars().next().unwrap()),
+fn unescape_sequence(pair: &Pair<Rule>) -> ParseResult<Unescaped> {
+    Ok(match pair.as_rule() {
+        Rule::CharLiteral | Rule::TripleCharLiteral => {
+            Unescaped::Unicode(pair.as_str().chars().next().unwrap())
+        }
         Rule::Escape => {
             let s = &pair.as_str()[1..];
             match &s[..1] {
@@ -308,41 +695,43 @@ fn unescape_sequence(pair: &Pair<Rule>) -> Unescaped {
                 "n" => Unescaped::Byte(b'\n'),
                 "\"" => Unescaped::Byte(b'"'),
                 "x" => Unescaped::Byte(u8::from_str_radix(&s[1..], 16).unwrap()),
+                // The grammar allows any four hex digits, including surrogates.
                 "u" => Unescaped::Unicode(
-                    char::try_from(u32::from_str_radix(&s[1..], 16).unwrap()).unwrap(),
+                    char::try_from(u32::from_str_radix(&s[1..], 16).unwrap())
+                        .map_err(|_| ParseError::InvalidEscape(pair.as_str().to_owned()))?,
                 ),
                 "0" | "1" | "2" | "3" => Unescaped::Byte(u8::from_str_radix(s, 8).unwrap()),
                 _ => unreachable!("unexpected string literal {}", s),
             }
         }
         _ => unreachable!(),
-    }
+    })
 }
 
-fn extract_list(pair: Pair<Rule>) -> ParseResult<Literal> {
+fn extract_list(pair: Pair<Rule>, spans: bool) -> ParseResult<Literal> {
     assert_eq!(pair.as_rule(), Rule::ListLiteral);
     let mut vs = Vec::new();
     for p in pair.into_inner() {
-        vs.push(extract_expression(p)?);
+        vs.push(extract_expression(p, spans)?);
     }
     Ok(Literal::List(vs))
 }
 
-fn extract_map(pair: Pair<Rule>) -> ParseResult<Literal> {
+fn extract_map(pair: Pair<Rule>, spans: bool) -> ParseResult<Literal> {
     assert_eq!(pair.as_rule(), Rule::MapLiteral);
     let mut fields = Vec::new();
     for p in pair.into_inner() {
-        fields.push(extract_map_field(p)?);
+        fields.push(extract_map_field(p, spans)?);
     }
     Ok(Literal::Map(fields))
 }
 
-fn extract_map_field(pair: Pair<Rule>) -> ParseResult<(Expression, Expression)> {
+fn extract_map_field(pair: Pair<Rule>, spans: bool) -> ParseResult<(Expression, Expression)> {
     assert_eq!(pair.as_rule(), Rule::MapField);
     let mut pairs = pair.into_inner();
     Ok((
-        extract_expression(pairs.next().unwrap())?,
-        extract_expression(pairs.next().unwrap())?,
+        extract_expression(pairs.next().unwrap(), spans)?,
+        extract_expression(pairs.next().unwrap(), spans)?,
     ))
 }
 
@@ -387,6 +776,15 @@ mod test {
         assert_invalid("3._0");
     }
 
+    #[test]
+    fn scientific_float_literals() {
+        let float = |f| Ok(Expression::Lit(Literal::F64(f)));
+        assert_eq!(parse("1e10"), float(1e10));
+        assert_eq!(parse("2.5e-3"), float(2.5e-3));
+        assert_eq!(parse("6.02E+23"), float(6.02e23));
+        assert_invalid("1e");
+    }
+
     #[test]
     fn float_literal_overflow() {
         assert_eq!(
@@ -407,14 +805,58 @@ mod test {
         assert_valid("1_000_000_000");
     }
 
+    #[test]
+    fn hex_int_literals() {
+        let int = |i| Ok(Expression::Lit(Literal::I64(i)));
+        assert_eq!(parse("0x1F"), int(31));
+        assert_eq!(parse("0XfF_ff"), int(0xffff));
+        assert_eq!(parse("0x7fffffffffffffff"), int(i64::MAX));
+        assert_eq!(
+            parse("0x8000000000000000"),
+            Err(ParseError::IntLiteralOutOfRange(
+                "0x8000000000000000".to_owned()
+            ))
+        );
+        assert_invalid("0x");
+        assert_invalid("0xg");
+    }
+
+    #[test]
+    fn reserved_words() {
+        let reserved = |word: &str| Err(ParseError::ReservedWord(word.to_owned()));
+        assert_eq!(parse("let true = 1; 2"), reserved("true"));
+        assert_eq!(parse("x.null"), reserved("null"));
+        assert_eq!(parse("in"), reserved("in"));
+        assert_eq!(parse("if(x)"), reserved("if"));
+        assert_eq!(parse("[].namespace()"), reserved("namespace"));
+        assert_eq!(parse("let"), reserved("let"));
+        // Words that only start with one are fine.
+        assert_eq!(
+            parse("trueish || nullable || inner"),
+            Ok(Expression::Or(vec![
+                Expression::Binding(Identifier::new("trueish")),
+                Expression::Binding(Identifier::new("nullable")),
+                Expression::Binding(Identifier::new("inner")),
+            ]))
+        );
+        assert_valid("true && null == null");
+    }
+
     #[test]
     fn int_literal_overflow() {
         assert_eq!(
             parse("9999999999999999999999999"),
-            Err(ParseError::IllegalInt(
-                "number too large to fit in target type".to_owned()
+            Err(ParseError::IntLiteralOutOfRange(
+                "9999999999999999999999999".to_owned()
             ))
         );
+        assert_eq!(
+            parse("[1, 9_223_372_036_854_775_808]"),
+            Err(ParseError::IntLiteralOutOfRange(
+                "9_223_372_036_854_775_808".to_owned()
+            ))
+        );
+        assert!(parse("9_223_372_036_854_775_807").is_ok());
     }
 
     #[test]
@@ -491,6 +933,27 @@ mod test {
         assert_valid(r#" '¢' "#);
     }
 
+    #[test]
+    fn triple_quoted_strings() {
+        assert_eq!(
+            parse("\"\"\"line one\n\"quoted\" line two\"\"\"").unwrap(),
+            literal(&"line one\n\"quoted\" line two")
+        );
+        assert_eq!(parse(r#" '''it's''' "#).unwrap(), literal(&"it's"));
+        assert_eq!(parse(r#" """a\tb""" "#).unwrap(), literal(&"a\tb"));
+        assert_eq!(parse(r#" """""" "#).unwrap(), literal(&""));
+        assert_eq!(
+            parse(r#" b"""x"y""" "#).unwrap(),
+            literal(&"x\"y".as_bytes())
+        );
+        assert_valid(r#" """a""" + '' + "" "#);
+        assert_invalid(r#" """unterminated"" "#);
+        let recovered = parse_recovering(r#" f("""a) "#);
+        assert_eq!(recovered.errors.len(), 1);
+        assert!(recovered.expr.is_some());
+        assert_invalid(r#" '''a''' ''' "#);
+    }
+
     #[test]
     fn cel_escaped_quote_string() {
         let input = r#""as\"df""#;
@@ -532,6 +995,18 @@ mod test {
         assert_eq!(parse(r#" "\uFFFF" "#).unwrap(), literal(&"\u{FFFF}"));
     }
 
+    #[test]
+    fn surrogate_escapes() {
+        assert_eq!(
+            parse(r#" "\uD800" "#),
+            Err(ParseError::InvalidEscape(r"\uD800".to_owned()))
+        );
+        assert_eq!(
+            parse(r#" b"a\uDFFF" "#),
+            Err(ParseError::InvalidEscape(r"\uDFFF".to_owned()))
+        );
+    }
+
     #[test]
     fn valid_bytes() {
         assert_eq!(parse(r#" b"asdf" "#).unwrap(), literal(&"asdf".as_bytes()));
@@ -546,6 +1021,20 @@ mod test {
         assert_valid(r#" ([1] + [2]).foo.bar.baz(1,2,3).length.asdf("asdf") "#);
     }
 
+    #[test]
+    fn function_call() {
+        assert_valid(r#" timestamp("2024-01-01T00:00:00Z") "#);
+        assert_valid(r#" duration("1h").foo "#);
+        assert_valid(r#" f() + g(1, 2, 3,) "#);
+        assert_eq!(
+            parse(r#" f(1) "#),
+            Ok(Expression::Function(
+                Identifier::new("f"),
+                vec![Expression::Lit(Literal::I64(1))]
+            ))
+        );
+    }
+
     #[test]
     fn member_access() {
         assert_valid(r#" foo "#);
@@ -596,4 +1085,128 @@ mod test {
         assert_valid(r#" { true ? "a" : "b" : "foo"  } "#); //  evaluates to { "a": "foo" }
         assert_valid(r#" { "a" : true ? "foo" : bar } "#); //  evaluates to { "a": "foo" }
     }
+
+    #[test]
+    fn nested_ternaries_parse_in_linear_time() {
+        // Used to be exponential in the nesting depth.
+        let input = format!("{}1{}", "(true ? ".repeat(40), " : 0)".repeat(40));
+        assert_valid(&input);
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(
+            parse("-!x"),
+            Ok(Expression::Neg(Box::new(Expression::Not(Box::new(
+                Expression::Binding(Identifier::new("x"))
+            )))))
+        );
+        assert_valid(&format!("{}1", "-".repeat(5000)));
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        for open in &["(", "[", "{1: ", "f(", "true ? 1 : "] {
+            let input = format!("{}1", open.repeat(1000));
+            assert_eq!(
+                parse(&input),
+                Err(ParseError::NestingTooDeep(NESTING_LIMIT)),
+                "{}",
+                open
+            );
+        }
+    }
+
+    #[test]
+    fn nesting_within_limit() {
+        let input = format!("{}1{}", "[".repeat(50), "]".repeat(50));
+        assert_valid(&input);
+        // Brackets inside strings, and ternaries that have ended, don't nest.
+        assert_valid(&format!("'{}'", "(".repeat(1000)));
+        assert_valid(&format!("[{}]", "true ? 1 : 2, ".repeat(1000)));
+    }
+
+    #[test]
+    fn spans() {
+        let span = |start, end, e| Expression::Spanned(Span { start, end }, Box::new(e));
+        let lit = |start, n| span(start, start + 1, Expression::Lit(Literal::I64(n)));
+        assert_eq!(
+            parse_with_spans("1 + 2 * -x"),
+            Ok(span(
+                0,
+                10,
+                Expression::Add(
+                    Box::new(lit(0, 1)),
+                    Box::new(span(
+                        4,
+                        10,
+                        Expression::Mul(
+                            Box::new(lit(4, 2)),
+                            Box::new(span(
+                                8,
+                                10,
+                                Expression::Neg(Box::new(span(
+                                    9,
+                                    10,
+                                    Expression::Binding(Identifier::new("x"))
+                                )))
+                            ))
+                        )
+                    ))
+                )
+            ))
+        );
+        assert_eq!(parse_with_spans(" 1 "), Ok(lit(1, 1)));
+        assert_eq!(
+            parse_with_spans("(x ? 1 : 2)"),
+            Ok(span(
+                1,
+                10,
+                Expression::Ternary {
+                    condition: Box::new(span(1, 2, Expression::Binding(Identifier::new("x")))),
+                    true_branch: Box::new(lit(5, 1)),
+                    else_branch: Box::new(lit(9, 2)),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn recovers_from_errors() {
+        let hole = || Box::new(Expression::Binding(Identifier::new("")));
+        let lit = |n| Box::new(Expression::Lit(Literal::I64(n)));
+        let offsets = |r: &Recovered| r.errors.iter().map(|d| d.offset).collect::<Vec<_>>();
+
+        let r = parse_recovering("1 + * 2");
+        assert_eq!(
+            r.expr,
+            Some(Expression::Add(
+                lit(1),
+                Box::new(Expression::Mul(hole(), lit(2)))
+            ))
+        );
+        assert_eq!(offsets(&r), vec![4]);
+
+        let r = parse_recovering("[1, , f(2 3, 4), (5 +");
+        assert_eq!(
+            r.expr,
+            Some(Expression::Lit(Literal::List(vec![
+                *lit(1),
+                *hole(),
+                Expression::Function(Identifier::new("f"), vec![*lit(2), *lit(4)]),
+                Expression::Add(lit(5), hole()),
+            ])))
+        );
+        assert_eq!(offsets(&r), vec![4, 10, 21]);
+
+        let r = parse_recovering("1 + 2");
+        assert_eq!((r.expr, r.errors), (parse("1 + 2").ok(), vec![]));
+        assert_eq!(parse_recovering("a.").expr, None);
+    }
+
+    #[test]
+    fn holes_only_when_recovering() {
+        assert_invalid("1 + \0");
+        assert_valid("'\0'");
+    }
 }
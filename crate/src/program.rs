@@ -0,0 +1,338 @@
+//! Compiled expressions, saved as bytes so that hosts can store them, e.g. in IndexedDB, and load
+//! them again without parsing their source.
+//!
+//! A program is stored as JSON, with a header like a bundle's and then the nodes of the expression
+//! in postfix order, each taking as many operands as its arity from the values before it:
+//!
+//! ```json
+//! {"format": 1, "version": "0.1.0", "features": 0, "nodes": [{"Binding": "x"}, {"I64": 1}, "Add"]}
+//! ```
+//!
+//! Since the nodes are flat, an expression loads without recursion however deeply it nests.
+//! Doubles are stored as their bits, so that those that aren't finite, which JSON can't express,
+//! survive the trip.
+
+use crate::features::Features;
+use crate::interpreter::EvalContext;
+use crate::model::{EvalResult, Expression, Identifier, Literal, Span};
+use crate::parser::{self, ParseError};
+use serde::{Deserialize, Serialize};
+
+/// The version of the program format that this crate reads and writes.
+pub const FORMAT: u32 = 1;
+
+/// A parsed expression, ready to evaluate or to save.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    expr: Expression,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProgramError {
+    /// The bytes aren't a stored program.
+    Malformed(String),
+    /// The program is in a format this crate can't read.
+    UnsupportedFormat(u32),
+    /// The program, written by the given version of this crate, uses language features that this
+    /// runtime doesn't support.
+    UnsupportedFeatures(String, Features),
+}
+
+/// A program as stored.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    format: u32,
+    version: String,
+    features: Features,
+    nodes: Vec<Node>,
+}
+
+/// A node of an expression, without its operands.
+#[derive(Serialize, Deserialize)]
+enum Node {
+    Let(Identifier),
+    Ternary,
+    Or(usize),
+    And(usize),
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gte,
+    Gt,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    OptionalIndex,
+    Neg,
+    Not,
+    Member(Identifier),
+    SafeMember(Identifier),
+    /// A method, and the number of its arguments, which follow its receiver.
+    Method(Identifier, usize),
+    Function(Identifier, usize),
+    List(usize),
+    /// A map literal, and the number of its entries, each a key and then a value.
+    Map(usize),
+    I64(i64),
+    /// The bits of a double.
+    F64(u64),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    Null,
+    Binding(Identifier),
+    Spanned(Span),
+}
+
+impl Program {
+    pub fn new(expr: Expression) -> Program {
+        Program { expr }
+    }
+
+    pub fn compile(source: &str) -> Result<Program, ParseError> {
+        parser::parse(source).map(Program::new)
+    }
+
+    pub fn expression(&self) -> &Expression {
+        &self.expr
+    }
+
+    pub fn evaluate(&self, ctx: &EvalContext) -> EvalResult {
+        ctx.evaluate(self.expr.clone())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let stored = Stored {
+            format: FORMAT,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            features: Features::of(&self.expr),
+            nodes: postfix(&self.expr),
+        };
+        serde_json::to_vec(&stored).expect("serialize")
+    }
+
+    /// Load a program that `to_bytes` saved.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, ProgramError> {
+        // Check the format before the rest, which may be shaped differently in other formats.
+        #[derive(Deserialize)]
+        struct Header {
+            format: u32,
+            #[serde(default)]
+            version: String,
+            #[serde(default)]
+            features: Features,
+        }
+        let header: Header = serde_json::from_slice(bytes)
+            .map_err(|err| ProgramError::Malformed(err.to_string()))?;
+        if header.format != FORMAT {
+            return Err(ProgramError::UnsupportedFormat(header.format));
+        }
+        let unsupported = header.features.unsupported();
+        if !unsupported.is_empty() {
+            return Err(ProgramError::UnsupportedFeatures(
+                header.version,
+                unsupported,
+            ));
+        }
+        let stored: Stored = serde_json::from_slice(bytes)
+            .map_err(|err| ProgramError::Malformed(err.to_string()))?;
+        let expr = rebuild(stored.nodes).map_err(ProgramError::Malformed)?;
+        Ok(Program::new(expr))
+    }
+}
+
+/// The nodes of `expr` in postfix order.
+fn postfix(expr: &Expression) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    // Each expression, and whether its operands have been visited yet.
+    let mut stack = vec![(expr, false)];
+    while let Some((expr, visited)) = stack.pop() {
+        if visited {
+            nodes.push(node(expr));
+            continue;
+        }
+        stack.push((expr, true));
+        let children: Vec<&Expression> = match expr {
+            Expression::Spanned(_, e) => vec![e],
+            e => e.children().into_iter().map(|(_, child)| child).collect(),
+        };
+        stack.extend(children.into_iter().rev().map(|child| (child, false)));
+    }
+    nodes
+}
+
+fn node(expr: &Expression) -> Node {
+    match expr {
+        Expression::LetBinding { id, .. } => Node::Let(id.clone()),
+        Expression::Ternary { .. } => Node::Ternary,
+        Expression::Or(operands) => Node::Or(operands.len()),
+        Expression::And(operands) => Node::And(operands.len()),
+        Expression::Eq(..) => Node::Eq,
+        Expression::Neq(..) => Node::Neq,
+        Expression::Lt(..) => Node::Lt,
+        Expression::Lte(..) => Node::Lte,
+        Expression::Gte(..) => Node::Gte,
+        Expression::Gt(..) => Node::Gt,
+        Expression::Add(..) => Node::Add,
+        Expression::Sub(..) => Node::Sub,
+        Expression::Mul(..) => Node::Mul,
+        Expression::Div(..) => Node::Div,
+        Expression::Mod(..) => Node::Mod,
+        Expression::OptionalIndex(..) => Node::OptionalIndex,
+        Expression::Neg(_) => Node::Neg,
+        Expression::Not(_) => Node::Not,
+        Expression::Member(_, id) => Node::Member(id.clone()),
+        Expression::SafeMember(_, id) => Node::SafeMember(id.clone()),
+        Expression::Method(_, id, args) => Node::Method(id.clone(), args.len()),
+        Expression::Function(id, args) => Node::Function(id.clone(), args.len()),
+        Expression::Lit(Literal::List(elems)) => Node::List(elems.len()),
+        Expression::Lit(Literal::Map(kvs)) => Node::Map(kvs.len()),
+        Expression::Lit(Literal::I64(n)) => Node::I64(*n),
+        Expression::Lit(Literal::F64(x)) => Node::F64(x.to_bits()),
+        Expression::Lit(Literal::Bool(b)) => Node::Bool(*b),
+        Expression::Lit(Literal::String(s)) => Node::String(s.clone()),
+        Expression::Lit(Literal::Bytes(bs)) => Node::Bytes(bs.clone()),
+        Expression::Lit(Literal::Null) => Node::Null,
+        Expression::Binding(id) => Node::Binding(id.clone()),
+        Expression::Spanned(span, _) => Node::Spanned(*span),
+    }
+}
+
+/// The expression whose nodes, in postfix order, are `nodes`.
+fn rebuild(nodes: Vec<Node>) -> Result<Expression, String> {
+    let mut operands: Vec<Expression> = Vec::new();
+    for node in nodes {
+        let arity = match &node {
+            Node::Let(_) => 2,
+            Node::Ternary => 3,
+            Node::Or(n) | Node::And(n) | Node::Function(_, n) | Node::List(n) => *n,
+            Node::Method(_, n) => n.saturating_add(1),
+            Node::Map(n) => n.saturating_mul(2),
+            Node::Neg | Node::Not | Node::Member(_) | Node::SafeMember(_) | Node::Spanned(_) => 1,
+            Node::I64(_)
+            | Node::F64(_)
+            | Node::Bool(_)
+            | Node::String(_)
+            | Node::Bytes(_)
+            | Node::Null
+            | Node::Binding(_) => 0,
+            _ => 2,
+        };
+        if arity > operands.len() {
+            return Err("a node is missing operands".to_owned());
+        }
+        let mut args = operands.split_off(operands.len() - arity).into_iter();
+        let mut next = || Box::new(args.next().expect("operand"));
+        let expr = match node {
+            Node::Let(id) => Expression::LetBinding {
+                id,
+                value: next(),
+                body: next(),
+            },
+            Node::Ternary => Expression::Ternary {
+                condition: next(),
+                true_branch: next(),
+                else_branch: next(),
+            },
+            Node::Or(_) => Expression::Or(args.collect()),
+            Node::And(_) => Expression::And(args.collect()),
+            Node::Eq => Expression::Eq(next(), next()),
+            Node::Neq => Expression::Neq(next(), next()),
+            Node::Lt => Expression::Lt(next(), next()),
+            Node::Lte => Expression::Lte(next(), next()),
+            Node::Gte => Expression::Gte(next(), next()),
+            Node::Gt => Expression::Gt(next(), next()),
+            Node::Add => Expression::Add(next(), next()),
+            Node::Sub => Expression::Sub(next(), next()),
+            Node::Mul => Expression::Mul(next(), next()),
+            Node::Div => Expression::Div(next(), next()),
+            Node::Mod => Expression::Mod(next(), next()),
+            Node::OptionalIndex => Expression::OptionalIndex(next(), next()),
+            Node::Neg => Expression::Neg(next()),
+            Node::Not => Expression::Not(next()),
+            Node::Member(id) => Expression::Member(next(), id),
+            Node::SafeMember(id) => Expression::SafeMember(next(), id),
+            Node::Method(id, _) => Expression::Method(next(), id, args.collect()),
+            Node::Function(id, _) => Expression::Function(id, args.collect()),
+            Node::List(_) => Expression::Lit(Literal::List(args.collect())),
+            Node::Map(_) => {
+                let mut kvs = Vec::new();
+                while let (Some(k), Some(v)) = (args.next(), args.next()) {
+                    kvs.push((k, v));
+                }
+                Expression::Lit(Literal::Map(kvs))
+            }
+            Node::I64(n) => Expression::Lit(Literal::I64(n)),
+            Node::F64(bits) => Expression::Lit(Literal::F64(f64::from_bits(bits))),
+            Node::Bool(b) => Expression::Lit(Literal::Bool(b)),
+            Node::String(s) => Expression::Lit(Literal::String(s)),
+            Node::Bytes(bs) => Expression::Lit(Literal::Bytes(bs)),
+            Node::Null => Expression::Lit(Literal::Null),
+            Node::Binding(id) => Expression::Binding(id),
+            Node::Spanned(span) => Expression::Spanned(span, next()),
+        };
+        operands.push(expr);
+    }
+    match (operands.pop(), operands.is_empty()) {
+        (Some(expr), true) => Ok(expr),
+        _ => Err("the nodes don't make one expression".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Value;
+    use crate::parser::parse_with_spans;
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            "let x = 1; x + 2 * -y.z",
+            "a || b && !c ? [1, 2.5, 'x'] : {b'k': null, 'v': [true]}",
+            "f(1, 2).m() + m.n(3)",
+        ];
+        for input in inputs {
+            let program = Program::new(parse_with_spans(input).unwrap());
+            let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+            assert_eq!(loaded, program, "{}", input);
+        }
+        let inf = Program::new(Expression::Lit(Literal::F64(f64::INFINITY)));
+        assert_eq!(Program::from_bytes(&inf.to_bytes()), Ok(inf));
+
+        // Deeper than serde_json would parse a nested representation.
+        let deep = Program::compile(&format!("0{}", " + 1".repeat(1000))).unwrap();
+        let loaded = Program::from_bytes(&deep.to_bytes()).unwrap();
+        assert_eq!(
+            loaded.evaluate(&EvalContext::default()),
+            Ok(Value::I64(1000))
+        );
+    }
+
+    #[test]
+    fn rejects() {
+        let load = |json: &str| Program::from_bytes(json.as_bytes());
+        assert_eq!(
+            load(r#"{"format": 2, "version": "9.0.0", "features": 0, "nodes": []}"#),
+            Err(ProgramError::UnsupportedFormat(2))
+        );
+        assert_eq!(
+            load(r#"{"format": 1, "version": "9.0.0", "features": 256, "nodes": []}"#),
+            Err(ProgramError::UnsupportedFeatures(
+                "9.0.0".to_owned(),
+                Features(256)
+            ))
+        );
+        assert!(matches!(
+            load(r#"{"format": 1, "version": "", "features": 0, "nodes": ["Add"]}"#),
+            Err(ProgramError::Malformed(_))
+        ));
+        assert!(matches!(
+            load(r#"{"format": 1, "version": "", "features": 0, "nodes": [{"I64": 1}, "Null"]}"#),
+            Err(ProgramError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+//! A register machine, an alternative to the stack machine for expressions evaluated many times.
+//!
+//! Each instruction names the registers it reads and writes, rather than popping and pushing a
+//! stack, and may take a literal or a `let`'s value as an operand directly, so `x * 2 + 1` is two
+//! instructions after `x` is looked up. Programs are translated from the stack machine's (see
+//! `compiler`), so the two agree on every result, errors included.
+
+use crate::model::{Identifier, Value};
+
+/// Where an instruction reads a value from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    /// A register, which is left empty once read.
+    Reg(usize),
+    /// The value of the `let` in the slot, as in `stack::Operation::Load`.
+    Slot(usize),
+    Const(Value),
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BinaryOp {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gte,
+    Gt,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    OptionalIndex,
+    Or,
+    And,
+}
+
+/// A single instruction for the register machine. Those that make a value write it to the first
+/// register they name. Jumps are to the index of an instruction, or to the end of the program.
+#[derive(Debug, PartialEq)]
+pub enum Instruction {
+    /// As in `stack::Operation::Depth`.
+    Depth(usize),
+    Const(usize, Value),
+    Load(usize, usize),
+    Lookup(usize, Identifier),
+    /// Move the value in the register into the slot.
+    Store(usize, usize),
+    Binary(BinaryOp, usize, Operand, Operand),
+    Neg(usize, Operand),
+    Not(usize, Operand),
+    Member(usize, Operand, Identifier),
+    SafeMember(usize, Operand, Identifier),
+    /// Make a list of the values in the `n` registers from the first.
+    MakeList(usize, usize),
+    /// Make a map of the keys and values in the `2 * n` registers from the first.
+    MakeMap(usize, usize),
+    /// Call the function with the values in the `n` registers from the first.
+    Call(usize, Identifier, usize),
+    /// Call the method on the value in the first register, with the values in the `n` after it.
+    CallMethod(usize, Identifier, usize),
+    Jump(usize),
+    /// Jump if the register holds `true`.
+    JumpIfTrue(usize, usize),
+    /// Jump if the register holds `false`.
+    JumpIfFalse(usize, usize),
+    /// Empty the register, and jump unless it held `true`.
+    JumpUnlessTrue(usize, usize),
+}
+
+/// Instructions, and how many registers and slots they use. The result is left in register 0.
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    pub code: Vec<Instruction>,
+    pub registers: usize,
+    pub slots: usize,
+}
+
+pub mod compiler;
+pub mod runtime;
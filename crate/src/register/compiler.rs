@@ -0,0 +1,295 @@
+use crate::model::Expression;
+use crate::register::{BinaryOp, Instruction, Operand, Program};
+use crate::stack::walker::linearize;
+use crate::stack::Operation;
+
+pub fn compile(expr: Expression) -> Program {
+    translate(linearize(expr))
+}
+
+/// Translate a stack machine program. The value at depth `d` of the stack goes in register `d`.
+///
+/// Literals and loads from slots aren't copied to their registers until something needs them there:
+/// an operator reads them in place. Everything is in its register at each jump, and each place
+/// jumped to, so that every path agrees on where the values are.
+pub fn translate(ops: Vec<Operation>) -> Program {
+    let end = ops.len();
+    let mut t = Translator {
+        code: Vec::new(),
+        pending: Vec::new(),
+        registers: 1,
+        slots: 0,
+        targets: vec![None; end + 1],
+        jumps: Vec::new(),
+        reachable: true,
+    };
+    // Where the translation of each operation, and of the end, starts.
+    let mut starts = vec![0; end + 1];
+    for (i, op) in ops.into_iter().enumerate() {
+        t.arrive(i);
+        starts[i] = t.code.len();
+        t.translate(op, i);
+    }
+    t.arrive(end);
+    if t.reachable {
+        t.flush();
+    }
+    starts[end] = t.code.len();
+    for (at, target) in t.jumps {
+        let target = starts[target];
+        match &mut t.code[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfTrue(_, to)
+            | Instruction::JumpIfFalse(_, to)
+            | Instruction::JumpUnlessTrue(_, to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+    Program {
+        code: t.code,
+        registers: t.registers,
+        slots: t.slots,
+    }
+}
+
+struct Translator {
+    code: Vec<Instruction>,
+    /// What is at each depth of the stack: an operand not yet copied to its register, or `None` if
+    /// it's already there.
+    pending: Vec<Option<Operand>>,
+    registers: usize,
+    slots: usize,
+    /// The stack depth at each operation that is jumped to.
+    targets: Vec<Option<usize>>,
+    /// Jumps to patch, and the operation each jumps to.
+    jumps: Vec<(usize, usize)>,
+    /// Whether the operation being translated can be reached other than by a jump.
+    reachable: bool,
+}
+
+impl Translator {
+    /// Get ready to translate operation `i`, which may be reached by jumps.
+    fn arrive(&mut self, i: usize) {
+        if let Some(depth) = self.targets[i] {
+            if self.reachable {
+                self.flush();
+            }
+            self.pending.resize(depth, None);
+            self.reachable = true;
+        }
+    }
+
+    fn translate(&mut self, op: Operation, i: usize) {
+        let binary = match op {
+            Operation::Eq => BinaryOp::Eq,
+            Operation::Neq => BinaryOp::Neq,
+            Operation::Lt => BinaryOp::Lt,
+            Operation::Lte => BinaryOp::Lte,
+            Operation::Gte => BinaryOp::Gte,
+            Operation::Gt => BinaryOp::Gt,
+            Operation::Add => BinaryOp::Add,
+            Operation::Sub => BinaryOp::Sub,
+            Operation::Mul => BinaryOp::Mul,
+            Operation::Div => BinaryOp::Div,
+            Operation::Mod => BinaryOp::Mod,
+            Operation::OptionalIndex => BinaryOp::OptionalIndex,
+            Operation::Or => BinaryOp::Or,
+            Operation::And => BinaryOp::And,
+            other => return self.translate_other(other, i),
+        };
+        let b = self.pop();
+        let a = self.pop();
+        let dst = self.push();
+        self.code.push(Instruction::Binary(binary, dst, a, b));
+    }
+
+    fn translate_other(&mut self, op: Operation, i: usize) {
+        match op {
+            Operation::Depth(depth) => self.code.push(Instruction::Depth(depth)),
+            Operation::Lit(v) => self.pending.push(Some(Operand::Const(v))),
+            Operation::Load(slot) => self.pending.push(Some(Operand::Slot(slot))),
+            Operation::Lookup(name) => {
+                let dst = self.push();
+                self.code.push(Instruction::Lookup(dst, name));
+            }
+            Operation::Store(slot) => {
+                // Operands read from the slot must be read before it changes.
+                self.flush();
+                let src = self.depth() - 1;
+                self.pending.pop();
+                self.slots = self.slots.max(slot + 1);
+                self.code.push(Instruction::Store(slot, src));
+            }
+            Operation::Neg => {
+                let a = self.pop();
+                let dst = self.push();
+                self.code.push(Instruction::Neg(dst, a));
+            }
+            Operation::Not => {
+                let a = self.pop();
+                let dst = self.push();
+                self.code.push(Instruction::Not(dst, a));
+            }
+            Operation::Member(name) => {
+                let a = self.pop();
+                let dst = self.push();
+                self.code.push(Instruction::Member(dst, a, name));
+            }
+            Operation::SafeMember(name) => {
+                let a = self.pop();
+                let dst = self.push();
+                self.code.push(Instruction::SafeMember(dst, a, name));
+            }
+            Operation::MakeList(n) => {
+                let dst = self.gather(n);
+                self.code.push(Instruction::MakeList(dst, n));
+            }
+            Operation::MakeMap(n) => {
+                let dst = self.gather(2 * n);
+                self.code.push(Instruction::MakeMap(dst, n));
+            }
+            Operation::Call(name, n) => {
+                let dst = self.gather(n);
+                self.code.push(Instruction::Call(dst, name, n));
+            }
+            Operation::CallMethod(name, n) => {
+                let dst = self.gather(n + 1);
+                self.code.push(Instruction::CallMethod(dst, name, n));
+            }
+            Operation::Jump(n) => {
+                self.flush();
+                self.jump(Instruction::Jump(0), i + 1 + n);
+                self.reachable = false;
+            }
+            Operation::JumpIfTrue(n) => {
+                self.flush();
+                let reg = self.depth() - 1;
+                self.jump(Instruction::JumpIfTrue(reg, 0), i + 1 + n);
+            }
+            Operation::JumpIfFalse(n) => {
+                self.flush();
+                let reg = self.depth() - 1;
+                self.jump(Instruction::JumpIfFalse(reg, 0), i + 1 + n);
+            }
+            Operation::JumpUnlessTrue(n) => {
+                self.flush();
+                self.pending.pop();
+                let reg = self.depth();
+                self.jump(Instruction::JumpUnlessTrue(reg, 0), i + 1 + n);
+            }
+            _ => unreachable!("binary operators are translated by `translate`"),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Take the top of the stack as an operand.
+    fn pop(&mut self) -> Operand {
+        let reg = self.depth() - 1;
+        match self.pending.pop().expect("operand") {
+            Some(operand) => operand,
+            None => Operand::Reg(reg),
+        }
+    }
+
+    /// Push a value that will be written to its register, and return the register.
+    fn push(&mut self) -> usize {
+        self.pending.push(None);
+        self.registers = self.registers.max(self.depth());
+        self.depth() - 1
+    }
+
+    /// Copy the top `n` values to their registers, pop them, and return the first register, where
+    /// the value that replaces them goes.
+    fn gather(&mut self, n: usize) -> usize {
+        let first = self.depth() - n;
+        self.flush_from(first);
+        self.pending.truncate(first);
+        self.push()
+    }
+
+    /// Copy every pending operand to its register.
+    fn flush(&mut self) {
+        self.flush_from(0);
+    }
+
+    fn flush_from(&mut self, first: usize) {
+        for reg in first..self.depth() {
+            match self.pending[reg].take() {
+                Some(Operand::Const(v)) => self.code.push(Instruction::Const(reg, v)),
+                Some(Operand::Slot(slot)) => self.code.push(Instruction::Load(reg, slot)),
+                Some(Operand::Reg(_)) | None => {}
+            }
+        }
+        self.registers = self.registers.max(self.depth());
+    }
+
+    /// Emit `jump` to the operation `target`, which the stack reaches at the current depth.
+    fn jump(&mut self, jump: Instruction, target: usize) {
+        self.targets[target] = Some(self.depth());
+        self.jumps.push((self.code.len(), target));
+        self.code.push(jump);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{Identifier, Value};
+    use crate::parser::parse;
+
+    use super::*;
+
+    #[test]
+    fn compile_arithmetic() {
+        let program = compile(parse(r#" x * 2 + 1 "#).unwrap());
+        assert_eq!(
+            program.code,
+            vec![
+                Instruction::Depth(1),
+                Instruction::Lookup(0, Identifier::new("x")),
+                Instruction::Binary(
+                    BinaryOp::Mul,
+                    0,
+                    Operand::Reg(0),
+                    Operand::Const(Value::I64(2))
+                ),
+                Instruction::Binary(
+                    BinaryOp::Add,
+                    0,
+                    Operand::Reg(0),
+                    Operand::Const(Value::I64(1))
+                ),
+            ]
+        );
+        assert_eq!(program.registers, 1);
+    }
+
+    #[test]
+    fn compile_let_and_ternary() {
+        let program = compile(parse(r#" let y = 1; y < 2 ? [y] : y "#).unwrap());
+        assert_eq!(
+            program.code,
+            vec![
+                Instruction::Depth(3),
+                Instruction::Const(0, Value::I64(1)),
+                Instruction::Store(0, 0),
+                Instruction::Binary(
+                    BinaryOp::Lt,
+                    0,
+                    Operand::Slot(0),
+                    Operand::Const(Value::I64(2))
+                ),
+                Instruction::JumpUnlessTrue(0, 9),
+                Instruction::Depth(3),
+                Instruction::Load(0, 0),
+                Instruction::MakeList(0, 1),
+                Instruction::Jump(11),
+                Instruction::Depth(2),
+                Instruction::Load(0, 0),
+            ]
+        );
+        assert_eq!(program.slots, 1);
+    }
+}
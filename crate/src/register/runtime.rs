@@ -0,0 +1,208 @@
+use crate::interpreter::{self, EvalContext};
+use crate::model::{Error, ErrorPolicy, EvalResult, Value};
+use crate::register::{BinaryOp, Instruction, Operand, Program};
+use crate::stack::runtime::{and, make_map, operands, or};
+
+pub fn evaluate(program: &Program) -> EvalResult {
+    evaluate_in(program, &EvalContext::default())
+}
+
+/// Run `program` with the bindings, error policy and limits of `ctx`, counting each instruction and
+/// each value it makes against the limits, and checking depths, as the stack machine does.
+pub fn evaluate_in(program: &Program, ctx: &EvalContext) -> EvalResult {
+    let policy = ctx.error_policy();
+    let mut registers: Vec<EvalResult> = vec![Ok(Value::Null); program.registers];
+    let mut slots: Vec<EvalResult> = vec![Ok(Value::Null); program.slots];
+    let mut pc = 0;
+    while let Some(instruction) = program.code.get(pc) {
+        pc += 1;
+        if let Instruction::Depth(depth) = instruction {
+            if *depth >= ctx.limits().max_depth {
+                return Err(Error::RecursionLimitExceeded);
+            }
+            continue;
+        }
+        ctx.charge_operation()?;
+        let (dst, result) = match instruction {
+            Instruction::Depth(_) => unreachable!("checked above"),
+            Instruction::Const(dst, v) => (*dst, Ok(v.clone())),
+            Instruction::Load(dst, slot) => (*dst, slots[*slot].clone()),
+            Instruction::Lookup(dst, name) => (*dst, ctx.resolve(name.clone())),
+            Instruction::Binary(op, dst, a, b) => {
+                let a = read(a, &mut registers, &slots);
+                let b = read(b, &mut registers, &slots);
+                (*dst, binary(*op, a, b, policy))
+            }
+            Instruction::Neg(dst, a) => (
+                *dst,
+                read(a, &mut registers, &slots).and_then(interpreter::neg),
+            ),
+            Instruction::Not(dst, a) => (
+                *dst,
+                read(a, &mut registers, &slots).and_then(interpreter::not),
+            ),
+            Instruction::Member(dst, a, name) => (
+                *dst,
+                read(a, &mut registers, &slots).and_then(|v| interpreter::member(v, name.clone())),
+            ),
+            Instruction::SafeMember(dst, a, name) => (
+                *dst,
+                read(a, &mut registers, &slots)
+                    .and_then(|v| interpreter::safe_member(v, name.clone())),
+            ),
+            Instruction::MakeList(dst, n) => {
+                let elems = gather(&mut registers[*dst..*dst + n]);
+                (*dst, policy.collect(elems).map(Value::List))
+            }
+            Instruction::MakeMap(dst, n) => {
+                let entries = gather(&mut registers[*dst..*dst + 2 * n]);
+                (*dst, make_map(entries, policy))
+            }
+            Instruction::Call(dst, name, n) => {
+                let args = gather(&mut registers[*dst..*dst + n]);
+                let result = policy
+                    .collect(args)
+                    .and_then(|args| ctx.call_function(name.clone(), args));
+                (*dst, result)
+            }
+            Instruction::CallMethod(dst, name, n) => {
+                let args = gather(&mut registers[*dst..=*dst + n]);
+                let result = policy.collect(args).and_then(|mut args| {
+                    let operand = args.remove(0);
+                    ctx.call_method(name.clone(), operand, args)
+                });
+                (*dst, result)
+            }
+            Instruction::Store(slot, src) => {
+                slots[*slot] = take(&mut registers[*src]);
+                continue;
+            }
+            Instruction::Jump(target) => {
+                pc = *target;
+                continue;
+            }
+            Instruction::JumpIfTrue(reg, target) => {
+                if let Ok(Value::Bool(true)) = registers[*reg] {
+                    pc = *target;
+                }
+                continue;
+            }
+            Instruction::JumpIfFalse(reg, target) => {
+                if let Ok(Value::Bool(false)) = registers[*reg] {
+                    pc = *target;
+                }
+                continue;
+            }
+            Instruction::JumpUnlessTrue(reg, target) => {
+                // Like the interpreter, a condition that fails or isn't a bool takes the else branch.
+                if take(&mut registers[*reg]) != Ok(Value::Bool(true)) {
+                    pc = *target;
+                }
+                continue;
+            }
+        };
+        if let Ok(ref v) = result {
+            ctx.charge_bytes(v.size())?;
+        }
+        registers[dst] = result;
+    }
+    take(&mut registers[0])
+}
+
+fn read(operand: &Operand, registers: &mut [EvalResult], slots: &[EvalResult]) -> EvalResult {
+    match operand {
+        Operand::Reg(reg) => take(&mut registers[*reg]),
+        Operand::Slot(slot) => slots[*slot].clone(),
+        Operand::Const(v) => Ok(v.clone()),
+    }
+}
+
+/// The values in `registers`, leaving them empty.
+fn gather(registers: &mut [EvalResult]) -> Vec<EvalResult> {
+    registers.iter_mut().map(take).collect()
+}
+
+/// The value in `register`, leaving it empty.
+fn take(register: &mut EvalResult) -> EvalResult {
+    std::mem::replace(register, Ok(Value::Null))
+}
+
+fn binary(op: BinaryOp, a: EvalResult, b: EvalResult, policy: ErrorPolicy) -> EvalResult {
+    let f = match op {
+        BinaryOp::Or => return or(a, b, policy),
+        BinaryOp::And => return and(a, b, policy),
+        BinaryOp::Eq => interpreter::eq,
+        BinaryOp::Neq => interpreter::neq,
+        BinaryOp::Lt => interpreter::lt,
+        BinaryOp::Lte => interpreter::lte,
+        BinaryOp::Gte => interpreter::gte,
+        BinaryOp::Gt => interpreter::gt,
+        BinaryOp::Add => interpreter::add,
+        BinaryOp::Sub => interpreter::sub,
+        BinaryOp::Mul => interpreter::mul,
+        BinaryOp::Div => interpreter::div,
+        BinaryOp::Mod => interpreter::rem,
+        BinaryOp::OptionalIndex => interpreter::optional_index,
+    };
+    operands(a, b, policy).and_then(|(x, y)| f(x, y))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Identifier;
+    use crate::parser::parse;
+    use crate::register::compiler::compile;
+    use crate::stack;
+    use crate::stack::walker::linearize;
+
+    #[test]
+    fn eval_arithmetic() {
+        let ctx = EvalContext::default();
+        let ctx = ctx.with_binding(Identifier::new("x"), Ok(Value::I64(20)));
+        let program = compile(parse(r#" x * 2 + 1 - x / 3 % 4 "#).unwrap());
+        assert_eq!(evaluate_in(&program, &ctx), Ok(Value::I64(39)));
+        // A program can run many times.
+        assert_eq!(evaluate_in(&program, &ctx), Ok(Value::I64(39)));
+    }
+
+    #[test]
+    fn matches_stack_machine() {
+        let inputs = [
+            r#" 1 + 2 / 3 % 4 "#,
+            r#" (1 / 0) + ("a" + 1) "#,
+            r#" [1 % 0, 1 + "a"] "#,
+            r#" false || (1 / 0) || (1 % 0) "#,
+            r#" 1 / 0 || "a" || true "#,
+            r#" 1 / 0 && "a" && false "#,
+            r#" {"a": 1, 2: 3, "a": 4} "#,
+            r#" {"a": [1, {"b": x}]}.a "#,
+            r#" int("42") + 1 "#,
+            r#" frobnicate(1) "#,
+            r#" let y = x * x; let z = y - 1; [y, z, -z, !(y < z)] "#,
+            r#" let y = 1 / 0; let y = 2; y "#,
+            r#" x > 1 ? x < 5 ? "mid" : "high" : "low" "#,
+            r#" (1 / 0) ? 1 : [x, 0 + 0] "#,
+            r#" [x].contains(3) && x.pow(2) == 9 "#,
+            r#" {"k": x}.k + {"k": 1}.missing "#,
+            r#" [{"k": x}?.k, {"k": 1}?.missing, null?.k, x?.k] "#,
+            r#" [[x][?0], [x][?1].orValue(x), {"k": x}[?"k"][?0], x[?0]] "#,
+            r#" undefined || x == 3 "#,
+        ];
+        for &policy in &[ErrorPolicy::Leftmost, ErrorPolicy::Merged] {
+            let mut ctx = EvalContext::default();
+            ctx.set_error_policy(policy);
+            let ctx = ctx.with_binding(Identifier::new("x"), Ok(Value::I64(3)));
+            for input in &inputs {
+                let expr = parse(input).unwrap();
+                assert_eq!(
+                    evaluate_in(&compile(expr.clone()), &ctx),
+                    stack::runtime::evaluate_in(linearize(expr), &ctx),
+                    "{} under {:?}",
+                    input,
+                    policy
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! Partial evaluation of an expression whose bindings are only partly known, e.g. an authorization
+//! policy before the `resource` it will be checked against is loaded, and normalization of what
+//! remains into conjunctive form, so that hosts can translate the policy into a database filter.
+//!
+//! A subexpression that fails whatever the unknowns turn out to be makes the whole residual fail,
+//! since a residual expression has no way to express the error.
+
+use crate::functions::{
+    FUNCTION_DOUBLE, FUNCTION_DURATION, FUNCTION_OPTIONAL_NONE, FUNCTION_OPTIONAL_OF,
+    FUNCTION_TIMESTAMP, FUNCTION_UINT,
+};
+use crate::interpreter::EvalContext;
+use crate::model::{
+    Error, EvalResult, Expression, Identifier, Limit, LimitExceeded, Literal, Op, Value,
+};
+use crate::ordering;
+use crate::time;
+use std::mem;
+use std::rc::Rc;
+
+/// How many clauses `residual_filter` may produce. Distributing `||` over `&&` can multiply them.
+pub const MAX_CLAUSES: usize = 1024;
+
+/// What is left of an expression after evaluating everything that doesn't depend on the unknowns.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Partial {
+    /// The expression doesn't depend on the unknowns (or not in a way that matters).
+    Known(EvalResult),
+    /// An equivalent expression that refers only to the unknowns, with every other subexpression
+    /// replaced by its value.
+    Residual(Expression),
+}
+
+/// A condition in conjunctive normal form: it holds if every clause has an atom that holds. No
+/// clauses at all always holds, and an empty clause never does.
+///
+/// Atoms are comparisons, lookups, and other expressions over the unknowns, possibly negated with
+/// `!`; never `&&`, `||`, or ternaries.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Conjunction {
+    pub clauses: Vec<Vec<Expression>>,
+}
+
+impl Conjunction {
+    /// The condition as a single expression, `(a || b) && (c || d) && ...`.
+    pub fn to_expression(&self) -> Expression {
+        let clause = |atoms: &Vec<Expression>| match atoms.as_slice() {
+            [] => Expression::Lit(Literal::Bool(false)),
+            [atom] => atom.clone(),
+            _ => Expression::Or(atoms.clone()),
+        };
+        match self.clauses.as_slice() {
+            [] => Expression::Lit(Literal::Bool(true)),
+            [atoms] => clause(atoms),
+            clauses => Expression::And(clauses.iter().map(clause).collect()),
+        }
+    }
+}
+
+/// Evaluate as much of `expr` as possible in `ctx`, leaving the parts that depend on `unknowns`.
+pub fn partial_evaluate(ctx: &EvalContext, expr: Expression, unknowns: &[Identifier]) -> Partial {
+    PartialEvaluator {
+        ctx,
+        unknowns,
+        scopes: Vec::new(),
+    }
+    .eval(expr)
+}
+
+/// Partially evaluate `expr`, a condition, and normalize what is left into conjunctive form. A
+/// condition that is known not to be a bool fails as an operand of `&&` would.
+pub fn residual_filter(
+    ctx: &EvalContext,
+    expr: Expression,
+    unknowns: &[Identifier],
+) -> Result<Conjunction, Error> {
+    let clauses = match partial_evaluate(ctx, expr, unknowns) {
+        Partial::Known(Ok(Value::Bool(true))) => vec![],
+        Partial::Known(Ok(Value::Bool(false))) => vec![vec![]],
+        Partial::Known(Ok(v)) => return Err(Error::InvalidTypeForOperator(v.kind(), Op::And)),
+        Partial::Known(Err(e)) => return Err(e),
+        Partial::Residual(expr) => simplify(conjunctive(expr, false)?),
+    };
+    Ok(Conjunction { clauses })
+}
+
+struct PartialEvaluator<'a> {
+    ctx: &'a EvalContext<'a>,
+    unknowns: &'a [Identifier],
+    /// What enclosing `let`s bound their names to.
+    scopes: Vec<(Identifier, Partial)>,
+}
+
+impl PartialEvaluator<'_> {
+    fn eval(&mut self, mut expr: Expression) -> Partial {
+        match &mut expr {
+            Expression::Spanned(_, e) => self.eval(e.take()),
+            Expression::Binding(id) => {
+                if let Some((_, partial)) = self.scopes.iter().rev().find(|(name, _)| name == id) {
+                    partial.clone()
+                } else if self.unknowns.contains(id) {
+                    Partial::Residual(expr)
+                } else {
+                    Partial::Known(self.ctx.evaluate(expr))
+                }
+            }
+            Expression::LetBinding { id, value, body } => {
+                let value = self.eval(value.take());
+                self.scopes.push((mem::take(id), value));
+                let result = self.eval(body.take());
+                self.scopes.pop();
+                result
+            }
+            Expression::Or(operands) => self.logical(true, mem::take(operands)),
+            Expression::And(operands) => self.logical(false, mem::take(operands)),
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            } => match self.eval(condition.take()) {
+                Partial::Known(Ok(Value::Bool(true))) => self.eval(true_branch.take()),
+                Partial::Known(Ok(Value::Bool(false))) => self.eval(else_branch.take()),
+                Partial::Known(Ok(v)) => {
+                    **condition = literal(v);
+                    Partial::Known(self.ctx.evaluate(expr))
+                }
+                Partial::Known(Err(e)) => Partial::Known(Err(e)),
+                Partial::Residual(condition) => {
+                    let branches = (self.eval(true_branch.take()), self.eval(else_branch.take()));
+                    match (into_expression(branches.0), into_expression(branches.1)) {
+                        (Ok(t), Ok(e)) => Partial::Residual(Expression::Ternary {
+                            condition: Box::new(condition),
+                            true_branch: Box::new(t),
+                            else_branch: Box::new(e),
+                        }),
+                        (Err(e), _) | (_, Err(e)) => Partial::Known(Err(e)),
+                    }
+                }
+            },
+            _ => self.strict(expr),
+        }
+    }
+
+    /// `||` (if `is_or`) or `&&`: an operand known to decide the result decides it, and operands
+    /// known not to drop out.
+    fn logical(&mut self, is_or: bool, operands: Vec<Expression>) -> Partial {
+        let op = if is_or { Op::Or } else { Op::And };
+        let mut residuals = Vec::new();
+        let mut error = None;
+        for operand in operands {
+            match self.eval(operand) {
+                Partial::Known(Ok(Value::Bool(b))) if b == is_or => {
+                    return Partial::Known(Ok(Value::Bool(b)))
+                }
+                Partial::Known(Ok(Value::Bool(_))) => {}
+                Partial::Known(Ok(v)) => {
+                    let e = Error::InvalidTypeForOperator(v.kind(), op.clone());
+                    error = error.or(Some(e));
+                }
+                Partial::Known(Err(e)) => error = error.or(Some(e)),
+                Partial::Residual(e) => residuals.push(e),
+            }
+        }
+        if let Some(e) = error {
+            return Partial::Known(Err(e));
+        }
+        match residuals.len() {
+            0 => Partial::Known(Ok(Value::Bool(!is_or))),
+            1 => Partial::Residual(residuals.pop().unwrap()),
+            _ if is_or => Partial::Residual(Expression::Or(residuals)),
+            _ => Partial::Residual(Expression::And(residuals)),
+        }
+    }
+
+    /// Anything that needs the values of all of its operands.
+    fn strict(&mut self, expr: Expression) -> Partial {
+        let (operands, rebuild) = split(expr);
+        let mut residual = false;
+        let mut exprs = Vec::new();
+        for operand in operands {
+            match self.eval(operand) {
+                Partial::Known(Ok(v)) => exprs.push(literal(v)),
+                Partial::Known(Err(e)) => return Partial::Known(Err(e)),
+                Partial::Residual(e) => {
+                    residual = true;
+                    exprs.push(e);
+                }
+            }
+        }
+        let expr = rebuild(exprs);
+        if residual {
+            Partial::Residual(expr)
+        } else {
+            Partial::Known(self.ctx.evaluate(expr))
+        }
+    }
+}
+
+pub(crate) type Rebuild = Box<dyn FnOnce(Vec<Expression>) -> Expression>;
+
+/// Take the operands of `expr`, along with a function that puts it back together from them.
+/// Bindings, `let`s and scalar literals have no operands.
+pub(crate) fn split(mut expr: Expression) -> (Vec<Expression>, Rebuild) {
+    type Binary = fn(Box<Expression>, Box<Expression>) -> Expression;
+    fn binary(f: Binary, a: Expression, b: Expression) -> (Vec<Expression>, Rebuild) {
+        let rebuild = move |mut v: Vec<Expression>| {
+            let b = v.pop().unwrap();
+            let a = v.pop().unwrap();
+            f(Box::new(a), Box::new(b))
+        };
+        (vec![a, b], Box::new(rebuild))
+    }
+    match &mut expr {
+        Expression::Eq(a, b) => binary(Expression::Eq, a.take(), b.take()),
+        Expression::Neq(a, b) => binary(Expression::Neq, a.take(), b.take()),
+        Expression::Lt(a, b) => binary(Expression::Lt, a.take(), b.take()),
+        Expression::Lte(a, b) => binary(Expression::Lte, a.take(), b.take()),
+        Expression::Gte(a, b) => binary(Expression::Gte, a.take(), b.take()),
+        Expression::Gt(a, b) => binary(Expression::Gt, a.take(), b.take()),
+        Expression::Add(a, b) => binary(Expression::Add, a.take(), b.take()),
+        Expression::Sub(a, b) => binary(Expression::Sub, a.take(), b.take()),
+        Expression::Mul(a, b) => binary(Expression::Mul, a.take(), b.take()),
+        Expression::Div(a, b) => binary(Expression::Div, a.take(), b.take()),
+        Expression::Mod(a, b) => binary(Expression::Mod, a.take(), b.take()),
+        Expression::OptionalIndex(a, b) => binary(Expression::OptionalIndex, a.take(), b.take()),
+        Expression::Or(operands) => (mem::take(operands), Box::new(Expression::Or)),
+        Expression::And(operands) => (mem::take(operands), Box::new(Expression::And)),
+        Expression::Ternary {
+            condition,
+            true_branch,
+            else_branch,
+        } => {
+            let rebuild = |mut v: Vec<Expression>| {
+                let else_branch = v.pop().unwrap();
+                let true_branch = v.pop().unwrap();
+                Expression::Ternary {
+                    condition: Box::new(v.pop().unwrap()),
+                    true_branch: Box::new(true_branch),
+                    else_branch: Box::new(else_branch),
+                }
+            };
+            (
+                vec![condition.take(), true_branch.take(), else_branch.take()],
+                Box::new(rebuild),
+            )
+        }
+        Expression::Neg(a) => (
+            vec![a.take()],
+            Box::new(|mut v| Expression::Neg(Box::new(v.remove(0)))),
+        ),
+        Expression::Not(a) => (
+            vec![a.take()],
+            Box::new(|mut v| Expression::Not(Box::new(v.remove(0)))),
+        ),
+        Expression::Member(a, id) => {
+            let id = mem::take(id);
+            (
+                vec![a.take()],
+                Box::new(move |mut v| Expression::Member(Box::new(v.remove(0)), id)),
+            )
+        }
+        Expression::SafeMember(a, id) => {
+            let id = mem::take(id);
+            (
+                vec![a.take()],
+                Box::new(move |mut v| Expression::SafeMember(Box::new(v.remove(0)), id)),
+            )
+        }
+        Expression::Method(a, id, args) => {
+            let id = mem::take(id);
+            (
+                std::iter::once(a.take()).chain(args.drain(..)).collect(),
+                Box::new(move |v| {
+                    let mut v = v.into_iter();
+                    Expression::Method(Box::new(v.next().unwrap()), id, v.collect())
+                }),
+            )
+        }
+        Expression::Function(id, args) => {
+            let id = mem::take(id);
+            (
+                mem::take(args),
+                Box::new(move |v| Expression::Function(id, v)),
+            )
+        }
+        Expression::Lit(Literal::List(elems)) => (
+            mem::take(elems),
+            Box::new(|v| Expression::Lit(Literal::List(v))),
+        ),
+        Expression::Lit(Literal::Map(kvs)) => {
+            let operands = kvs.drain(..).flat_map(|(k, v)| vec![k, v]).collect();
+            let rebuild = |v: Vec<Expression>| {
+                let mut v = v.into_iter();
+                let mut kvs = Vec::new();
+                while let (Some(k), Some(v)) = (v.next(), v.next()) {
+                    kvs.push((k, v));
+                }
+                Expression::Lit(Literal::Map(kvs))
+            };
+            (operands, Box::new(rebuild))
+        }
+        _ => (Vec::new(), Box::new(move |_| expr)),
+    }
+}
+
+fn into_expression(partial: Partial) -> Result<Expression, Error> {
+    match partial {
+        Partial::Known(result) => result.map(literal),
+        Partial::Residual(expr) => Ok(expr),
+    }
+}
+
+/// An expression that evaluates to `value`.
+pub(crate) fn literal(value: Value) -> Expression {
+    let call = |name: &str, arg: String| {
+        let arg = Expression::Lit(Literal::String(arg));
+        Expression::Function(Identifier::new(name), vec![arg])
+    };
+    match value {
+        Value::I64(i) => Expression::Lit(Literal::I64(i)),
+        Value::U64(u) => call(FUNCTION_UINT, u.to_string()),
+        Value::F64(f) if f.is_finite() => Expression::Lit(Literal::F64(f)),
+        Value::F64(f) => call(FUNCTION_DOUBLE, f.to_string()),
+        Value::Bool(b) => Expression::Lit(Literal::Bool(b)),
+        Value::String(s) => Expression::Lit(Literal::String(Rc::unwrap_or_clone(s))),
+        Value::Bytes(b) => Expression::Lit(Literal::Bytes(Rc::unwrap_or_clone(b))),
+        Value::List(vs) => Expression::Lit(Literal::List(vs.into_iter().map(literal).collect())),
+        Value::Map(fields) => {
+            let kvs = ordering::into_entries(fields)
+                .into_iter()
+                .map(|(k, v)| (Expression::Lit(Literal::String(k)), literal(v)))
+                .collect();
+            Expression::Lit(Literal::Map(kvs))
+        }
+        Value::Timestamp(t) => call(FUNCTION_TIMESTAMP, time::format_timestamp(t)),
+        Value::Duration(d) => call(FUNCTION_DURATION, time::format_duration(d)),
+        Value::Null => Expression::Lit(Literal::Null),
+        Value::Optional(None) => {
+            Expression::Function(Identifier::new(FUNCTION_OPTIONAL_NONE), vec![])
+        }
+        Value::Optional(Some(v)) => {
+            Expression::Function(Identifier::new(FUNCTION_OPTIONAL_OF), vec![literal(*v)])
+        }
+    }
+}
+
+/// The clauses of `expr` (or of `!expr`, if `negated`) in conjunctive normal form.
+fn conjunctive(mut expr: Expression, negated: bool) -> Result<Vec<Vec<Expression>>, Error> {
+    let (all, any) = match (&mut expr, negated) {
+        (Expression::Not(e), negated) => return conjunctive(e.take(), !negated),
+        (Expression::And(cs), false) | (Expression::Or(cs), true) => (mem::take(cs), Vec::new()),
+        (Expression::Or(cs), false) | (Expression::And(cs), true) => (Vec::new(), mem::take(cs)),
+        (
+            Expression::Ternary {
+                condition,
+                true_branch,
+                else_branch,
+            },
+            negated,
+        ) => {
+            // `c ? t : e` holds exactly when `(!c || t) && (c || e)` does.
+            let not_c = Expression::Not(condition.clone());
+            let expr = Expression::And(vec![
+                Expression::Or(vec![not_c, true_branch.take()]),
+                Expression::Or(vec![condition.take(), else_branch.take()]),
+            ]);
+            return conjunctive(expr, negated);
+        }
+        (Expression::Eq(a, b), true) => {
+            let atom = Expression::Neq(Box::new(a.take()), Box::new(b.take()));
+            return Ok(vec![vec![atom]]);
+        }
+        (Expression::Neq(a, b), true) => {
+            let atom = Expression::Eq(Box::new(a.take()), Box::new(b.take()));
+            return Ok(vec![vec![atom]]);
+        }
+        (Expression::Lit(Literal::Bool(b)), negated) => {
+            let atom = Expression::Lit(Literal::Bool(*b != negated));
+            return Ok(vec![vec![atom]]);
+        }
+        (_, true) => return Ok(vec![vec![Expression::Not(Box::new(expr))]]),
+        (_, false) => return Ok(vec![vec![expr]]),
+    };
+    let mut clauses = Vec::new();
+    for c in all {
+        clauses.extend(conjunctive(c, negated)?);
+    }
+    if any.is_empty() {
+        return Ok(clauses);
+    }
+    // Distribute: `(a && b) || c` is `(a || c) && (b || c)`.
+    let mut product = vec![Vec::new()];
+    for c in any {
+        let c = conjunctive(c, negated)?;
+        if product.len() * c.len() > MAX_CLAUSES {
+            return Err(Error::EvaluationTooLarge(LimitExceeded {
+                limit: Limit::Clauses,
+                threshold: MAX_CLAUSES,
+                consumed: product.len() * c.len(),
+            }));
+        }
+        product = product
+            .iter()
+            .flat_map(|a| {
+                c.iter()
+                    .map(move |b| a.iter().chain(b).cloned().collect::<Vec<_>>())
+            })
+            .collect();
+    }
+    Ok(product)
+}
+
+/// Drop repeated atoms and clauses, `false` atoms, and clauses that always hold.
+fn simplify(clauses: Vec<Vec<Expression>>) -> Vec<Vec<Expression>> {
+    let mut simplified: Vec<Vec<Expression>> = Vec::new();
+    for clause in clauses {
+        let mut atoms: Vec<Expression> = Vec::new();
+        let mut holds = false;
+        for atom in clause {
+            let negation = match &atom {
+                Expression::Not(e) => (**e).clone(),
+                e => Expression::Not(Box::new(e.clone())),
+            };
+            holds |= atom == Expression::Lit(Literal::Bool(true)) || atoms.contains(&negation);
+            if atom != Expression::Lit(Literal::Bool(false)) && !atoms.contains(&atom) {
+                atoms.push(atom);
+            }
+        }
+        let seen = simplified
+            .iter()
+            .any(|c| c.len() == atoms.len() && c.iter().all(|a| atoms.contains(a)));
+        if !holds && !seen {
+            simplified.push(atoms);
+        }
+    }
+    simplified
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Kind;
+    use crate::parser::parse;
+
+    fn filter(input: &str) -> Result<Conjunction, Error> {
+        let ctx = EvalContext::default();
+        let ctx = ctx.with_binding(
+            Identifier::new("user"),
+            Ok(Value::String("alice".to_owned().into())),
+        );
+        let ctx = ctx.with_binding(Identifier::new("limit"), Ok(Value::I64(10)));
+        let resource = [Identifier::new("resource")];
+        residual_filter(&ctx, parse(input).unwrap(), &resource)
+    }
+
+    fn clauses(clauses: &[&[&str]]) -> Result<Conjunction, Error> {
+        let clauses = clauses
+            .iter()
+            .map(|atoms| atoms.iter().map(|a| parse(a).unwrap()).collect())
+            .collect();
+        Ok(Conjunction { clauses })
+    }
+
+    #[test]
+    fn partial_evaluation() {
+        let ctx = EvalContext::default();
+        let unknowns = [Identifier::new("x")];
+        let partial = |input: &str| partial_evaluate(&ctx, parse(input).unwrap(), &unknowns);
+        assert_eq!(
+            partial("let y = [1 + 2, 'a']; x.f(y) > 2 * 3"),
+            Partial::Residual(parse("x.f([3, 'a']) > 6").unwrap())
+        );
+        assert_eq!(
+            partial("x.a || 1 < 2"),
+            Partial::Known(Ok(Value::Bool(true)))
+        );
+        assert_eq!(
+            partial("1 / 0 == x"),
+            Partial::Known(Err(Error::DivisionByZero))
+        );
+        assert_eq!(
+            partial("let x = 1; x + 1"),
+            Partial::Known(Ok(Value::I64(2)))
+        );
+    }
+
+    #[test]
+    fn conjunctive_form() {
+        assert_eq!(
+            filter("resource.owner == user && (resource.public || resource.size < limit)"),
+            clauses(&[
+                &["resource.owner == 'alice'"],
+                &["resource.public", "resource.size < 10"],
+            ])
+        );
+        assert_eq!(
+            filter("!(resource.a == 1 || resource.b) || user == 'bob'"),
+            clauses(&[&["resource.a != 1"], &["!resource.b"]])
+        );
+        assert_eq!(
+            filter("resource.a ? resource.b : false"),
+            clauses(&[&["!resource.a", "resource.b"], &["resource.a"]])
+        );
+        assert_eq!(
+            filter("(resource.a || resource.a) && (resource.b || !resource.b)"),
+            clauses(&[&["resource.a"]])
+        );
+    }
+
+    #[test]
+    fn known_conditions() {
+        assert_eq!(filter("user == 'alice' || resource.x"), clauses(&[]));
+        assert_eq!(filter("user == 'bob' && resource.x"), clauses(&[&[]]));
+        assert_eq!(
+            filter("limit"),
+            Err(Error::InvalidTypeForOperator(Kind::I64, Op::And))
+        );
+        assert_eq!(
+            clauses(&[&[]]).unwrap().to_expression(),
+            Expression::Lit(Literal::Bool(false))
+        );
+    }
+
+    #[test]
+    fn too_many_clauses() {
+        let input = (0..11)
+            .map(|i| format!("(resource.a{} && resource.b{})", i, i))
+            .collect::<Vec<_>>()
+            .join(" || ");
+        // Distributing the first ten disjuncts makes 2 ** 10 clauses; the eleventh doubles that.
+        assert_eq!(
+            filter(&input),
+            Err(Error::EvaluationTooLarge(LimitExceeded {
+                limit: Limit::Clauses,
+                threshold: MAX_CLAUSES,
+                consumed: 2048,
+            }))
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! A probe for whether a condition can ever hold, so that policy tools can flag contradictory rules
+//! like `x > 5 && x < 3`.
+//!
+//! The probe is a bounded search: it tries values for each declared binding drawn from the
+//! constants in the condition (each constant, and its neighbors on either side). A binding that is
+//! only ever compared directly to literals can't tell apart values between the same two constants,
+//! so when every binding is used that way, trying them all settles the question.
+
+use crate::checker::strip_span;
+use crate::interpreter::EvalContext;
+use crate::model::{Expression, Identifier, Kind, Literal, Value};
+use crate::residual::{partial_evaluate, Partial};
+use serde::Serialize;
+
+/// How many assignments of values to the declared bindings `satisfiable` tries before giving up.
+pub const MAX_ASSIGNMENTS: usize = 4096;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Satisfiability {
+    /// The condition holds with the declared bindings bound to these values, whatever the values of
+    /// the others.
+    Satisfiable(Vec<(Identifier, Value)>),
+    /// The condition never holds.
+    Unsatisfiable,
+    /// The search didn't find values for which the condition holds, but couldn't rule them out.
+    Unknown,
+}
+
+/// Whether `expr` can evaluate to `true` in `ctx` with each of `declared` bound to some value of its
+/// kind. Undeclared bindings, and declared ones of kinds other than scalars, may have any value.
+pub fn satisfiable(
+    ctx: &EvalContext,
+    expr: &Expression,
+    declared: &[(Identifier, Kind)],
+) -> Satisfiability {
+    let mut scan = Scan {
+        declared,
+        constants: Vec::new(),
+        unknowns: Vec::new(),
+        used: Vec::new(),
+        exhaustive: true,
+    };
+    scan.visit(expr);
+    let mut domains = Vec::new();
+    for &(id, kind) in &scan.used {
+        match candidates(kind, &scan.constants) {
+            Some(values) => domains.push((id, values)),
+            None => scan.unknowns.push(id.clone()),
+        }
+    }
+    let total = domains
+        .iter()
+        .try_fold(1usize, |n, (_, values)| n.checked_mul(values.len()))
+        .filter(|&n| n <= MAX_ASSIGNMENTS);
+    let mut undecided = !scan.exhaustive || total.is_none();
+    for mut n in 0..total.unwrap_or(MAX_ASSIGNMENTS) {
+        let assignment: Vec<(Identifier, Value)> = domains
+            .iter()
+            .map(|(id, values)| {
+                let value = values[n % values.len()].clone();
+                n /= values.len();
+                ((*id).clone(), value)
+            })
+            .collect();
+        match evaluate(ctx, &assignment, expr.clone(), &scan.unknowns) {
+            Partial::Known(Ok(Value::Bool(true))) => {
+                return Satisfiability::Satisfiable(assignment)
+            }
+            Partial::Known(_) => {}
+            Partial::Residual(_) => undecided = true,
+        }
+    }
+    if undecided {
+        Satisfiability::Unknown
+    } else {
+        Satisfiability::Unsatisfiable
+    }
+}
+
+/// Partially evaluate `expr` with each of `bindings` in scope.
+fn evaluate(
+    ctx: &EvalContext,
+    bindings: &[(Identifier, Value)],
+    expr: Expression,
+    unknowns: &[Identifier],
+) -> Partial {
+    match bindings.split_first() {
+        Some(((id, value), rest)) => {
+            let ctx = ctx.with_binding(id.clone(), Ok(value.clone()));
+            evaluate(&ctx, rest, expr, unknowns)
+        }
+        None => partial_evaluate(ctx, expr, unknowns),
+    }
+}
+
+struct Scan<'a> {
+    declared: &'a [(Identifier, Kind)],
+    /// The scalar literals in the condition.
+    constants: Vec<Literal>,
+    /// The undeclared bindings.
+    unknowns: Vec<Identifier>,
+    /// The declared bindings that the condition refers to, with their kinds.
+    used: Vec<(&'a Identifier, Kind)>,
+    /// Whether every declared binding with infinitely many values is only compared to literals.
+    exhaustive: bool,
+}
+
+impl Scan<'_> {
+    fn visit(&mut self, expr: &Expression) {
+        let expr = strip_span(expr);
+        match expr {
+            Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::Gt(a, b)
+                if self.compared(a, b) || self.compared(b, a) =>
+            {
+                return
+            }
+            Expression::Lit(Literal::List(_)) | Expression::Lit(Literal::Map(_)) => {}
+            Expression::Lit(lit) => self.constants.push(lit.clone()),
+            Expression::Binding(id) => match self.lookup(id) {
+                Some(Kind::Bool) | Some(Kind::Null) => {}
+                Some(_) => self.exhaustive = false,
+                None if !self.unknowns.contains(id) => self.unknowns.push(id.clone()),
+                None => {}
+            },
+            _ => {}
+        }
+        for (_, child) in expr.children() {
+            self.visit(child);
+        }
+    }
+
+    /// Whether `side` is a declared binding and `other` a scalar literal, noting the literal if so.
+    fn compared(&mut self, side: &Expression, other: &Expression) -> bool {
+        match (strip_span(side), strip_span(other)) {
+            (Expression::Binding(id), Expression::Lit(lit))
+                if !matches!(lit, Literal::List(_) | Literal::Map(_)) =>
+            {
+                if self.lookup(id).is_none() {
+                    return false;
+                }
+                self.constants.push(lit.clone());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The kind of `id` if it is declared, noting that it is used.
+    fn lookup(&mut self, id: &Identifier) -> Option<Kind> {
+        let (name, kind) = self.declared.iter().find(|(name, _)| name == id)?;
+        if !self.used.iter().any(|(used, _)| used == &name) {
+            self.used.push((name, *kind));
+        }
+        Some(*kind)
+    }
+}
+
+/// Values of `kind` to try: one between each pair of neighboring `constants`, and beyond them on
+/// either side, as well as the constants themselves. `None` for kinds that aren't searched.
+fn candidates(kind: Kind, constants: &[Literal]) -> Option<Vec<Value>> {
+    let ints = || {
+        constants.iter().flat_map(|c| match c {
+            Literal::I64(n) => vec![*n],
+            Literal::F64(x) if x.is_finite() => vec![x.floor() as i64, x.ceil() as i64],
+            _ => vec![],
+        })
+    };
+    let values = match kind {
+        Kind::Bool => vec![Value::Bool(false), Value::Bool(true)],
+        Kind::Null => vec![Value::Null],
+        Kind::I64 => {
+            let mut ns: Vec<i64> = ints()
+                .flat_map(|n| vec![n.saturating_sub(1), n, n.saturating_add(1)])
+                .chain(Some(0))
+                .collect();
+            ns.sort_unstable();
+            ns.dedup();
+            ns.into_iter().map(Value::I64).collect()
+        }
+        Kind::U64 => {
+            let mut ns: Vec<u64> = ints()
+                .flat_map(|n| vec![n.saturating_sub(1), n, n.saturating_add(1)])
+                .filter(|&n| n >= 0)
+                .map(|n| n as u64)
+                .chain(Some(0))
+                .collect();
+            ns.sort_unstable();
+            ns.dedup();
+            ns.into_iter().map(Value::U64).collect()
+        }
+        Kind::F64 => {
+            let mut xs: Vec<f64> = constants
+                .iter()
+                .filter_map(|c| match c {
+                    Literal::I64(n) => Some(*n as f64),
+                    Literal::F64(x) if x.is_finite() => Some(*x),
+                    _ => None,
+                })
+                .collect();
+            xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            xs.dedup();
+            let mut values = match (xs.first(), xs.last()) {
+                (Some(&min), Some(&max)) => {
+                    vec![min - min.abs().max(1.0), max + max.abs().max(1.0)]
+                }
+                _ => vec![0.0],
+            };
+            values.extend(xs.windows(2).map(|w| w[0] / 2.0 + w[1] / 2.0));
+            values.extend(xs);
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            // Last, since it's an unhelpful example: NaN compares unequal to everything.
+            values.push(f64::NAN);
+            values.into_iter().map(Value::F64).collect()
+        }
+        Kind::String => {
+            // The string just after `s` is `s` followed by a NUL, so that and the empty string
+            // fill in around the constants.
+            let mut ss: Vec<String> = constants
+                .iter()
+                .filter_map(|c| match c {
+                    Literal::String(s) => Some(vec![s.clone(), format!("{}\0", s)]),
+                    _ => None,
+                })
+                .flatten()
+                .chain(Some(String::new()))
+                .collect();
+            ss.sort();
+            ss.dedup();
+            ss.into_iter().map(|s| Value::String(s.into())).collect()
+        }
+        _ => return None,
+    };
+    Some(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse, parse_with_spans};
+
+    fn probe(input: &str) -> Satisfiability {
+        let declared = [
+            (Identifier::new("x"), Kind::I64),
+            (Identifier::new("d"), Kind::F64),
+            (Identifier::new("s"), Kind::String),
+            (Identifier::new("b"), Kind::Bool),
+            (Identifier::new("l"), Kind::List),
+        ];
+        let ctx = EvalContext::default();
+        let result = satisfiable(&ctx, &parse(input).unwrap(), &declared);
+        let spanned = satisfiable(&ctx, &parse_with_spans(input).unwrap(), &declared);
+        assert_eq!(result, spanned);
+        result
+    }
+
+    fn witness(input: &str) -> Vec<(Identifier, Value)> {
+        match probe(input) {
+            Satisfiability::Satisfiable(assignment) => assignment,
+            result => panic!("{} is {:?}", input, result),
+        }
+    }
+
+    fn value_of(name: &str, assignment: &[(Identifier, Value)]) -> Value {
+        let (_, value) = assignment.iter().find(|(id, _)| id.0 == name).unwrap();
+        value.clone()
+    }
+
+    #[test]
+    fn contradictions() {
+        assert_eq!(probe("x > 5 && x < 3"), Satisfiability::Unsatisfiable);
+        assert_eq!(
+            probe("x > 5 && x < 7 && x != 6"),
+            Satisfiability::Unsatisfiable
+        );
+        assert_eq!(probe("d >= 2.5 && 2.0 > d"), Satisfiability::Unsatisfiable);
+        assert_eq!(
+            probe(r#" s == "a" && s != "a" "#),
+            Satisfiability::Unsatisfiable
+        );
+        assert_eq!(
+            probe("b && !b || x < 0 && x > 0"),
+            Satisfiability::Unsatisfiable
+        );
+        // Undeclared bindings don't matter when the rest decides.
+        assert_eq!(
+            probe("y.size() > 2 && 1 > 2"),
+            Satisfiability::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn witnesses() {
+        assert_eq!(value_of("x", &witness("x > 5 && x < 7")), Value::I64(6));
+        assert_eq!(
+            value_of("d", &witness("d > 1.0 && d < 2.0")),
+            Value::F64(1.5)
+        );
+        assert_eq!(
+            value_of("s", &witness(r#" s > "a" && s < "b" "#)),
+            Value::String("a\0".to_owned().into())
+        );
+        assert_eq!(
+            witness("!b && x == 3"),
+            vec![
+                (Identifier::new("b"), Value::Bool(false)),
+                (Identifier::new("x"), Value::I64(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn undecided() {
+        // `x + 1` isn't compared directly to a literal, so values other than those tried might
+        // satisfy it.
+        assert_eq!(probe("x + 1 > 5 && x < 3"), Satisfiability::Unknown);
+        assert_eq!(probe("l.size() > 0"), Satisfiability::Unknown);
+        assert_eq!(probe("y > 2"), Satisfiability::Unknown);
+    }
+}
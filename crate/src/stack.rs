@@ -1,15 +1,62 @@
-use crate::model::Value;
+use crate::model::{Identifier, Value};
 
-#[derive(Debug, PartialEq)]
+/// A single instruction for the stack machine.
+///
+/// Programs are in postfix order and run front to back: operands are evaluated left to right (map
+/// entries key first, in source order) before the operation that consumes them. When several
+/// operands fail, the error from the leftmost one is reported, matching the tree-walking interpreter.
+///
+/// Jumps only go forward, by skipping the given number of operations, so every program finishes
+/// after running each of its operations at most once.
+///
+/// A `Depth` starts each run of operations between jumps, so that the program stops where the
+/// interpreter would find an expression nested too deeply.
+#[derive(Debug, PartialEq, Clone)]
 pub enum Operation {
+    /// Stop with `RecursionLimitExceeded` if the deepest subexpression the operations up to the next
+    /// jump evaluate is nested at least as deep as the context's depth limit. Not an operation of the
+    /// expression's, it doesn't count against the operation limit.
+    Depth(usize),
     Lit(Value),
     MakeList(usize),
     MakeMap(usize),
+    /// Call the named global function with the top `n` values as arguments.
+    Call(Identifier, usize),
+    /// Call the named method with the top `n` values as arguments, on the value below them.
+    CallMethod(Identifier, usize),
+    /// Replace the top value with its member of the given name.
+    Member(Identifier),
+    /// Replace the top value with its member of the given name, or with null if it's null or a map
+    /// without one.
+    SafeMember(Identifier),
+    /// Pop the top value into the slot, for the body of a `let`. Slots are numbered by how many
+    /// `let`s enclose the binding, so storing into a slot frees it and every slot after it.
+    Store(usize),
+    /// Push the value in the slot.
+    Load(usize),
+    /// Push the value bound to the name by the context the program runs in.
+    Lookup(Identifier),
+    /// Skip the next `n` operations.
+    Jump(usize),
+    /// Skip the next `n` operations if the top value is `true`, leaving it, which decides an `||`.
+    JumpIfTrue(usize),
+    /// Skip the next `n` operations if the top value is `false`, leaving it, which decides an `&&`.
+    JumpIfFalse(usize),
+    /// Pop the condition of a ternary, and skip the next `n` operations unless it is `true`.
+    JumpUnlessTrue(usize),
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gte,
+    Gt,
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    /// Pop a key and then a list or map, and push an optional holding the element it indexes.
+    OptionalIndex,
     Neg,
     Not,
     Or,
@@ -18,3 +65,4 @@ pub enum Operation {
 
 pub mod walker;
 pub mod runtime;
+pub mod bytecode;
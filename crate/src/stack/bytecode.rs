@@ -0,0 +1,345 @@
+//! Programs for the stack machine, saved as bytes so that a server can compile expressions once and
+//! ship them to clients that only run them.
+//!
+//! Bytecode is stored as JSON, with a header like a stored program's and then the operations:
+//!
+//! ```json
+//! {"format": 1, "version": "0.1.0", "ops": [{"Depth": 1}, {"Lookup": "x"}, {"I64": 1}, "Add"]}
+//! ```
+//!
+//! Loading checks that the operations make a program: that each has its operands on the stack,
+//! reads only slots that have been stored, and jumps within the program, and that it leaves one
+//! value. So bytecode that loads runs without panicking, wherever it came from.
+
+use crate::model::{Identifier, Kind, Value};
+use crate::stack::Operation;
+use serde::{Deserialize, Serialize};
+
+/// The version of the bytecode format that this crate reads and writes.
+pub const FORMAT: u32 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum BytecodeError {
+    /// The bytes aren't bytecode, or their operations don't make a program.
+    Malformed(String),
+    /// The bytecode is in a format this crate can't read.
+    UnsupportedFormat(u32),
+    /// The program pushes a literal of a kind that bytecode can't store.
+    UnsupportedLiteral(Kind),
+}
+
+/// Bytecode as stored.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    format: u32,
+    version: String,
+    ops: Vec<Op>,
+}
+
+/// An operation as stored, with each kind of literal a variant of its own.
+#[derive(Serialize, Deserialize)]
+enum Op {
+    Depth(usize),
+    Null,
+    I64(i64),
+    /// The bits of a double.
+    F64(u64),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    MakeList(usize),
+    MakeMap(usize),
+    Call(Identifier, usize),
+    CallMethod(Identifier, usize),
+    Member(Identifier),
+    SafeMember(Identifier),
+    Store(usize),
+    Load(usize),
+    Lookup(Identifier),
+    Jump(usize),
+    JumpIfTrue(usize),
+    JumpIfFalse(usize),
+    JumpUnlessTrue(usize),
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gte,
+    Gt,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    OptionalIndex,
+    Neg,
+    Not,
+    Or,
+    And,
+}
+
+/// `program` as bytes. Fails if it pushes a list, map or other value that no literal spells.
+pub fn to_bytes(program: &[Operation]) -> Result<Vec<u8>, BytecodeError> {
+    let stored = Stored {
+        format: FORMAT,
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        ops: program.iter().map(store).collect::<Result<_, _>>()?,
+    };
+    Ok(serde_json::to_vec(&stored).expect("serialize"))
+}
+
+/// Load a program that `to_bytes` saved.
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<Operation>, BytecodeError> {
+    // Check the format before the rest, which may be shaped differently in other formats.
+    #[derive(Deserialize)]
+    struct Header {
+        format: u32,
+    }
+    let header: Header =
+        serde_json::from_slice(bytes).map_err(|err| BytecodeError::Malformed(err.to_string()))?;
+    if header.format != FORMAT {
+        return Err(BytecodeError::UnsupportedFormat(header.format));
+    }
+    let stored: Stored =
+        serde_json::from_slice(bytes).map_err(|err| BytecodeError::Malformed(err.to_string()))?;
+    let program: Vec<Operation> = stored.ops.into_iter().map(load).collect();
+    verify(&program).map_err(BytecodeError::Malformed)?;
+    Ok(program)
+}
+
+fn store(op: &Operation) -> Result<Op, BytecodeError> {
+    Ok(match op {
+        Operation::Depth(depth) => Op::Depth(*depth),
+        Operation::Lit(Value::Null) => Op::Null,
+        Operation::Lit(Value::I64(n)) => Op::I64(*n),
+        Operation::Lit(Value::F64(x)) => Op::F64(x.to_bits()),
+        Operation::Lit(Value::Bool(b)) => Op::Bool(*b),
+        Operation::Lit(Value::String(s)) => Op::String(s.to_string()),
+        Operation::Lit(Value::Bytes(bs)) => Op::Bytes(bs.to_vec()),
+        Operation::Lit(other) => return Err(BytecodeError::UnsupportedLiteral(other.kind())),
+        Operation::MakeList(n) => Op::MakeList(*n),
+        Operation::MakeMap(n) => Op::MakeMap(*n),
+        Operation::Call(id, n) => Op::Call(id.clone(), *n),
+        Operation::CallMethod(id, n) => Op::CallMethod(id.clone(), *n),
+        Operation::Member(id) => Op::Member(id.clone()),
+        Operation::SafeMember(id) => Op::SafeMember(id.clone()),
+        Operation::Store(slot) => Op::Store(*slot),
+        Operation::Load(slot) => Op::Load(*slot),
+        Operation::Lookup(id) => Op::Lookup(id.clone()),
+        Operation::Jump(n) => Op::Jump(*n),
+        Operation::JumpIfTrue(n) => Op::JumpIfTrue(*n),
+        Operation::JumpIfFalse(n) => Op::JumpIfFalse(*n),
+        Operation::JumpUnlessTrue(n) => Op::JumpUnlessTrue(*n),
+        Operation::Eq => Op::Eq,
+        Operation::Neq => Op::Neq,
+        Operation::Lt => Op::Lt,
+        Operation::Lte => Op::Lte,
+        Operation::Gte => Op::Gte,
+        Operation::Gt => Op::Gt,
+        Operation::Add => Op::Add,
+        Operation::Sub => Op::Sub,
+        Operation::Mul => Op::Mul,
+        Operation::Div => Op::Div,
+        Operation::Mod => Op::Mod,
+        Operation::OptionalIndex => Op::OptionalIndex,
+        Operation::Neg => Op::Neg,
+        Operation::Not => Op::Not,
+        Operation::Or => Op::Or,
+        Operation::And => Op::And,
+    })
+}
+
+fn load(op: Op) -> Operation {
+    match op {
+        Op::Depth(depth) => Operation::Depth(depth),
+        Op::Null => Operation::Lit(Value::Null),
+        Op::I64(n) => Operation::Lit(Value::I64(n)),
+        Op::F64(bits) => Operation::Lit(Value::F64(f64::from_bits(bits))),
+        Op::Bool(b) => Operation::Lit(Value::Bool(b)),
+        Op::String(s) => Operation::Lit(Value::String(s.into())),
+        Op::Bytes(bs) => Operation::Lit(Value::Bytes(bs.into())),
+        Op::MakeList(n) => Operation::MakeList(n),
+        Op::MakeMap(n) => Operation::MakeMap(n),
+        Op::Call(id, n) => Operation::Call(id, n),
+        Op::CallMethod(id, n) => Operation::CallMethod(id, n),
+        Op::Member(id) => Operation::Member(id),
+        Op::SafeMember(id) => Operation::SafeMember(id),
+        Op::Store(slot) => Operation::Store(slot),
+        Op::Load(slot) => Operation::Load(slot),
+        Op::Lookup(id) => Operation::Lookup(id),
+        Op::Jump(n) => Operation::Jump(n),
+        Op::JumpIfTrue(n) => Operation::JumpIfTrue(n),
+        Op::JumpIfFalse(n) => Operation::JumpIfFalse(n),
+        Op::JumpUnlessTrue(n) => Operation::JumpUnlessTrue(n),
+        Op::Eq => Operation::Eq,
+        Op::Neq => Operation::Neq,
+        Op::Lt => Operation::Lt,
+        Op::Lte => Operation::Lte,
+        Op::Gte => Operation::Gte,
+        Op::Gt => Operation::Gt,
+        Op::Add => Operation::Add,
+        Op::Sub => Operation::Sub,
+        Op::Mul => Operation::Mul,
+        Op::Div => Operation::Div,
+        Op::Mod => Operation::Mod,
+        Op::OptionalIndex => Operation::OptionalIndex,
+        Op::Neg => Operation::Neg,
+        Op::Not => Operation::Not,
+        Op::Or => Operation::Or,
+        Op::And => Operation::And,
+    }
+}
+
+/// Check that `program` can run: follow each path through it, tracking how many values are on the
+/// stack and how many slots are stored before each operation.
+fn verify(program: &[Operation]) -> Result<(), String> {
+    // Stack depth and stored slots before each operation, and at the end, once a path reaches it.
+    // Paths that meet must agree on the depth; only the slots that both stored may be read after.
+    let mut states: Vec<Option<(usize, usize)>> = vec![None; program.len() + 1];
+    states[0] = Some((0, 0));
+    for (i, op) in program.iter().enumerate() {
+        let (depth, slots) = match states[i] {
+            Some(state) => state,
+            None => continue,
+        };
+        let (pops, pushes) = arity(op);
+        if pops > depth {
+            return Err(format!("operation {} is missing operands", i));
+        }
+        let mut next = (depth - pops + pushes, slots);
+        match op {
+            Operation::Store(slot) if *slot > slots => {
+                return Err(format!("operation {} skips a slot", i));
+            }
+            Operation::Store(slot) => next.1 = slot + 1,
+            Operation::Load(slot) if *slot >= slots => {
+                return Err(format!("operation {} loads a slot before it's stored", i));
+            }
+            _ => {}
+        }
+        match op {
+            Operation::Jump(n)
+            | Operation::JumpIfTrue(n)
+            | Operation::JumpIfFalse(n)
+            | Operation::JumpUnlessTrue(n) => {
+                let target = n
+                    .checked_add(i + 1)
+                    .filter(|&target| target <= program.len())
+                    .ok_or_else(|| format!("operation {} jumps past the end", i))?;
+                merge(&mut states[target], next, target)?;
+            }
+            _ => {}
+        }
+        if !matches!(op, Operation::Jump(_)) {
+            merge(&mut states[i + 1], next, i + 1)?;
+        }
+    }
+    match states[program.len()] {
+        Some((1, _)) => Ok(()),
+        _ => Err("the program doesn't leave one value".to_owned()),
+    }
+}
+
+fn merge(
+    state: &mut Option<(usize, usize)>,
+    next: (usize, usize),
+    at: usize,
+) -> Result<(), String> {
+    *state = match *state {
+        None => Some(next),
+        Some((depth, _)) if depth != next.0 => {
+            return Err(format!("paths to operation {} disagree on the stack", at));
+        }
+        Some((depth, slots)) => Some((depth, slots.min(next.1))),
+    };
+    Ok(())
+}
+
+/// How many values `op` pops, and how many it then pushes.
+fn arity(op: &Operation) -> (usize, usize) {
+    match op {
+        Operation::Lit(_) | Operation::Load(_) | Operation::Lookup(_) => (0, 1),
+        Operation::MakeList(n) | Operation::Call(_, n) => (*n, 1),
+        Operation::MakeMap(n) => (n.saturating_mul(2), 1),
+        Operation::CallMethod(_, n) => (n.saturating_add(1), 1),
+        Operation::Store(_) | Operation::JumpUnlessTrue(_) => (1, 0),
+        Operation::Depth(_) | Operation::Jump(_) => (0, 0),
+        Operation::Member(_)
+        | Operation::SafeMember(_)
+        | Operation::JumpIfTrue(_)
+        | Operation::JumpIfFalse(_)
+        | Operation::Neg
+        | Operation::Not => (1, 1),
+        Operation::Eq
+        | Operation::Neq
+        | Operation::Lt
+        | Operation::Lte
+        | Operation::Gte
+        | Operation::Gt
+        | Operation::Add
+        | Operation::Sub
+        | Operation::Mul
+        | Operation::Div
+        | Operation::Mod
+        | Operation::OptionalIndex
+        | Operation::Or
+        | Operation::And => (2, 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stack::walker::linearize;
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            r#" let x = 1.5; x * 2.0 > 1.0 ? [null, b"ab"] : {"a": x}.a "#,
+            r#" a || b && !c.d.contains(-1) "#,
+            r#" int("42") % 5 "#,
+        ];
+        for input in inputs {
+            let program = linearize(parse(input).unwrap());
+            let bytes = to_bytes(&program).unwrap();
+            assert_eq!(from_bytes(&bytes), Ok(program), "{}", input);
+        }
+        assert_eq!(
+            to_bytes(&[Operation::Lit(Value::List(vec![]))]),
+            Err(BytecodeError::UnsupportedLiteral(Kind::List))
+        );
+    }
+
+    #[test]
+    fn rejects() {
+        let load = |ops: &str| {
+            let json = format!(r#"{{"format": 1, "version": "", "ops": {}}}"#, ops);
+            from_bytes(json.as_bytes())
+        };
+        assert_eq!(
+            from_bytes(br#"{"format": 2, "ops": []}"#),
+            Err(BytecodeError::UnsupportedFormat(2))
+        );
+        assert_eq!(load(r#"[{"I64": 1}]"#).map(|ops| ops.len()), Ok(1));
+        let malformed = [
+            r#"[]"#,
+            r#"["Add"]"#,
+            r#"[{"I64": 1}, {"I64": 1}]"#,
+            r#"[{"Load": 0}]"#,
+            r#"[{"I64": 1}, {"Store": 1}, {"I64": 1}]"#,
+            r#"[{"Bool": true}, {"JumpIfTrue": 5}]"#,
+            // One branch pushes a value that the other doesn't.
+            r#"[{"Bool": true}, {"JumpUnlessTrue": 1}, {"I64": 1}, {"I64": 2}]"#,
+            r#"[{"MakeMap": 18446744073709551615}]"#,
+            r#"["Frobnicate"]"#,
+        ];
+        for ops in malformed {
+            assert!(
+                matches!(load(ops), Err(BytecodeError::Malformed(_))),
+                "{}",
+                ops
+            );
+        }
+    }
+}
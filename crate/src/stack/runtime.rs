@@ -1,120 +1,240 @@
-use crate::model::{Value, EvalResult, Error, Op};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::interpreter::{self, EvalContext};
+use crate::model::{Value, EvalResult, Error, ErrorPolicy, Op};
 use crate::stack::Operation;
 
-fn evaluate(mut program: Vec<Operation>) -> EvalResult {
+pub fn evaluate(program: Vec<Operation>) -> EvalResult {
+    evaluate_with_policy(program, ErrorPolicy::default())
+}
+
+pub fn evaluate_with_policy(program: Vec<Operation>, policy: ErrorPolicy) -> EvalResult {
+    let mut ctx = EvalContext::default();
+    ctx.set_error_policy(policy);
+    evaluate_in(program, &ctx)
+}
+
+/// Run `program` with the bindings, error policy and limits of `ctx`.
+///
+/// Like the interpreter, each operation counts against the operation limit and each value made
+/// against the byte limit. Unlike it, exceeding either stops the program at once, whatever it would
+/// have gone on to do with the error. The same goes for the depth limit, which `Depth` checks.
+pub fn evaluate_in(program: Vec<Operation>, ctx: &EvalContext) -> EvalResult {
+    let policy = ctx.error_policy();
     let mut stack = Vec::new();
-    while let Some(op) = program.pop() {
+    // The values of the enclosing `let`s, each of which may be an error that only matters if used.
+    let mut slots: Vec<EvalResult> = Vec::new();
+    let mut program = program.into_iter();
+    while let Some(op) = program.next() {
+        if let Operation::Depth(depth) = op {
+            if depth >= ctx.limits().max_depth {
+                return Err(Error::RecursionLimitExceeded);
+            }
+            continue;
+        }
+        ctx.charge_operation()?;
+        let makes_value = !matches!(op,
+            Operation::Store(_)
+            | Operation::Jump(_)
+            | Operation::JumpIfTrue(_)
+            | Operation::JumpIfFalse(_)
+            | Operation::JumpUnlessTrue(_));
         match op {
+            Operation::Depth(_) => unreachable!("checked above"),
             Operation::Lit(v) => stack.push(Ok(v)),
-            Operation::MakeList(n) => {
-                let mut acc = Ok(Vec::new());
-                for _ in 0 .. n {
-                    let v = stack.pop().unwrap();
-                    if let Ok(ref mut vs) = acc {
-                        match v {
-                            Ok(v) => vs.push(v),
-                            Err(e) => acc = Err(e),
-                        }
-                    }
+            Operation::Store(slot) => {
+                let v = pop(&mut stack)?;
+                slots.truncate(slot);
+                slots.push(v);
+            }
+            Operation::Load(slot) => {
+                let v = slots.get(slot).cloned().ok_or_else(|| malformed("loads an empty slot"))?;
+                stack.push(v);
+            }
+            Operation::Lookup(name) => stack.push(ctx.resolve(name)),
+            Operation::Jump(n) => skip(&mut program, n),
+            Operation::JumpIfTrue(n) => {
+                if let Some(Ok(Value::Bool(true))) = stack.last() {
+                    skip(&mut program, n);
                 }
-                stack.push(acc.map(|vs| Value::List(vs)));
-            }
-            Operation::MakeMap(_) => unimplemented!(),
-            Operation::Add => {
-                let a = stack.pop().unwrap();
-                let b = stack.pop().unwrap();
-                match (a, b) {
-                    (Ok(x), Ok(y)) => stack.push(add(x, y)),
-                    (Err(e), _) => stack.push(Err(e)),
-                    (_, Err(e)) => stack.push(Err(e)),
+            }
+            Operation::JumpIfFalse(n) => {
+                if let Some(Ok(Value::Bool(false))) = stack.last() {
+                    skip(&mut program, n);
                 }
             }
-            Operation::Sub => unimplemented!(),
-            Operation::Mul => unimplemented!(),
-            Operation::Div => {
-                let a = stack.pop().unwrap();
-                let b = stack.pop().unwrap();
-                match (a, b) {
-                    (Ok(x), Ok(y)) => stack.push(div(x, y)),
-                    (Err(e), _) => stack.push(Err(e)),
-                    (_, Err(e)) => stack.push(Err(e)),
+            Operation::JumpUnlessTrue(n) => {
+                // Like the interpreter, a condition that fails or isn't a bool takes the else branch.
+                if pop(&mut stack)? != Ok(Value::Bool(true)) {
+                    skip(&mut program, n);
                 }
             }
-            Operation::Mod => unimplemented!(),
-            Operation::Neg => unimplemented!(),
-            Operation::Not => unimplemented!(),
+            Operation::MakeList(n) => {
+                let elems = pop_n(&mut stack, n)?;
+                stack.push(policy.collect(elems).map(Value::List));
+            }
+            Operation::MakeMap(n) => {
+                let entries = pop_n(&mut stack, 2 * n)?;
+                stack.push(make_map(entries, policy));
+            }
+            Operation::Call(name, n) => {
+                let args = pop_n(&mut stack, n)?;
+                stack.push(policy.collect(args).and_then(|args| ctx.call_function(name, args)));
+            }
+            Operation::CallMethod(name, n) => {
+                let operands = pop_n(&mut stack, n + 1)?;
+                stack.push(policy.collect(operands).and_then(|mut args| {
+                    let operand = args.remove(0);
+                    ctx.call_method(name, operand, args)
+                }));
+            }
+            Operation::Member(name) => {
+                let a = pop(&mut stack)?;
+                stack.push(a.and_then(|v| interpreter::member(v, name)));
+            }
+            Operation::SafeMember(name) => {
+                let a = pop(&mut stack)?;
+                stack.push(a.and_then(|v| interpreter::safe_member(v, name)));
+            }
+            Operation::Eq => binary(&mut stack, policy, interpreter::eq)?,
+            Operation::Neq => binary(&mut stack, policy, interpreter::neq)?,
+            Operation::Lt => binary(&mut stack, policy, interpreter::lt)?,
+            Operation::Lte => binary(&mut stack, policy, interpreter::lte)?,
+            Operation::Gte => binary(&mut stack, policy, interpreter::gte)?,
+            Operation::Gt => binary(&mut stack, policy, interpreter::gt)?,
+            Operation::Add => binary(&mut stack, policy, interpreter::add)?,
+            Operation::Sub => binary(&mut stack, policy, interpreter::sub)?,
+            Operation::Mul => binary(&mut stack, policy, interpreter::mul)?,
+            Operation::Div => binary(&mut stack, policy, interpreter::div)?,
+            Operation::Mod => binary(&mut stack, policy, interpreter::rem)?,
+            Operation::OptionalIndex => binary(&mut stack, policy, interpreter::optional_index)?,
+            Operation::Neg => {
+                let a = pop(&mut stack)?;
+                stack.push(a.and_then(interpreter::neg));
+            }
+            Operation::Not => {
+                let a = pop(&mut stack)?;
+                stack.push(a.and_then(interpreter::not));
+            }
             Operation::Or => {
-                let a = stack.pop().unwrap();
-                let b = stack.pop().unwrap();
-                stack.push(or(a, b));
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(or(a, b, policy));
             }
             Operation::And => {
-                let a = stack.pop().unwrap();
-                let b = stack.pop().unwrap();
-                stack.push(and(a, b));
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(and(a, b, policy));
             }
         }
+        if let (true, Some(Ok(v))) = (makes_value, stack.last()) {
+            ctx.charge_bytes(v.size())?;
+        }
+    }
+    match (stack.pop(), stack.is_empty()) {
+        (Some(result), true) => result,
+        _ => Err(malformed("doesn't leave a single value on the stack")),
     }
-    assert_eq!(stack.len(), 1);
-    stack.pop().expect("valid programs always result in a single value on the stack")
 }
 
-fn add(a: Value, b: Value) -> EvalResult {
-    match (a, b) {
-        (Value::I64(a), Value::I64(b)) => Ok(Value::I64(a + b)),
-        (Value::F64(a), Value::F64(b)) => Ok(Value::F64(a + b)),
-        (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Plus))
+/// The error for a program that couldn't have come from `walker::linearize` or `bytecode`, which
+/// only run programs that can.
+fn malformed(problem: &str) -> Error {
+    Error::Internal(format!("the program {}", problem))
+}
+
+fn pop(stack: &mut Vec<EvalResult>) -> Result<EvalResult, Error> {
+    stack.pop().ok_or_else(|| malformed("is missing operands"))
+}
+
+fn pop_n(stack: &mut Vec<EvalResult>, n: usize) -> Result<Vec<EvalResult>, Error> {
+    let at = stack.len().checked_sub(n).ok_or_else(|| malformed("is missing operands"))?;
+    Ok(stack.split_off(at))
+}
+
+fn skip(program: &mut impl Iterator<Item = Operation>, n: usize) {
+    if n > 0 {
+        program.nth(n - 1);
     }
 }
 
-fn div(a: Value, b: Value) -> EvalResult {
+/// Pops two operands and pushes the result of `f` on them, shared with the interpreter so that
+/// both agree on every operator.
+fn binary(stack: &mut Vec<EvalResult>, policy: ErrorPolicy, f: fn(Value, Value) -> EvalResult) -> Result<(), Error> {
+    let b = pop(stack)?;
+    let a = pop(stack)?;
+    stack.push(operands(a, b, policy).and_then(|(x, y)| f(x, y)));
+    Ok(())
+}
+
+pub(crate) fn operands(a: EvalResult, b: EvalResult, policy: ErrorPolicy) -> Result<(Value, Value), Error> {
     match (a, b) {
-        (Value::I64(a), Value::I64(b)) => {
-            if b == 0 {
-                Err(Error::DivisionByZero)
-            } else {
-                Ok(Value::I64(a / b))
-            }
-        },
-        (Value::F64(a), Value::F64(b)) => {
-            if b == 0.0 {
-                Err(Error::DivisionByZero)
-            } else {
-                Ok(Value::F64(a / b))
+        (Ok(a), Ok(b)) => Ok((a, b)),
+        (Err(e), Ok(_)) | (Ok(_), Err(e)) => Err(e),
+        (Err(a), Err(b)) => Err(policy.combine(a, b)),
+    }
+}
+
+/// Builds a map from alternating keys and values, reporting problems in source order.
+pub(crate) fn make_map(entries: Vec<EvalResult>, policy: ErrorPolicy) -> EvalResult {
+    let mut m = HashMap::new();
+    let mut entries = entries.into_iter();
+    let mut checked = Vec::new();
+    while let (Some(k), Some(v)) = (entries.next(), entries.next()) {
+        checked.push(match operands(k, v, policy) {
+            Ok((Value::String(k), v)) => {
+                let k = Rc::unwrap_or_clone(k);
+                if m.insert(k.clone(), v).is_some() {
+                    Err(Error::DuplicateMapKey(k))
+                } else {
+                    Ok(())
+                }
             }
-        },
-        (a, b) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Plus))
+            Ok((other, _)) => Err(Error::InvalidMapKey(other.kind())),
+            Err(e) => Err(e),
+        });
     }
+    policy.collect(checked)?;
+    Ok(Value::Map(m))
 }
 
-fn or(a: EvalResult, b: EvalResult) -> EvalResult {
+/// Like the interpreter: a `true` operand wins, even over errors; otherwise errors and non-bool
+/// operands are reported left to right.
+pub(crate) fn or(a: EvalResult, b: EvalResult, policy: ErrorPolicy) -> EvalResult {
     match (a, b) {
         (Ok(Value::Bool(true)), _) | (_, Ok(Value::Bool(true))) => {
             Ok(Value::Bool(true))
         },
-        (Ok(Value::Bool(false)), Ok(Value::Bool(false))) => {
-            Ok(Value::Bool(false))
-        },
-        (Err(e), _) | (_, Err(e)) => Err(e),
-        (Ok(a), Ok(b)) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::Or))
+        (a, b) => {
+            operands(logical(a, Op::Or), logical(b, Op::Or), policy).map(|_| Value::Bool(false))
+        }
     }
 }
 
-fn and(a: EvalResult, b: EvalResult) -> EvalResult {
+/// Like `or`, with `false` winning instead.
+pub(crate) fn and(a: EvalResult, b: EvalResult, policy: ErrorPolicy) -> EvalResult {
     match (a, b) {
         (Ok(Value::Bool(false)), _) | (_, Ok(Value::Bool(false))) => {
             Ok(Value::Bool(false))
         },
-        (Ok(Value::Bool(true)), Ok(Value::Bool(true))) => {
-            Ok(Value::Bool(true))
-        },
-        (Err(e), _) | (_, Err(e)) => Err(e),
-        (Ok(a), Ok(b)) => Err(Error::InvalidTypesForOperator(a.kind(), b.kind(), Op::And))
+        (a, b) => {
+            operands(logical(a, Op::And), logical(b, Op::And), policy).map(|_| Value::Bool(true))
+        }
+    }
+}
+
+fn logical(v: EvalResult, op: Op) -> EvalResult {
+    match v? {
+        Value::Bool(b) => Ok(Value::Bool(b)),
+        other => Err(Error::InvalidTypeForOperator(other.kind(), op)),
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::interpreter::{EvalContext, EvalLimits};
+    use crate::model::{Identifier, Kind};
     use crate::parser::parse;
     use crate::stack::walker::linearize;
 
@@ -129,6 +249,15 @@ mod test {
         );
     }
 
+    #[test]
+    fn eval_add_overflow() {
+        let program = linearize(parse(r#" 9223372036854775807 + 1 "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::IntegerOverflow)
+        );
+    }
+
     #[test]
     fn eval_list_empty() {
         let program = linearize(parse(r#" [] "#).unwrap());
@@ -156,6 +285,132 @@ mod test {
         );
     }
 
+    #[test]
+    fn eval_mod() {
+        let program = linearize(parse(r#" 7 % 3 "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::I64(1))
+        );
+    }
+
+    #[test]
+    fn eval_mod_by_zero() {
+        let program = linearize(parse(r#" 1 % 0 "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::DivisionByZero),
+        );
+    }
+
+    #[test]
+    fn eval_arithmetic() {
+        let program = linearize(parse(r#" -(7 - 2 * 3) "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::I64(-1))
+        );
+        let program = linearize(parse(r#" -(-9223372036854775807 - 1) "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::IntegerOverflow)
+        );
+    }
+
+    #[test]
+    fn eval_comparison() {
+        let program = linearize(parse(r#" !(1 < 2) || "a" + "b" == "ab" "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::Bool(true))
+        );
+        let program = linearize(parse(r#" 1 <= "a" "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::InvalidTypesForOperator(Kind::I64, Kind::String, Op::Lte))
+        );
+    }
+
+    #[test]
+    fn eval_let() {
+        let expr = parse(r#" let x = 1; let y = x + 1; let x = x + y; [x, y] "#).unwrap();
+        let program = linearize(expr);
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::List(vec![Value::I64(3), Value::I64(2)]))
+        );
+        // An error in a value only matters if the value is used.
+        let program = linearize(parse(r#" let x = 1 / 0; let y = 2; y "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::I64(2))
+        );
+    }
+
+    #[test]
+    fn eval_bindings() {
+        let ctx = EvalContext::default();
+        let ctx = ctx.with_binding(Identifier::new("x"), Ok(Value::I64(2)));
+        let program = linearize(parse(r#" let y = x * 3; x + y "#).unwrap());
+        assert_eq!(
+            evaluate_in(program, &ctx),
+            Ok(Value::I64(8))
+        );
+        let program = linearize(parse(r#" xx "#).unwrap());
+        assert_eq!(
+            evaluate_in(program, &ctx),
+            Err(Error::NoSuchBinding(Identifier::new("xx"), vec![Identifier::new("x")]))
+        );
+    }
+
+    #[test]
+    fn eval_map() {
+        let program = linearize(parse(r#" {"a": 1, "b": 2 + 3} "#).unwrap());
+        let mut expected = HashMap::new();
+        expected.insert("a".to_owned(), Value::I64(1));
+        expected.insert("b".to_owned(), Value::I64(5));
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::Map(expected))
+        );
+    }
+
+    #[test]
+    fn eval_map_duplicate_key() {
+        let program = linearize(parse(r#" {"a": 1, "a": 2} "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::DuplicateMapKey("a".to_owned())),
+        );
+    }
+
+    #[test]
+    fn eval_leftmost_error_wins() {
+        let program = linearize(parse(r#" (1 / 0) + ("a" + 1) "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::DivisionByZero),
+        );
+    }
+
+    #[test]
+    fn eval_list_leftmost_error_wins() {
+        let program = linearize(parse(r#" [1 % 0, 1 + "a"] "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::DivisionByZero),
+        );
+    }
+
+    #[test]
+    fn eval_map_leftmost_error_wins() {
+        let program = linearize(parse(r#" {"a": 1, 2: 3, "a": 4} "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::InvalidMapKey(Kind::I64)),
+        );
+    }
+
     #[test]
     fn eval_or_simple() {
         let program = linearize(parse(r#" true || false "#).unwrap());
@@ -209,4 +464,176 @@ mod test {
             Ok(Value::Bool(false)),
         );
     }
+
+    #[test]
+    fn eval_ternary() {
+        let program = linearize(parse(r#" 1 < 2 ? "yes" : 1 / 0 "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::String("yes".to_owned().into()))
+        );
+        let program = linearize(parse(r#" let x = 3; 1 > 2 ? 1 / 0 : x "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::I64(3))
+        );
+    }
+
+    #[test]
+    fn eval_short_circuit() {
+        // The skipped operand would fail if it ran.
+        let program = linearize(parse(r#" true || undefined "#).unwrap());
+        assert_eq!(program.len(), 6);
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::Bool(true))
+        );
+        let program = linearize(parse(r#" 1 / 0 && false && undefined "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::Bool(false))
+        );
+    }
+
+    #[test]
+    fn eval_limits() {
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_ops: 6,
+            ..EvalLimits::default()
+        });
+        let program = || linearize(parse(r#" 1 + 2 + 3 "#).unwrap());
+        assert_eq!(evaluate_in(program(), &ctx), Ok(Value::I64(6)));
+        // The count carries over, like the interpreter's.
+        assert_eq!(evaluate_in(program(), &ctx), Err(Error::TooManyOperations));
+
+        let ctx = EvalContext::with_limits(EvalLimits {
+            max_bytes: 10_000,
+            ..EvalLimits::default()
+        });
+        let s = Value::String("x".repeat(1000).into());
+        let ctx = ctx.with_binding(Identifier::new("s"), Ok(s));
+        // Each doubling is charged, so this stops long before it runs out of memory.
+        let mut input = "let s0 = s;".to_owned();
+        for i in 1..64 {
+            input.push_str(&format!(" let s{} = s{} + s{};", i, i - 1, i - 1));
+        }
+        let program = linearize(parse(&(input + " s63")).unwrap());
+        assert!(matches!(evaluate_in(program, &ctx), Err(Error::EvaluationTooLarge(_))));
+    }
+
+    #[test]
+    fn eval_merged_errors() {
+        let program = linearize(parse(r#" [1 / 0, 1, 1 + "a"] "#).unwrap());
+        assert_eq!(
+            evaluate_with_policy(program, ErrorPolicy::Merged),
+            Err(Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::InvalidTypesForOperator(Kind::I64, Kind::String, Op::Plus),
+            ])),
+        );
+    }
+
+    #[test]
+    fn eval_or_non_bool() {
+        let program = linearize(parse(r#" "a" || 1 / 0 "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Err(Error::InvalidTypeForOperator(Kind::String, Op::Or))
+        );
+        let program = linearize(parse(r#" 1 / 0 || "a" "#).unwrap());
+        assert_eq!(
+            evaluate_with_policy(program, ErrorPolicy::Merged),
+            Err(Error::Multiple(vec![
+                Error::DivisionByZero,
+                Error::InvalidTypeForOperator(Kind::String, Op::Or),
+            ])),
+        );
+    }
+
+    #[test]
+    fn eval_function() {
+        let program = linearize(parse(r#" int("42") + 1 "#).unwrap());
+        assert_eq!(
+            evaluate(program),
+            Ok(Value::I64(43))
+        );
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let inputs = vec![
+            r#" 1 + 2 / 3 % 4 "#,
+            r#" (1 / 0) + ("a" + 1) "#,
+            r#" ("a" + 1) + (1 / 0) "#,
+            r#" [1 % 0, 1 + "a"] "#,
+            r#" [1 + "a", 1 % 0] "#,
+            r#" false || (1 / 0) || (1 % 0) "#,
+            r#" "a" || 1 / 0 "#,
+            r#" 1 / 0 || "a" "#,
+            r#" 1 / 0 || "a" || true "#,
+            r#" "a" && 1 / 0 "#,
+            r#" 1 / 0 && "a" "#,
+            r#" 1 / 0 && "a" && false "#,
+            r#" {"a": 1, 2: 3, "a": 4} "#,
+            r#" {"a": 1, "a": 2, 3: 4} "#,
+            r#" {1: 1 / 0} "#,
+            r#" int("42") + 1 "#,
+            r#" frobnicate(1) "#,
+            r#" int(1 / 0, 1 % 0) "#,
+            r#" 3 - 2 * 4 >= -5 "#,
+            r#" 1.5 * 2.0 > 2.5 && 1 != 2 "#,
+            r#" [1, "a"] == [1, "a"] && b"x" <= b"y" "#,
+            r#" 1 < "a" || !1 "#,
+            r#" -"a" + (1 * 0.5) "#,
+            r#" "a" / 1 "#,
+            r#" let x = 2; let y = x * x; let x = y - 1; x * y "#,
+            r#" let x = 1 / 0; let x = 1; x "#,
+            r#" let x = "a" + 1; x + (1 / 0) "#,
+            r#" 1 < 2 ? 3 : 1 / 0 "#,
+            r#" "a" ? 1 / 0 : 1 % 0 "#,
+            r#" (1 / 0) ? 1 : false ? 2 : 3 "#,
+            r#" true ? false || 1 / 0 : true "#,
+            r#" false || 1 / 0 || "a" + 1 || true "#,
+            r#" 1 / 0 || "a" || "b" "#,
+            r#" true && "a" && 1 / 0 "#,
+            r#" {"a": {"b": 2}}.a.b.pow(3) + [1].len() "#,
+            r#" {"a": 1}.b "#,
+            r#" 1.a "#,
+            r#" [1, 2].contains(1 / 0, 1 % 0) "#,
+            r#" (1 / 0).len() "#,
+            r#" [1].frobnicate() "#,
+        ];
+        for &policy in &[ErrorPolicy::Leftmost, ErrorPolicy::Merged] {
+            let mut ctx = EvalContext::default();
+            ctx.set_error_policy(policy);
+            for input in &inputs {
+                let expr = parse(input).unwrap();
+                assert_eq!(
+                    evaluate_with_policy(linearize(expr.clone()), policy),
+                    ctx.evaluate(expr),
+                    "{} under {:?}", input, policy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn eval_malformed() {
+        assert_eq!(
+            evaluate(vec![Operation::Add]),
+            Err(Error::Internal("the program is missing operands".to_owned()))
+        );
+        assert_eq!(
+            evaluate(vec![Operation::Load(0)]),
+            Err(Error::Internal("the program loads an empty slot".to_owned()))
+        );
+        assert_eq!(
+            evaluate(vec![Operation::Lit(Value::Null), Operation::Lit(Value::Null)]),
+            Err(Error::Internal("the program doesn't leave a single value on the stack".to_owned()))
+        );
+        assert_eq!(
+            evaluate(vec![Operation::MakeList(3)]),
+            Err(Error::Internal("the program is missing operands".to_owned()))
+        );
+    }
 }
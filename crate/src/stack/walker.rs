@@ -1,105 +1,226 @@
-use crate::model::{Expression, Literal, Value};
+use crate::interpreter::is_binary;
+use crate::model::{Expression, Identifier, Literal, Value};
 use crate::stack::Operation;
+use std::mem;
 
+/// Compile `e` to stack machine operations. Subexpressions are tracked on an explicit work stack
+/// rather than by recursion, so no expression is too deeply nested to compile.
+///
+/// Each run of operations between jumps starts with a `Depth` giving how deeply nested the deepest
+/// subexpression it evaluates is, counted as the interpreter counts it, so that the program stops
+/// where the interpreter would run out of depth.
 pub fn linearize(e: Expression) -> Vec<Operation> {
-    let mut walker = Walker::new();
-    walker.walk(e);
-    walker.0
+    let mut walker = Walker { ops: Vec::new(), names: Vec::new(), tasks: vec![Task::Walk(e, 0)], jumps: Vec::new(), run: None };
+    while let Some(task) = walker.tasks.pop() {
+        walker.run(task);
+    }
+    walker.ops
+}
+
+/// A unit of pending work for `linearize`.
+enum Task {
+    /// Compile the expression, nested the given depth.
+    Walk(Expression, usize),
+    Emit(Operation),
+    /// Store the value on top of the stack in the next slot, bound to the name.
+    Bind(Identifier),
+    /// Leave the innermost `let`.
+    Unbind,
+    /// Emit a jump whose target isn't known yet, to be patched by a later `Land`.
+    Jump(Operation),
+    /// End the true branch of a ternary: jump over the else branch, which the condition's jump lands
+    /// on.
+    Else,
+    /// Patch the last `n` jumps emitted to land on the next operation.
+    Land(usize),
 }
 
-struct Walker(Vec<Operation>);
+struct Walker {
+    ops: Vec<Operation>,
+    /// The names bound by the enclosing `let`s, whose slots are their positions.
+    names: Vec<Identifier>,
+    tasks: Vec<Task>,
+    /// Where the jumps waiting for a target are.
+    jumps: Vec<usize>,
+    /// Where the `Depth` starting the current run of operations is, if it has one yet.
+    run: Option<usize>,
+}
 impl Walker {
-    fn new() -> Walker {
-        Walker(Vec::new())
-    }
-    fn walk(&mut self, e: Expression) {
-        match e {
-            Expression::LetBinding { .. } => unimplemented!(),
-            Expression::Ternary { .. } => unimplemented!(),
-            Expression::Or(vs) => {
-                let n = vs.len();
-                for (i, v) in vs.into_iter().enumerate() {
-                    if i < n - 1 {
-                        self.0.push(Operation::Or);
-                    }
-                    self.walk(v);
-                }
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Walk(e, depth) => self.walk(e, depth),
+            Task::Emit(op) => self.ops.push(op),
+            Task::Bind(id) => {
+                self.ops.push(Operation::Store(self.names.len()));
+                self.names.push(id);
             }
-            Expression::And(vs) => {
-                let n = vs.len();
-                for (i, v) in vs.into_iter().enumerate() {
-                    if i < n - 1 {
-                        self.0.push(Operation::And);
-                    }
-                    self.walk(v);
+            Task::Unbind => {
+                self.names.pop();
+            }
+            Task::Jump(op) => {
+                self.jumps.push(self.ops.len());
+                self.ops.push(op);
+                self.run = None;
+            }
+            Task::Else => {
+                let branch = self.jumps.pop().expect("branch");
+                self.jumps.push(self.ops.len());
+                self.ops.push(Operation::Jump(0));
+                self.run = None;
+                self.land(branch);
+            }
+            Task::Land(n) => {
+                for _ in 0..n {
+                    let at = self.jumps.pop().expect("jump");
+                    self.land(at);
                 }
             }
-            Expression::Eq(_, _) => unimplemented!(),
-            Expression::Neq(_, _) => unimplemented!(),
-            Expression::Lt(_, _) => unimplemented!(),
-            Expression::Lte(_, _) => unimplemented!(),
-            Expression::Gte(_, _) => unimplemented!(),
-            Expression::Gt(_, _) => unimplemented!(),
-            Expression::Add(a, b) => {
-                self.0.push(Operation::Add);
-                self.walk(*a);
-                self.walk(*b);
+        }
+    }
+
+    /// Point the jump at `at` to the next operation, which starts a new run.
+    fn land(&mut self, at: usize) {
+        self.run = None;
+        let n = self.ops.len() - at - 1;
+        match &mut self.ops[at] {
+            Operation::Jump(to) | Operation::JumpIfTrue(to) | Operation::JumpIfFalse(to) | Operation::JumpUnlessTrue(to) => *to = n,
+            _ => unreachable!("not a jump"),
+        }
+    }
+
+    /// Schedule `tasks`, to run in order before anything already scheduled.
+    fn then(&mut self, tasks: Vec<Task>) {
+        self.tasks.extend(tasks.into_iter().rev());
+    }
+
+    /// Raise the depth the current run of operations checks to `depth`, starting the run if need be.
+    fn reach(&mut self, depth: usize) {
+        match self.run {
+            Some(at) => match &mut self.ops[at] {
+                Operation::Depth(deepest) => *deepest = depth.max(*deepest),
+                _ => unreachable!("not a depth"),
+            },
+            None => {
+                self.run = Some(self.ops.len());
+                self.ops.push(Operation::Depth(depth));
             }
-            Expression::Sub(a, b) => {
-                self.0.push(Operation::Sub);
-                self.walk(*a);
-                self.walk(*b);
+        }
+    }
+
+    /// Like the interpreter, a chain like `1 + 2 - 3` nests to the left as one level, not one per
+    /// operator.
+    fn binary(&mut self, a: Expression, b: Expression, op: Operation, depth: usize) {
+        let left = if is_binary(&a) { depth } else { depth + 1 };
+        self.then(vec![Task::Walk(a, left), Task::Walk(b, depth + 1), Task::Emit(op)]);
+    }
+
+    fn walk(&mut self, mut e: Expression, depth: usize) {
+        // Spans aren't evaluated as such, so they don't count as a level.
+        if let Expression::Spanned(_, a) = &mut e {
+            return self.tasks.push(Task::Walk(a.take(), depth));
+        }
+        self.reach(depth);
+        let inner = depth + 1;
+        let walk = |e: Expression| Task::Walk(e, inner);
+        match &mut e {
+            Expression::LetBinding { id, value, body } => {
+                self.then(vec![walk(value.take()), Task::Bind(mem::take(id)), walk(body.take()), Task::Unbind]);
             }
-            Expression::Mul(a, b) => {
-                self.0.push(Operation::Mul);
-                self.walk(*a);
-                self.walk(*b);
+            Expression::Ternary { condition, true_branch, else_branch } => {
+                self.then(vec![
+                    walk(condition.take()),
+                    Task::Jump(Operation::JumpUnlessTrue(0)),
+                    walk(true_branch.take()),
+                    Task::Else,
+                    walk(else_branch.take()),
+                    Task::Land(1),
+                ]);
             }
-            Expression::Div(a, b) => {
-                self.0.push(Operation::Div);
-                self.walk(*a);
-                self.walk(*b);
+            Expression::Or(vs) => self.walk_logical(mem::take(vs), true, inner),
+            Expression::And(vs) => self.walk_logical(mem::take(vs), false, inner),
+            Expression::Eq(a, b) => self.binary(a.take(), b.take(), Operation::Eq, depth),
+            Expression::Neq(a, b) => self.binary(a.take(), b.take(), Operation::Neq, depth),
+            Expression::Lt(a, b) => self.binary(a.take(), b.take(), Operation::Lt, depth),
+            Expression::Lte(a, b) => self.binary(a.take(), b.take(), Operation::Lte, depth),
+            Expression::Gte(a, b) => self.binary(a.take(), b.take(), Operation::Gte, depth),
+            Expression::Gt(a, b) => self.binary(a.take(), b.take(), Operation::Gt, depth),
+            Expression::Add(a, b) => self.binary(a.take(), b.take(), Operation::Add, depth),
+            Expression::Sub(a, b) => self.binary(a.take(), b.take(), Operation::Sub, depth),
+            Expression::Mul(a, b) => self.binary(a.take(), b.take(), Operation::Mul, depth),
+            Expression::Div(a, b) => self.binary(a.take(), b.take(), Operation::Div, depth),
+            Expression::Mod(a, b) => self.binary(a.take(), b.take(), Operation::Mod, depth),
+            Expression::OptionalIndex(a, b) => self.binary(a.take(), b.take(), Operation::OptionalIndex, depth),
+            Expression::Neg(a) => self.then(vec![walk(a.take()), Task::Emit(Operation::Neg)]),
+            Expression::Not(a) => self.then(vec![walk(a.take()), Task::Emit(Operation::Not)]),
+            Expression::Member(a, name) => self.then(vec![walk(a.take()), Task::Emit(Operation::Member(mem::take(name)))]),
+            Expression::SafeMember(a, name) => self.then(vec![walk(a.take()), Task::Emit(Operation::SafeMember(mem::take(name)))]),
+            Expression::Method(a, name, args) => {
+                let n = args.len();
+                let mut tasks = vec![walk(a.take())];
+                tasks.extend(args.drain(..).map(walk));
+                tasks.push(Task::Emit(Operation::CallMethod(mem::take(name), n)));
+                self.then(tasks);
             }
-            Expression::Mod(a, b) => {
-                self.0.push(Operation::Mod);
-                self.walk(*a);
-                self.walk(*b);
+            Expression::Function(name, args) => {
+                let n = args.len();
+                let mut tasks: Vec<Task> = args.drain(..).map(walk).collect();
+                tasks.push(Task::Emit(Operation::Call(mem::take(name), n)));
+                self.then(tasks);
             }
-            Expression::Neg(a) => {
-                self.0.push(Operation::Neg);
-                self.walk(*a);
+            Expression::Lit(lit) => self.walk_literal(mem::replace(lit, Literal::Null), inner),
+            Expression::Binding(id) => match self.names.iter().rposition(|name| name == id) {
+                Some(slot) => self.ops.push(Operation::Load(slot)),
+                None => self.ops.push(Operation::Lookup(mem::take(id))),
+            },
+            Expression::Spanned(..) => unreachable!("spans are unwrapped above"),
+        }
+    }
+
+    /// Combines the operands of an `||` (if `is_or`) or `&&` left to right, jumping to the end as
+    /// soon as one decides it.
+    fn walk_logical(&mut self, vs: Vec<Expression>, is_or: bool, depth: usize) {
+        let jump = if is_or { Operation::JumpIfTrue } else { Operation::JumpIfFalse };
+        let combine = if is_or { Operation::Or } else { Operation::And };
+        let mut tasks = Vec::new();
+        let mut jumps = 0;
+        for (i, v) in vs.into_iter().enumerate() {
+            if i > 0 {
+                tasks.push(Task::Jump(jump(0)));
+                jumps += 1;
             }
-            Expression::Not(a) => {
-                self.0.push(Operation::Not);
-                self.walk(*a);
+            tasks.push(Task::Walk(v, depth));
+            if i > 0 {
+                tasks.push(Task::Emit(combine.clone()));
             }
-            Expression::Member(_, _) => unimplemented!(),
-            Expression::Method(_, _, _) => unimplemented!(),
-            Expression::Lit(lit) => self.walk_literal(lit),
-            Expression::Binding(_) => unimplemented!(),
         }
+        tasks.push(Task::Land(jumps));
+        self.then(tasks);
     }
 
-    fn walk_literal(&mut self, lit: Literal) {
+    /// Emit `lit`, whose elements are nested `depth` deep.
+    fn walk_literal(&mut self, lit: Literal, depth: usize) {
         match lit {
-            Literal::Null => self.0.push(Operation::Lit(Value::Null)),
-            Literal::I64(v) => self.0.push(Operation::Lit(Value::I64(v))),
-            Literal::F64(v) => self.0.push(Operation::Lit(Value::F64(v))),
-            Literal::Bool(v) => self.0.push(Operation::Lit(Value::Bool(v))),
-            Literal::String(v) => self.0.push(Operation::Lit(Value::String(v))),
-            Literal::Bytes(v) => self.0.push(Operation::Lit(Value::Bytes(v))),
+            Literal::Null => self.ops.push(Operation::Lit(Value::Null)),
+            Literal::I64(v) => self.ops.push(Operation::Lit(Value::I64(v))),
+            Literal::F64(v) => self.ops.push(Operation::Lit(Value::F64(v))),
+            Literal::Bool(v) => self.ops.push(Operation::Lit(Value::Bool(v))),
+            Literal::String(v) => self.ops.push(Operation::Lit(Value::String(v.into()))),
+            Literal::Bytes(v) => self.ops.push(Operation::Lit(Value::Bytes(v.into()))),
             Literal::List(vs) => {
-                self.0.push(Operation::MakeList(vs.len()));
-                for v in vs {
-                    self.walk(v);
-                }
+                let n = vs.len();
+                let mut tasks: Vec<Task> = vs.into_iter().map(|v| Task::Walk(v, depth)).collect();
+                tasks.push(Task::Emit(Operation::MakeList(n)));
+                self.then(tasks);
             }
             Literal::Map(vs) => {
-                self.0.push(Operation::MakeMap(vs.len()));
+                let n = vs.len();
+                let mut tasks = Vec::new();
                 for (k, v) in vs {
-                    self.walk(k);
-                    self.walk(v);
+                    tasks.push(Task::Walk(k, depth));
+                    tasks.push(Task::Walk(v, depth));
                 }
+                tasks.push(Task::Emit(Operation::MakeMap(n)));
+                self.then(tasks);
             }
         }
     }
@@ -107,6 +228,7 @@ impl Walker {
 
 #[cfg(test)]
 mod test {
+    use crate::model::Identifier;
     use crate::parser::parse;
 
     use super::*;
@@ -117,9 +239,10 @@ mod test {
         assert_eq!(
             linearize(expr),
             vec![
-                Operation::Add,
+                Operation::Depth(1),
                 Operation::Lit(Value::I64(1)),
                 Operation::Lit(Value::I64(1)),
+                Operation::Add,
             ]
         );
     }
@@ -130,9 +253,62 @@ mod test {
         assert_eq!(
             linearize(expr),
             vec![
+                Operation::Depth(1),
+                Operation::Lit(Value::I64(1)),
+                Operation::Lit(Value::I64(2)),
                 Operation::Sub,
+            ]
+        );
+    }
+
+    #[test]
+    fn linearize_comparison() {
+        let expr = parse(r#" -1 < 2 "#).unwrap();
+        assert_eq!(
+            linearize(expr),
+            vec![
+                Operation::Depth(2),
                 Operation::Lit(Value::I64(1)),
+                Operation::Neg,
                 Operation::Lit(Value::I64(2)),
+                Operation::Lt,
+            ]
+        );
+    }
+
+    #[test]
+    fn linearize_let() {
+        let expr = parse(r#" let x = 1; let y = x; let x = x + y; x + z "#).unwrap();
+        assert_eq!(
+            linearize(expr),
+            vec![
+                Operation::Depth(4),
+                Operation::Lit(Value::I64(1)),
+                Operation::Store(0),
+                Operation::Load(0),
+                Operation::Store(1),
+                Operation::Load(0),
+                Operation::Load(1),
+                Operation::Add,
+                Operation::Store(2),
+                Operation::Load(2),
+                Operation::Lookup(Identifier::new("z")),
+                Operation::Add,
+            ]
+        );
+    }
+
+    #[test]
+    fn linearize_method() {
+        let expr = parse(r#" x.y.pow(2) "#).unwrap();
+        assert_eq!(
+            linearize(expr),
+            vec![
+                Operation::Depth(2),
+                Operation::Lookup(Identifier::new("x")),
+                Operation::Member(Identifier::new("y")),
+                Operation::Lit(Value::I64(2)),
+                Operation::CallMethod(Identifier::new("pow"), 1),
             ]
         );
     }
@@ -143,11 +319,12 @@ mod test {
         assert_eq!(
             linearize(expr),
             vec![
-                Operation::MakeList(2),
+                Operation::Depth(2),
                 Operation::Lit(Value::I64(1)),
-                Operation::Add,
                 Operation::Lit(Value::I64(2)),
                 Operation::Lit(Value::I64(3)),
+                Operation::Add,
+                Operation::MakeList(2),
             ]
         );
     }
@@ -158,6 +335,7 @@ mod test {
         assert_eq!(
             linearize(expr),
             vec![
+                Operation::Depth(0),
                 Operation::MakeList(0),
             ]
         );
@@ -169,9 +347,12 @@ mod test {
         assert_eq!(
             linearize(expr),
             vec![
-                Operation::Or,
+                Operation::Depth(1),
                 Operation::Lit(Value::Bool(true)),
+                Operation::JumpIfTrue(3),
+                Operation::Depth(1),
                 Operation::Lit(Value::Bool(false)),
+                Operation::Or,
             ]
         );
     }
@@ -182,13 +363,81 @@ mod test {
         assert_eq!(
             linearize(expr),
             vec![
-                Operation::Or,
+                Operation::Depth(1),
                 Operation::Lit(Value::I64(0)),
-                Operation::Or,
+                Operation::JumpIfTrue(11),
+                Operation::Depth(1),
                 Operation::Lit(Value::I64(1)),
                 Operation::Or,
+                Operation::JumpIfTrue(7),
+                Operation::Depth(1),
                 Operation::Lit(Value::I64(2)),
+                Operation::Or,
+                Operation::JumpIfTrue(3),
+                Operation::Depth(1),
                 Operation::Lit(Value::I64(3)),
+                Operation::Or,
+            ]
+        );
+    }
+
+    #[test]
+    fn linearize_ternary() {
+        let expr = parse(r#" a ? 1 : 2 + 3 "#).unwrap();
+        assert_eq!(
+            linearize(expr),
+            vec![
+                Operation::Depth(1),
+                Operation::Lookup(Identifier::new("a")),
+                Operation::JumpUnlessTrue(3),
+                Operation::Depth(1),
+                Operation::Lit(Value::I64(1)),
+                Operation::Jump(4),
+                Operation::Depth(2),
+                Operation::Lit(Value::I64(2)),
+                Operation::Lit(Value::I64(3)),
+                Operation::Add,
+            ]
+        );
+    }
+
+    #[test]
+    fn linearize_long_chain() {
+        let expr = parse(&format!("0{}", " + 1".repeat(100000))).unwrap();
+        let ops = linearize(expr);
+        assert_eq!(ops.len(), 200002);
+        assert_eq!((&ops[0], &ops[200001]), (&Operation::Depth(1), &Operation::Add));
+        let expr = parse(&format!("false{}", " || x == 1".repeat(5000))).unwrap();
+        assert_eq!(linearize(expr)[2], Operation::JumpIfTrue(6 * 5000 - 1));
+    }
+
+    #[test]
+    fn linearize_map() {
+        let expr = parse(r#" {"a": 1, "b": 2} "#).unwrap();
+        assert_eq!(
+            linearize(expr),
+            vec![
+                Operation::Depth(1),
+                Operation::Lit(Value::String("a".to_owned().into())),
+                Operation::Lit(Value::I64(1)),
+                Operation::Lit(Value::String("b".to_owned().into())),
+                Operation::Lit(Value::I64(2)),
+                Operation::MakeMap(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn linearize_function() {
+        let expr = parse(r#" int("42") + 1 "#).unwrap();
+        assert_eq!(
+            linearize(expr),
+            vec![
+                Operation::Depth(2),
+                Operation::Lit(Value::String("42".to_owned().into())),
+                Operation::Call(Identifier::new("int"), 1),
+                Operation::Lit(Value::I64(1)),
+                Operation::Add,
             ]
         );
     }
@@ -0,0 +1,74 @@
+use crate::model::Identifier;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The candidates closest to `target` by edit distance, nearest first, for "did you mean" hints.
+///
+/// A candidate is only suggested if it is within a third of `target`'s length (and at least one edit)
+/// of it, so that wildly different names aren't offered up.
+pub fn similar<'a>(target: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<Identifier> {
+    let threshold = std::cmp::max(1, target.chars().count() / 3);
+    let mut scored: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|&c| c != target)
+        .map(|c| (edit_distance(target, c), c))
+        .filter(|&(d, _)| d <= threshold)
+        .collect();
+    scored.sort();
+    scored.dedup();
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| Identifier::new(c))
+        .collect()
+}
+
+/// Levenshtein distance, counted in unicode code points.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("contians", "contains"), 2);
+    }
+
+    #[test]
+    fn nearest_first() {
+        let candidates = vec!["contains", "count", "keys", "container"];
+        assert_eq!(
+            similar("contain", candidates),
+            vec![Identifier::new("contains"), Identifier::new("container")]
+        );
+    }
+
+    #[test]
+    fn nothing_similar() {
+        assert_eq!(similar("x", vec!["len", "keys"]), vec![]);
+    }
+
+    #[test]
+    fn duplicates_removed() {
+        assert_eq!(
+            similar("lenn", vec!["len", "len", "len"]),
+            vec![Identifier::new("len")]
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::model::{Error, Timestamp};
+
+const NANOS_PER_MICRO: i128 = 1_000;
+const NANOS_PER_MILLI: i128 = 1_000_000;
+const NANOS_PER_SECOND: i128 = 1_000_000_000;
+const NANOS_PER_MINUTE: i128 = 60 * NANOS_PER_SECOND;
+const NANOS_PER_HOUR: i128 = 60 * NANOS_PER_MINUTE;
+const SECONDS_PER_DAY: i128 = 24 * 60 * 60;
+/// 0001-01-01T00:00:00Z, the earliest valid timestamp.
+const MIN_TIMESTAMP_SECONDS: i128 = -62_135_596_800;
+/// 9999-12-31T23:59:59Z, the latest valid timestamp (ignoring its fraction).
+const MAX_TIMESTAMP_SECONDS: i128 = 253_402_300_799;
+
+/// Parse an RFC 3339 timestamp such as `2024-01-01T00:00:00Z`.
+pub fn parse_timestamp(input: &str) -> Result<Timestamp, Error> {
+    let invalid = || Error::InvalidTimestamp(input.to_owned());
+    let b = input.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return Err(invalid());
+    }
+    if b[10] != b'T' && b[10] != b't' {
+        return Err(invalid());
+    }
+    let year = digits(&b[0..4]).ok_or_else(invalid)?;
+    let month = digits(&b[5..7]).ok_or_else(invalid)?;
+    let day = digits(&b[8..10]).ok_or_else(invalid)?;
+    let hour = digits(&b[11..13]).ok_or_else(invalid)?;
+    let minute = digits(&b[14..16]).ok_or_else(invalid)?;
+    let second = digits(&b[17..19]).ok_or_else(invalid)?;
+    if year < 1
+        || !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+
+    let mut rest = &b[19..];
+    let mut nanos = 0;
+    if rest[0] == b'.' {
+        let n = rest[1..].iter().take_while(|c| c.is_ascii_digit()).count();
+        if n == 0 || n > 9 {
+            return Err(invalid());
+        }
+        nanos = digits(&rest[1..=n]).ok_or_else(invalid)? * 10i128.pow(9 - n as u32);
+        rest = &rest[n + 1..];
+    }
+
+    let offset = match rest {
+        b"Z" | b"z" => 0,
+        [sign @ b'+', h1, h2, b':', m1, m2] | [sign @ b'-', h1, h2, b':', m1, m2] => {
+            let h = digits(&[*h1, *h2]).ok_or_else(invalid)?;
+            let m = digits(&[*m1, *m2]).ok_or_else(invalid)?;
+            if h > 23 || m > 59 {
+                return Err(invalid());
+            }
+            let offset = (h * 60 + m) * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second
+            - offset;
+    timestamp_from_nanos(seconds * NANOS_PER_SECOND + nanos)
+}
+
+/// Nanoseconds since the Unix epoch.
+pub fn timestamp_to_nanos(t: Timestamp) -> i128 {
+    i128::from(t.seconds) * NANOS_PER_SECOND + i128::from(t.nanos)
+}
+
+/// The timestamp `nanos` nanoseconds after the Unix epoch, if it is within the valid range.
+pub fn timestamp_from_nanos(nanos: i128) -> Result<Timestamp, Error> {
+    let seconds = nanos.div_euclid(NANOS_PER_SECOND);
+    if !(MIN_TIMESTAMP_SECONDS..=MAX_TIMESTAMP_SECONDS).contains(&seconds) {
+        return Err(Error::TimestampOutOfRange);
+    }
+    Ok(Timestamp {
+        seconds: seconds as i64,
+        nanos: nanos.rem_euclid(NANOS_PER_SECOND) as u32,
+    })
+}
+
+/// Parse a duration string such as `1h30m`, `-1.5s`, or `250ms` into nanoseconds.
+///
+/// Accepts the same syntax as Go's `time.ParseDuration`: an optional sign followed by a sequence of
+/// decimal numbers, each with an optional fraction and a unit suffix (`h`, `m`, `s`, `ms`, `us`, `ns`).
+pub fn parse_duration(input: &str) -> Result<i64, Error> {
+    let invalid = || Error::InvalidDuration(input.to_owned());
+    let (negative, mut rest) = match input.as_bytes() {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        rest => (false, rest),
+    };
+    if rest == b"0" {
+        return Ok(0);
+    }
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total: i128 = 0;
+    while !rest.is_empty() {
+        let n = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        let whole = match n {
+            0 => 0,
+            _ => digits(&rest[..n]).ok_or(Error::DurationOutOfRange)?,
+        };
+        rest = &rest[n..];
+        let (mut fraction, mut scale) = (0, 1);
+        let mut m = 0;
+        if let [b'.', tail @ ..] = rest {
+            m = tail.iter().take_while(|c| c.is_ascii_digit()).count();
+            for &c in tail[..m].iter().take(18) {
+                fraction = fraction * 10 + i128::from(c - b'0');
+                scale *= 10;
+            }
+            rest = &tail[m..];
+        }
+        if n == 0 && m == 0 {
+            return Err(invalid());
+        }
+
+        let u = rest
+            .iter()
+            .take_while(|&&c| c != b'.' && !c.is_ascii_digit())
+            .count();
+        let unit = match &rest[..u] {
+            b"ns" => 1,
+            b"us" | [0xC2, 0xB5, b's'] => NANOS_PER_MICRO,
+            b"ms" => NANOS_PER_MILLI,
+            b"s" => NANOS_PER_SECOND,
+            b"m" => NANOS_PER_MINUTE,
+            b"h" => NANOS_PER_HOUR,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[u..];
+
+        total = whole
+            .checked_mul(unit)
+            .and_then(|v| v.checked_add(fraction * unit / scale))
+            .and_then(|v| total.checked_add(v))
+            .ok_or(Error::DurationOutOfRange)?;
+    }
+    to_i64(if negative { -total } else { total }).ok_or(Error::DurationOutOfRange)
+}
+
+/// Format a timestamp in RFC 3339 format, in UTC.
+pub fn format_timestamp(t: Timestamp) -> String {
+    let seconds = i128::from(t.seconds);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    let time_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        format_fraction(i128::from(t.nanos)),
+    )
+}
+
+/// Format nanoseconds as a duration in seconds, e.g. `5400s` or `-1.5s`, with no trailing zeros.
+pub fn format_duration(nanos: i64) -> String {
+    let nanos = i128::from(nanos);
+    let sign = if nanos < 0 { "-" } else { "" };
+    let fraction = format_fraction(nanos.abs() % NANOS_PER_SECOND);
+    format!(
+        "{}{}{}s",
+        sign,
+        nanos.abs() / NANOS_PER_SECOND,
+        fraction.trim_end_matches('0'),
+    )
+}
+
+/// Format a sub-second nanosecond count as `.fff`, `.ffffff`, or `.fffffffff`, or nothing at all.
+fn format_fraction(nanos: i128) -> String {
+    if nanos == 0 {
+        String::new()
+    } else if nanos % 1_000_000 == 0 {
+        format!(".{:03}", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!(".{:06}", nanos / 1_000)
+    } else {
+        format!(".{:09}", nanos)
+    }
+}
+
+fn digits(b: &[u8]) -> Option<i128> {
+    if b.is_empty() || b.len() > 30 || !b.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(b.iter().fold(0, |acc, &c| acc * 10 + i128::from(c - b'0')))
+}
+
+fn to_i64(v: i128) -> Option<i64> {
+    if v < i128::from(i64::MIN) || v > i128::from(i64::MAX) {
+        None
+    } else {
+        Some(v as i64)
+    }
+}
+
+fn is_leap_year(year: i128) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i128, month: i128) -> i128 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i128, month: i128, day: i128) -> i128 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i128) -> (i128, i128, i128) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nanos(input: &str) -> Result<i128, Error> {
+        parse_timestamp(input).map(timestamp_to_nanos)
+    }
+
+    #[test]
+    fn timestamp_epoch() {
+        assert_eq!(
+            parse_timestamp("1970-01-01T00:00:00Z"),
+            Ok(Timestamp {
+                seconds: 0,
+                nanos: 0
+            })
+        );
+    }
+
+    #[test]
+    fn timestamp_fraction_and_offset() {
+        assert_eq!(nanos("1970-01-01T01:00:00.5+01:00"), Ok(500_000_000));
+        assert_eq!(
+            nanos("2024-01-01T00:00:00Z"),
+            Ok(1_704_067_200 * 1_000_000_000)
+        );
+        assert_eq!(nanos("1969-12-31T23:59:59.75Z"), Ok(-250_000_000));
+    }
+
+    #[test]
+    fn timestamp_invalid() {
+        assert!(parse_timestamp("2024-02-30T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-01 00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-01T00:00:00").is_err());
+        assert!(parse_timestamp("asdf").is_err());
+    }
+
+    #[test]
+    fn timestamp_range() {
+        assert_eq!(
+            timestamp_to_nanos(parse_timestamp("0001-01-01T00:00:00Z").unwrap()),
+            MIN_TIMESTAMP_SECONDS * NANOS_PER_SECOND
+        );
+        assert_eq!(
+            timestamp_to_nanos(parse_timestamp("9999-12-31T23:59:59Z").unwrap()),
+            MAX_TIMESTAMP_SECONDS * NANOS_PER_SECOND
+        );
+        assert_eq!(
+            parse_timestamp("0001-01-01T00:00:00+00:01"),
+            Err(Error::TimestampOutOfRange)
+        );
+        assert_eq!(
+            parse_timestamp("9999-12-31T23:59:59-00:01"),
+            Err(Error::TimestampOutOfRange)
+        );
+        assert_eq!(
+            timestamp_from_nanos((MAX_TIMESTAMP_SECONDS + 1) * NANOS_PER_SECOND),
+            Err(Error::TimestampOutOfRange)
+        );
+    }
+
+    #[test]
+    fn timestamp_round_trip() {
+        for input in &[
+            "1970-01-01T00:00:00Z",
+            "1969-12-31T23:59:59.999Z",
+            "2000-02-29T12:34:56.000001Z",
+            "2262-04-11T23:47:16.854775807Z",
+            "0001-01-01T00:00:00Z",
+            "1600-01-01T00:00:00Z",
+            "9999-12-31T23:59:59.999999999Z",
+        ] {
+            let t = parse_timestamp(input).expect("parse");
+            assert_eq!(&format_timestamp(t), input);
+        }
+    }
+
+    #[test]
+    fn duration_format() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(90 * 60 * 1_000_000_000), "5400s");
+        assert_eq!(format_duration(-1_500_000_000), "-1.5s");
+        assert_eq!(format_duration(1_250_000), "0.00125s");
+        assert_eq!(format_duration(1), "0.000000001s");
+    }
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(parse_duration("1h30m"), Ok(90 * 60 * 1_000_000_000));
+        assert_eq!(parse_duration("-1.5s"), Ok(-1_500_000_000));
+        assert_eq!(parse_duration("250ms"), Ok(250_000_000));
+        assert_eq!(parse_duration("1us1ns"), Ok(1_001));
+        assert_eq!(parse_duration("0"), Ok(0));
+    }
+
+    #[test]
+    fn duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1").is_err());
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration(".s").is_err());
+    }
+
+    #[test]
+    fn duration_long_digit_runs() {
+        assert_eq!(
+            parse_duration("9999999999999999999999999999999h"),
+            Err(Error::DurationOutOfRange)
+        );
+        assert_eq!(
+            parse_duration("0000000000000000000000000000001s"),
+            Err(Error::DurationOutOfRange)
+        );
+        assert_eq!(parse_duration("000001s"), Ok(1_000_000_000));
+    }
+}
@@ -0,0 +1,310 @@
+//! Checks on the values that hosts bind, so that a value the evaluator would mishandle is reported
+//! where it is bound rather than surfacing as a confusing result.
+//!
+//! Values bound as JSON are converted with `json::from_json`: a number is an int if it fits in an
+//! `i64`, otherwise a uint if it fits in a `u64`, and otherwise a double. Since JS has only the one
+//! kind of number, a binding declared with a `Schema` can instead be converted to the declared kinds
+//! with `coerce`, so that e.g. a double field bound as `42` still compares with `42.5`.
+
+use crate::checker::Schema;
+use crate::json;
+use crate::model::{Identifier, Kind, Value};
+use crate::ordering;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// The ends of the ranges of `i64` and `u64`, as doubles. `i64::MAX as f64` would round up to the
+// end rather than being the last double in range.
+const I64_END: f64 = 9_223_372_036_854_775_808.0;
+const U64_END: f64 = 18_446_744_073_709_551_616.0;
+
+/// Bounds on bound values, and what to do with doubles that aren't finite.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct BindingOptions {
+    /// How deeply lists and maps may nest. A scalar is at depth 0.
+    pub max_depth: usize,
+    /// How many values a binding may hold, counting every element and map entry.
+    pub max_values: usize,
+    /// The total length, in bytes, of the strings, bytes and map keys a binding may hold.
+    pub max_bytes: usize,
+    pub non_finite: NonFinite,
+}
+
+/// What to do with NaN and the infinities.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum NonFinite {
+    Reject,
+    /// Bind them as null, as `JSON.stringify` does.
+    Null,
+}
+
+impl Default for BindingOptions {
+    fn default() -> BindingOptions {
+        BindingOptions {
+            max_depth: 64,
+            max_values: 1 << 16,
+            max_bytes: 1 << 20,
+            non_finite: NonFinite::Reject,
+        }
+    }
+}
+
+/// A bound value that breaks one of the `BindingOptions`. Each names the path to the offending
+/// value, e.g. `request.users[3]`.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum BindingError {
+    /// A double is NaN or infinite.
+    NonFinite(String),
+    /// A list or map is nested more deeply than `max_depth`.
+    TooDeep(String),
+    /// The binding holds more than `max_values` values, or `max_bytes` bytes, by this one.
+    TooLarge(String),
+    /// A number is declared to be of the given kind, but isn't one, e.g. `1.5` declared an int.
+    NotConvertible(String, Kind),
+    /// A document whose fields were to be bound isn't a JSON object, but of the given kind.
+    NotAnObject(Kind),
+}
+
+/// Convert `value`, bound as `name`, from JSON and validate it.
+pub fn from_json(
+    name: &str,
+    value: serde_json::Value,
+    options: &BindingOptions,
+) -> Result<Value, BindingError> {
+    validate(name, json::from_json(value), options)
+}
+
+/// `value`, a JS number, as an int if it is integral and fits in an `i64`, otherwise as a double.
+pub fn from_js_number(value: f64) -> Value {
+    if value.fract() == 0.0 && (-I64_END..I64_END).contains(&value) {
+        Value::I64(value as i64)
+    } else {
+        Value::F64(value)
+    }
+}
+
+/// Convert and validate each of `bindings`, a map from names to values as JSON. Reports the first
+/// error, in order of name.
+pub fn from_json_bindings(
+    bindings: HashMap<String, serde_json::Value>,
+    options: &BindingOptions,
+) -> Result<Vec<(Identifier, Value)>, BindingError> {
+    let mut bindings: Vec<(String, serde_json::Value)> = bindings.into_iter().collect();
+    bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+    bindings
+        .into_iter()
+        .map(|(name, value)| {
+            let value = from_json(&name, value, options)?;
+            Ok((Identifier(name), value))
+        })
+        .collect()
+}
+
+/// Check `value`, bound as `name`, against `options`, mapping non-finite doubles to null if they
+/// say to.
+pub fn validate(name: &str, value: Value, options: &BindingOptions) -> Result<Value, BindingError> {
+    let mut validator = Validator {
+        options,
+        path: name.to_owned(),
+        values: 0,
+        bytes: 0,
+    };
+    validator.visit(value, 0)
+}
+
+/// Convert the numbers in `value`, bound as `name`, to the kinds that `schema` declares them to be.
+/// Values other than numbers are left as they are, even if they don't match the declaration.
+pub fn coerce(name: &str, value: Value, schema: &Schema) -> Result<Value, BindingError> {
+    match (value, schema) {
+        (Value::Map(entries), Schema::Map(fields)) => {
+            let mut coerced = HashMap::with_capacity(entries.len());
+            for (key, value) in entries {
+                let value = match fields.get(&key) {
+                    Some(field) => coerce(&format!("{}.{}", name, key), value, field)?,
+                    None => value,
+                };
+                coerced.insert(key, value);
+            }
+            Ok(Value::Map(coerced))
+        }
+        (value @ Value::I64(_), Schema::Kind(kind))
+        | (value @ Value::U64(_), Schema::Kind(kind))
+        | (value @ Value::F64(_), Schema::Kind(kind)) => convert(&value, *kind)
+            .ok_or_else(|| BindingError::NotConvertible(name.to_owned(), *kind)),
+        (value, _) => Ok(value),
+    }
+}
+
+/// `value`, a number, as `kind` if it can be exactly, or as it is if `kind` isn't a number.
+fn convert(value: &Value, kind: Kind) -> Option<Value> {
+    match (value, kind) {
+        (Value::I64(n), Kind::F64) => Some(Value::F64(*n as f64)),
+        (Value::U64(n), Kind::F64) => Some(Value::F64(*n as f64)),
+        (Value::U64(n), Kind::I64) => i64::try_from(*n).ok().map(Value::I64),
+        (Value::I64(n), Kind::U64) => u64::try_from(*n).ok().map(Value::U64),
+        (Value::F64(x), Kind::I64) if x.fract() == 0.0 && (-I64_END..I64_END).contains(x) => {
+            Some(Value::I64(*x as i64))
+        }
+        (Value::F64(x), Kind::U64) if x.fract() == 0.0 && (0.0..U64_END).contains(x) => {
+            Some(Value::U64(*x as u64))
+        }
+        (value, kind) if value.kind() == kind => Some(value.clone()),
+        (_, Kind::I64) | (_, Kind::U64) | (_, Kind::F64) => None,
+        (value, _) => Some(value.clone()),
+    }
+}
+
+struct Validator<'a> {
+    options: &'a BindingOptions,
+    /// The path to the value being visited.
+    path: String,
+    values: usize,
+    bytes: usize,
+}
+
+impl Validator<'_> {
+    fn visit(&mut self, value: Value, depth: usize) -> Result<Value, BindingError> {
+        self.values += 1;
+        self.bytes += match &value {
+            Value::String(s) => s.len(),
+            Value::Bytes(bs) => bs.len(),
+            Value::Map(entries) => entries.keys().map(String::len).sum(),
+            _ => 0,
+        };
+        if self.values > self.options.max_values || self.bytes > self.options.max_bytes {
+            return Err(BindingError::TooLarge(self.path.clone()));
+        }
+        match value {
+            Value::F64(x) if !x.is_finite() => match self.options.non_finite {
+                NonFinite::Reject => Err(BindingError::NonFinite(self.path.clone())),
+                NonFinite::Null => Ok(Value::Null),
+            },
+            Value::List(_) | Value::Map(_) if depth >= self.options.max_depth => {
+                Err(BindingError::TooDeep(self.path.clone()))
+            }
+            Value::List(values) => {
+                let mut checked = Vec::with_capacity(values.len());
+                for (i, value) in values.into_iter().enumerate() {
+                    checked.push(self.visit_at(&format!("[{}]", i), value, depth + 1)?);
+                }
+                Ok(Value::List(checked))
+            }
+            Value::Map(entries) => {
+                // In order of key, so that the same map always reports the same error.
+                let mut checked = HashMap::with_capacity(entries.len());
+                for (key, value) in ordering::into_entries(entries) {
+                    let value = self.visit_at(&format!(".{}", key), value, depth + 1)?;
+                    checked.insert(key, value);
+                }
+                Ok(Value::Map(checked))
+            }
+            value => Ok(value),
+        }
+    }
+
+    /// Visit `value`, with `step` appended to the path.
+    fn visit_at(&mut self, step: &str, value: Value, depth: usize) -> Result<Value, BindingError> {
+        let len = self.path.len();
+        self.path.push_str(step);
+        let result = self.visit(value, depth);
+        self.path.truncate(len);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn numbers() {
+        let options = BindingOptions::default();
+        let bind = |value| from_json("x", value, &options);
+        assert_eq!(bind(json!(1)), Ok(Value::I64(1)));
+        assert_eq!(bind(json!(-1)), Ok(Value::I64(-1)));
+        assert_eq!(bind(json!(u64::MAX)), Ok(Value::U64(u64::MAX)));
+        assert_eq!(bind(json!(1.0)), Ok(Value::F64(1.0)));
+        assert_eq!(bind(json!(1.5)), Ok(Value::F64(1.5)));
+
+        let nan = Value::List(vec![Value::I64(1), Value::F64(f64::NAN)]);
+        assert_eq!(
+            validate("x", nan.clone(), &options),
+            Err(BindingError::NonFinite("x[1]".to_owned()))
+        );
+        let options = BindingOptions {
+            non_finite: NonFinite::Null,
+            ..options
+        };
+        assert_eq!(
+            validate("x", nan, &options),
+            Ok(Value::List(vec![Value::I64(1), Value::Null]))
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        let options = BindingOptions {
+            max_depth: 2,
+            max_values: 5,
+            max_bytes: 8,
+            non_finite: NonFinite::Reject,
+        };
+        let bind = |value| from_json("r", value, &options);
+        assert!(bind(json!({"a": [1, 2], "b": "xyz"})).is_ok());
+        assert_eq!(
+            bind(json!({"a": [[1]]})),
+            Err(BindingError::TooDeep("r.a[0]".to_owned()))
+        );
+        assert_eq!(
+            bind(json!({"a": [1, 2, 3], "b": [4]})),
+            Err(BindingError::TooLarge("r.b".to_owned()))
+        );
+        assert_eq!(
+            bind(json!({"a": "12345678"})),
+            Err(BindingError::TooLarge("r.a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn declared_kinds() {
+        let schema = Schema::from_json_schema(&json!({
+            "properties": {
+                "count": {"type": "integer"},
+                "ratio": {"type": "number"},
+                "name": {"type": "number"},
+            },
+        }));
+        let bind = |value| coerce("r", json::from_json(value), &schema);
+        let coerced = bind(json!({"count": 3.0, "ratio": 1, "name": "x", "other": 2})).unwrap();
+        let mut expected = HashMap::new();
+        expected.insert("count".to_owned(), Value::I64(3));
+        expected.insert("ratio".to_owned(), Value::F64(1.0));
+        expected.insert("name".to_owned(), Value::String("x".to_owned().into()));
+        expected.insert("other".to_owned(), Value::I64(2));
+        assert_eq!(coerced, Value::Map(expected));
+        assert_eq!(
+            bind(json!({"count": 1.5})),
+            Err(BindingError::NotConvertible(
+                "r.count".to_owned(),
+                Kind::I64
+            ))
+        );
+        assert_eq!(
+            bind(json!({"count": 1e19})),
+            Err(BindingError::NotConvertible(
+                "r.count".to_owned(),
+                Kind::I64
+            ))
+        );
+        assert_eq!(
+            coerce("n", Value::F64(-1.0), &Schema::Kind(Kind::U64)),
+            Err(BindingError::NotConvertible("n".to_owned(), Kind::U64))
+        );
+        assert_eq!(
+            coerce("n", Value::U64(7), &Schema::Kind(Kind::I64)),
+            Ok(Value::I64(7))
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! A request/response protocol over byte streams, for running the evaluator outside the browser,
+//! e.g. as a WASI module under wasmtime. See `src/bin/cel-wasi.rs`.
+//!
+//! Each message, in either direction, is a 4-byte little-endian length followed by that many bytes
+//! of JSON. Requests look like `{"cmd": "parse", "input": "1 + 2"}` or
+//! `{"cmd": "eval", "input": "x + 2", "bindings": {"x": 1}}`; the response is `{"Ok": ...}` with
+//! the AST or value, or `{"Err": ...}`.
+
+use crate::interpreter::EvalContext;
+use crate::model::{EvalResult, Expression, Identifier, Value};
+use crate::parser;
+use crate::validation::{self, BindingOptions};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// Requests larger than this are rejected rather than buffered.
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    Parse {
+        input: String,
+    },
+    Eval {
+        input: String,
+        #[serde(default)]
+        bindings: HashMap<String, serde_json::Value>,
+    },
+}
+
+/// Answer requests from `input` on `output` until `input` is exhausted.
+pub fn serve(mut input: impl Read, mut output: impl Write) -> io::Result<()> {
+    while let Some(request) = read_message(&mut input)? {
+        let response = match serde_json::from_slice(&request) {
+            Ok(request) => respond(request),
+            Err(err) => serde_json::json!({ "Err": format!("invalid request: {}", err) }),
+        };
+        write_message(&mut output, &serde_json::to_vec(&response)?)?;
+    }
+    output.flush()
+}
+
+fn respond(request: Request) -> serde_json::Value {
+    match request {
+        Request::Parse { input } => match parser::parse(&input) {
+            Ok(expr) => serde_json::json!({ "Ok": expr }),
+            Err(err) => serde_json::json!({ "Err": format!("{:?}", err) }),
+        },
+        Request::Eval { input, bindings } => match parser::parse(&input) {
+            Ok(expr) => {
+                match validation::from_json_bindings(bindings, &BindingOptions::default()) {
+                    Ok(bindings) => {
+                        serde_json::json!(evaluate(&EvalContext::default(), bindings, expr))
+                    }
+                    Err(err) => {
+                        serde_json::json!({ "Err": format!("invalid bindings: {:?}", err) })
+                    }
+                }
+            }
+            Err(err) => serde_json::json!({ "Err": format!("{:?}", err) }),
+        },
+    }
+}
+
+/// Evaluate `expr` with each of `bindings` in scope.
+pub(crate) fn evaluate(
+    ctx: &EvalContext,
+    bindings: Vec<(Identifier, Value)>,
+    expr: Expression,
+) -> EvalResult {
+    ctx.clone().with_bindings(bindings).evaluate(expr)
+}
+
+/// Read one length-prefixed message, or `None` at the end of the stream.
+fn read_message(input: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too large", len),
+        ));
+    }
+    let mut message = vec![0; len];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(output: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    output.write_all(&(message.len() as u32).to_le_bytes())?;
+    output.write_all(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(requests: &[&str]) -> Vec<serde_json::Value> {
+        let mut input = Vec::new();
+        for request in requests {
+            write_message(&mut input, request.as_bytes()).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(input.as_slice(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            responses.push(serde_json::from_slice(&message).unwrap());
+        }
+        responses
+    }
+
+    #[test]
+    fn eval_with_bindings() {
+        let responses = round_trip(&[
+            r#"{"cmd": "eval", "input": "x + y.z", "bindings": {"x": 1, "y": {"z": 2}}}"#,
+            r#"{"cmd": "eval", "input": "1 / 0"}"#,
+        ]);
+        assert_eq!(
+            responses,
+            vec![
+                serde_json::json!({"Ok": {"t": "I64", "c": 3}}),
+                serde_json::json!({"Err": "DivisionByZero"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn eval_with_many_bindings() {
+        let bindings: serde_json::Map<String, serde_json::Value> = (0..100_000)
+            .map(|i| (format!("x{}", i), serde_json::json!(i)))
+            .collect();
+        let request = serde_json::json!({
+            "cmd": "eval",
+            "input": "x0 + x99999",
+            "bindings": bindings,
+        });
+        let responses = round_trip(&[&request.to_string()]);
+        assert_eq!(
+            responses,
+            vec![serde_json::json!({"Ok": {"t": "I64", "c": 99999}})]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let responses = round_trip(&[r#"{"cmd": "parse", "input": "1"}"#]);
+        assert_eq!(
+            responses,
+            vec![serde_json::json!({"Ok": {"Lit": {"I64": 1}}})]
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let responses = round_trip(&[
+            r#"{"cmd": "frobnicate"}"#,
+            r#"{"cmd": "parse", "input": "1 +"}"#,
+        ]);
+        assert!(responses[0]["Err"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid request"));
+        assert!(responses[1]["Err"].as_str().unwrap().starts_with("Pest"));
+    }
+
+    #[test]
+    fn oversized_message() {
+        let input = ((MAX_MESSAGE_LEN + 1) as u32).to_le_bytes();
+        assert!(serve(&input[..], Vec::new()).is_err());
+    }
+}
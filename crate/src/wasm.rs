@@ -0,0 +1,1119 @@
+//! The exports for JS, built with wasm-bindgen. Enabled by the `wasm` feature, which is on by
+//! default; without it, the crate is a plain Rust library.
+
+use crate::backend::{Backend, Evaluator};
+use crate::bundle::{Bundle, BundleError};
+use crate::checker::{Checker, Schema};
+use crate::cost::CostOptions;
+use crate::features::Usage;
+use crate::intern::Strings;
+use crate::interpreter::EvalContext;
+use crate::messages::Catalog;
+use crate::model::{EvalResult, Expression, Identifier, Kind, Value};
+use crate::panics::{self, InternalError};
+use crate::parser::ParseError;
+use crate::program::Program;
+use crate::stack::{bytecode, runtime, walker};
+use crate::validation::{self, BindingOptions};
+use crate::{
+    ast_json, canonical, cost, format, json, messages, parsed_expr, parser, satisfiability,
+    signatures, EvaluatedAst, ExploreOptions,
+};
+use serde::Serialize;
+use serde_wasm_bindgen::Serializer;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+/// Serialize `value` into a JS value, building the objects directly rather than by way of a JSON
+/// string. The result is what `JSON.parse` would make of `value` as JSON: maps become plain objects
+/// and `None` becomes `null`.
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    const SERIALIZER: Serializer = Serializer::json_compatible();
+    match value.serialize(&SERIALIZER) {
+        Ok(js) => js,
+        // Integers beyond 2^53 aren't JS numbers, so the direct route refuses them. JSON rounds
+        // them to the nearest double instead.
+        Err(_) => {
+            let json = serde_json::to_string(value).expect("serialize");
+            js_sys::JSON::parse(&json).expect("parse")
+        }
+    }
+}
+
+/// The JS value for `json`, by way of `JSON.parse`, which unlike serializing the value directly
+/// doesn't recurse per level of nesting. For trees that are as deep as the expressions they're of.
+fn json_to_js(json: &str) -> JsValue {
+    js_sys::JSON::parse(json).expect("parse")
+}
+
+/// Deserialize a JS value, the inverse of `to_js`.
+fn from_js(value: &JsValue) -> Result<serde_json::Value, String> {
+    serde_wasm_bindgen::from_value(value.clone()).map_err(|err| err.to_string())
+}
+
+// TypeScript declarations for what the exports return, beyond those derived for the model's types:
+// `Value`, `Error`, `Op`, `Kind`, `Span`, `Signature` and what they refer to.
+#[wasm_bindgen(typescript_custom_section)]
+const TS_DECLARATIONS: &str = r#"
+export type EvalResult = { Ok: Value } | { Err: Error };
+
+/** A node of the tree that `process` returns. */
+export interface EvaluatedNode {
+    id?: number;
+    /** The node's role in its parent, e.g. "condition". */
+    role?: string;
+    op: Op;
+    /** Absent if only the tree's shape was asked for. */
+    result?: EvalResult | "Skipped";
+    span?: Span;
+    children?: EvaluatedNode[];
+    /** In place of `children` below `max_levels`: the id to pass to `process_subtree`. */
+    expand?: number;
+}
+
+/** What `process_interned` returns: each string in a result is its index into `strings`. */
+export interface InternedEvaluatedAst {
+    root: EvaluatedNode;
+    strings: string[];
+}
+
+/** A node of the tree that `parse_to_ast` returns. */
+export interface AstNode {
+    id: number;
+    role?: string;
+    op: Op;
+    precedence: number;
+    arity: number;
+    /** The name of a binding or a `let`. */
+    name?: string;
+    /** The value of a scalar literal. */
+    literal?: { I64: number } | { F64: number } | { Bool: boolean } | { String: string } | { Bytes: number[] } | "Null";
+    children: AstNode[];
+}
+"#;
+
+/// Run `f`, the body of the export `entry`, serializing a panic in it as the failed evaluation
+/// `{"Err": {"Internal": message}}`. Only a build that unwinds can catch the panic; under the
+/// default `panic = "abort"` it traps the instance instead (see `panics`).
+fn guarded(entry: &'static str, f: impl FnOnce() -> JsValue) -> JsValue {
+    panics::guard(entry, f).unwrap_or_else(internal)
+}
+
+fn internal(err: InternalError) -> JsValue {
+    let result: EvalResult = Err(err.into());
+    to_js(&result)
+}
+
+/// Parse `input` into an AST, then serialize it as JSON.
+///
+/// Every node is `{id, op, precedence, arity, children}`, with each child labeled by its `role` in
+/// its parent, so that renderers can draw any node without knowing each `Op`'s shape. Bindings and
+/// `let`s also have a `name`, and scalar literals a `literal`.
+///
+/// `id`s number the nodes in pre-order. A node has the same id in `process`'s output for the same
+/// input, so that tools can match up the two trees.
+#[wasm_bindgen]
+pub fn parse_to_ast(input: String) -> JsValue {
+    guarded("parse_to_ast", || match parser::parse(&input) {
+        Ok(parsed) => json_to_js(&ast_json(&parsed, None)),
+        Err(err) => JsValue::from_str(&format!("{:?}", err)),
+    })
+}
+
+/// Parse `input` and serialize it as cel-spec's `ParsedExpr`, in proto3 JSON (see `parsed_expr`),
+/// for other CEL implementations to check or evaluate.
+#[wasm_bindgen]
+pub fn parse_to_parsed_expr(input: String) -> JsValue {
+    guarded("parse_to_parsed_expr", || {
+        match parser::parse_with_spans(&input) {
+            Ok(parsed) => to_js(&parsed_expr::to_parsed_expr(&parsed, &input)),
+            Err(err) => JsValue::from_str(&format!("{:?}", err)),
+        }
+    })
+}
+
+/// Like `parse_to_ast`, but serializes `{root, strings}`, where `root` is the AST with each name
+/// and string literal replaced by its index into `strings`.
+#[wasm_bindgen]
+pub fn parse_to_ast_interned(input: String) -> JsValue {
+    guarded("parse_to_ast_interned", || {
+        match parser::parse(&input) {
+            Ok(parsed) => {
+                let strings = Strings::default();
+                // The root must be written first, to fill in the table.
+                let root = ast_json(&parsed, Some(&strings));
+                let strings = serde_json::to_string(&strings).expect("serialize");
+                json_to_js(&format!("{{\"root\":{},\"strings\":{}}}", root, strings))
+            }
+            Err(err) => JsValue::from_str(&format!("{:?}", err)),
+        }
+    })
+}
+
+/// Parse `input` into an AST, evaluate it fully, then serialize the resulting `EvaluatedAst` as JSON.
+#[wasm_bindgen]
+pub fn process(input: String) -> JsValue {
+    process_with_options(input, true, None)
+}
+
+/// Like `process`, but if `intermediate_results` is false only the root's result and the errors of
+/// failed subexpressions are included, which is much cheaper to serialize.
+///
+/// If `max_levels` is set, nodes that deep stand in for their subtrees with an `expand` field
+/// holding the node's `id`; pass it to `process_subtree` to fetch the subtree.
+#[wasm_bindgen]
+pub fn process_with_options(
+    input: String,
+    intermediate_results: bool,
+    max_levels: Option<u32>,
+) -> JsValue {
+    guarded("process_with_options", || {
+        match explore_input(&input, intermediate_results, max_levels, false) {
+            Ok(ast) => json_to_js(&ast.to_json()),
+            Err(err) => err,
+        }
+    })
+}
+
+/// Like `process_with_options`, but serializes `{root, strings}`, where `root` is the tree with the
+/// contents of string values replaced by their index into `strings`. For expressions that produce
+/// the same strings over and over, this is much smaller.
+#[wasm_bindgen]
+pub fn process_interned(
+    input: String,
+    intermediate_results: bool,
+    max_levels: Option<u32>,
+) -> JsValue {
+    guarded("process_interned", || {
+        match explore_input(&input, intermediate_results, max_levels, true) {
+            Ok(ast) => json_to_js(&ast.to_json()),
+            Err(err) => err,
+        }
+    })
+}
+
+/// Serialize the subtree of node `id` of what `process_with_options` returns for the same
+/// arguments. Evaluation is deterministic, so node ids are the same from one call to the next.
+#[wasm_bindgen]
+pub fn process_subtree(
+    input: String,
+    intermediate_results: bool,
+    max_levels: Option<u32>,
+    id: u32,
+) -> JsValue {
+    guarded("process_subtree", || {
+        match explore_input(&input, intermediate_results, max_levels, false) {
+            Ok(ast) => match ast.subtree(id as usize) {
+                Some(subtree) => json_to_js(&subtree),
+                None => JsValue::from_str(&format!("no node with id {}", id)),
+            },
+            Err(err) => err,
+        }
+    })
+}
+
+fn explore_input(
+    input: &str,
+    intermediate_results: bool,
+    max_levels: Option<u32>,
+    intern_strings: bool,
+) -> Result<EvaluatedAst, JsValue> {
+    let ast =
+        parser::parse_with_spans(input).map_err(|err| JsValue::from_str(&format!("{:?}", err)))?;
+    let options = ExploreOptions {
+        intermediate_results,
+        max_levels: max_levels.map(|n| n as usize),
+        intern_strings,
+    };
+    Ok(EvaluatedAst::with_options(
+        &EvalContext::default(),
+        ast,
+        options,
+    ))
+}
+
+/// Parse `input` and serialize it back as canonical source text (see `format`), as `{"Ok": text}`,
+/// or `{"Err": error}` if it doesn't parse.
+#[wasm_bindgen]
+pub fn format_expression(input: String) -> JsValue {
+    guarded("format_expression", || {
+        let formatted = parser::parse(&input)
+            .map(|expr| format::format(&expr))
+            .map_err(|err| format!("{:?}", err));
+        to_js(&formatted)
+    })
+}
+
+/// Parse `input` and serialize its canonical form (see `canonical`) as `{"Ok": {"text": text,
+/// "hash": hash}}`, where `hash` is the 16 hex digits of its semantic hash, or `{"Err": error}` if
+/// it doesn't parse.
+#[wasm_bindgen]
+pub fn canonicalize_expression(input: String) -> JsValue {
+    guarded("canonicalize_expression", || {
+        let canonical = parser::parse(&input)
+            .map(|expr| {
+                let hash = format!("{:016x}", canonical::semantic_hash(&expr));
+                let text = format::format(&canonical::canonicalize(expr));
+                let mut out = BTreeMap::new();
+                out.insert("text", text);
+                out.insert("hash", hash);
+                out
+            })
+            .map_err(|err| format!("{:?}", err));
+        to_js(&canonical)
+    })
+}
+
+/// Parse `input` and serialize the names it needs bound (see `Expression::free_variables`) as
+/// `{"Ok": names}`, or `{"Err": error}` if it doesn't parse.
+#[wasm_bindgen]
+pub fn free_variables(input: String) -> JsValue {
+    guarded("free_variables", || {
+        let names = parser::parse(&input)
+            .map(|expr| {
+                expr.free_variables()
+                    .into_iter()
+                    .map(|id| id.0)
+                    .collect::<Vec<_>>()
+            })
+            .map_err(|err| format!("{:?}", err));
+        to_js(&names)
+    })
+}
+
+/// Parse `input` and serialize the 32 hex digits of its fingerprint (see `Expression::fingerprint`)
+/// as `{"Ok": fingerprint}`, or `{"Err": error}` if it doesn't parse.
+#[wasm_bindgen]
+pub fn fingerprint(input: String) -> JsValue {
+    guarded("fingerprint", || {
+        let fingerprint = parser::parse(&input)
+            .map(|expr| format!("{:032x}", expr.fingerprint()))
+            .map_err(|err| format!("{:?}", err));
+        to_js(&fingerprint)
+    })
+}
+
+/// Parse `input` and serialize the operators, methods, functions and kinds of literal it uses (see
+/// `features::Usage`) as `{"Ok": usage}`, or `{"Err": error}` if it doesn't parse.
+#[wasm_bindgen]
+pub fn feature_usage(input: String) -> JsValue {
+    guarded("feature_usage", || {
+        let usage = parser::parse(&input)
+            .map(|expr| Usage::of(&expr))
+            .map_err(|err| format!("{:?}", err));
+        to_js(&usage)
+    })
+}
+
+/// Parse `input` and serialize an upper bound on the cost of evaluating it (see `cost`), assuming
+/// bound values hold no more than `max_binding_size` elements, entries or bytes, as `{"Ok": cost}`,
+/// or `{"Err": error}` if it doesn't parse.
+#[wasm_bindgen]
+pub fn estimate_cost(input: String, max_binding_size: f64) -> JsValue {
+    guarded("estimate_cost", || {
+        let options = CostOptions {
+            max_binding_size: max_binding_size as u64,
+        };
+        let cost = parser::parse(&input)
+            .map(|expr| cost::estimate(&expr, &options))
+            .map_err(|err| format!("{:?}", err));
+        to_js(&cost)
+    })
+}
+
+/// Parse `input` and serialize the AST as a Graphviz DOT graph (see `Expression::to_dot`), as
+/// `{"Ok": graph}`, or `{"Err": error}` if it doesn't parse.
+#[wasm_bindgen]
+pub fn parse_to_dot(input: String) -> JsValue {
+    guarded("parse_to_dot", || {
+        let dot = parser::parse(&input)
+            .map(|expr| expr.to_dot())
+            .map_err(|err| format!("{:?}", err));
+        to_js(&dot)
+    })
+}
+
+/// Check the member accesses in `input` against `schemas`, a JSON object mapping binding names to
+/// JSON Schemas of their values, and serialize the list of `CheckError`s.
+#[wasm_bindgen]
+pub fn check_bindings(input: String, schemas: String) -> JsValue {
+    guarded("check_bindings", || {
+        let expr = match parser::parse(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        let schemas: serde_json::Map<String, serde_json::Value> =
+            match serde_json::from_str(&schemas) {
+                Ok(schemas) => schemas,
+                Err(err) => return JsValue::from_str(&format!("invalid schemas: {}", err)),
+            };
+        let mut checker = Checker::new();
+        for (name, schema) in &schemas {
+            checker.declare(name, Schema::from_json_schema(schema));
+        }
+        to_js(&checker.check(&expr))
+    })
+}
+
+/// Probe whether `input` can ever evaluate to `true` with `kinds`, a JSON object mapping binding
+/// names to their kinds (e.g. `{"x": "I64"}`), and serialize the `Satisfiability`.
+#[wasm_bindgen]
+pub fn check_satisfiable(input: String, kinds: String) -> JsValue {
+    guarded("check_satisfiable", || {
+        let expr = match parser::parse(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        let kinds: Vec<(Identifier, Kind)> =
+            match serde_json::from_str::<HashMap<String, Kind>>(&kinds) {
+                Ok(kinds) => kinds
+                    .into_iter()
+                    .map(|(name, kind)| (Identifier(name), kind))
+                    .collect(),
+                Err(err) => return JsValue::from_str(&format!("invalid kinds: {}", err)),
+            };
+        to_js(&satisfiability::satisfiable(
+            &EvalContext::default(),
+            &expr,
+            &kinds,
+        ))
+    })
+}
+
+/// Verify `bundle`, a policy bundle as JSON, and serialize the list of problems with it.
+#[wasm_bindgen]
+pub fn verify_bundle(bundle: String) -> JsValue {
+    guarded("verify_bundle", || {
+        let errors = match Bundle::from_json(&bundle) {
+            Ok(bundle) => bundle.verify(),
+            Err(err) => vec![err],
+        };
+        to_js(&bundle_errors(errors))
+    })
+}
+
+/// Load `bundle`, a policy bundle as JSON, and evaluate each of its policies with `bindings`, a
+/// JSON object mapping names to values, in scope. Serializes `[name, result]` pairs in the order
+/// the bundle lists its policies, or `{"Err": [...]}` with the bundle's problems if it doesn't
+/// verify.
+#[wasm_bindgen]
+pub fn evaluate_bundle(bundle: String, bindings: String) -> JsValue {
+    guarded("evaluate_bundle", || {
+        let bundle = match Bundle::from_json(&bundle) {
+            Ok(bundle) => bundle,
+            Err(err) => return to_js(&serde_json::json!({ "Err": bundle_errors(vec![err]) })),
+        };
+        let policies = match bundle.compile() {
+            Ok(policies) => policies,
+            Err(errors) => return to_js(&serde_json::json!({ "Err": bundle_errors(errors) })),
+        };
+        let bindings: HashMap<String, serde_json::Value> = match serde_json::from_str(&bindings) {
+            Ok(bindings) => bindings,
+            Err(err) => return JsValue::from_str(&format!("invalid bindings: {}", err)),
+        };
+        let bindings = validation::from_json_bindings(bindings, &BindingOptions::default())
+            .and_then(|bindings| bundle.coerce(bindings));
+        let bindings = match bindings {
+            Ok(bindings) => bindings,
+            Err(err) => return JsValue::from_str(&format!("invalid bindings: {:?}", err)),
+        };
+        to_js(&in_scope(&EvalContext::default(), &bindings, |ctx| {
+            policies.evaluate_all(ctx)
+        }))
+    })
+}
+
+fn bundle_errors(errors: Vec<BundleError>) -> Vec<String> {
+    errors.iter().map(|err| format!("{:?}", err)).collect()
+}
+
+/// Call `f` with a context that has each of `bindings` in scope.
+fn in_scope<T, F: FnOnce(&EvalContext) -> T>(
+    ctx: &EvalContext,
+    bindings: &[(Identifier, Value)],
+    f: F,
+) -> T {
+    f(&ctx.clone().with_bindings(bindings.iter().cloned()))
+}
+
+/// Values to bind when evaluating with `evaluate_with_bindings`.
+///
+/// Strings and bytes are copied into wasm memory once, when set, and from then on are shared
+/// rather than copied: looking one up, or passing it along unchanged, only bumps a reference count.
+/// This makes it cheap to evaluate many expressions against the same large document.
+///
+/// JS has only the one kind of number, so whether one is bound as an int or a double is up to the
+/// binding's declaration, if it has one (see `declare`). Otherwise integral numbers become ints.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Bindings {
+    values: Vec<(Identifier, Value)>,
+    declarations: HashMap<String, Schema>,
+}
+
+#[wasm_bindgen]
+impl Bindings {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Bindings {
+        Bindings::default()
+    }
+
+    /// Bind `name` to the string `value`. Returns an error message only if this crate has a bug.
+    pub fn set_string(&mut self, name: String, value: String) -> Option<String> {
+        settled("Bindings.set_string", || {
+            self.set(name, Value::String(Rc::new(value)));
+            None
+        })
+    }
+
+    /// Bind `name` to the bytes of `value`, a `Uint8Array`. Returns an error message only if this
+    /// crate has a bug.
+    pub fn set_bytes(&mut self, name: String, value: Vec<u8>) -> Option<String> {
+        settled("Bindings.set_bytes", || {
+            self.set(name, Value::Bytes(Rc::new(value)));
+            None
+        })
+    }
+
+    /// Declare that `name` will be bound to values shaped like `schema`, a JSON Schema, so that
+    /// numbers set from then on are converted to the declared kinds: `{"type": "number"}` for a
+    /// double and `{"type": "integer"}` for an int. Returns an error message if `schema` isn't
+    /// valid JSON.
+    pub fn declare(&mut self, name: String, schema: String) -> Option<String> {
+        settled("Bindings.declare", || match serde_json::from_str(&schema) {
+            Ok(schema) => {
+                let schema = Schema::from_json_schema(&schema);
+                self.declarations.insert(name, schema);
+                None
+            }
+            Err(err) => Some(format!("invalid schema: {}", err)),
+        })
+    }
+
+    /// Bind `name` to the number `value`. Returns an error message if it isn't finite, or can't be
+    /// converted to the declared kind.
+    pub fn set_number(&mut self, name: String, value: f64) -> Option<String> {
+        settled("Bindings.set_number", || {
+            let value = validation::from_js_number(value);
+            self.set_checked(name, value)
+        })
+    }
+
+    /// Bind `name` to `value`, given as JSON. Returns an error message if it isn't valid JSON, or
+    /// breaks the default `BindingOptions`.
+    pub fn set_json(&mut self, name: String, value: String) -> Option<String> {
+        settled("Bindings.set_json", || {
+            let value = match serde_json::from_str(&value) {
+                Ok(value) => value,
+                Err(err) => return Some(format!("invalid JSON: {}", err)),
+            };
+            self.set_checked(name, json::from_json(value))
+        })
+    }
+}
+
+/// Run `f`, the body of the setter `entry`, which returns an error message if it fails, returning a
+/// panic's as well if the build can catch it (see `panics`).
+fn settled(entry: &'static str, f: impl FnOnce() -> Option<String>) -> Option<String> {
+    panics::guard(entry, f).unwrap_or_else(|err| Some(format!("internal error: {}", err)))
+}
+
+impl Bindings {
+    /// Validate `value` and convert it to the declared kinds before binding it.
+    fn set_checked(&mut self, name: String, value: Value) -> Option<String> {
+        let value = validation::validate(&name, value, &BindingOptions::default());
+        let value = match (value, self.declarations.get(&name)) {
+            (Ok(value), Some(schema)) => validation::coerce(&name, value, schema),
+            (value, _) => value,
+        };
+        match value {
+            Ok(value) => {
+                self.set(name, value);
+                None
+            }
+            Err(err) => Some(format!("{:?}", err)),
+        }
+    }
+
+    fn set(&mut self, name: String, value: Value) {
+        let id = Identifier(name);
+        self.values.retain(|(other, _)| *other != id);
+        self.values.push((id, value));
+    }
+}
+
+/// Parse `input`, evaluate it with `bindings` in scope, and serialize the `EvalResult`.
+#[wasm_bindgen]
+pub fn evaluate_with_bindings(input: String, bindings: &Bindings) -> JsValue {
+    guarded("evaluate_with_bindings", || {
+        let expr = match parser::parse(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        to_js(&in_scope(
+            &EvalContext::default(),
+            &bindings.values,
+            |ctx| ctx.evaluate(expr),
+        ))
+    })
+}
+
+/// Parse `input` once, and evaluate it against each element of `contexts`, an array of objects whose
+/// fields are bound by name (see `EvalContext::from_json`). Serializes an array of the `EvalResult`
+/// for each, or `{"Err": {"InvalidBindings": ...}}` for one whose fields can't be bound. Each
+/// evaluation has limits of its own.
+#[wasm_bindgen]
+pub fn evaluate_batch(input: String, contexts: JsValue) -> JsValue {
+    guarded("evaluate_batch", || {
+        let expr = match parser::parse(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        match from_js(&contexts) {
+            Ok(serde_json::Value::Array(contexts)) => to_js(&evaluate_each(&expr, &contexts)),
+            Ok(_) => JsValue::from_str("invalid contexts: not an array"),
+            Err(err) => JsValue::from_str(&format!("invalid contexts: {}", err)),
+        }
+    })
+}
+
+fn evaluate_each(expr: &Expression, contexts: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    contexts.iter().map(|doc| evaluate_one(expr, doc)).collect()
+}
+
+fn evaluate_one(expr: &Expression, doc: &serde_json::Value) -> serde_json::Value {
+    match EvalContext::from_json(doc) {
+        Ok(ctx) => serde_json::to_value(ctx.evaluate(expr.clone())).expect("serialize"),
+        Err(err) => serde_json::json!({ "Err": { "InvalidBindings": err } }),
+    }
+}
+
+/// Evaluates an expression against each record of newline-delimited JSON fed to it in chunks, so
+/// that a large file can be filtered without holding all of it, or all of the results, in memory.
+///
+/// Each record is bound as an element of `evaluate_batch`'s `contexts` is. Blank lines are skipped.
+#[wasm_bindgen]
+pub struct NdjsonStream {
+    expr: Expression,
+    /// The start of a line whose end hasn't been fed yet.
+    partial: Vec<u8>,
+    /// The number of the line being read, from 1.
+    line: usize,
+    /// Whether the line being read is too long, and is being skipped.
+    skipping: bool,
+}
+
+/// Lines longer than this are reported rather than buffered.
+const MAX_LINE_LEN: usize = 16 << 20;
+
+#[wasm_bindgen]
+impl NdjsonStream {
+    /// Parse `input`, throwing the parse error if it doesn't parse.
+    #[wasm_bindgen(constructor)]
+    pub fn new(input: String) -> Result<NdjsonStream, JsValue> {
+        let parsed = panics::guard("NdjsonStream.new", || parser::parse(&input));
+        match parsed.map_err(internal)? {
+            Ok(expr) => Ok(NdjsonStream::of(expr)),
+            Err(err) => Err(JsValue::from_str(&format!("{:?}", err))),
+        }
+    }
+
+    /// Feed the next `chunk` of the input, a `Uint8Array`, which may end part way through a line.
+    /// Serializes `[{line, result}]`, with the `EvalResult` for each line the chunk completes.
+    pub fn push(&mut self, chunk: &[u8]) -> JsValue {
+        guarded("NdjsonStream.push", || to_js(&self.feed(chunk)))
+    }
+
+    /// End the input, serializing the result for its last line, if it didn't end with a newline, as
+    /// `push` does.
+    pub fn finish(&mut self) -> JsValue {
+        guarded("NdjsonStream.finish", || to_js(&self.feed(b"\n")))
+    }
+}
+
+impl NdjsonStream {
+    fn of(expr: Expression) -> NdjsonStream {
+        NdjsonStream {
+            expr,
+            partial: Vec::new(),
+            line: 1,
+            skipping: false,
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Vec<serde_json::Value> {
+        let mut results = Vec::new();
+        let mut lines = chunk.split(|&b| b == b'\n').peekable();
+        while let Some(part) = lines.next() {
+            let complete = lines.peek().is_some();
+            if !self.skipping {
+                if self.partial.len() + part.len() > MAX_LINE_LEN {
+                    let err = format!("longer than {} bytes", MAX_LINE_LEN);
+                    results.push(self.result(serde_json::json!({ "Err": { "InvalidJson": err } })));
+                    self.partial.clear();
+                    self.skipping = true;
+                } else {
+                    self.partial.extend_from_slice(part);
+                }
+            }
+            if !complete {
+                break;
+            }
+            if !self.skipping && !self.partial.iter().all(u8::is_ascii_whitespace) {
+                let result = match serde_json::from_slice(&self.partial) {
+                    Ok(doc) => evaluate_one(&self.expr, &doc),
+                    Err(err) => serde_json::json!({ "Err": { "InvalidJson": err.to_string() } }),
+                };
+                results.push(self.result(result));
+            }
+            self.partial.clear();
+            self.skipping = false;
+            self.line += 1;
+        }
+        results
+    }
+
+    fn result(&self, result: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({ "line": self.line, "result": result })
+    }
+}
+
+/// Bindings that last across calls, and the expressions parsed so far, so that a frontend that
+/// evaluates as the user types only sends what changed.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Session {
+    bindings: Bindings,
+    /// Expressions by their source, so that evaluating the same source again doesn't parse it.
+    parsed: HashMap<String, Expression>,
+}
+
+/// How many parsed expressions a `Session` keeps before it forgets them all.
+const SESSION_PARSED: usize = 64;
+
+#[wasm_bindgen]
+impl Session {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Bind `name` to `value`, any JSON-like JS value. Returns an error message if it isn't one, or
+    /// breaks the default `BindingOptions`.
+    pub fn set(&mut self, name: String, value: JsValue) -> Option<String> {
+        settled("Session.set", || match from_js(&value) {
+            Ok(value) => self.bindings.set_checked(name, json::from_json(value)),
+            Err(err) => Some(format!("invalid value: {}", err)),
+        })
+    }
+
+    /// Forget the binding of `name`, returning whether there was one.
+    pub fn remove(&mut self, name: String) -> bool {
+        let id = Identifier(name);
+        let before = self.bindings.values.len();
+        self.bindings.values.retain(|(other, _)| *other != id);
+        self.bindings.values.len() != before
+    }
+
+    /// Evaluate `input` with the session's bindings in scope, and serialize the `EvalResult`.
+    pub fn eval(&mut self, input: String) -> JsValue {
+        guarded("Session.eval", || match self.evaluate(input) {
+            Ok(result) => to_js(&result),
+            Err(err) => JsValue::from_str(&format!("{:?}", err)),
+        })
+    }
+
+    /// Evaluate `input` as `eval` does, and bind `name` to its value if it succeeds, as a `let`
+    /// would for the expressions evaluated after it. Serializes the `EvalResult`.
+    #[wasm_bindgen(js_name = letBinding)]
+    pub fn let_binding(&mut self, name: String, input: String) -> JsValue {
+        guarded("Session.letBinding", || match self.bind(name, input) {
+            Ok(result) => to_js(&result),
+            Err(err) => JsValue::from_str(&format!("{:?}", err)),
+        })
+    }
+}
+
+impl Session {
+    fn evaluate(&mut self, input: String) -> Result<EvalResult, ParseError> {
+        if !self.parsed.contains_key(&input) {
+            if self.parsed.len() >= SESSION_PARSED {
+                self.parsed.clear();
+            }
+            let expr = parser::parse(&input)?;
+            self.parsed.insert(input.clone(), expr);
+        }
+        let expr = self.parsed[&input].clone();
+        Ok(in_scope(
+            &EvalContext::default(),
+            &self.bindings.values,
+            |ctx| ctx.evaluate(expr),
+        ))
+    }
+
+    fn bind(&mut self, name: String, input: String) -> Result<EvalResult, ParseError> {
+        let result = self.evaluate(input)?;
+        if let Ok(ref value) = result {
+            self.bindings.set(name, value.clone());
+        }
+        Ok(result)
+    }
+}
+
+/// Like `evaluate_with_bindings`, but on the named backend: `"interpreter"`, `"stack"` or
+/// `"register"` (see `backend`).
+#[wasm_bindgen]
+pub fn evaluate_with_backend(input: String, bindings: &Bindings, backend: String) -> JsValue {
+    guarded("evaluate_with_backend", || {
+        let backend: Backend = match backend.parse() {
+            Ok(backend) => backend,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        let expr = match parser::parse(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        to_js(&in_scope(
+            &EvalContext::default(),
+            &bindings.values,
+            |ctx| backend.evaluate(expr, ctx),
+        ))
+    })
+}
+
+/// A compiled expression, which can be evaluated many times, and exported as bytes to be stored and
+/// imported again later without parsing it (see `program`).
+#[wasm_bindgen]
+pub struct CelProgram {
+    program: Program,
+}
+
+#[wasm_bindgen]
+impl CelProgram {
+    /// Parse `input`, throwing the parse error if it doesn't parse.
+    pub fn compile(input: String) -> Result<CelProgram, JsValue> {
+        let compiled = panics::guard("CelProgram.compile", || Program::compile(&input));
+        match compiled.map_err(internal)? {
+            Ok(program) => Ok(CelProgram { program }),
+            Err(err) => Err(JsValue::from_str(&format!("{:?}", err))),
+        }
+    }
+
+    /// Load a program from what `export` returned, throwing if it is malformed, or from a version
+    /// of this crate that supports language features this one doesn't.
+    pub fn import(bytes: &[u8]) -> Result<CelProgram, JsValue> {
+        let imported = panics::guard("CelProgram.import", || Program::from_bytes(bytes));
+        match imported.map_err(internal)? {
+            Ok(program) => Ok(CelProgram { program }),
+            Err(err) => Err(JsValue::from_str(&format!("{:?}", err))),
+        }
+    }
+
+    /// The program as bytes. Throws only if this crate has a bug.
+    pub fn export(&self) -> Result<Vec<u8>, JsValue> {
+        panics::guard("CelProgram.export", || self.program.to_bytes()).map_err(internal)
+    }
+
+    /// Evaluate the program with `bindings` in scope, and serialize the `EvalResult`.
+    pub fn evaluate(&self, bindings: &Bindings) -> JsValue {
+        guarded("CelProgram.evaluate", || {
+            to_js(&in_scope(
+                &EvalContext::default(),
+                &bindings.values,
+                |ctx| self.program.evaluate(ctx),
+            ))
+        })
+    }
+}
+
+/// Parse `input` and compile it for the stack machine (see `stack`), returning the bytecode as a
+/// `Uint8Array`, and throwing the parse error if it doesn't parse.
+#[wasm_bindgen]
+pub fn compile_to_bytecode(input: String) -> Result<Vec<u8>, JsValue> {
+    let compiled = panics::guard("compile_to_bytecode", || {
+        let expr = parser::parse(&input).map_err(|err| format!("{:?}", err))?;
+        bytecode::to_bytes(&walker::linearize(expr)).map_err(|err| format!("{:?}", err))
+    });
+    match compiled.map_err(internal)? {
+        Ok(bytes) => Ok(bytes),
+        Err(err) => Err(JsValue::from_str(&err)),
+    }
+}
+
+/// Run `bytes`, bytecode from `compile_to_bytecode`, with `bindings` in scope, and serialize the
+/// `EvalResult`, or the `BytecodeError` if it doesn't load.
+#[wasm_bindgen]
+pub fn run_bytecode(bytes: &[u8], bindings: &Bindings) -> JsValue {
+    guarded("run_bytecode", || {
+        let program = match bytecode::from_bytes(bytes) {
+            Ok(program) => program,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        to_js(&in_scope(
+            &EvalContext::default(),
+            &bindings.values,
+            |ctx| runtime::evaluate_in(program, ctx),
+        ))
+    })
+}
+
+/// Parse `input`, evaluate it with `bindings` in scope, and serialize `{result, causes}`: the
+/// `EvalResult`, and the subexpressions that decided it (see `EvaluatedAst::explain`), each with its
+/// `id`, `op`, `span` and `result`.
+#[wasm_bindgen]
+pub fn explain(input: String, bindings: &Bindings) -> JsValue {
+    guarded("explain", || {
+        let expr = match parser::parse_with_spans(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        let ast = in_scope(&EvalContext::default(), &bindings.values, |ctx| {
+            EvaluatedAst::new(ctx, expr)
+        });
+        let result = ast.nodes.last().and_then(|root| root.result.clone());
+        to_js(&serde_json::json!({ "result": result, "causes": ast.explain() }))
+    })
+}
+
+/// Like `evaluate_with_bindings`, but an error serializes as a `LocalizedError`, with its message
+/// rendered from `templates`, a JSON object mapping error codes to templates in place of the
+/// English ones (see `messages`).
+#[wasm_bindgen]
+pub fn evaluate_localized(input: String, bindings: &Bindings, templates: String) -> JsValue {
+    guarded("evaluate_localized", || {
+        let expr = match parser::parse_with_spans(&input) {
+            Ok(expr) => expr,
+            Err(err) => return JsValue::from_str(&format!("{:?}", err)),
+        };
+        let templates: HashMap<String, String> = match serde_json::from_str(&templates) {
+            Ok(templates) => templates,
+            Err(err) => return JsValue::from_str(&format!("invalid templates: {}", err)),
+        };
+        let mut catalog = Catalog::english();
+        for (code, template) in &templates {
+            catalog.translate(code, template);
+        }
+        let result = in_scope(&EvalContext::default(), &bindings.values, |ctx| {
+            ctx.evaluate(expr)
+        });
+        to_js(&result.map_err(|err| catalog.localize(&err)))
+    })
+}
+
+/// Serialize the English template for each error code, as a JSON object.
+#[wasm_bindgen]
+pub fn error_templates() -> JsValue {
+    guarded("error_templates", || {
+        let templates: BTreeMap<&str, &str> = messages::ENGLISH.iter().copied().collect();
+        to_js(&templates)
+    })
+}
+
+/// List the built-in methods and functions (names, operand and argument kinds, docs) as JSON.
+///
+/// Custom functions cannot be registered through the wasm bindings, so none are listed here.
+#[wasm_bindgen]
+pub fn list_signatures() -> JsValue {
+    guarded("list_signatures", || to_js(&signatures()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Error;
+
+    #[test]
+    fn declared_numbers() {
+        let mut bindings = Bindings::new();
+        assert_eq!(bindings.set_number("n".to_owned(), 42.0), None);
+        let declared = r#"{"properties": {"x": {"type": "number"}}}"#;
+        assert_eq!(bindings.declare("d".to_owned(), declared.to_owned()), None);
+        assert_eq!(
+            bindings.set_json("d".to_owned(), r#"{"x": 42}"#.to_owned()),
+            None
+        );
+        assert_eq!(
+            bindings.declare("i".to_owned(), r#"{"type": "integer"}"#.to_owned()),
+            None
+        );
+        assert!(bindings.set_number("i".to_owned(), 1.5).is_some());
+        assert!(bindings.set_number("i".to_owned(), f64::NAN).is_some());
+        assert_eq!(bindings.set_number("i".to_owned(), 7.0), None);
+        let evaluate = |input: &str| {
+            let expr = parser::parse(input).unwrap();
+            in_scope(&EvalContext::default(), &bindings.values, |ctx| {
+                ctx.evaluate(expr)
+            })
+        };
+        assert_eq!(evaluate("n"), Ok(Value::I64(42)));
+        assert_eq!(evaluate("d.x < 42.5"), Ok(Value::Bool(true)));
+        assert_eq!(evaluate("i"), Ok(Value::I64(7)));
+    }
+
+    #[test]
+    fn typescript_declarations() {
+        use tsify::Tsify;
+        assert_eq!(
+            Value::DECL,
+            "export type Value = { t: \"I64\"; c: number } | { t: \"U64\"; c: number } | \
+             { t: \"F64\"; c: number } | { t: \"Bool\"; c: boolean } | \
+             { t: \"String\"; c: string } | { t: \"Bytes\"; c: number[] } | \
+             { t: \"List\"; c: Value[] } | { t: \"Map\"; c: Record<string, Value> } | \
+             { t: \"Timestamp\"; c: Timestamp } | { t: \"Duration\"; c: number } | \
+             { t: \"Null\" } | { t: \"Optional\"; c: Value | null };"
+        );
+        assert!(Error::DECL.contains("{ At: [Span, Error] }"));
+        // The declarations describe what is actually serialized.
+        let value = Value::Map(vec![("k".to_owned(), Value::Null)].into_iter().collect());
+        assert_eq!(
+            serde_json::to_value(value).unwrap(),
+            serde_json::json!({"t": "Map", "c": {"k": {"t": "Null"}}})
+        );
+    }
+
+    #[test]
+    fn batch() {
+        let expr = parser::parse("age >= 18 && name != 'root'").unwrap();
+        let contexts = serde_json::json!([
+            {"age": 20, "name": "ada"},
+            {"age": 12, "name": "bob"},
+            {"age": 30},
+            [],
+        ]);
+        let results = evaluate_each(&expr, contexts.as_array().unwrap());
+        assert_eq!(
+            results,
+            vec![
+                serde_json::json!({"Ok": {"t": "Bool", "c": true}}),
+                serde_json::json!({"Ok": {"t": "Bool", "c": false}}),
+                serde_json::to_value(EvalResult::Err(Error::NoSuchBinding(
+                    Identifier::new("name"),
+                    vec![]
+                )))
+                .unwrap(),
+                serde_json::json!({"Err": {"InvalidBindings": {"NotAnObject": "List"}}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        let mut stream = NdjsonStream::of(parser::parse("n * 2").unwrap());
+        let ok = |line: usize, n: i64| serde_json::json!({"line": line, "result": {"Ok": {"t": "I64", "c": n}}});
+        assert_eq!(stream.feed(b"{\"n\": 1}\n{\"n\""), vec![ok(1, 2)]);
+        assert!(stream.feed(b": 2}").is_empty());
+        assert_eq!(
+            stream.feed(b"\n\n  \n{\"n\": 3}\n{"),
+            vec![ok(2, 4), ok(5, 6)]
+        );
+        let results = stream.feed(b"\n{\"n\": 4}");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["line"], 6);
+        assert!(results[0]["result"]["Err"]["InvalidJson"].is_string());
+        assert_eq!(stream.feed(b"\n"), vec![ok(7, 8)]);
+        assert!(stream.feed(b"\n").is_empty());
+
+        let mut stream = NdjsonStream::of(parser::parse("1").unwrap());
+        let long = vec![b' '; MAX_LINE_LEN];
+        assert!(stream.feed(&long).is_empty());
+        let results = stream.feed(b"[]\n{}");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["line"], 1);
+        assert!(stream.feed(b"").is_empty());
+        assert_eq!(
+            stream.feed(b"\n"),
+            vec![serde_json::json!({"line": 2, "result": {"Ok": {"t": "I64", "c": 1}}})]
+        );
+    }
+
+    #[test]
+    fn session() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.bind("x".to_owned(), "1 + 2".to_owned()),
+            Ok(Ok(Value::I64(3)))
+        );
+        assert!(session
+            .bind("y".to_owned(), "x / 0".to_owned())
+            .unwrap()
+            .is_err());
+        assert!(session.bind("y".to_owned(), "x +".to_owned()).is_err());
+        assert_eq!(
+            session.evaluate("let z = x * x; z".to_owned()),
+            Ok(Ok(Value::I64(9)))
+        );
+        assert!(session.evaluate("y".to_owned()).unwrap().is_err());
+        // A binding set since the source was parsed is seen the next time it is evaluated.
+        session.bindings.set("y".to_owned(), Value::I64(1));
+        assert_eq!(session.evaluate("y".to_owned()), Ok(Ok(Value::I64(1))));
+        assert!(session.remove("x".to_owned()));
+        assert!(!session.remove("x".to_owned()));
+        assert!(session
+            .evaluate("let z = x * x; z".to_owned())
+            .unwrap()
+            .is_err());
+
+        for i in 0..2 * SESSION_PARSED {
+            assert_eq!(
+                session.evaluate(i.to_string()),
+                Ok(Ok(Value::I64(i as i64)))
+            );
+        }
+        assert!(session.parsed.len() <= SESSION_PARSED);
+    }
+
+    #[test]
+    fn bindings_are_shared() {
+        let mut bindings = Bindings::new();
+        bindings.set_string("s".to_owned(), "x".repeat(1000));
+        bindings.set_bytes("b".to_owned(), vec![0; 1000]);
+        assert!(bindings.set_json("j".to_owned(), "{".to_owned()).is_some());
+        assert_eq!(
+            bindings.set_json("j".to_owned(), r#"{"k": 1}"#.to_owned()),
+            None
+        );
+        let (s, b) = match &bindings.values[..] {
+            [(_, Value::String(s)), (_, Value::Bytes(b)), _] => (s.clone(), b.clone()),
+            values => panic!("{:?}", values),
+        };
+        let evaluate = |input: &str| {
+            let expr = parser::parse(input).unwrap();
+            in_scope(&EvalContext::default(), &bindings.values, |ctx| {
+                ctx.evaluate(expr)
+            })
+        };
+        match evaluate("let t = s; [t, b, j.k]") {
+            Ok(Value::List(values)) => match &values[..] {
+                [Value::String(t), Value::Bytes(c), Value::I64(1)] => {
+                    assert!(Rc::ptr_eq(t, &s));
+                    assert!(Rc::ptr_eq(c, &b));
+                }
+                values => panic!("{:?}", values),
+            },
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(
+            evaluate("s + s"),
+            Ok(Value::String("x".repeat(2000).into()))
+        );
+    }
+
+    #[test]
+    fn many_bindings() {
+        let mut bindings = Bindings::new();
+        for i in 0..100_000 {
+            let name = Identifier(format!("x{}", i));
+            bindings.values.push((name, Value::I64(i)));
+        }
+        bindings
+            .values
+            .push((Identifier::new("x0"), Value::I64(-1)));
+        let expr = parser::parse("x0 + x99999").unwrap();
+        let result = in_scope(&EvalContext::default(), &bindings.values, |ctx| {
+            ctx.evaluate(expr)
+        });
+        // A later binding of a name shadows an earlier one.
+        assert_eq!(result, Ok(Value::I64(99998)));
+    }
+}